@@ -0,0 +1,139 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+
+/// Splits an alias definition into arguments the way a POSIX shell would: on whitespace, except
+/// within single or double quotes, with a backslash escaping the next character (inside double
+/// quotes only `"` and `\`).
+pub fn split_words(definition: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = definition.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("Unterminated ' in '{}'", definition)),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(anyhow!("Unterminated \" in '{}'", definition)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("Unterminated \" in '{}'", definition)),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = chars.next().ok_or_else(|| anyhow!("Trailing \\ in '{}'", definition))?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Joins arguments into a definition `split_words` turns back into the same arguments.
+pub fn join_words(words: &[String]) -> String {
+    words.iter()
+        .map(|word| {
+            if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || "'\"\\".contains(c)) {
+                word.clone()
+            } else {
+                format!("'{}'", word.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Replaces an alias at the start of `args` (the command line without the program name) with
+/// its definition, keeping the arguments after it. A definition may itself start with an
+/// alias, which is expanded in turn; an alias that leads back to itself is an error.
+pub fn expand(mut args: Vec<OsString>, aliases: &BTreeMap<String, String>) -> Result<Vec<OsString>> {
+    let mut chain: Vec<String> = Vec::new();
+
+    while let Some((name, definition)) = args.first()
+        .and_then(|first| first.to_str())
+        .and_then(|first| aliases.get_key_value(first))
+    {
+        if chain.iter().any(|seen| seen == name) {
+            chain.push(name.clone());
+            return Err(anyhow!("Alias '{}' leads back to itself: {}", chain[0], chain.join(" -> ")));
+        }
+        chain.push(name.clone());
+
+        let mut expanded: Vec<OsString> = split_words(definition)
+            .map_err(|e| anyhow!("Invalid alias '{}': {}", name, e))?
+            .into_iter()
+            .map(OsString::from)
+            .collect();
+        expanded.extend(args.drain(1..));
+        args = expanded;
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(definitions: &[(&str, &str)]) -> BTreeMap<String, String> {
+        definitions.iter().map(|(name, definition)| (name.to_string(), definition.to_string())).collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn splits_like_a_shell() {
+        assert_eq!(
+            split_words(r#"chat -m 'gpt 4o' --system "Say \"hi\" \\ \n" a\ b"#).unwrap(),
+            ["chat", "-m", "gpt 4o", "--system", "Say \"hi\" \\ \\n", "a b"]
+        );
+        assert_eq!(split_words("  ''  x").unwrap(), ["", "x"]);
+        assert!(split_words("chat 'open").is_err());
+        assert!(split_words("chat \"open").is_err());
+        assert!(split_words("chat \\").is_err());
+    }
+
+    #[test]
+    fn joins_words_back_into_the_same_arguments() {
+        let words: Vec<String> = ["chat", "it's", "two words", "", "back\\slash", "\"quoted\""].iter().map(|word| word.to_string()).collect();
+        assert_eq!(split_words(&join_words(&words)).unwrap(), words);
+    }
+
+    #[test]
+    fn expands_aliases_in_turn_keeping_the_arguments_after_them() {
+        let aliases = aliases(&[("r", "review --staged"), ("rr", "r --fail-on high")]);
+        assert_eq!(expand(args(&["rr", "--output", "json"]), &aliases).unwrap(), args(&["review", "--staged", "--fail-on", "high", "--output", "json"]));
+        assert_eq!(expand(args(&["chat", "r"]), &aliases).unwrap(), args(&["chat", "r"]));
+    }
+
+    #[test]
+    fn rejects_aliases_leading_back_to_themselves() {
+        let error = expand(args(&["a"]), &aliases(&[("a", "b -x"), ("b", "c"), ("c", "a")])).unwrap_err();
+        assert_eq!(error.to_string(), "Alias 'a' leads back to itself: a -> b -> c -> a");
+        assert!(expand(args(&["self"]), &aliases(&[("self", "self --again")])).is_err());
+    }
+}
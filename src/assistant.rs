@@ -0,0 +1,562 @@
+//! The commands that ask the model once and act on its answer: `code`, `commit`, `edit`, `pr`,
+//! `review`, `shell`, `summarize`, `translate` and `tasks`.
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use dialoguer::{Confirm, Input};
+use reqwest::Client;
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+use crate::providers::Provider;
+use crate::session::{report_repo_context, OutputFormat};
+use crate::ui::{check_budget, confirm_large_prompt, ensure_interactive, non_interactive, show_progress, waiting_spinner};
+use crate::usage::{self, UsageEntry};
+use crate::{auth, chunk, clipboard, code, config, context, cost, git, history, lock, models, patch, pr, review, shell, summarize, tasks, translate};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TaskFormat {
+    Markdown,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ReviewOutput {
+    Text,
+    Json,
+}
+
+/// Whether `code` runs the code it generated.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Execution {
+    None,
+    /// `--exec`: once the user confirms
+    Confirmed,
+    /// `--exec-unconfirmed`: straight away
+    Unconfirmed,
+}
+
+/// The model the commands ask, and how they go about it.
+pub struct Assistant<'a> {
+    pub provider: &'a dyn Provider,
+    pub model: &'a str,
+    pub max_words: u32,
+    /// Go ahead without the confirmations `--yes` skips
+    pub yes: bool,
+    pub output: OutputFormat,
+}
+
+impl Assistant<'_> {
+    /// Sends a prompt, asking for a new API key if the current one is rejected, and records what
+    /// the exchange cost.
+    async fn ask(&self, prompt: &str, rates: &BTreeMap<String, f64>) -> Result<String> {
+        let response = auth::with_recovery(self.provider, || self.provider.chat(self.model, prompt, self.max_words)).await?;
+        usage::record(&UsageEntry::new(self.model, prompt, &response, rates))?;
+        Ok(response)
+    }
+
+    /// Runs an extraction pass over a stored conversation and prints its action items.
+    pub async fn extract_tasks(&self, id: &str, format: TaskFormat, append: Option<&Path>) -> Result<()> {
+        let record = history::load(id)?;
+        if record.turns.is_empty() {
+            return Err(anyhow!("Conversation {} has no messages", id));
+        }
+        models::validate(self.model)?;
+
+        let prompt = tasks::extraction_prompt(&record);
+        let response = self.ask(&prompt, &config::Config::load()?.model_rates).await?;
+        let tasks = tasks::parse_tasks(&response)?;
+
+        let markdown = tasks::render_markdown(&tasks);
+        match format {
+            TaskFormat::Markdown if tasks.is_empty() => println!("No action items found."),
+            TaskFormat::Markdown => print!("{}", markdown),
+            TaskFormat::Json => println!("{}", serde_json::to_string_pretty(&tasks)?),
+        }
+
+        if let Some(path) = append.filter(|_| !tasks.is_empty()) {
+            tasks::append_to_file(path, &markdown)?;
+            eprintln!("Appended {} task(s) to {}", tasks.len(), path.display());
+        }
+        Ok(())
+    }
+
+    /// Generates code for a task, stripping any prose or fences the model adds around it.
+    pub async fn generate_code(
+        &self,
+        task: &str,
+        lang: Option<&str>,
+        out: Option<&Path>,
+        exec: Execution,
+    ) -> Result<()> {
+        models::validate(self.model)?;
+        let lang = lang.or_else(|| out.and_then(code::language_for_path));
+        if exec != Execution::None && lang.is_none() {
+            return Err(anyhow!("--exec needs --lang (or an --out file extension) to pick an interpreter"));
+        }
+
+        let prompt = code::code_prompt(task, lang);
+        let response = self.ask(&prompt, &config::Config::load()?.model_rates).await?;
+        let code = code::strip_fences(&response);
+
+        match out {
+            Some(path) => {
+                std::fs::write(path, &code)?;
+                println!("Code written to {}", path.display());
+            }
+            None => print!("{}", code),
+        }
+
+        if let (true, Some(lang)) = (exec != Execution::None, lang) {
+            let target = match out {
+                Some(path) => path.display().to_string(),
+                None => "the code above".to_string(),
+            };
+            // Unlike other confirmations, this one isn't skipped by --yes.
+            let confirmed = exec == Execution::Unconfirmed || {
+                ensure_interactive("Running generated code", "review the code and run it yourself, or pass --exec-unconfirmed")?;
+                Confirm::new()
+                    .with_prompt(format!("Run {} as {}? Review it first: generated code can do anything you can", target, lang))
+                    .default(false)
+                    .interact()?
+            };
+            if !confirmed {
+                println!("Not running it.");
+                return Ok(());
+            }
+
+            let status = code::execute(&code, lang, out)?;
+            if !status.success() {
+                return Err(anyhow!("The generated code exited with {}", status));
+            }
+        }
+        Ok(())
+    }
+
+    /// Proposes a commit message for the staged diff and commits with it once confirmed.
+    pub async fn commit_staged(&self, conventional: bool, amend: bool) -> Result<()> {
+        models::validate(self.model)?;
+        let diff = git::staged_diff(amend)?;
+
+        let prompt = git::commit_prompt(&diff, conventional);
+        let response = self.ask(&prompt, &config::Config::load()?.model_rates).await?;
+        let message = git::clean_message(&response);
+        if message.is_empty() {
+            return Err(anyhow!("The model returned an empty commit message"));
+        }
+
+        println!("{}\n", message);
+        let confirmed = self.yes || {
+            ensure_interactive("Committing", "pass --yes to commit with the generated message")?;
+            Confirm::new()
+                .with_prompt(if amend { "Amend the last commit with this message?" } else { "Commit with this message?" })
+                .default(true)
+                .interact()?
+        };
+        if !confirmed {
+            println!("Aborted.");
+            return Ok(());
+        }
+        git::commit(&message, amend)
+    }
+
+    /// Asks for a diff making the change to the file, asking once more if it doesn't apply, then
+    /// previews it and applies it once confirmed, keeping the original as `<file>.bak`.
+    pub async fn edit_file(
+        &self,
+        path: &Path,
+        instruction: &str,
+        dry_run: bool,
+        repo: Option<(&context::Repo, usize)>,
+    ) -> Result<()> {
+        let model = self.model;
+        models::validate(model)?;
+        let original = std::fs::read_to_string(path).with_context(|| format!("Could not read {}", path.display()))?;
+        let context = match repo {
+            Some((repo, limit)) => {
+                // The file itself is in the prompt already.
+                let relative = path.canonicalize()?
+                    .strip_prefix(repo.root.canonicalize()?)
+                    .map(|relative| relative.display().to_string())
+                    .unwrap_or_default();
+                let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+                let query = format!("{} {}", stem, instruction);
+                let selection = repo.select(&query, limit.saturating_sub(cost::estimate_tokens(&original)), &[relative.as_str()]);
+                report_repo_context(&selection);
+                selection.render()
+            }
+            None => String::new(),
+        };
+
+        let config = config::Config::load()?;
+        let rates = &config.model_rates;
+        let first_prompt = patch::edit_prompt(&path.display().to_string(), &original, instruction, &context);
+        if !confirm_large_prompt(&first_prompt, model, &config, self.yes)? {
+            println!("Aborted.");
+            return Ok(());
+        }
+        let mut prompt = first_prompt.clone();
+        let spinner = show_progress().then(waiting_spinner);
+        let (diff, patched) = loop {
+            let response = self.ask(&prompt, rates).await?;
+            let applied = patch::Patch::parse(&response).and_then(|diff| {
+                let patched = diff.apply(&original)?;
+                Ok((diff, patched))
+            });
+            match applied {
+                Ok(applied) => break applied,
+                Err(e) if prompt == first_prompt => {
+                    if let Some(spinner) = &spinner {
+                        spinner.set_message("the diff didn't apply, asking again…");
+                    }
+                    prompt = patch::retry_prompt(&first_prompt, &response, &e);
+                }
+                Err(e) => return Err(anyhow!("The model's diff doesn't apply to {}: {}", path.display(), e)),
+            }
+        };
+        drop(spinner);
+
+        print!("{}", diff.render_colored());
+        let (added, removed) = diff.stats();
+        println!("\n{}: {} line(s) added, {} removed", path.display(), added, removed);
+        if dry_run {
+            return Ok(());
+        }
+        let confirmed = self.yes || {
+            ensure_interactive("Applying the edit", "check it with --dry-run and pass --yes to apply it")?;
+            Confirm::new()
+                .with_prompt(format!("Apply this change to {}?", path.display()))
+                .default(true)
+                .interact()?
+        };
+        if !confirmed {
+            println!("Aborted.");
+            return Ok(());
+        }
+
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        std::fs::copy(path, &backup).with_context(|| format!("Could not back up {}", path.display()))?;
+        std::fs::write(path, patched)?;
+        println!("Edited {} (the original is in {})", path.display(), Path::new(&backup).display());
+        Ok(())
+    }
+
+    /// Writes a pull request title and description for the branch's commits and diff, and creates
+    /// the pull request with gh when asked to.
+    pub async fn write_pull_request(
+        &self,
+        base: &str,
+        template: Option<&Path>,
+        push_gh: bool,
+        draft: bool,
+    ) -> Result<()> {
+        models::validate(self.model)?;
+        if push_gh {
+            pr::ensure_gh()?;
+        }
+        let log = git::branch_log(base)?;
+        if log.trim().is_empty() {
+            return Err(anyhow!("This branch has no commits that aren't on {}", base));
+        }
+        let diff = git::diff(&git::DiffTarget::Branch(base))?;
+        let template = pr::load_template(template)?;
+
+        let prompt = pr::pr_prompt(base, &log, &diff, &template);
+        let spinner = show_progress().then(waiting_spinner);
+        let response = self.ask(&prompt, &config::Config::load()?.model_rates).await;
+        drop(spinner);
+        let response = response?;
+        let (title, body) = pr::parse_pr(&response)?;
+
+        println!("{}\n\n{}", title, body);
+        if !push_gh {
+            return Ok(());
+        }
+        let confirmed = self.yes || {
+            ensure_interactive("Creating the pull request", "pass --yes to create it with the generated description")?;
+            Confirm::new()
+                .with_prompt(format!("\nCreate this pull request into {}?", base))
+                .default(true)
+                .interact()?
+        };
+        if !confirmed {
+            println!("Aborted.");
+            return Ok(());
+        }
+        pr::create_with_gh(base, &title, &body, draft)
+    }
+
+    /// Reviews a diff part by part and prints the findings grouped by file.
+    pub async fn review_changes(
+        &self,
+        target: &git::DiffTarget<'_>,
+        output: ReviewOutput,
+        fail_on: Option<review::Severity>,
+        chunk_tokens: usize,
+        repo: Option<(&context::Repo, usize)>,
+    ) -> Result<()> {
+        let model = self.model;
+        models::validate(model)?;
+        let diff = git::diff(target)?;
+        let files = review::split_files(&diff);
+        let parts = review::parts(&files, chunk_tokens);
+        if parts.len() > 1 {
+            eprintln!("The diff of {} files is reviewed in {} parts.", files.len(), parts.len());
+        }
+
+        let config = config::Config::load()?;
+        let rates = &config.model_rates;
+        let spinner = (output == ReviewOutput::Text && show_progress()).then(waiting_spinner);
+        let mut issues = Vec::new();
+        for (index, part) in parts.iter().enumerate() {
+            if let Some(spinner) = &spinner {
+                spinner.set_message(format!("reviewing part {} of {}…", index + 1, parts.len()));
+            }
+            // The diff's identifiers and paths find the code it touches and uses.
+            let context = match repo {
+                Some((repo, limit)) => {
+                    let selection = repo.select(part, limit.saturating_sub(cost::estimate_tokens(part)), &[]);
+                    if output == ReviewOutput::Text {
+                        report_repo_context(&selection);
+                    }
+                    selection.render()
+                }
+                None => String::new(),
+            };
+            let prompt = review::review_prompt(part, &context);
+            let credits = cost::estimate_credits(cost::estimate_tokens(&prompt), model, rates);
+            let what = format!("Reviewing part {} of {}", index + 1, parts.len());
+            let allowed = match &spinner {
+                Some(spinner) => spinner.suspend(|| check_budget(&what, credits, &config))?,
+                None => check_budget(&what, credits, &config)?,
+            };
+            if !allowed {
+                return Err(anyhow!("Review aborted"));
+            }
+            let response = self.ask(&prompt, rates).await?;
+            issues.extend(review::parse_issues(&response)?);
+        }
+        drop(spinner);
+
+        match output {
+            ReviewOutput::Text if issues.is_empty() => println!("No issues found."),
+            ReviewOutput::Text => print!("{}", review::render_text(&issues)),
+            ReviewOutput::Json => println!("{}", serde_json::to_string_pretty(&issues)?),
+        }
+        if let Some(threshold) = fail_on {
+            let failing = issues.iter().filter(|issue| issue.severity <= threshold).count();
+            if failing > 0 {
+                return Err(anyhow!("{} finding(s) of severity {} or worse", failing, threshold.name()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks the model for a shell command and lets the user run, edit, copy or discard it.
+    pub async fn suggest_command(&self, task: &str) -> Result<()> {
+        models::validate(self.model)?;
+        let shell = shell::detect_shell();
+
+        let prompt = shell::command_prompt(task, &shell);
+        let response = self.ask(&prompt, &config::Config::load()?.model_rates).await?;
+        let mut command = shell::clean_command(&response);
+        if command.is_empty() {
+            return Err(anyhow!("The model did not suggest a command"));
+        }
+
+        // Nobody can pick an action, so just hand the command over, e.g. to a pipe.
+        if non_interactive() {
+            println!("{}", command);
+            return Ok(());
+        }
+
+        loop {
+            println!("\n  {}\n", command.replace('\n', "\n  "));
+            let choice: String = Input::new()
+                .with_prompt("[r]un / [e]dit / [c]opy / [a]bort")
+                .default("a".to_string())
+                .interact_text()?;
+
+            match choice.trim().to_lowercase().as_str() {
+                "r" | "run" => {
+                    let confirmed = Confirm::new()
+                        .with_prompt(format!("Run this in {}?", shell))
+                        .default(false)
+                        .interact()?;
+                    if !confirmed {
+                        continue;
+                    }
+                    let status = shell::run(&command, &shell)?;
+                    if !status.success() {
+                        return Err(anyhow!("The command exited with {}", status));
+                    }
+                    return Ok(());
+                }
+                "e" | "edit" => {
+                    command = Input::new()
+                        .with_prompt("Command")
+                        .with_initial_text(command.clone())
+                        .interact_text()?;
+                }
+                "c" | "copy" => {
+                    clipboard::copy(&command)?;
+                    println!("Copied to the clipboard.");
+                    return Ok(());
+                }
+                "a" | "abort" => {
+                    println!("Aborted.");
+                    return Ok(());
+                }
+                other => println!("Unknown choice '{}'.", other),
+            }
+        }
+    }
+
+    /// Summarizes a file or web page, condensing texts too long for one request part by part first.
+    /// `length` describes how long the summary should be.
+    pub async fn summarize_source(
+        &self,
+        http: &Client,
+        source: &str,
+        length: &str,
+        bullets: bool,
+        chunk_tokens: usize,
+    ) -> Result<()> {
+        let model = self.model;
+        models::validate(model)?;
+        let source = summarize::read_source(http, source).await?;
+        if source.text.trim().is_empty() {
+            return Err(anyhow!("{} has no text to summarize", source.label));
+        }
+
+        let config = config::Config::load()?;
+        let parts = chunk::split(&source.text, chunk_tokens).len();
+        let prompt_tokens = cost::estimate_tokens(&source.text);
+        if parts > 1 {
+            eprintln!("{} is long (~{} tokens); summarizing it in {} parts.", source.label, prompt_tokens, parts);
+        }
+        if !confirm_large_prompt(&source.text, model, &config, self.yes)? {
+            println!("Aborted.");
+            return Ok(());
+        }
+
+        let rates = &config.model_rates;
+        let spinner = (self.output == OutputFormat::Text && show_progress()).then(waiting_spinner);
+        let summary = chunk::map_reduce(
+            &source.text,
+            chunk_tokens,
+            summarize::PART_INSTRUCTION,
+            &summarize::final_instruction(&source.label, length, bullets),
+            |prompt| async move { self.ask(&prompt, rates).await },
+            |done, total| {
+                if let Some(spinner) = &spinner {
+                    spinner.set_message(format!("summarized {} of {} parts…", done, total));
+                }
+            },
+        ).await;
+        if let Some(spinner) = spinner {
+            spinner.finish_and_clear();
+        }
+        let summary = summary?;
+
+        match self.output {
+            OutputFormat::Text => println!("{}", summary.trim()),
+            OutputFormat::Json | OutputFormat::Jsonl => println!("{}", serde_json::json!({
+                "source": source.label,
+                "model": model,
+                "parts": parts,
+                "summary": summary.trim(),
+            })),
+        }
+        Ok(())
+    }
+
+    /// Translates a file or stdin part by part, keeping code blocks out of the model's hands.
+    pub async fn translate_text(
+        &self,
+        file: Option<&Path>,
+        to: &str,
+        from: Option<&str>,
+        in_place: bool,
+        chunk_tokens: usize,
+    ) -> Result<()> {
+        let model = self.model;
+        models::validate(model)?;
+        let text = match file {
+            Some(path) => std::fs::read_to_string(path).with_context(|| format!("Could not read {}", path.display()))?,
+            None if !io::stdin().is_terminal() => io::read_to_string(io::stdin())?,
+            None => return Err(anyhow!("Give a file to translate, or pipe the text in")),
+        };
+        if text.trim().is_empty() {
+            return Err(anyhow!("There is no text to translate"));
+        }
+
+        let config = config::Config::load()?;
+        if !confirm_large_prompt(&text, model, &config, self.yes)? {
+            println!("Aborted.");
+            return Ok(());
+        }
+
+        let rates = &config.model_rates;
+        let spinner = (self.output == OutputFormat::Text && show_progress()).then(waiting_spinner);
+        let translation = translate::translate(
+            &text,
+            to,
+            from,
+            chunk_tokens,
+            |prompt| async move { self.ask(&prompt, rates).await },
+            |part, total| {
+                if let Some(spinner) = &spinner {
+                    spinner.set_message(format!("translating part {} of {}…", part, total));
+                }
+            },
+        ).await;
+        if let Some(spinner) = spinner {
+            spinner.finish_and_clear();
+        }
+        let (detected, translation) = translation?;
+        if let (None, Some(language)) = (from, &detected) {
+            eprintln!("Detected source language: {}", language);
+        }
+
+        match file.filter(|_| in_place) {
+            Some(path) => {
+                lock::write_atomic(path, translation.as_bytes())?;
+                eprintln!("Translated {} into {}", path.display(), to);
+            }
+            None => print!("{}", translation),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::OneMinClient;
+    use crate::retry;
+    use mockito::Matcher;
+
+    #[tokio::test]
+    async fn asks_and_records_the_cost() {
+        usage::use_scratch_dirs();
+        retry::configure(0, 0);
+        let mut server = mockito::Server::new_async().await;
+        let asked = server.mock("POST", "/api/features")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::Regex("Write a haiku".to_string()))
+            .with_body("An old silent pond")
+            .expect(1)
+            .create_async().await;
+        let client = OneMinClient::new(reqwest::Client::new(), "key").with_base_url(server.url());
+        let assistant = Assistant { provider: &client, model: "gpt-4o", max_words: 100, yes: true, output: OutputFormat::Text };
+
+        let before = usage::spent_this_month().unwrap();
+        let rates = BTreeMap::from([("gpt-4o".to_string(), 1000.0)]);
+        assert_eq!(assistant.ask("Write a haiku", &rates).await.unwrap(), "An old silent pond");
+        asked.assert_async().await;
+        assert!(usage::spent_this_month().unwrap() > before);
+    }
+}
@@ -0,0 +1,208 @@
+//! Recovering from a rejected API key. Every request that fails authentication goes through the
+//! [`AuthManager`], whose [`Recovery`] strategy decides whether to ask for a new key, read it
+//! again from where it came from, or fail at once. The process's manager is [`install`]ed once
+//! the key is known, and [`with_recovery`] retries a request with the key it comes up with.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use dialoguer::Input;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use crate::credentials;
+use crate::error::AiCliError;
+use crate::providers::Provider;
+
+pub const API_KEY_ENV: &str = "AI_CLI_API_KEY";
+
+/// Set once the API key has been resolved; recovers from the API rejecting it.
+static INSTALLED: OnceLock<AuthManager> = OnceLock::new();
+
+/// Where the API key in use came from.
+#[derive(Clone, Debug)]
+pub enum KeySource {
+    File(PathBuf),
+    Env,
+    /// The system keyring, including keys typed in at a prompt and saved there
+    Keyring,
+    /// The encrypted credential file, used instead of the keyring when there is none
+    CredentialFile(PathBuf),
+    /// Another provider's key, from the environment variable named in its settings (if it needs one)
+    Provider(Option<String>),
+}
+
+impl KeySource {
+    /// What to do about a rejected API key from here.
+    pub fn hint(&self) -> String {
+        match self {
+            KeySource::File(path) => format!("Update the API key in {}.", path.display()),
+            KeySource::Env => format!("Update the {} environment variable.", API_KEY_ENV),
+            KeySource::Keyring | KeySource::CredentialFile(_) => "Run 'ai-cli config' to update your API key.".to_string(),
+            KeySource::Provider(Some(env)) => format!("Update the {} environment variable.", env),
+            KeySource::Provider(None) => "Set api_key_env for the provider in the config file.".to_string(),
+        }
+    }
+
+    /// Reads the key from here again, if there is one.
+    fn read(&self) -> Result<Option<String>> {
+        let key = match self {
+            KeySource::File(path) => fs::read_to_string(path).ok(),
+            KeySource::Env => std::env::var(API_KEY_ENV).ok(),
+            KeySource::Keyring | KeySource::CredentialFile(_) => credentials::load()?.map(|(key, _)| key),
+            KeySource::Provider(Some(env)) => std::env::var(env).ok(),
+            KeySource::Provider(None) => None,
+        };
+        Ok(key.map(|key| key.trim().to_string()).filter(|key| !key.is_empty()))
+    }
+}
+
+/// What to do when the API rejects the key.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+#[value(rename_all = "lowercase")]
+pub enum Recovery {
+    /// Ask for a new key and save it, when the key came from the keyring or credential file.
+    /// Running non-interactively, this fails like `fail`
+    #[default]
+    Prompt,
+    /// Fail at once with an authentication error
+    Fail,
+    /// Read the key again from its file, environment variable or keyring, in case it was
+    /// rotated meanwhile, and fail if it hasn't changed
+    Reload,
+}
+
+/// Replaces a rejected API key according to the recovery strategy.
+pub struct AuthManager {
+    recovery: Recovery,
+    source: KeySource,
+    /// The key last handed to the provider, so a reloaded key that is the same isn't tried again
+    current: Mutex<String>,
+}
+
+impl AuthManager {
+    pub fn new(recovery: Recovery, source: KeySource, key: &str) -> AuthManager {
+        AuthManager { recovery, source, current: Mutex::new(key.to_string()) }
+    }
+
+    pub fn source(&self) -> &KeySource {
+        &self.source
+    }
+
+    /// The same manager for a process nobody answers prompts in, such as the daemon: instead of
+    /// asking for a new key, it reads the key again from where it came from.
+    pub fn unattended(&self) -> AuthManager {
+        let recovery = match self.recovery {
+            Recovery::Prompt => Recovery::Reload,
+            recovery => recovery,
+        };
+        let current = self.current.lock().expect("the key lock isn't poisoned").clone();
+        AuthManager { recovery, source: self.source.clone(), current: Mutex::new(current) }
+    }
+
+    /// Gets a new key after the API rejected the current one and hands it to the provider, or
+    /// fails with an authentication error when the strategy can't come up with one.
+    pub fn recover(&self, provider: &dyn Provider) -> Result<()> {
+        let key = match self.recovery {
+            Recovery::Fail => None,
+            Recovery::Prompt => self.prompt()?,
+            Recovery::Reload => self.reload()?,
+        };
+        let Some(key) = key else {
+            return Err(AiCliError::Auth("the API key was rejected".to_string()).into());
+        };
+        *self.current.lock().expect("the key lock isn't poisoned") = key.clone();
+        provider.set_api_key(key);
+        Ok(())
+    }
+
+    /// Keys from a file or the environment (including other providers' keys) can't be fixed by
+    /// saving a new one, so only saved keys are asked for again.
+    fn prompt(&self) -> Result<Option<String>> {
+        if !matches!(self.source, KeySource::Keyring | KeySource::CredentialFile(_)) {
+            return Ok(None);
+        }
+        let key: String = Input::<String>::new()
+            .with_prompt("Invalid API key. Please enter a new one")
+            .allow_empty(false)
+            .interact()?;
+        credentials::save(&key)?;
+        Ok(Some(key))
+    }
+
+    fn reload(&self) -> Result<Option<String>> {
+        let current = self.current.lock().expect("the key lock isn't poisoned").clone();
+        Ok(self.source.read()?.filter(|key| *key != current))
+    }
+}
+
+/// Makes `manager` the one requests recover through. Only the first call counts.
+pub fn install(manager: AuthManager) {
+    let _ = INSTALLED.set(manager);
+}
+
+pub fn installed() -> Option<&'static AuthManager> {
+    INSTALLED.get()
+}
+
+/// What to do about a rejected API key, depending on where it came from.
+pub fn hint() -> String {
+    installed().map(|auth| auth.source().hint()).unwrap_or_default()
+}
+
+/// Gets a new API key after the API rejected the current one, as `--auth-recovery` says.
+pub fn reauthenticate(provider: &dyn Provider) -> Result<()> {
+    match installed() {
+        Some(auth) => auth.recover(provider),
+        None => Err(AiCliError::Auth("the API key was rejected".to_string()).into()),
+    }
+}
+
+/// Makes a request, and makes it again with a new key each time the API rejects the key, until
+/// it is accepted or no new key can be had.
+pub async fn with_recovery<T, Fut>(provider: &dyn Provider, mut request: impl FnMut() -> Fut) -> Result<T>
+where
+    Fut: Future<Output = Result<T, AiCliError>>,
+{
+    loop {
+        match request().await {
+            Err(e) if e.is_auth() => reauthenticate(provider)?,
+            result => return Ok(result?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::OneMinClient;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn with_recovery_only_retries_rejected_keys() {
+        let client = OneMinClient::new(reqwest::Client::new(), "key".to_string());
+        let calls = Cell::new(0);
+
+        let result = with_recovery(&client, || {
+            calls.set(calls.get() + 1);
+            async { Err::<(), _>(AiCliError::InvalidResponse("no choices".to_string())) }
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+
+        // Without a manager installed, a rejected key can't be replaced.
+        calls.set(0);
+        let result = with_recovery(&client, || {
+            calls.set(calls.get() + 1);
+            async { Err::<(), _>(AiCliError::Auth("invalid key".to_string())) }
+        }).await;
+        let error = result.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(AiCliError::Auth(_))));
+        assert_eq!(calls.get(), 1);
+
+        assert_eq!(with_recovery(&client, || async { Ok(7) }).await.unwrap(), 7);
+    }
+}
@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Context, Result};
+use dialoguer::Confirm;
+use futures_util::{stream, StreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::config::Config;
+use crate::error::AiCliError;
+use crate::providers::Provider;
+use crate::ui::{check_budget, ensure_interactive, show_progress};
+use crate::usage::{self, UsageEntry};
+use crate::{cost, models, retry};
+
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// One prompt to run, from a line of the input file.
+#[derive(Deserialize)]
+pub struct BatchItem {
+    /// Caller-chosen identifier, copied to the result
+    #[serde(default)]
+    pub id: Option<String>,
+    pub prompt: String,
+    /// Overrides the batch's model for this prompt
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Overrides the batch's system prompt for this prompt
+    #[serde(default)]
+    pub system: Option<String>,
+    #[serde(default)]
+    pub max_words: Option<u32>,
+    /// Line of the input file the prompt came from
+    #[serde(skip)]
+    pub line: usize,
+}
+
+/// Settings shared by every prompt in a batch.
+pub struct BatchOptions {
+    pub model: String,
+    pub system: Option<String>,
+    pub max_words: u32,
+    pub concurrency: usize,
+}
+
+/// The outcome of one prompt, written as a line of the results file.
+#[derive(Serialize)]
+pub struct BatchResult {
+    pub line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub model: String,
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub attempts: u32,
+    pub elapsed_ms: u64,
+}
+
+impl BatchItem {
+    /// The prompt as sent, with the system prompt in front.
+    pub fn full_prompt(&self, options: &BatchOptions) -> String {
+        match self.system.as_ref().or(options.system.as_ref()) {
+            Some(system) => format!("{}\n\n{}", system, self.prompt),
+            None => self.prompt.clone(),
+        }
+    }
+
+    pub fn model<'a>(&'a self, options: &'a BatchOptions) -> &'a str {
+        self.model.as_deref().unwrap_or(&options.model)
+    }
+}
+
+/// Reads the prompts to run. `.jsonl` and `.ndjson` files hold one JSON object per line with a
+/// `prompt` and optional `id`, `model`, `system` and `max_words`; any other file holds one prompt
+/// per line. Blank lines are skipped either way.
+pub fn load_items(path: &Path) -> Result<Vec<BatchItem>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    let jsonl = matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("jsonl" | "ndjson")
+    );
+
+    let mut items = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut item = if jsonl {
+            serde_json::from_str::<BatchItem>(line)
+                .with_context(|| format!("{}:{}: not a valid batch entry", path.display(), i + 1))?
+        } else {
+            BatchItem { id: None, prompt: line.to_string(), model: None, system: None, max_words: None, line: 0 }
+        };
+        item.line = i + 1;
+        items.push(item);
+    }
+
+    if items.is_empty() {
+        return Err(anyhow!("{} contains no prompts", path.display()));
+    }
+    Ok(items)
+}
+
+/// Runs one prompt, retrying rate limits, network failures and server errors (including ones
+/// that happen mid-stream, which the HTTP layer can't retry). Fails only on authentication
+/// errors, since every other prompt would fail the same way; other errors end up in the result.
+async fn run_item(provider: &dyn Provider, item: BatchItem, options: &BatchOptions) -> Result<BatchResult, AiCliError> {
+    let started = Instant::now();
+    let model = item.model(options).to_string();
+    let prompt = item.full_prompt(options);
+    let max_words = item.max_words.unwrap_or(options.max_words);
+
+    let mut attempts = 0;
+    let outcome = loop {
+        attempts += 1;
+        match provider.chat(&model, &prompt, max_words).await {
+            Err(e) if e.is_auth() => return Err(e),
+            Err(e) if e.is_transient() && attempts <= retry::max_retries() => {
+                tokio::time::sleep(retry::delay(attempts - 1)).await;
+            }
+            result => break result,
+        }
+    };
+
+    let (response, error) = match outcome {
+        Ok(response) => (Some(response), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    Ok(BatchResult {
+        line: item.line,
+        id: item.id,
+        model,
+        prompt: item.prompt,
+        response,
+        error,
+        attempts,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+/// Runs the prompts with at most `options.concurrency` requests in flight, handing each result
+/// to `on_result` as soon as it is ready, so results arrive in completion order.
+pub async fn run(
+    provider: &dyn Provider,
+    items: Vec<BatchItem>,
+    options: &BatchOptions,
+    mut on_result: impl FnMut(BatchResult) -> Result<()>,
+) -> Result<()> {
+    let mut results = stream::iter(items)
+        .map(|item| run_item(provider, item, options))
+        .buffer_unordered(options.concurrency.max(1));
+
+    while let Some(result) = results.next().await {
+        on_result(result?)?;
+    }
+    Ok(())
+}
+
+/// Runs the prompts in `file` and writes a JSON line per result to `output`, or stdout, with a
+/// progress bar on stderr. Asks first when the batch is large or over the budget (unless `yes`),
+/// and fails at the end if any prompt did.
+pub async fn run_file(provider: &dyn Provider, file: &Path, options: &BatchOptions, output: Option<&Path>, config: &Config, yes: bool) -> Result<()> {
+    let items = load_items(file)?;
+    for model in items.iter().map(|item| item.model(options)).collect::<BTreeSet<_>>() {
+        models::validate(model)?;
+    }
+
+    let prompt_tokens: usize = items.iter().map(|item| cost::estimate_tokens(&item.full_prompt(options))).sum();
+    let credits: f64 = items.iter()
+        .map(|item| cost::estimate_credits(cost::estimate_tokens(&item.full_prompt(options)), item.model(options), &config.model_rates))
+        .sum();
+    if !check_budget(&format!("Running {} prompts", items.len()), credits, config)? {
+        println!("Aborted.");
+        return Ok(());
+    }
+    let threshold = config.confirm_above_tokens.unwrap_or(cost::DEFAULT_CONFIRM_ABOVE_TOKENS);
+    if prompt_tokens > threshold && !yes {
+        ensure_interactive(
+            &format!("Running {} prompts (~{} tokens)", items.len(), prompt_tokens),
+            "pass --yes to run the batch anyway",
+        )?;
+        let confirmed = Confirm::new()
+            .with_prompt(format!("Run {} prompts, ~{} prompt tokens in total?", items.len(), prompt_tokens))
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).with_context(|| format!("Could not create {}", path.display()))?),
+        None => Box::new(io::stdout()),
+    };
+    let progress = if show_progress() {
+        ProgressBar::new(items.len() as u64)
+    } else {
+        ProgressBar::hidden()
+    };
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:40} {pos}/{len} prompts, {msg} [{elapsed_precise}, eta {eta}]")
+            .expect("valid progress template"),
+    );
+    progress.set_message("0 failed");
+
+    let total = items.len();
+    let mut failed = 0;
+    let rates = &config.model_rates;
+    run(provider, items, options, |result| {
+        match &result.response {
+            Some(response) => usage::record(&UsageEntry::new(&result.model, &result.prompt, response, rates))?,
+            None => failed += 1,
+        }
+        writeln!(writer, "{}", serde_json::to_string(&result)?)?;
+        writer.flush()?;
+        progress.set_message(format!("{} failed", failed));
+        progress.inc(1);
+        Ok(())
+    }).await?;
+    progress.finish_and_clear();
+
+    match output {
+        Some(path) => eprintln!("{} of {} prompts answered; results written to {}", total - failed, total, path.display()),
+        None => eprintln!("{} of {} prompts answered", total - failed, total),
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} prompts failed; see the error field of their results", failed, total));
+    }
+    Ok(())
+}
@@ -0,0 +1,515 @@
+//! Directory-wide `translate` and `summarize` runs: every text file under a directory, several
+//! at a time, retrying those that fail on transient errors and skipping those already processed
+//! the same way, with a report of what happened to each.
+
+use anyhow::{anyhow, Context, Result};
+use dialoguer::Confirm;
+use futures_util::{stream, StreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::error::AiCliError;
+use crate::providers::Provider;
+use crate::ui::{check_budget, ensure_interactive, show_progress};
+use crate::usage::{self, UsageEntry};
+use crate::{cache, chunk, cost, knowledge, lock, models, retry, summarize, translate};
+
+pub const DEFAULT_CONCURRENCY: usize = 4;
+/// Kept in the directory results are written to; hidden, so it is never processed itself
+pub const MANIFEST_FILE: &str = ".ai-cli-manifest.json";
+
+/// The files a run has processed, by path relative to the directory and the hash of their
+/// content together with the run's settings.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    files: BTreeMap<PathBuf, String>,
+}
+
+impl Manifest {
+    /// The manifest in `dir`, or an empty one if nothing was processed there yet.
+    pub fn load(dir: &Path) -> Result<Manifest> {
+        let path = dir.join(MANIFEST_FILE);
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).with_context(|| format!("{} is corrupted", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e).with_context(|| format!("Could not read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        lock::write_atomic(&dir.join(MANIFEST_FILE), serde_json::to_string_pretty(self)?.as_bytes())
+    }
+}
+
+/// Identifies a file's content processed with `settings` (the model, target language and so on),
+/// so changing either processes the file again.
+pub fn content_hash(settings: &str, text: &str) -> String {
+    format!("{:016x}", cache::fnv1a(format!("{}\0{}", settings, text).as_bytes()))
+}
+
+/// A file to process, by path relative to the directory.
+#[derive(Clone)]
+pub struct Job {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+/// What happened to one file.
+#[derive(Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub status: Status,
+    /// Why a file was skipped, or why it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub attempts: u32,
+    /// Estimated from the model's rate, like the usage ledger
+    pub credits: f64,
+    pub elapsed_ms: u64,
+}
+
+impl FileReport {
+    fn skipped(path: PathBuf, reason: &str) -> FileReport {
+        FileReport { path, status: Status::Skipped, reason: Some(reason.to_string()), attempts: 0, credits: 0.0, elapsed_ms: 0 }
+    }
+}
+
+/// The outcome of a run, written with `--report`.
+#[derive(Serialize)]
+pub struct Report {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub credits: f64,
+    pub elapsed_ms: u64,
+    pub files: Vec<FileReport>,
+}
+
+impl Report {
+    pub fn new(mut files: Vec<FileReport>, elapsed: Duration) -> Report {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let count = |status| files.iter().filter(|file| file.status == status).count();
+        Report {
+            succeeded: count(Status::Succeeded),
+            failed: count(Status::Failed),
+            skipped: count(Status::Skipped),
+            credits: files.iter().map(|file| file.credits).sum(),
+            elapsed_ms: elapsed.as_millis() as u64,
+            files,
+        }
+    }
+
+    /// One line with the counts, the cost and how long the run took.
+    pub fn summary(&self) -> String {
+        let seconds = self.elapsed_ms / 1000;
+        format!(
+            "{} succeeded, {} failed, {} skipped in {}m {:02}s, ~{:.0} credits",
+            self.succeeded, self.failed, self.skipped, seconds / 60, seconds % 60, self.credits
+        )
+    }
+}
+
+/// Reads the text files under `dir` (except those under `exclude`, where results may be written)
+/// and sorts out the ones to process: files that aren't text, or whose content was already
+/// processed with `settings` according to `manifest`, are skipped.
+pub fn plan(dir: &Path, exclude: Option<&Path>, settings: &str, manifest: &Manifest) -> Result<(Vec<Job>, Vec<FileReport>)> {
+    let mut jobs = Vec::new();
+    let mut skipped = Vec::new();
+    for path in knowledge::collect_files(dir)? {
+        if exclude.is_some_and(|exclude| path.starts_with(exclude)) {
+            continue;
+        }
+        let bytes = fs::read(dir.join(&path)).with_context(|| format!("Could not read {}", path.display()))?;
+        let text = match String::from_utf8(bytes) {
+            Ok(text) if !text.contains('\0') => text,
+            _ => {
+                skipped.push(FileReport::skipped(path, "not a text file"));
+                continue;
+            }
+        };
+        if text.trim().is_empty() {
+            skipped.push(FileReport::skipped(path, "empty"));
+        } else if manifest.files.get(&path).is_some_and(|hash| *hash == content_hash(settings, &text)) {
+            skipped.push(FileReport::skipped(path, "unchanged since the last run"));
+        } else {
+            jobs.push(Job { path, text });
+        }
+    }
+    Ok((jobs, skipped))
+}
+
+/// Runs `process` on each job with at most `concurrency` files in flight. `process` writes the
+/// result and returns the credits its requests cost; files that fail on rate limits, network
+/// failures or server errors are tried again. The manifest in `manifest_dir` is saved after each
+/// file, so an interrupted run picks up where it stopped; it records the hash of the file's
+/// content after processing, which differs from before for files translated in place.
+///
+/// Fails only on authentication errors, since every other file would fail the same way.
+#[allow(clippy::too_many_arguments)]
+pub async fn run<F, Fut>(
+    dir: &Path,
+    settings: &str,
+    jobs: Vec<Job>,
+    concurrency: usize,
+    manifest: &mut Manifest,
+    manifest_dir: &Path,
+    process: F,
+    mut on_done: impl FnMut(&FileReport),
+) -> Result<Vec<FileReport>>
+where
+    F: Fn(Job) -> Fut,
+    Fut: Future<Output = Result<f64>>,
+{
+    let process = &process;
+    let mut results = stream::iter(jobs)
+        .map(|job| async move {
+            let started = Instant::now();
+            let (outcome, attempts) = with_retries(retry::max_retries(), || process(job.clone())).await;
+            let (status, reason, credits) = match outcome {
+                Ok(credits) => (Status::Succeeded, None, credits),
+                Err(e) if api_error(&e).is_some_and(AiCliError::is_auth) => return Err(e),
+                Err(e) => (Status::Failed, Some(format!("{:#}", e)), 0.0),
+            };
+            Ok(FileReport { path: job.path, status, reason, attempts, credits, elapsed_ms: started.elapsed().as_millis() as u64 })
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut reports = Vec::new();
+    while let Some(report) = results.next().await {
+        let report: FileReport = report?;
+        if report.status == Status::Succeeded {
+            let text = fs::read_to_string(dir.join(&report.path)).unwrap_or_default();
+            manifest.files.insert(report.path.clone(), content_hash(settings, &text));
+            manifest.save(manifest_dir)?;
+        }
+        on_done(&report);
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Calls `attempt` until it succeeds, fails with anything but a transient error, or has been
+/// tried again `retries` times, and returns the outcome with the number of attempts made.
+async fn with_retries<Fut>(retries: u32, attempt: impl Fn() -> Fut) -> (Result<f64>, u32)
+where
+    Fut: Future<Output = Result<f64>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match attempt().await {
+            Err(e) if api_error(&e).is_some_and(AiCliError::is_transient) && attempts <= retries => {
+                tokio::time::sleep(retry::delay(attempts - 1)).await;
+            }
+            outcome => return (outcome, attempts),
+        }
+    }
+}
+
+fn api_error(error: &anyhow::Error) -> Option<&AiCliError> {
+    error.chain().find_map(|cause| cause.downcast_ref::<AiCliError>())
+}
+
+/// What a `summarize` or `translate` run over a directory does with each file.
+#[derive(Clone, Copy)]
+pub enum Task<'a> {
+    /// `length` describes how long each summary should be
+    Summarize { length: &'a str, bullets: bool },
+    Translate { to: &'a str, from: Option<&'a str>, in_place: bool },
+}
+
+impl Task<'_> {
+    /// Where the result for `path`, relative to the directory, goes relative to `output_dir`.
+    fn output_path(self, path: &Path) -> PathBuf {
+        match self {
+            Task::Summarize { .. } => path.with_extension("summary.md"),
+            Task::Translate { .. } => path.to_path_buf(),
+        }
+    }
+}
+
+/// How a directory is processed.
+pub struct Options<'a> {
+    pub model: &'a str,
+    pub max_words: u32,
+    /// Largest part of a file sent in one request
+    pub chunk_tokens: usize,
+    pub output_dir: Option<&'a Path>,
+    pub concurrency: usize,
+    /// Where to write the JSON report on every file
+    pub report: Option<&'a Path>,
+    /// Print the report on stdout as well
+    pub print_report: bool,
+    /// Go ahead with a large run without asking
+    pub yes: bool,
+}
+
+/// Summarizes or translates every text file under `dir`, several at a time, writing the results
+/// under `options.output_dir` (or over the files, with `translate --in-place`). Files already
+/// processed the same way and unchanged since are skipped, and a report on each file ends the run.
+pub async fn process_directory(provider: &dyn Provider, dir: &Path, task: Task<'_>, options: &Options<'_>) -> Result<()> {
+    let (model, max_words, chunk_tokens) = (options.model, options.max_words, options.chunk_tokens);
+    models::validate(model)?;
+    let output_dir = match (task, options.output_dir) {
+        (Task::Translate { in_place: true, .. }, _) => dir.to_path_buf(),
+        (_, Some(output_dir)) => output_dir.to_path_buf(),
+        (Task::Summarize { .. }, None) => return Err(anyhow!("Give a directory to write the summaries to with --output-dir")),
+        (Task::Translate { .. }, None) => {
+            return Err(anyhow!("Give a directory to write the translations to with --output-dir, or pass --in-place"))
+        }
+    };
+    fs::create_dir_all(&output_dir).with_context(|| format!("Could not create {}", output_dir.display()))?;
+    // Results written inside the directory aren't processed on the next run.
+    let exclude = fs::canonicalize(&output_dir)?
+        .strip_prefix(fs::canonicalize(dir)?)
+        .ok()
+        .filter(|inside| !inside.as_os_str().is_empty())
+        .map(Path::to_path_buf);
+    let settings = match task {
+        Task::Summarize { length, bullets } => {
+            format!("summary by {} {}, bullets: {}, words: {}, parts: {}", model, length, bullets, max_words, chunk_tokens)
+        }
+        Task::Translate { to, from, .. } => {
+            format!("translation by {} from {} into {}, words: {}, parts: {}", model, from.unwrap_or("any language"), to, max_words, chunk_tokens)
+        }
+    };
+
+    let mut manifest = Manifest::load(&output_dir)?;
+    let (jobs, skipped) = plan(dir, exclude.as_deref(), &settings, &manifest)?;
+
+    let config = Config::load()?;
+    let prompt_tokens: usize = jobs.iter().map(|job| cost::estimate_tokens(&job.text)).sum();
+    let what = format!("Processing {} files", jobs.len());
+    if !check_budget(&what, cost::estimate_credits(prompt_tokens, model, &config.model_rates), &config)? {
+        println!("Aborted.");
+        return Ok(());
+    }
+    let threshold = config.confirm_above_tokens.unwrap_or(cost::DEFAULT_CONFIRM_ABOVE_TOKENS);
+    if prompt_tokens > threshold && !options.yes {
+        ensure_interactive(&format!("{} (~{} tokens)", what, prompt_tokens), "pass --yes to process them anyway")?;
+        let confirmed = Confirm::new()
+            .with_prompt(format!("Process {} files, ~{} prompt tokens in total?", jobs.len(), prompt_tokens))
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    let progress = if show_progress() {
+        ProgressBar::new(jobs.len() as u64)
+    } else {
+        ProgressBar::hidden()
+    };
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:40} {pos}/{len} files, {msg} [{elapsed_precise}, eta {eta}]")
+            .expect("valid progress template"),
+    );
+    progress.set_message("0 failed");
+
+    let started = Instant::now();
+    let rates = &config.model_rates;
+    let process = |job: Job| {
+        let output = output_dir.join(task.output_path(&job.path));
+        async move {
+            let credits = std::cell::Cell::new(0.0);
+            let ask = |prompt: String| {
+                let credits = &credits;
+                async move {
+                    let response = provider.chat(model, &prompt, max_words).await?;
+                    let entry = UsageEntry::new(model, &prompt, &response, rates);
+                    usage::record(&entry)?;
+                    credits.set(credits.get() + entry.credits);
+                    Ok(response)
+                }
+            };
+            let result = match task {
+                Task::Summarize { length, bullets } => {
+                    let instruction = summarize::final_instruction(&job.path.display().to_string(), length, bullets);
+                    let summary = chunk::map_reduce(&job.text, chunk_tokens, summarize::PART_INSTRUCTION, &instruction, ask, |_, _| {}).await?;
+                    format!("{}\n", summary.trim())
+                }
+                Task::Translate { to, from, .. } => translate::translate(&job.text, to, from, chunk_tokens, ask, |_, _| {}).await?.1,
+            };
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            lock::write_atomic(&output, result.as_bytes())?;
+            Ok(credits.get())
+        }
+    };
+    let mut failed = 0;
+    let results = run(dir, &settings, jobs, options.concurrency, &mut manifest, &output_dir, process, |file| {
+        if file.status == Status::Failed {
+            failed += 1;
+            progress.set_message(format!("{} failed", failed));
+        }
+        progress.inc(1);
+    }).await;
+    progress.finish_and_clear();
+
+    let report = Report::new(skipped.into_iter().chain(results?).collect(), started.elapsed());
+    for file in report.files.iter().filter(|file| file.status == Status::Failed) {
+        eprintln!("{} failed: {}", file.path.display(), file.reason.as_deref().unwrap_or_default());
+    }
+    eprintln!("{}", report.summary());
+    if let Some(path) = options.report {
+        fs::write(path, serde_json::to_string_pretty(&report)?).with_context(|| format!("Could not write {}", path.display()))?;
+        eprintln!("Report written to {}", path.display());
+    }
+    if options.print_report {
+        println!("{}", serde_json::to_string(&report)?);
+    }
+    if report.failed > 0 {
+        return Err(anyhow!("{} of {} files failed; run again to retry them", report.failed, report.failed + report.succeeded));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use reqwest::StatusCode;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    const SETTINGS: &str = "summarize gpt-4o";
+
+    fn server_error() -> anyhow::Error {
+        let error = AiCliError::ApiError { status: StatusCode::BAD_GATEWAY, code: None, message: "upstream down".to_string() };
+        anyhow::Error::from(error).context("Could not summarize")
+    }
+
+    fn write(dir: &Path, path: &str, contents: &[u8]) {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn paths(reports: &[FileReport]) -> Vec<(&str, Status, Option<&str>)> {
+        reports.iter().map(|report| (report.path.to_str().unwrap(), report.status, report.reason.as_deref())).collect()
+    }
+
+    #[test]
+    fn plan_skips_files_that_are_not_text_empty_or_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "notes/a.txt", b"first");
+        write(dir.path(), "notes/b.txt", b"second, edited");
+        write(dir.path(), "image.png", b"\x89PNG\0\0");
+        write(dir.path(), "blank.md", b" \n");
+        write(dir.path(), "summaries/a.summary.md", b"excluded");
+
+        let mut manifest = Manifest::default();
+        manifest.files.insert(PathBuf::from("notes/a.txt"), content_hash(SETTINGS, "first"));
+        manifest.files.insert(PathBuf::from("notes/b.txt"), content_hash(SETTINGS, "second"));
+        let (jobs, skipped) = plan(dir.path(), Some(Path::new("summaries")), SETTINGS, &manifest).unwrap();
+
+        assert_eq!(jobs.iter().map(|job| job.path.to_str().unwrap()).collect::<Vec<_>>(), ["notes/b.txt"]);
+        assert_eq!(paths(&skipped), [
+            ("blank.md", Status::Skipped, Some("empty")),
+            ("image.png", Status::Skipped, Some("not a text file")),
+            ("notes/a.txt", Status::Skipped, Some("unchanged since the last run")),
+        ]);
+
+        // Other settings process everything again.
+        let (jobs, _) = plan(dir.path(), Some(Path::new("summaries")), "summarize gpt-4o-mini", &manifest).unwrap();
+        assert_eq!(jobs.len(), 2);
+    }
+
+    #[test]
+    fn manifests_are_saved_and_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Manifest::load(dir.path()).unwrap().files.is_empty());
+
+        let mut manifest = Manifest::default();
+        manifest.files.insert(PathBuf::from("a.txt"), content_hash(SETTINGS, "text"));
+        manifest.save(dir.path()).unwrap();
+        assert_eq!(Manifest::load(dir.path()).unwrap().files, manifest.files);
+
+        fs::write(dir.path().join(MANIFEST_FILE), "{").unwrap();
+        let Err(error) = Manifest::load(dir.path()) else { panic!("a corrupted manifest was loaded") };
+        assert!(error.to_string().ends_with("is corrupted"));
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried_up_to_the_limit() {
+        retry::configure(0, 0);
+        let calls = AtomicU32::new(0);
+        let (outcome, attempts) = with_retries(3, || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(server_error()),
+                _ => Ok(2.5),
+            }
+        }).await;
+        assert_eq!((outcome.unwrap(), attempts), (2.5, 3));
+
+        let (outcome, attempts) = with_retries(2, || async { Err(server_error()) }).await;
+        assert!(outcome.is_err());
+        assert_eq!(attempts, 3);
+
+        let (outcome, attempts) = with_retries(2, || async { Err(anyhow!("No such file")) }).await;
+        assert!(outcome.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn run_records_successes_in_the_manifest_and_carries_on_after_failures() {
+        retry::configure(0, 0);
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", b"alpha");
+        write(dir.path(), "b.txt", b"beta");
+        let (jobs, _) = plan(dir.path(), None, SETTINGS, &Manifest::default()).unwrap();
+
+        let mut manifest = Manifest::default();
+        let mut done = Vec::new();
+        let reports = run(dir.path(), SETTINGS, jobs, 2, &mut manifest, dir.path(), |job| async move {
+            match job.text.as_str() {
+                "alpha" => Ok(1.5),
+                _ => Err(anyhow!("Response was empty")).context(format!("Could not summarize {}", job.path.display())),
+            }
+        }, |report| done.push(report.path.clone())).await.unwrap();
+
+        let report = Report::new(reports, Duration::from_secs(61));
+        assert_eq!(paths(&report.files), [
+            ("a.txt", Status::Succeeded, None),
+            ("b.txt", Status::Failed, Some("Could not summarize b.txt: Response was empty")),
+        ]);
+        assert_eq!(report.summary(), "1 succeeded, 1 failed, 0 skipped in 1m 01s, ~2 credits");
+        assert_eq!(done.len(), 2);
+        let saved = Manifest::load(dir.path()).unwrap();
+        assert_eq!(saved.files.keys().collect::<Vec<_>>(), [Path::new("a.txt")]);
+        assert_eq!(saved.files[Path::new("a.txt")], content_hash(SETTINGS, "alpha"));
+    }
+
+    #[tokio::test]
+    async fn run_stops_when_the_api_key_is_rejected() {
+        retry::configure(0, 0);
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.txt", b"alpha");
+        let (jobs, _) = plan(dir.path(), None, SETTINGS, &Manifest::default()).unwrap();
+
+        let mut manifest = Manifest::default();
+        let result = run(dir.path(), SETTINGS, jobs, 1, &mut manifest, dir.path(), |_| async {
+            Err(AiCliError::Auth("the API key was rejected".to_string()).into())
+        }, |_| {}).await;
+        let Err(error) = result else { panic!("the run went on after the key was rejected") };
+        assert!(api_error(&error).is_some_and(AiCliError::is_auth));
+        assert!(!dir.path().join(MANIFEST_FILE).exists());
+    }
+}
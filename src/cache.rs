@@ -0,0 +1,124 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::lock;
+use crate::sampling::Sampling;
+
+const RESPONSES_DIR: &str = "responses";
+
+/// What a cached response is looked up by: the same prompt to the same model with the same
+/// options gets the same answer.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct CacheKey {
+    pub provider: String,
+    pub model: String,
+    /// The prompt as sent, including the system prompt and attachments
+    pub prompt: String,
+    pub max_words: u32,
+    /// Left out when empty, so keys from before sampling parameters existed still match
+    #[serde(default, skip_serializing_if = "Sampling::is_empty")]
+    pub sampling: Sampling,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    key: CacheKey,
+    response: String,
+    created: DateTime<Local>,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    pub oldest: Option<DateTime<Local>>,
+    pub newest: Option<DateTime<Local>>,
+}
+
+fn cache_dir() -> Result<PathBuf> {
+    let dir = dirs::cache_dir()
+        .ok_or_else(|| anyhow!("Could not determine the local cache directory"))?
+        .join("ai-cli")
+        .join(RESPONSES_DIR);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// FNV-1a, which (unlike std's hasher) is stable across Rust releases, so cache files keep
+/// their names.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn entry_path(key: &CacheKey) -> Result<PathBuf> {
+    let hash = fnv1a(&serde_json::to_vec(key)?);
+    Ok(cache_dir()?.join(format!("{:016x}.json", hash)))
+}
+
+fn entries() -> Result<Vec<(PathBuf, CacheEntry)>> {
+    let mut entries = Vec::new();
+    for file in fs::read_dir(cache_dir()?)? {
+        let path = file?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        if let Some(entry) = fs::read_to_string(&path).ok().and_then(|contents| serde_json::from_str(&contents).ok()) {
+            entries.push((path, entry));
+        }
+    }
+    Ok(entries)
+}
+
+/// The cached response for a request, if it was answered before. The full key is compared, so
+/// a hash collision is a miss rather than a wrong answer.
+pub fn lookup(key: &CacheKey) -> Option<String> {
+    let contents = fs::read_to_string(entry_path(key).ok()?).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+    (entry.key == *key).then_some(entry.response)
+}
+
+/// Needs no lock: each entry is a file of its own, replaced whole, so concurrent writers of the
+/// same entry leave one complete response behind.
+pub fn store(key: &CacheKey, response: &str) -> Result<()> {
+    let entry = CacheEntry { key: key.clone(), response: response.to_string(), created: Local::now() };
+    lock::write_atomic(&entry_path(key)?, serde_json::to_string_pretty(&entry)?.as_bytes())
+}
+
+/// Removes every cached response and returns how many there were.
+pub fn clear() -> Result<usize> {
+    let mut removed = 0;
+    for file in fs::read_dir(cache_dir()?)? {
+        let path = file?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+pub fn stats() -> Result<CacheStats> {
+    let entries = entries()?;
+    let bytes = entries.iter()
+        .filter_map(|(path, _)| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    Ok(CacheStats {
+        entries: entries.len(),
+        bytes,
+        oldest: entries.iter().map(|(_, entry)| entry.created).min(),
+        newest: entries.iter().map(|(_, entry)| entry.created).max(),
+    })
+}
+
+pub fn print_stats(stats: &CacheStats) {
+    println!("Cached responses: {}", stats.entries);
+    println!("Size:             {:.1} KiB", stats.bytes as f64 / 1024.0);
+    if let (Some(oldest), Some(newest)) = (stats.oldest, stats.newest) {
+        println!("Oldest:           {}", oldest.format("%Y-%m-%d %H:%M"));
+        println!("Newest:           {}", newest.format("%Y-%m-%d %H:%M"));
+    }
+}
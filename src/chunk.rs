@@ -0,0 +1,113 @@
+use anyhow::Result;
+use std::future::Future;
+
+use crate::cost;
+
+/// Chunk size used when a command doesn't pick one; leaves room for the instructions and the
+/// answer within the context of the smaller models.
+pub const DEFAULT_CHUNK_TOKENS: usize = 12_000;
+/// Condensing passes before the reduce step gets whatever is left, in case partial answers
+/// stop getting shorter.
+const MAX_PASSES: usize = 4;
+
+/// Splits text into chunks of at most `max_tokens` (estimated), breaking between paragraphs
+/// where possible, then between lines, and only mid-line as a last resort.
+pub fn split(text: &str, max_tokens: usize) -> Vec<String> {
+    let max_tokens = max_tokens.max(1);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for piece in pieces(text, max_tokens) {
+        let separator = if current.is_empty() { "" } else { "\n\n" };
+        if !current.is_empty() && cost::estimate_tokens(&format!("{}{}{}", current, separator, piece)) > max_tokens {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(&piece);
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Breaks text into paragraphs, splitting any paragraph that is too big on its own.
+fn pieces(text: &str, max_tokens: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        if cost::estimate_tokens(paragraph) <= max_tokens {
+            pieces.push(paragraph.to_string());
+            continue;
+        }
+
+        let mut block = String::new();
+        for line in paragraph.lines() {
+            for part in split_line(line, max_tokens) {
+                if !block.is_empty() && cost::estimate_tokens(&format!("{}\n{}", block, part)) > max_tokens {
+                    pieces.push(std::mem::take(&mut block));
+                }
+                if !block.is_empty() {
+                    block.push('\n');
+                }
+                block.push_str(&part);
+            }
+        }
+        if !block.is_empty() {
+            pieces.push(block);
+        }
+    }
+    pieces
+}
+
+/// Cuts a single over-long line into pieces that fit, on character boundaries.
+fn split_line(line: &str, max_tokens: usize) -> Vec<String> {
+    if cost::estimate_tokens(line) <= max_tokens {
+        return vec![line.to_string()];
+    }
+    let chars: Vec<char> = line.chars().collect();
+    let size = (chars.len() * max_tokens / cost::estimate_tokens(line)).max(1);
+    chars.chunks(size).map(|piece| piece.iter().collect()).collect()
+}
+
+/// Condenses text that may be too long for one request. Each chunk is answered with
+/// `map_instruction`; while the combined partial answers are still too long they are chunked
+/// and condensed again, and `reduce_instruction` produces the final answer. Text that fits in
+/// one chunk goes straight to the reduce step.
+///
+/// `ask` sends a prompt and returns the response, so callers choose the model and handle auth.
+/// `on_progress` is called with (done, total) for the chunk requests of each pass.
+pub async fn map_reduce<F, Fut>(
+    text: &str,
+    max_tokens: usize,
+    map_instruction: &str,
+    reduce_instruction: &str,
+    mut ask: F,
+    mut on_progress: impl FnMut(usize, usize),
+) -> Result<String>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let mut text = text.to_string();
+    for _ in 0..MAX_PASSES {
+        let chunks = split(&text, max_tokens);
+        if chunks.len() <= 1 {
+            break;
+        }
+
+        let mut partials = Vec::with_capacity(chunks.len());
+        for (i, chunk) in chunks.iter().enumerate() {
+            on_progress(i, chunks.len());
+            let prompt = format!(
+                "{}\n\nThis is part {} of {} of a longer text.\n\n{}",
+                map_instruction, i + 1, chunks.len(), chunk
+            );
+            partials.push(ask(prompt).await?);
+        }
+        on_progress(chunks.len(), chunks.len());
+        text = partials.join("\n\n");
+    }
+    ask(format!("{}\n\n{}", reduce_instruction, text)).await
+}
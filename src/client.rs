@@ -0,0 +1,1108 @@
+use futures_util::{Stream, StreamExt};
+use reqwest::{header, Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tracing::{debug, trace};
+
+use crate::error::AiCliError;
+use crate::jobs::{self, Polling};
+use crate::retry;
+use crate::sampling::Sampling;
+use crate::stream::StreamDecoder;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.1min.ai";
+pub const ASSET_BASE_URL: &str = "https://asset.1min.ai/";
+pub const DEFAULT_SPEECH_TO_TEXT_MODEL: &str = "whisper-1";
+pub const DEFAULT_TEXT_TO_SPEECH_MODEL: &str = "tts-1";
+pub const DEFAULT_TTS_VOICE: &str = "alloy";
+
+const CONVERSATIONS_PATH: &str = "/api/conversations";
+const FEATURES_PATH: &str = "/api/features";
+const STREAMING_FEATURES_PATH: &str = "/api/features?isStreaming=true";
+const ASSETS_PATH: &str = "/api/assets";
+pub const MODELS_PATH: &str = "/api/models";
+const DEFAULT_UPLOAD_FILENAME: &str = "upload";
+
+#[derive(Serialize)]
+struct ConversationRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    title: &'a str,
+    /// Asset paths of the documents a CHAT_WITH_PDF conversation is about
+    #[serde(rename = "fileList", skip_serializing_if = "Option::is_none")]
+    file_list: Option<&'a [String]>,
+}
+
+#[derive(Serialize)]
+struct RenameConversationRequest<'a> {
+    title: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ConversationResponse {
+    conversation: Conversation,
+}
+
+#[derive(Deserialize)]
+struct Conversation {
+    uuid: String,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    #[serde(rename = "conversationId", skip_serializing_if = "Option::is_none")]
+    conversation_id: Option<&'a str>,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: PromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct PromptObject<'a> {
+    prompt: &'a str,
+    #[serde(rename = "isMixed")]
+    is_mixed: bool,
+    #[serde(rename = "webSearch")]
+    web_search: bool,
+    #[serde(rename = "numOfSite")]
+    num_of_site: u32,
+    #[serde(rename = "maxWord")]
+    max_word: u32,
+    #[serde(flatten)]
+    sampling: SamplingParameters,
+}
+
+/// [`Sampling`] in the names the API uses.
+#[derive(Serialize)]
+struct SamplingParameters {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(rename = "topP", skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(rename = "frequencyPenalty", skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+}
+
+impl From<Sampling> for SamplingParameters {
+    fn from(sampling: Sampling) -> Self {
+        SamplingParameters {
+            temperature: sampling.temperature,
+            top_p: sampling.top_p,
+            frequency_penalty: sampling.frequency_penalty,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DocumentChatRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    #[serde(rename = "conversationId")]
+    conversation_id: &'a str,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: DocumentPromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct DocumentPromptObject<'a> {
+    prompt: &'a str,
+    #[serde(rename = "maxWord")]
+    max_word: u32,
+    #[serde(flatten)]
+    sampling: SamplingParameters,
+}
+
+#[derive(Serialize)]
+struct ImageChatRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    #[serde(rename = "conversationId", skip_serializing_if = "Option::is_none")]
+    conversation_id: Option<&'a str>,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: ImageChatPromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct ImageChatPromptObject<'a> {
+    prompt: &'a str,
+    #[serde(rename = "isMixed")]
+    is_mixed: bool,
+    #[serde(rename = "imageList")]
+    image_list: &'a [String],
+    #[serde(rename = "maxWord")]
+    max_word: u32,
+    #[serde(flatten)]
+    sampling: SamplingParameters,
+}
+
+#[derive(Serialize)]
+struct ImageGenerationRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: ImagePromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct ImagePromptObject<'a> {
+    prompt: &'a str,
+    n: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style: Option<&'a str>,
+    #[serde(rename = "aspectRatio", skip_serializing_if = "Option::is_none")]
+    aspect_ratio: Option<&'a str>,
+    #[serde(rename = "negativePrompt", skip_serializing_if = "Option::is_none")]
+    negative_prompt: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    steps: Option<u32>,
+    #[serde(rename = "cfgScale", skip_serializing_if = "Option::is_none")]
+    cfg_scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ImageToolRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: ImageToolPromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct ImageToolPromptObject<'a> {
+    #[serde(rename = "imageUrl")]
+    image_url: &'a str,
+    #[serde(rename = "maskUrl", skip_serializing_if = "Option::is_none")]
+    mask_url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt: Option<&'a str>,
+    n: u32,
+    size: &'a str,
+}
+
+#[derive(Serialize)]
+struct SpeechToTextRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: SpeechToTextPromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct SpeechToTextPromptObject<'a> {
+    #[serde(rename = "audioUrl")]
+    audio_url: &'a str,
+    response_format: &'a str,
+}
+
+#[derive(Serialize)]
+struct TextToSpeechRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: TextToSpeechPromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct TextToSpeechPromptObject<'a> {
+    text: &'a str,
+    voice: &'a str,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: EmbeddingPromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct EmbeddingPromptObject<'a> {
+    input: &'a [String],
+}
+
+/// One vector of an EMBEDDINGS result, either bare or OpenAI-style.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EmbeddingResult {
+    Vector(Vec<f32>),
+    Object { embedding: Vec<f32> },
+}
+
+#[derive(Deserialize)]
+struct AssetResponse {
+    #[serde(rename = "fileContent")]
+    file_content: AssetFileContent,
+}
+
+#[derive(Deserialize)]
+struct AssetFileContent {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct AiRecordResponse {
+    #[serde(rename = "aiRecord")]
+    ai_record: AiRecord,
+}
+
+/// The record the API returns for a finished (or failed) feature request.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AiRecord {
+    #[serde(default)]
+    pub uuid: String,
+    #[serde(rename = "temporaryUrl", default)]
+    pub temporary_url: String,
+    pub status: String,
+    #[serde(rename = "aiRecordDetail", default)]
+    pub detail: Option<AiRecordDetail>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AiRecordDetail {
+    #[serde(rename = "resultObject", default)]
+    pub result_object: Option<serde_json::Value>,
+}
+
+impl AiRecord {
+    /// Collects the download URLs for every generated file, resolving relative asset paths
+    /// against `asset_base_url` (see [`OneMinClient::asset_base_url`]).
+    pub fn result_urls(&self, asset_base_url: &str) -> Vec<String> {
+        let results: Vec<String> = self.detail.as_ref()
+            .and_then(|detail| detail.result_object.as_ref())
+            .map(|result| match result {
+                serde_json::Value::Array(items) => items.iter()
+                    .filter_map(|item| item.as_str().map(|s| s.to_string()))
+                    .collect(),
+                serde_json::Value::String(item) => vec![item.clone()],
+                _ => Vec::new(),
+            })
+            .unwrap_or_default();
+
+        if results.len() <= 1 && !self.temporary_url.is_empty() {
+            return vec![self.temporary_url.clone()];
+        }
+
+        results.into_iter()
+            .map(|path| if path.starts_with("http") { path } else { format!("{}{}", asset_base_url, path) })
+            .collect()
+    }
+}
+
+/// Options for an IMAGE_GENERATOR request. Those left out aren't sent, so the model's own
+/// defaults apply; `image_models::resolve` checks them against what the model supports.
+pub struct ImageGeneration<'a> {
+    pub prompt: &'a str,
+    pub model: &'a str,
+    pub count: u32,
+    pub size: Option<&'a str>,
+    pub quality: Option<&'a str>,
+    pub style: Option<&'a str>,
+    pub aspect_ratio: Option<&'a str>,
+    pub negative_prompt: Option<&'a str>,
+    pub steps: Option<u32>,
+    pub cfg_scale: Option<f32>,
+    pub seed: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ImageProcessingRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: ImageProcessingPromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct ImageProcessingPromptObject<'a> {
+    #[serde(rename = "imageUrl")]
+    image_url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scale: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct VideoGenerationRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    model: &'a str,
+    #[serde(rename = "promptObject")]
+    prompt_object: VideoPromptObject<'a>,
+}
+
+#[derive(Serialize)]
+struct VideoPromptObject<'a> {
+    prompt: &'a str,
+    /// Length of the video in seconds
+    duration: u32,
+}
+
+/// Options for a VIDEO_GENERATOR request.
+pub struct VideoGeneration<'a> {
+    pub prompt: &'a str,
+    pub model: &'a str,
+    pub duration: u32,
+}
+
+/// Image tools that process an uploaded image without a prompt.
+#[derive(Clone, Copy)]
+pub enum ImageProcessing {
+    Upscale { scale: u32 },
+    RemoveBackground,
+}
+
+impl ImageProcessing {
+    fn feature_type(self) -> &'static str {
+        match self {
+            ImageProcessing::Upscale { .. } => "IMAGE_UPSCALER",
+            ImageProcessing::RemoveBackground => "BACKGROUND_REMOVER",
+        }
+    }
+}
+
+/// Image tools that transform an uploaded image.
+#[derive(Clone, Copy)]
+pub enum ImageTool {
+    Edit,
+    Variations,
+}
+
+impl ImageTool {
+    fn feature_type(self) -> &'static str {
+        match self {
+            ImageTool::Edit => "IMAGE_EDITOR",
+            ImageTool::Variations => "IMAGE_VARIATOR",
+        }
+    }
+}
+
+/// Options for an image tool request; image and mask are asset paths from [`OneMinClient::upload_asset`].
+pub struct ImageToolOptions<'a> {
+    pub tool: ImageTool,
+    pub model: &'a str,
+    pub image: &'a str,
+    pub mask: Option<&'a str>,
+    pub prompt: Option<&'a str>,
+    pub count: u32,
+    pub size: &'a str,
+}
+
+/// A streaming chat response, yielding text chunks as they arrive.
+pub struct ChatStream {
+    inner: Pin<Box<dyn Stream<Item = reqwest::Result<Vec<u8>>> + Send>>,
+    decoder: StreamDecoder,
+    finished: bool,
+    /// Text known up front, returned before anything from `inner`
+    ready: Option<String>,
+    started: Instant,
+    chunks: usize,
+}
+
+impl ChatStream {
+    /// Wraps a streaming response body, in any of the formats [`StreamDecoder`] understands.
+    pub(crate) fn from_response(response: Response) -> Self {
+        let stream = response.bytes_stream().map(|chunk| chunk.map(|bytes| bytes.to_vec()));
+        ChatStream {
+            inner: Box::pin(stream),
+            decoder: StreamDecoder::new(),
+            finished: false,
+            ready: None,
+            started: Instant::now(),
+            chunks: 0,
+        }
+    }
+
+    /// A stream that yields an already complete response at once, e.g. one from the cache.
+    pub fn from_text(text: String) -> Self {
+        ChatStream {
+            inner: Box::pin(futures_util::stream::empty()),
+            decoder: StreamDecoder::new(),
+            finished: true,
+            ready: Some(text),
+            started: Instant::now(),
+            chunks: 0,
+        }
+    }
+
+    /// Returns the next piece of generated text, or `None` once the response is complete.
+    pub async fn next(&mut self) -> Option<Result<String, AiCliError>> {
+        if let Some(text) = self.ready.take() {
+            return Some(Ok(text));
+        }
+        while !self.finished {
+            let text = match self.inner.next().await {
+                Some(Ok(bytes)) => {
+                    self.chunks += 1;
+                    debug!("stream chunk {} at {}ms: {} bytes", self.chunks, self.started.elapsed().as_millis(), bytes.len());
+                    trace!("  {:?}", String::from_utf8_lossy(&bytes));
+                    self.decoder.push(&bytes)
+                }
+                Some(Err(e)) => return Some(Err(e.into())),
+                None => {
+                    debug!("stream finished after {} chunks in {}ms", self.chunks, self.started.elapsed().as_millis());
+                    self.finished = true;
+                    self.decoder.finish()
+                }
+            };
+            if !text.is_empty() {
+                return Some(Ok(text));
+            }
+        }
+        None
+    }
+    /// Waits for the rest of the response and returns it whole.
+    pub async fn collect(mut self) -> Result<String, AiCliError> {
+        let mut response = String::new();
+        while let Some(chunk) = self.next().await {
+            response.push_str(&chunk?);
+        }
+        Ok(response)
+    }
+}
+
+/// Client for the 1min.ai API.
+pub struct OneMinClient {
+    http: Client,
+    base_url: String,
+    asset_base_url: String,
+    api_key: RwLock<String>,
+}
+
+impl OneMinClient {
+    pub fn new(http: Client, api_key: impl Into<String>) -> Self {
+        OneMinClient {
+            http,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            asset_base_url: ASSET_BASE_URL.to_string(),
+            api_key: RwLock::new(api_key.into()),
+        }
+    }
+
+    /// Points the client at a different API host (e.g. a proxy or a mock server).
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Points relative result paths at a different asset host. Every endpoint path is derived
+    /// from the API base URL, but generated files are served from a host of their own.
+    pub fn with_asset_base_url(mut self, asset_base_url: impl Into<String>) -> Self {
+        self.asset_base_url = format!("{}/", asset_base_url.into().trim_end_matches('/'));
+        self
+    }
+
+    pub fn asset_base_url(&self) -> &str {
+        &self.asset_base_url
+    }
+
+    /// The underlying HTTP client, for requests outside the typed API (e.g. health checks).
+    pub fn http(&self) -> &Client {
+        &self.http
+    }
+
+    pub fn api_key(&self) -> String {
+        self.api_key.read().map(|key| key.clone()).unwrap_or_default()
+    }
+
+    /// Replaces the API key used for subsequent requests, e.g. after re-authenticating.
+    pub fn set_api_key(&self, api_key: impl Into<String>) {
+        if let Ok(mut key) = self.api_key.write() {
+            *key = api_key.into();
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn post_json<T: Serialize>(&self, path: &str, body: &T) -> RequestBuilder {
+        self.http
+            .post(self.url(path))
+            .header("API-KEY", self.api_key())
+            .header(header::CONTENT_TYPE, "application/json")
+            .json(body)
+    }
+
+    async fn send(request: RequestBuilder) -> Result<Response, AiCliError> {
+        let response = retry::send(request).await?;
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(AiCliError::from_response(response).await)
+        }
+    }
+
+    async fn feature(&self, body: &impl Serialize) -> Result<AiRecord, AiCliError> {
+        let response = Self::send(self.post_json(FEATURES_PATH, body)).await?;
+        let body = response.text().await?;
+        trace!("features response: {}", body);
+        let record: AiRecordResponse = serde_json::from_str(&body)
+            .map_err(|e| AiCliError::InvalidResponse(e.to_string()))?;
+        Ok(record.ai_record)
+    }
+
+    /// Creates a chat conversation and returns its id.
+    pub async fn create_conversation(&self, title: &str) -> Result<String, AiCliError> {
+        let request = ConversationRequest {
+            request_type: "CHAT_WITH_AI",
+            title,
+            file_list: None,
+        };
+
+        let response = Self::send(self.post_json(CONVERSATIONS_PATH, &request)).await?;
+        let conversation: ConversationResponse = response.json().await?;
+        Ok(conversation.conversation.uuid)
+    }
+
+    /// Creates a conversation about uploaded documents and returns its id. `documents` are asset
+    /// paths from [`OneMinClient::upload_asset`].
+    pub async fn create_document_conversation(&self, title: &str, documents: &[String]) -> Result<String, AiCliError> {
+        let request = ConversationRequest {
+            request_type: "CHAT_WITH_PDF",
+            title,
+            file_list: Some(documents),
+        };
+
+        let response = Self::send(self.post_json(CONVERSATIONS_PATH, &request)).await?;
+        let conversation: ConversationResponse = response.json().await?;
+        Ok(conversation.conversation.uuid)
+    }
+
+    /// Changes the title of an existing conversation.
+    pub async fn rename_conversation(&self, conversation_id: &str, title: &str) -> Result<(), AiCliError> {
+        let request = self.http
+            .patch(self.url(&format!("{}/{}", CONVERSATIONS_PATH, conversation_id)))
+            .header("API-KEY", self.api_key())
+            .header(header::CONTENT_TYPE, "application/json")
+            .json(&RenameConversationRequest { title });
+
+        Self::send(request).await?;
+        Ok(())
+    }
+
+    /// Sends a prompt and streams back the response.
+    ///
+    /// Without a conversation id the prompt is answered on its own, which saves the round-trip
+    /// of creating a conversation for one-off questions.
+    pub async fn chat_stream(
+        &self,
+        conversation_id: Option<&str>,
+        model: &str,
+        prompt: &str,
+        max_words: u32,
+        sampling: Sampling,
+    ) -> Result<ChatStream, AiCliError> {
+        let request = ChatRequest {
+            request_type: "CHAT_WITH_AI",
+            conversation_id,
+            model,
+            prompt_object: PromptObject {
+                prompt,
+                is_mixed: false,
+                web_search: false,
+                num_of_site: 0,
+                max_word: max_words,
+                sampling: sampling.into(),
+            },
+        };
+
+        self.stream(&request).await
+    }
+
+    /// Sends a prompt together with uploaded images to a vision-capable model and streams back
+    /// the response. `images` are asset paths from [`OneMinClient::upload_asset`].
+    pub async fn image_chat_stream(
+        &self,
+        conversation_id: Option<&str>,
+        model: &str,
+        prompt: &str,
+        images: &[String],
+        max_words: u32,
+        sampling: Sampling,
+    ) -> Result<ChatStream, AiCliError> {
+        let request = ImageChatRequest {
+            request_type: "CHAT_WITH_IMAGE",
+            conversation_id,
+            model,
+            prompt_object: ImageChatPromptObject {
+                prompt,
+                is_mixed: false,
+                image_list: images,
+                max_word: max_words,
+                sampling: sampling.into(),
+            },
+        };
+
+        self.stream(&request).await
+    }
+
+    /// Asks a question about the documents of a conversation created with
+    /// [`OneMinClient::create_document_conversation`] and streams back the answer.
+    pub async fn document_chat_stream(
+        &self,
+        conversation_id: &str,
+        model: &str,
+        prompt: &str,
+        max_words: u32,
+        sampling: Sampling,
+    ) -> Result<ChatStream, AiCliError> {
+        let request = DocumentChatRequest {
+            request_type: "CHAT_WITH_PDF",
+            conversation_id,
+            model,
+            prompt_object: DocumentPromptObject {
+                prompt,
+                max_word: max_words,
+                sampling: sampling.into(),
+            },
+        };
+
+        self.stream(&request).await
+    }
+
+    async fn stream(&self, body: &impl Serialize) -> Result<ChatStream, AiCliError> {
+        let response = Self::send(self.post_json(STREAMING_FEATURES_PATH, body)).await?;
+        Ok(ChatStream::from_response(response))
+    }
+
+    /// Sends a prompt, optionally within a conversation, and waits for the complete response.
+    pub async fn chat(
+        &self,
+        conversation_id: Option<&str>,
+        model: &str,
+        prompt: &str,
+        max_words: u32,
+    ) -> Result<String, AiCliError> {
+        let mut stream = self.chat_stream(conversation_id, model, prompt, max_words, Sampling::default()).await?;
+        let mut response = String::new();
+        while let Some(chunk) = stream.next().await {
+            response.push_str(&chunk?);
+        }
+        Ok(response)
+    }
+
+    pub async fn generate_image(&self, options: &ImageGeneration<'_>) -> Result<AiRecord, AiCliError> {
+        let request = ImageGenerationRequest {
+            request_type: "IMAGE_GENERATOR",
+            model: options.model,
+            prompt_object: ImagePromptObject {
+                prompt: options.prompt,
+                n: options.count,
+                size: options.size,
+                quality: options.quality,
+                style: options.style,
+                aspect_ratio: options.aspect_ratio,
+                negative_prompt: options.negative_prompt,
+                steps: options.steps,
+                cfg_scale: options.cfg_scale,
+                seed: options.seed,
+            },
+        };
+
+        self.feature(&request).await
+    }
+
+    pub async fn image_tool(&self, options: &ImageToolOptions<'_>) -> Result<AiRecord, AiCliError> {
+        let request = ImageToolRequest {
+            request_type: options.tool.feature_type(),
+            model: options.model,
+            prompt_object: ImageToolPromptObject {
+                image_url: options.image,
+                mask_url: options.mask,
+                prompt: options.prompt,
+                n: options.count,
+                size: options.size,
+            },
+        };
+
+        self.feature(&request).await
+    }
+
+    /// Transcribes an uploaded audio asset to text.
+    pub async fn transcribe(&self, audio_path: &str, model: &str) -> Result<String, AiCliError> {
+        let request = SpeechToTextRequest {
+            request_type: "SPEECH_TO_TEXT",
+            model,
+            prompt_object: SpeechToTextPromptObject {
+                audio_url: audio_path,
+                response_format: "text",
+            },
+        };
+
+        let record = self.feature(&request).await?;
+        let transcript = match record.detail.and_then(|detail| detail.result_object) {
+            Some(serde_json::Value::Array(items)) => items.iter()
+                .filter_map(|item| item.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            Some(serde_json::Value::String(text)) => text,
+            _ => return Err(AiCliError::InvalidResponse("No transcript found in speech-to-text response".to_string())),
+        };
+        Ok(transcript.trim().to_string())
+    }
+
+    /// Synthesizes speech for the text with one of the model's voices and returns the audio (MP3).
+    pub async fn text_to_speech(&self, text: &str, model: &str, voice: &str) -> Result<Vec<u8>, AiCliError> {
+        let request = TextToSpeechRequest {
+            request_type: "TEXT_TO_SPEECH",
+            model,
+            prompt_object: TextToSpeechPromptObject { text, voice },
+        };
+
+        let record = self.feature(&request).await?;
+        if record.status != "SUCCESS" {
+            return Err(AiCliError::InvalidResponse(format!("Text-to-speech failed with status: {}", record.status)));
+        }
+        let url = record.result_urls(&self.asset_base_url).into_iter().next()
+            .ok_or_else(|| AiCliError::InvalidResponse("No audio URL found in text-to-speech response".to_string()))?;
+        self.download(&url).await
+    }
+
+    /// Embeds each input with an embedding model and returns the vectors, in the same order.
+    pub async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, AiCliError> {
+        let request = EmbeddingRequest {
+            request_type: "EMBEDDINGS",
+            model,
+            prompt_object: EmbeddingPromptObject { input: inputs },
+        };
+
+        let record = self.feature(&request).await?;
+        let results: Vec<EmbeddingResult> = match record.detail.and_then(|detail| detail.result_object) {
+            Some(result) => serde_json::from_value(result).map_err(|e| AiCliError::InvalidResponse(e.to_string()))?,
+            None => return Err(AiCliError::InvalidResponse("No embeddings found in the embeddings response".to_string())),
+        };
+        if results.len() != inputs.len() {
+            return Err(AiCliError::InvalidResponse(format!("{} embeddings returned for {} inputs", results.len(), inputs.len())));
+        }
+        Ok(results.into_iter()
+            .map(|result| match result {
+                EmbeddingResult::Vector(vector) | EmbeddingResult::Object { embedding: vector } => vector,
+            })
+            .collect())
+    }
+
+    /// Uploads a local file to the asset store and returns its asset path.
+    pub async fn upload_asset(&self, path: &Path) -> Result<String, AiCliError> {
+        let filename = path.file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(DEFAULT_UPLOAD_FILENAME)
+            .to_string();
+        self.upload_bytes(&filename, std::fs::read(path)?).await
+    }
+
+    /// Uploads in-memory data (e.g. a downloaded image) to the asset store and returns its asset path.
+    pub async fn upload_bytes(&self, filename: &str, bytes: Vec<u8>) -> Result<String, AiCliError> {
+        let part = reqwest::multipart::Part::bytes(bytes).file_name(filename.to_string());
+        let form = reqwest::multipart::Form::new().part("asset", part);
+
+        let request = self.http
+            .post(self.url(ASSETS_PATH))
+            .header("API-KEY", self.api_key())
+            .multipart(form);
+
+        let asset: AssetResponse = Self::send(request).await?.json().await?;
+        Ok(asset.file_content.path)
+    }
+
+    /// Upscales an uploaded image or removes its background, waiting until it is processed.
+    pub async fn process_image(&self, processing: ImageProcessing, model: &str, image: &str) -> Result<AiRecord, AiCliError> {
+        let request = ImageProcessingRequest {
+            request_type: processing.feature_type(),
+            model,
+            prompt_object: ImageProcessingPromptObject {
+                image_url: image,
+                scale: match processing {
+                    ImageProcessing::Upscale { scale } => Some(scale),
+                    ImageProcessing::RemoveBackground => None,
+                },
+            },
+        };
+
+        let record = self.feature(&request).await?;
+        self.wait_for(record, Polling::default(), |_, _| {}).await
+    }
+
+    /// Submits a video generation job. The record is usually still pending; see
+    /// [`OneMinClient::wait_for`].
+    pub async fn generate_video(&self, options: &VideoGeneration<'_>) -> Result<AiRecord, AiCliError> {
+        let request = VideoGenerationRequest {
+            request_type: "VIDEO_GENERATOR",
+            model: options.model,
+            prompt_object: VideoPromptObject {
+                prompt: options.prompt,
+                duration: options.duration,
+            },
+        };
+
+        self.feature(&request).await
+    }
+
+    /// The current state of a feature request.
+    pub async fn feature_record(&self, uuid: &str) -> Result<AiRecord, AiCliError> {
+        if uuid.is_empty() {
+            return Err(AiCliError::InvalidResponse("a pending feature request has no id to check on".to_string()));
+        }
+        debug!("checking on feature request {}", uuid);
+        let request = self.http
+            .get(self.url(&format!("{}/{}", FEATURES_PATH, uuid)))
+            .header("API-KEY", self.api_key());
+        let response: AiRecordResponse = Self::send(request).await?.json().await?;
+        Ok(response.ai_record)
+    }
+
+    /// Checks on a feature request until it has finished, for features that are processed in
+    /// the background and answer with a pending record first.
+    pub async fn wait_for(
+        &self,
+        record: AiRecord,
+        polling: Polling,
+        progress: impl FnMut(&AiRecord, Duration),
+    ) -> Result<AiRecord, AiCliError> {
+        jobs::poll(
+            record,
+            polling,
+            |record| jobs::is_pending(&record.status),
+            |record| {
+                let uuid = record.uuid.clone();
+                async move { self.feature_record(&uuid).await }
+            },
+            progress,
+        ).await
+    }
+
+    /// Lists the models available to this account, as returned by the API.
+    pub async fn list_models(&self) -> Result<serde_json::Value, AiCliError> {
+        let request = self.http
+            .get(self.url(MODELS_PATH))
+            .header("API-KEY", self.api_key());
+
+        Ok(Self::send(request).await?.json().await?)
+    }
+
+    /// Downloads a result file (e.g. a generated image).
+    pub async fn download(&self, url: &str) -> Result<Vec<u8>, AiCliError> {
+        let response = Self::send(self.http.get(url)).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// Downloads a generated image, checking that the server says it sent one and that the bytes
+    /// are one, so an error page served with a 200 isn't saved as a broken file. Returns the bytes
+    /// and the extension of their format.
+    pub async fn download_image(&self, url: &str) -> Result<(Vec<u8>, &'static str), AiCliError> {
+        let response = Self::send(self.http.get(url)).await?;
+        let content_type = response.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        // Storage buckets often serve everything as a generic binary.
+        if !content_type.is_empty() && !content_type.starts_with("image/") && !content_type.starts_with("application/octet-stream") {
+            return Err(AiCliError::InvalidResponse(format!("{} is {}, not an image", url, content_type)));
+        }
+        let bytes = response.bytes().await?.to_vec();
+        let extension = image_extension(&bytes)
+            .ok_or_else(|| AiCliError::InvalidResponse(format!("{} is not a PNG, JPEG, GIF or WebP image", url)))?;
+        Ok((bytes, extension))
+    }
+}
+
+/// The file extension of an image's format, from its magic bytes.
+fn image_extension(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if bytes.starts_with(b"\xff\xd8\xff") {
+        Some("jpg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server, ServerGuard};
+    use serde_json::json;
+
+    async fn client(server: &ServerGuard) -> OneMinClient {
+        // Error responses are classified as they are, not retried.
+        retry::configure(0, 0);
+        OneMinClient::new(Client::new(), "test-key").with_base_url(server.url())
+    }
+
+    fn streaming_features() -> Matcher {
+        Matcher::UrlEncoded("isStreaming".to_string(), "true".to_string())
+    }
+
+    #[tokio::test]
+    async fn chats_with_the_prompt_limit_and_sampling() {
+        let mut server = Server::new_async().await;
+        let mock = server.mock("POST", "/api/features")
+            .match_query(streaming_features())
+            .match_header("API-KEY", "test-key")
+            .match_body(Matcher::PartialJson(json!({
+                "type": "CHAT_WITH_AI",
+                "conversationId": "conv-1",
+                "model": "gpt-4o",
+                "promptObject": {"prompt": "Hi", "maxWord": 100, "temperature": 0.5, "isMixed": false},
+            })))
+            .with_body("Hello, world")
+            .create_async()
+            .await;
+
+        let client = client(&server).await;
+        let sampling = Sampling { temperature: Some(0.5), ..Sampling::default() };
+        let stream = client.chat_stream(Some("conv-1"), "gpt-4o", "Hi", 100, sampling).await.unwrap();
+        assert_eq!(stream.collect().await.unwrap(), "Hello, world");
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn streams_server_sent_events() {
+        let mut server = Server::new_async().await;
+        server.mock("POST", "/api/features")
+            .match_query(streaming_features())
+            .with_header("content-type", "text/event-stream")
+            .with_body("data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\ndata: {\"choices\":[{\"delta\":{\"content\":\"lo ✓\"}}]}\n\ndata: [DONE]\n\n")
+            .create_async()
+            .await;
+
+        let client = client(&server).await;
+        let mut stream = client.chat_stream(None, "gpt-4o", "Hi", 100, Sampling::default()).await.unwrap();
+        let mut text = String::new();
+        while let Some(chunk) = stream.next().await {
+            text.push_str(&chunk.unwrap());
+        }
+        assert_eq!(text, "Hello ✓");
+    }
+
+    #[tokio::test]
+    async fn sends_images_and_documents_with_their_options() {
+        let mut server = Server::new_async().await;
+        let images = server.mock("POST", "/api/features")
+            .match_query(streaming_features())
+            .match_body(Matcher::PartialJson(json!({
+                "type": "CHAT_WITH_IMAGE",
+                "promptObject": {"imageList": ["images/a.png"], "maxWord": 150, "topP": 0.9},
+            })))
+            .with_body("A cat")
+            .create_async()
+            .await;
+        let documents = server.mock("POST", "/api/features")
+            .match_query(streaming_features())
+            .match_body(Matcher::PartialJson(json!({
+                "type": "CHAT_WITH_PDF",
+                "conversationId": "doc-1",
+                "promptObject": {"prompt": "Summary?", "maxWord": 42},
+            })))
+            .with_body("It is short")
+            .create_async()
+            .await;
+
+        let client = client(&server).await;
+        let sampling = Sampling { top_p: Some(0.9), ..Sampling::default() };
+        let answer = client.image_chat_stream(None, "gpt-4o", "What is it?", &["images/a.png".to_string()], 150, sampling).await.unwrap();
+        assert_eq!(answer.collect().await.unwrap(), "A cat");
+        let answer = client.document_chat_stream("doc-1", "gpt-4o", "Summary?", 42, Sampling::default()).await.unwrap();
+        assert_eq!(answer.collect().await.unwrap(), "It is short");
+        images.assert_async().await;
+        documents.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn creates_conversations() {
+        let mut server = Server::new_async().await;
+        server.mock("POST", "/api/conversations")
+            .match_body(Matcher::PartialJson(json!({"type": "CHAT_WITH_AI", "title": "Notes"})))
+            .with_body(r#"{"conversation": {"uuid": "conv-42", "title": "Notes"}}"#)
+            .create_async()
+            .await;
+
+        let client = client(&server).await;
+        assert_eq!(client.create_conversation("Notes").await.unwrap(), "conv-42");
+    }
+
+    /// An error response, by status and body, and a check of the error it should become.
+    type ErrorCase = (usize, &'static str, fn(&AiCliError) -> bool);
+
+    #[tokio::test]
+    async fn maps_error_responses() {
+        let cases: [ErrorCase; 5] = [
+            (401, r#"{"message": "Invalid API key"}"#, |e| e.is_auth()),
+            (402, r#"{"message": "Not enough credits"}"#, |e| matches!(e, AiCliError::QuotaExceeded(m) if m == "Not enough credits")),
+            (429, "Slow down", |e| matches!(e, AiCliError::RateLimited { retry_after: Some(delay) } if delay.as_secs() == 3)),
+            (503, r#"{"message": "Model gpt-4o is overloaded"}"#, |e| e.is_transient() && e.exit_code() == crate::error::EXIT_SERVER_ERROR),
+            (400, r#"{"message": "400 Invalid model: gpt-9"}"#, |e| matches!(e, AiCliError::InvalidModel(m) if m == "Invalid model: gpt-9")),
+        ];
+        for (status, body, expected) in cases {
+            let mut server = Server::new_async().await;
+            server.mock("POST", "/api/features")
+                .match_query(streaming_features())
+                .with_status(status)
+                .with_header("retry-after", "3")
+                .with_body(body)
+                .create_async()
+                .await;
+
+            let client = client(&server).await;
+            match client.chat_stream(None, "gpt-4o", "Hi", 100, Sampling::default()).await {
+                Err(error) => assert!(expected(&error), "{}: {:?}", status, error),
+                Ok(_) => panic!("{} was taken for success", status),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_feature_records() {
+        let mut server = Server::new_async().await;
+        server.mock("POST", "/api/features")
+            .match_body(Matcher::PartialJson(json!({"type": "IMAGE_GENERATOR"})))
+            .with_body(r#"{"aiRecord": {"uuid": "rec-1", "status": "SUCCESS", "aiRecordDetail": {"resultObject": ["images/out.png"]}}}"#)
+            .create_async()
+            .await;
+
+        let client = client(&server).await.with_asset_base_url("https://assets.example");
+        let options = ImageGeneration {
+            prompt: "A cat",
+            model: "dall-e-3",
+            count: 1,
+            size: None,
+            quality: None,
+            style: None,
+            aspect_ratio: None,
+            negative_prompt: None,
+            steps: None,
+            cfg_scale: None,
+            seed: None,
+        };
+        let record = client.generate_image(&options).await.unwrap();
+        assert_eq!(record.status, "SUCCESS");
+        assert_eq!(record.result_urls(client.asset_base_url()), ["https://assets.example/images/out.png"]);
+    }
+}
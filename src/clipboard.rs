@@ -0,0 +1,32 @@
+use anyhow::{anyhow, Result};
+use arboard::Clipboard;
+use std::sync::Mutex;
+
+/// The clipboard handle is kept for the life of the process: on X11 and Wayland the copied
+/// text is served by its owner, so dropping the handle would drop the clipboard contents.
+static CLIPBOARD: Mutex<Option<Clipboard>> = Mutex::new(None);
+
+fn with_clipboard<T>(action: impl FnOnce(&mut Clipboard) -> Result<T>) -> Result<T> {
+    let mut guard = CLIPBOARD.lock().map_err(|_| anyhow!("Clipboard state is poisoned"))?;
+    if guard.is_none() {
+        *guard = Some(Clipboard::new().map_err(|e| anyhow!("Could not access the clipboard: {}", e))?);
+    }
+    action(guard.as_mut().expect("clipboard initialized above"))
+}
+
+/// Places text on the system clipboard.
+///
+/// On Linux the text is only available while this process runs, unless a clipboard manager
+/// takes it over, so text copied just before a one-shot command exits may be lost.
+pub fn copy(text: &str) -> Result<()> {
+    with_clipboard(|clipboard| {
+        clipboard.set_text(text).map_err(|e| anyhow!("Could not copy to the clipboard: {}", e))
+    })
+}
+
+/// Reads the text currently on the system clipboard.
+pub fn paste() -> Result<String> {
+    with_clipboard(|clipboard| {
+        clipboard.get_text().map_err(|e| anyhow!("Could not read the clipboard: {}", e))
+    })
+}
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+/// File extensions recognised when inferring the language from `--out`.
+const EXTENSION_LANGUAGES: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("py", "python"),
+    ("js", "javascript"),
+    ("mjs", "javascript"),
+    ("ts", "typescript"),
+    ("go", "go"),
+    ("rb", "ruby"),
+    ("pl", "perl"),
+    ("sh", "bash"),
+    ("bash", "bash"),
+    ("java", "java"),
+    ("kt", "kotlin"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cpp", "cpp"),
+    ("cs", "csharp"),
+    ("swift", "swift"),
+    ("php", "php"),
+    ("lua", "lua"),
+    ("sql", "sql"),
+];
+
+/// Other names models put on code fences, mapped to the names used above.
+const LANGUAGE_ALIASES: &[(&str, &str)] = &[
+    ("py", "python"),
+    ("python3", "python"),
+    ("js", "javascript"),
+    ("node", "javascript"),
+    ("ts", "typescript"),
+    ("sh", "bash"),
+    ("shell", "bash"),
+    ("zsh", "bash"),
+    ("console", "bash"),
+    ("rs", "rust"),
+    ("golang", "go"),
+    ("rb", "ruby"),
+    ("c++", "cpp"),
+    ("cs", "csharp"),
+    ("c#", "csharp"),
+    ("yml", "yaml"),
+];
+
+/// Scripting languages that `--exec` can run, with the interpreter and file extension to use.
+const INTERPRETERS: &[(&str, &str, &str)] = &[
+    ("python", "python3", "py"),
+    ("bash", "bash", "sh"),
+    ("sh", "sh", "sh"),
+    ("javascript", "node", "js"),
+    ("ruby", "ruby", "rb"),
+    ("perl", "perl", "pl"),
+    ("lua", "lua", "lua"),
+    ("php", "php", "php"),
+];
+
+/// Guesses the language from a file's extension.
+pub fn language_for_path(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    EXTENSION_LANGUAGES.iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, lang)| *lang)
+}
+
+/// Wraps a task in an instruction to answer with nothing but code.
+pub fn code_prompt(task: &str, lang: Option<&str>) -> String {
+    let language = match lang {
+        Some(lang) => format!(" in {}", lang),
+        None => String::new(),
+    };
+    format!(
+        "Write code{} for the following task. Respond with only the complete code, with no explanation \
+         before or after it. Put any notes in code comments.\n\nTask: {}",
+        language, task
+    )
+}
+
+/// Extracts the code from a response: the contents of the first fenced block if there is one,
+/// otherwise the whole response.
+pub fn strip_fences(response: &str) -> String {
+    let mut lines = response.lines().skip_while(|line| !line.trim_start().starts_with("```"));
+    let Some(opening) = lines.next() else {
+        return format!("{}\n", response.trim());
+    };
+
+    let fence_len = opening.trim_start().chars().take_while(|&c| c == '`').count();
+    let closing = "`".repeat(fence_len);
+    let mut code = String::new();
+    for line in lines {
+        if line.trim() == closing {
+            break;
+        }
+        code.push_str(line);
+        code.push('\n');
+    }
+    code
+}
+
+/// A fenced code block of a response.
+pub struct CodeBlock {
+    /// The language named after the opening fence, if any
+    pub lang: Option<String>,
+    pub code: String,
+}
+
+/// The canonical name of a language, so `py` matches `python` and `sh` matches `bash`.
+pub fn normalize_language(lang: &str) -> String {
+    let lang = lang.trim().to_ascii_lowercase();
+    LANGUAGE_ALIASES.iter()
+        .find(|(alias, _)| *alias == lang)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or(lang)
+}
+
+/// Parses every fenced (``` or ~~~) code block out of a Markdown response. An unclosed fence
+/// runs to the end of the response.
+pub fn code_blocks(response: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(char, usize, CodeBlock)> = None;
+
+    for line in response.lines() {
+        let trimmed = line.trim_start();
+        match &mut current {
+            Some((marker, len, block)) => {
+                let closing = trimmed.trim_end();
+                if closing.len() >= *len && closing.chars().all(|c| c == *marker) {
+                    if let Some((_, _, block)) = current.take() {
+                        blocks.push(block);
+                    }
+                } else {
+                    block.code.push_str(line);
+                    block.code.push('\n');
+                }
+            }
+            None => {
+                let Some(marker) = trimmed.chars().next().filter(|c| *c == '`' || *c == '~') else { continue };
+                let len = trimmed.chars().take_while(|&c| c == marker).count();
+                if len < 3 {
+                    continue;
+                }
+                let lang = trimmed[len..].split_whitespace().next()
+                    .map(|lang| lang.trim_matches(|c| c == '{' || c == '}' || c == '.').to_string())
+                    .filter(|lang| !lang.is_empty());
+                current = Some((marker, len, CodeBlock { lang, code: String::new() }));
+            }
+        }
+    }
+    if let Some((_, _, block)) = current {
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// The code of every block in the response, or of those in `lang` when given, one after the
+/// other. `None` when there is no such block.
+pub fn extract_code(response: &str, lang: Option<&str>) -> Option<String> {
+    let wanted = lang.map(normalize_language);
+    let code: Vec<String> = code_blocks(response).into_iter()
+        .filter(|block| match (&wanted, &block.lang) {
+            (None, _) => true,
+            (Some(wanted), Some(lang)) => normalize_language(lang) == *wanted,
+            (Some(_), None) => false,
+        })
+        .map(|block| block.code)
+        .collect();
+    (!code.is_empty()).then(|| code.join("\n"))
+}
+
+/// Runs the code with the language's interpreter, from a temporary file or `path` if given.
+pub fn execute(code: &str, lang: &str, path: Option<&Path>) -> Result<ExitStatus> {
+    let (_, interpreter, extension) = INTERPRETERS.iter()
+        .find(|(name, _, _)| name.eq_ignore_ascii_case(lang))
+        .ok_or_else(|| anyhow!(
+            "--exec only runs scripting languages ({}), not {}",
+            INTERPRETERS.iter().map(|(name, _, _)| *name).collect::<Vec<_>>().join(", "),
+            lang
+        ))?;
+
+    let script = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let script = std::env::temp_dir().join(format!("ai-cli-code-{}.{}", std::process::id(), extension));
+            std::fs::write(&script, code)?;
+            script
+        }
+    };
+
+    let status = Command::new(interpreter)
+        .arg(&script)
+        .status()
+        .with_context(|| format!("Could not run {}", interpreter));
+    if path.is_none() {
+        let _ = std::fs::remove_file(&script);
+    }
+    status
+}
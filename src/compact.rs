@@ -0,0 +1,38 @@
+use crate::cost;
+use crate::history::Turn;
+
+/// Roughly where long conversations start to crowd the context of common models.
+pub const DEFAULT_CONTEXT_LIMIT_TOKENS: usize = 100_000;
+/// The latest exchanges are carried over as they are, since the next prompt likely refers to them.
+pub const KEEP_RECENT_EXCHANGES: usize = 2;
+
+/// Instruction for condensing the earlier part of a conversation.
+pub const INSTRUCTION: &str = "Summarize the earlier part of a conversation between a user and an \
+    assistant, given below, so that it can continue without it. Keep every fact, decision, name, \
+    number, code snippet and open question that later messages may refer to. Respond with only the summary.";
+
+/// Writes out a conversation as plain text, after the summary of what came before it, if any.
+pub fn transcript(summary: Option<&str>, turns: &[Turn]) -> String {
+    let mut parts = Vec::new();
+    if let Some(summary) = summary {
+        parts.push(format!("Summary of the earlier conversation: {}", summary));
+    }
+    for turn in turns.iter().filter(|turn| turn.retry_of.is_none()) {
+        parts.push(format!("User: {}\n\nAssistant: {}", turn.prompt, turn.response));
+    }
+    parts.join("\n\n")
+}
+
+/// Splits a conversation's turns into the exchanges to summarize and those to keep, leaving out
+/// regenerated responses. At least one exchange is summarized when there is any.
+pub fn split(turns: &[Turn]) -> (Vec<Turn>, Vec<Turn>) {
+    let mut exchanges: Vec<Turn> = turns.iter().filter(|turn| turn.retry_of.is_none()).cloned().collect();
+    let keep = KEEP_RECENT_EXCHANGES.min(exchanges.len().saturating_sub(1));
+    let recent = exchanges.split_off(exchanges.len() - keep);
+    (exchanges, recent)
+}
+
+/// Approximate tokens a conversation takes up in the model's context.
+pub fn context_tokens(summary: Option<&str>, turns: &[Turn]) -> usize {
+    cost::estimate_tokens(&transcript(summary, turns))
+}
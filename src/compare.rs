@@ -0,0 +1,113 @@
+use serde::Serialize;
+
+/// Narrowest column worth printing side by side; below this the layout falls back to sequential.
+const MIN_COLUMN_WIDTH: usize = 24;
+const COLUMN_SEPARATOR: &str = " │ ";
+
+/// One model's answer in a comparison.
+#[derive(Serialize)]
+pub struct ModelResponse {
+    pub model: String,
+    /// Local conversation the exchange was stored in
+    pub conversation_id: String,
+    pub response: String,
+    /// Set when the request failed; the other models' responses are still shown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+impl ModelResponse {
+    fn heading(&self) -> String {
+        format!("{} ({:.2}s)", self.model, self.elapsed_ms as f64 / 1000.0)
+    }
+
+    fn body(&self) -> &str {
+        match &self.error {
+            Some(error) => error,
+            None => &self.response,
+        }
+    }
+}
+
+/// Prints each response under its own heading, one after another.
+pub fn render_sequential(responses: &[ModelResponse]) -> String {
+    let mut out = String::new();
+    for (i, response) in responses.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let heading = response.heading();
+        out.push_str(&format!("{}\n{}\n", heading, "=".repeat(heading.chars().count())));
+        if response.error.is_some() {
+            out.push_str("Error: ");
+        }
+        out.push_str(response.body().trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Wraps text to `width` characters, breaking long words where needed.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            while word.len() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                lines.push(word.drain(..width).collect());
+            }
+            let line_len = line.chars().count();
+            if line_len > 0 && line_len + 1 + word.len() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.extend(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Prints the responses next to each other in columns filling `width`. Returns `None` when the
+/// terminal is too narrow for that many columns.
+pub fn render_columns(responses: &[ModelResponse], width: usize) -> Option<String> {
+    let count = responses.len().max(1);
+    let separators = COLUMN_SEPARATOR.chars().count() * (count - 1);
+    let column = width.checked_sub(separators)? / count;
+    if column < MIN_COLUMN_WIDTH {
+        return None;
+    }
+
+    let columns: Vec<Vec<String>> = responses.iter()
+        .map(|response| {
+            let mut lines = wrap(&response.heading(), column);
+            lines.push("─".repeat(column));
+            match &response.error {
+                Some(error) => lines.extend(wrap(&format!("Error: {}", error), column)),
+                None => lines.extend(wrap(response.response.trim_end(), column)),
+            }
+            lines
+        })
+        .collect();
+
+    let height = columns.iter().map(|lines| lines.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for row in 0..height {
+        let cells: Vec<String> = columns.iter()
+            .map(|lines| {
+                let cell = lines.get(row).map(String::as_str).unwrap_or("");
+                format!("{}{}", cell, " ".repeat(column - cell.chars().count()))
+            })
+            .collect();
+        out.push_str(cells.join(COLUMN_SEPARATOR).trim_end());
+        out.push('\n');
+    }
+    Some(out)
+}
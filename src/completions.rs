@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Result};
+use clap_complete::env::Shells;
+use clap_complete::{CompletionCandidate, Shell};
+use std::io;
+
+use crate::{history, models};
+
+/// Environment variable the generated shell scripts set when asking the binary for completions.
+pub const COMPLETE_VAR: &str = "COMPLETE";
+
+/// Model names from the local model cache (refreshed by `ai-cli models`).
+pub fn model_candidates() -> Vec<CompletionCandidate> {
+    models::load_cache()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|model| CompletionCandidate::new(model.id).help(Some(model.name.into())))
+        .collect()
+}
+
+/// Ids of locally stored conversations, most recent first, described by their titles.
+pub fn conversation_candidates() -> Vec<CompletionCandidate> {
+    history::list()
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| {
+            CompletionCandidate::new(record.id)
+                .help(Some(record.title.into()))
+                .display_order(Some(i))
+        })
+        .collect()
+}
+
+/// Writes the script that registers completions for `bin` in the given shell.
+///
+/// The script calls back into the binary on every Tab press, so model names and conversation
+/// ids are always current.
+pub fn write_registration(shell: Shell, bin: &str) -> Result<()> {
+    let name = shell.to_string();
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&name)
+        .ok_or_else(|| anyhow!("Completions are not supported for {}", name))?;
+
+    let completer_path = std::env::current_exe()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| bin.to_string());
+    completer.write_registration(COMPLETE_VAR, bin, bin, &completer_path, &mut io::stdout())?;
+    Ok(())
+}
@@ -0,0 +1,389 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::auth::Recovery;
+use crate::credentials::CredentialStore;
+use crate::lock;
+use crate::mcp::ServerConfig;
+use crate::providers::ProviderConfig;
+use crate::redact::Redaction;
+use crate::sampling::Sampling;
+use crate::templates;
+use crate::tools::{Policy, Tool};
+
+const CONFIG_FILE: &str = "config.toml";
+
+/// Built-in response styles for `--response-style`; custom ones can be added under `[styles]`.
+const BUILTIN_STYLES: &[(&str, &str)] = &[
+    ("concise", "Answer concisely in a few sentences. No preamble."),
+    ("detailed", "Give a thorough, detailed answer, explaining your reasoning with examples where helpful."),
+    ("bullet", "Answer in at most 3 bullet points. No preamble."),
+    ("eli5", "Explain it simply, as if to a five-year-old, avoiding jargon."),
+];
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Config {
+    /// Named system prompts selectable with `--persona`
+    #[serde(default)]
+    pub personas: BTreeMap<String, String>,
+    /// Custom response styles selectable with `--response-style`
+    #[serde(default)]
+    pub styles: BTreeMap<String, String>,
+    /// Ask for confirmation before sending prompts estimated above this many tokens
+    #[serde(default)]
+    pub confirm_above_tokens: Option<usize>,
+    /// Credits per 1,000 tokens used for cost estimates, keyed by model (credits per image for image models)
+    #[serde(default)]
+    pub model_rates: BTreeMap<String, f64>,
+    /// Operations estimated to cost more credits than this need confirmation, and are refused
+    /// when running non-interactively
+    #[serde(default)]
+    pub max_cost_per_request: Option<f64>,
+    /// Credits the usage ledger may reach in a calendar month before operations need confirmation
+    #[serde(default)]
+    pub monthly_budget: Option<f64>,
+    /// What happens to prompts containing secrets (API keys, tokens, private keys, email
+    /// addresses): "off", "warn" (the default), "mask" or "block"
+    #[serde(default)]
+    pub redaction: Redaction,
+    /// The `[sampling]` table: `temperature`, `top_p` and `frequency_penalty` used when the
+    /// command line doesn't give them, for models that accept them
+    #[serde(default)]
+    pub sampling: Sampling,
+    /// Voices `-v` reads responses with, by the language they are written in (ISO 639-3 code such
+    /// as "deu", or English name), overriding the built-in ones
+    #[serde(default)]
+    pub voice_map: BTreeMap<String, String>,
+    /// Files attached with `--file` are truncated beyond this many bytes
+    #[serde(default)]
+    pub max_file_bytes: Option<u64>,
+    /// Statuspage-compatible status page checked by `ai-cli status` for open incidents
+    #[serde(default)]
+    pub status_page_url: Option<String>,
+    /// Git repository holding the shared prompt library, synced with `templates sync`
+    #[serde(default)]
+    pub templates_repo: Option<String>,
+    /// API host to use instead of https://api.1min.ai, e.g. a proxy or staging environment
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Host that relative result file paths (generated images, audio) are downloaded from
+    #[serde(default)]
+    pub asset_base_url: Option<String>,
+    /// Answer repeated identical prompts from the response cache, as if `--cache` were always given
+    #[serde(default)]
+    pub cache: bool,
+    /// Compact interactive conversations once they reach about this many tokens (0 turns it off)
+    #[serde(default)]
+    pub context_limit: Option<usize>,
+    /// Requests per minute to stay under, queueing requests beyond it (unlimited when unset)
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// What to do when the API rejects the API key: `prompt` (the default), `fail` or `reload`
+    #[serde(default)]
+    pub auth_recovery: Recovery,
+    /// Where `ai-cli` saves the API key: `keyring` (the default, falling back to an encrypted file
+    /// without one) or `file`
+    #[serde(default)]
+    pub credential_store: CredentialStore,
+    /// Provider used when `--provider` isn't given: `1min`, `openai`, `ollama` or a `[providers]` entry
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// Additional providers, or settings for the built-in ones, selectable with `--provider`
+    #[serde(default)]
+    pub providers: BTreeMap<String, ProviderConfig>,
+    /// Models used for each task when `--model` isn't given
+    #[serde(default)]
+    pub default: DefaultModels,
+    /// Whether each `--tools` tool runs without asking: `ask`, `allow` or `deny`
+    #[serde(default)]
+    pub tools: BTreeMap<Tool, Policy>,
+    /// Shortcuts for frequently used invocations: `ai-cli <name> ...` runs `ai-cli <definition> ...`
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Directory `--save-response` writes responses to when it isn't given one
+    #[serde(default)]
+    pub responses_dir: Option<PathBuf>,
+    /// Paste service `ai-cli share --to paste` posts conversations to, e.g. https://paste.rs
+    #[serde(default)]
+    pub paste_url: Option<String>,
+    /// Template the descriptions written by `ai-cli pr` follow, instead of the repository's own
+    #[serde(default)]
+    pub pr_template: Option<PathBuf>,
+    /// MCP servers whose tools and resources `--mcp` offers to the model
+    #[serde(default)]
+    pub mcp_servers: BTreeMap<String, ServerConfig>,
+    /// Key clients of `ai-cli serve` send as a bearer token; when unset, a new one is generated
+    /// and printed each time the server starts
+    #[serde(default)]
+    pub serve_api_key: Option<String>,
+}
+
+/// The kinds of requests that can have a default model of their own.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Task {
+    Chat,
+    Image,
+    Code,
+    Summarize,
+    Translate,
+    Embedding,
+}
+
+/// The `[default]` table: a model per task, e.g. `image_model = "flux-pro"`.
+#[derive(Serialize, Deserialize, Default)]
+pub struct DefaultModels {
+    #[serde(default)]
+    pub chat_model: Option<String>,
+    #[serde(default)]
+    pub image_model: Option<String>,
+    #[serde(default)]
+    pub code_model: Option<String>,
+    #[serde(default)]
+    pub summarize_model: Option<String>,
+    #[serde(default)]
+    pub translate_model: Option<String>,
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+}
+
+impl DefaultModels {
+    pub fn model(&self, task: Task) -> Option<&str> {
+        match task {
+            Task::Chat => self.chat_model.as_deref(),
+            Task::Image => self.image_model.as_deref(),
+            Task::Code => self.code_model.as_deref(),
+            Task::Summarize => self.summarize_model.as_deref(),
+            Task::Translate => self.translate_model.as_deref(),
+            Task::Embedding => self.embedding_model.as_deref(),
+        }
+    }
+}
+
+pub fn config_dir() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or_else(|| anyhow!("Could not determine the configuration directory"))?
+        .join("ai-cli"))
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join(CONFIG_FILE))
+}
+
+/// Reads a value given on the command line as a TOML integer, float or boolean where it is one,
+/// and as a string otherwise.
+fn parse_value(value: &str) -> toml_edit::Value {
+    if let Ok(integer) = value.parse::<i64>() {
+        integer.into()
+    } else if let Ok(float) = value.parse::<f64>() {
+        float.into()
+    } else if let Ok(boolean) = value.parse::<bool>() {
+        boolean.into()
+    } else {
+        value.into()
+    }
+}
+
+/// Applies `update` to the config file, creating it if needed, and saves the result. Comments
+/// and the layout of the rest of the file are kept. Returns the path of the config file.
+fn edit(update: impl FnOnce(&mut toml_edit::DocumentMut, &Path) -> Result<()>) -> Result<PathBuf> {
+    let path = config_path()?;
+    edit_file(&path, update)?;
+    Ok(path)
+}
+
+/// Holds the lock on the file's directory from the read through the write, so concurrent edits
+/// (`config set` and `alias add` run at once, say) each see the other's change.
+fn edit_file(path: &Path, update: impl FnOnce(&mut toml_edit::DocumentMut, &Path) -> Result<()>) -> Result<()> {
+    let _lock = lock::lock_dir(path.parent().expect("the config file is in a directory"))?;
+    let contents = if path.exists() { fs::read_to_string(path)? } else { String::new() };
+    let mut document: toml_edit::DocumentMut = contents.parse()
+        .map_err(|e| anyhow!("Invalid config file {}: {}", path.display(), e))?;
+
+    update(&mut document, path)?;
+
+    lock::write_atomic(path, document.to_string().as_bytes())
+}
+
+/// Changes one setting of the config file, e.g. `default.image_model`, creating any tables as
+/// needed.
+pub fn set(key: &str, value: &str) -> Result<PathBuf> {
+    edit(|document, path| set_in(document, path, key, value))
+}
+
+fn set_in(document: &mut toml_edit::DocumentMut, path: &Path, key: &str, value: &str) -> Result<()> {
+    let parts: Vec<&str> = key.split('.').collect();
+    if parts.iter().any(|part| part.trim().is_empty()) {
+        return Err(anyhow!("Invalid setting '{}': use a name like default.image_model", key));
+    }
+    let (name, tables) = parts.split_last().expect("split yields at least one part");
+    let mut table = document.as_table_mut();
+    for part in tables {
+        table = table.entry(part)
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Cannot set '{}': '{}' is not a table in {}", key, part, path.display()))?;
+    }
+    table.insert(name, toml_edit::Item::Value(parse_value(value)));
+
+    // Settings the config doesn't know would be silently ignored, so they are refused instead.
+    let config: Config = toml::from_str(&document.to_string())
+        .map_err(|e| anyhow!("Invalid value for {}: {}", key, e.message()))?;
+    let known = toml::Value::try_from(&config)?;
+    if parts.iter().try_fold(&known, |value, part| value.get(part)).is_none() {
+        return Err(anyhow!("Unknown setting '{}'", key));
+    }
+    Ok(())
+}
+
+/// Defines the alias `name`, replacing an earlier definition of it.
+pub fn set_alias(name: &str, definition: &str) -> Result<PathBuf> {
+    edit(|document, _| {
+        let aliases = document.entry("aliases")
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("'aliases' in the config file is not a table"))?;
+        aliases.insert(name, toml_edit::value(definition));
+        Ok(())
+    })
+}
+
+/// Removes the alias `name` from the config file.
+pub fn remove_alias(name: &str) -> Result<PathBuf> {
+    edit(|document, path| {
+        document.get_mut("aliases")
+            .and_then(|aliases| aliases.as_table_like_mut())
+            .and_then(|aliases| aliases.remove(name))
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("No alias named '{}' in {}", name, path.display()))
+    })
+}
+
+/// Registers the MCP server `name`, replacing an earlier one of that name.
+pub fn set_mcp_server(name: &str, server: &ServerConfig) -> Result<PathBuf> {
+    let table: toml_edit::DocumentMut = toml::to_string(server)?.parse()?;
+    edit(|document, _| {
+        let servers = document.entry("mcp_servers")
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("'mcp_servers' in the config file is not a table"))?;
+        servers.set_implicit(true);
+        servers.insert(name, toml_edit::Item::Table(table.as_table().clone()));
+        Ok(())
+    })
+}
+
+/// Removes the MCP server `name` from the config file.
+pub fn remove_mcp_server(name: &str) -> Result<PathBuf> {
+    edit(|document, path| {
+        document.get_mut("mcp_servers")
+            .and_then(|servers| servers.as_table_like_mut())
+            .and_then(|servers| servers.remove(name))
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("No MCP server named '{}' in {}", name, path.display()))
+    })
+}
+
+impl Config {
+    pub fn load() -> Result<Config> {
+        let path = config_path()?;
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = fs::read_to_string(&path)?;
+        toml::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    pub fn persona(&self, name: &str) -> Result<String> {
+        if let Some(persona) = self.personas.get(name) {
+            return Ok(persona.clone());
+        }
+        if let Some(persona) = templates::load_persona(name) {
+            return Ok(persona);
+        }
+
+        if self.personas.is_empty() {
+            Err(anyhow!("Unknown persona '{}'. No personas are defined in {} or the prompt library", name, CONFIG_FILE))
+        } else {
+            let names: Vec<&str> = self.personas.keys().map(|k| k.as_str()).collect();
+            Err(anyhow!("Unknown persona '{}'. Available personas: {}", name, names.join(", ")))
+        }
+    }
+
+    /// The model for a task when `--model` isn't given: the task's default from `[default]`,
+    /// then the provider's default model for text tasks, then `builtin`.
+    pub fn task_model(&self, task: Task, provider_default: Option<&str>, builtin: &str) -> String {
+        let provider_default = provider_default.filter(|_| !matches!(task, Task::Image | Task::Embedding));
+        self.default.model(task).or(provider_default).unwrap_or(builtin).to_string()
+    }
+
+    pub fn tool_policy(&self, tool: Tool) -> Policy {
+        self.tools.get(&tool).copied().unwrap_or_else(|| tool.default_policy())
+    }
+
+    pub fn style(&self, name: &str) -> Result<String> {
+        if let Some(style) = self.styles.get(name) {
+            return Ok(style.clone());
+        }
+        if let Some((_, style)) = BUILTIN_STYLES.iter().find(|(builtin, _)| *builtin == name) {
+            return Ok(style.to_string());
+        }
+
+        let names: Vec<&str> = BUILTIN_STYLES.iter()
+            .map(|(builtin, _)| *builtin)
+            .chain(self.styles.keys().map(|k| k.as_str()))
+            .collect();
+        Err(anyhow!("Unknown response style '{}'. Available styles: {}", name, names.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn edits_keep_comments_and_refuse_unknown_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(&path, "# my settings\n[default]\nchat_model = \"gpt-4o\"\n").unwrap();
+
+        edit_file(&path, |document, path| set_in(document, path, "default.image_model", "dall-e-3")).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# my settings\n"));
+        assert!(contents.contains("image_model = \"dall-e-3\""));
+
+        let error = edit_file(&path, |document, path| set_in(document, path, "default.colour", "red")).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown setting 'default.colour'");
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+    }
+
+    #[test]
+    fn concurrent_edits_are_all_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        let editors: Vec<_> = (0..8)
+            .map(|editor| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    edit_file(&path, |document, _| {
+                        let aliases = document.entry("aliases").or_insert(toml_edit::table()).as_table_mut().unwrap();
+                        aliases.insert(&format!("alias{}", editor), toml_edit::value("ask"));
+                        Ok(())
+                    })
+                    .unwrap();
+                })
+            })
+            .collect();
+        for editor in editors {
+            editor.join().unwrap();
+        }
+
+        let config: Config = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config.aliases.len(), 8);
+    }
+}
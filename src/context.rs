@@ -0,0 +1,263 @@
+//! Project context for `--repo`: the files of the git repository most relevant to a prompt,
+//! packed into the space the model's context leaves for them.
+
+use anyhow::{anyhow, Result};
+use glob::{MatchOptions, Pattern};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{cost, git, references};
+
+/// Patterns of files to leave out of the context, on top of `.gitignore`, in the repository's top level
+pub const IGNORE_FILE: &str = ".aiignore";
+/// Tokens kept free for the model's answer
+const RESPONSE_TOKENS: usize = 4000;
+/// Files scoring less than the best one divided by this are left out
+const MIN_RELATIVE_SCORE: f64 = 4.0;
+/// Files of generated content that match prompts by chance and are rarely worth their size
+const SKIPPED_FILES: &[&str] = &["Cargo.lock", "package-lock.json", "yarn.lock", "pnpm-lock.yaml", "poetry.lock", "go.sum"];
+/// Words too common in prompts to say anything about which files matter
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "that", "this", "with", "from", "what", "why", "how", "does", "are", "was", "can",
+    "should", "would", "could", "into", "when", "where", "which", "make", "use", "using", "file", "files",
+    "code", "function", "add", "fix", "change", "about", "there", "them", "its", "not", "all", "any", "you",
+];
+
+/// A file of the repository and how well it matches the prompt.
+pub struct RepoFile {
+    /// Path relative to the repository's top level
+    pub path: String,
+    pub contents: String,
+    pub score: f64,
+}
+
+/// The files of a repository that can go into the context, read once.
+pub struct Repo {
+    pub root: PathBuf,
+    files: Vec<(String, String)>,
+    /// Files sent earlier in the conversation, which the model already has
+    sent: HashSet<String>,
+}
+
+/// Files picked for one prompt.
+pub struct Selection {
+    pub files: Vec<RepoFile>,
+    /// Relevant files that didn't fit
+    pub left_out: usize,
+}
+
+impl Selection {
+    pub fn tokens(&self) -> usize {
+        self.files.iter().map(|file| cost::estimate_tokens(&file.contents)).sum()
+    }
+
+    /// The files as path-labeled code blocks to go after the prompt, or nothing.
+    pub fn render(&self) -> String {
+        if self.files.is_empty() {
+            return String::new();
+        }
+        let blocks: Vec<String> = self.files.iter()
+            .map(|file| references::fenced_block(&file.path, &file.contents))
+            .collect();
+        format!("\n\nFiles of the project that may be relevant:\n\n{}", blocks.join("\n\n"))
+    }
+}
+
+impl Repo {
+    /// Reads the text files of the current repository that git doesn't ignore, leaving out those
+    /// matching `.aiignore`.
+    pub fn load() -> Result<Repo> {
+        let root = git::toplevel()?;
+        let ignore = IgnoreList::load(&root.join(IGNORE_FILE))?;
+        let mut files = Vec::new();
+        for path in git::list_files(&root)? {
+            let name = path.rsplit('/').next().unwrap_or(&path);
+            if ignore.matches(&path) || SKIPPED_FILES.contains(&name) {
+                continue;
+            }
+            // Binary, unreadable, deleted and oversized files are skipped.
+            if let Ok(contents) = references::read_text(&root.join(&path).display().to_string()) {
+                files.push((path, contents));
+            }
+        }
+        if files.is_empty() {
+            return Err(anyhow!("The repository at {} has no text files to add to the context", root.display()));
+        }
+        Ok(Repo { root, files, sent: HashSet::new() })
+    }
+
+    /// Picks the files most relevant to `query`, best first, until they would take up more than
+    /// `budget` tokens. Files in `exclude` and those already sent are skipped.
+    pub fn select(&self, query: &str, budget: usize, exclude: &[&str]) -> Selection {
+        let budget = budget.saturating_sub(RESPONSE_TOKENS);
+        let keywords = keywords(query);
+        let candidates: Vec<(&String, &String, HashMap<String, usize>)> = self.files.iter()
+            .filter(|(path, _)| !exclude.contains(&path.as_str()) && !self.sent.contains(path))
+            .map(|(path, contents)| (path, contents, symbols(contents)))
+            .collect();
+        // A keyword found in few files says more about them than one found everywhere.
+        let weights: Vec<f64> = keywords.iter()
+            .map(|keyword| {
+                let found_in = candidates.iter().filter(|(_, _, symbols)| symbols.contains_key(keyword)).count();
+                (1.0 + candidates.len() as f64 / (1 + found_in) as f64).ln()
+            })
+            .collect();
+        let mut ranked: Vec<(f64, &String, &String)> = candidates.iter()
+            .map(|(path, contents, symbols)| (score(path, symbols, query, &keywords, &weights), *path, *contents))
+            .filter(|(score, _, _)| *score > 0.0)
+            .collect();
+        // Best first; among equally relevant files, smaller ones leave room for more.
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.2.len().cmp(&b.2.len())).then(a.1.cmp(b.1)));
+        // Files that merely share a common word with the query would crowd out the context.
+        let best = ranked.first().map_or(0.0, |(score, _, _)| *score);
+        ranked.retain(|(score, _, _)| *score * MIN_RELATIVE_SCORE >= best);
+
+        let mut selection = Selection { files: Vec::new(), left_out: 0 };
+        let mut used = 0;
+        for (score, path, contents) in ranked {
+            // The fence and path label take a few tokens of their own.
+            let tokens = cost::estimate_tokens(contents) + cost::estimate_tokens(path) + 4;
+            if used + tokens > budget {
+                selection.left_out += 1;
+                continue;
+            }
+            used += tokens;
+            selection.files.push(RepoFile { path: path.clone(), contents: contents.clone(), score });
+        }
+        selection
+    }
+
+    /// Remembers that the files of a selection are in the conversation now.
+    pub fn mark_sent(&mut self, selection: &Selection) {
+        self.sent.extend(selection.files.iter().map(|file| file.path.clone()));
+    }
+}
+
+/// The words of a query worth looking for: identifiers and words of at least three characters,
+/// lowercased, with `snake_case` and `camelCase` identifiers also split into their parts.
+fn keywords(query: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    for word in query.split(|c: char| !c.is_alphanumeric() && c != '_') {
+        let mut parts = vec![word.to_string()];
+        parts.extend(word.split('_').map(str::to_string));
+        parts.extend(split_camel_case(word));
+        for part in parts {
+            let part = part.to_lowercase();
+            if part.len() >= 3 && !STOP_WORDS.contains(&part.as_str()) && !words.contains(&part) {
+                words.push(part);
+            }
+        }
+    }
+    words
+}
+
+fn split_camel_case(word: &str) -> Vec<String> {
+    let mut parts: Vec<String> = Vec::new();
+    let mut previous_lower = false;
+    for c in word.chars() {
+        if c.is_uppercase() && previous_lower {
+            parts.push(String::new());
+        }
+        match parts.last_mut() {
+            Some(part) => part.push(c),
+            None => parts.push(c.to_string()),
+        }
+        previous_lower = c.is_lowercase();
+    }
+    if parts.len() > 1 { parts } else { Vec::new() }
+}
+
+/// How often each identifier (lowercased) occurs in a file.
+fn symbols(contents: &str) -> HashMap<String, usize> {
+    let mut symbols = HashMap::new();
+    for identifier in contents.split(|c: char| !c.is_alphanumeric() && c != '_').filter(|word| word.len() >= 3) {
+        *symbols.entry(identifier.to_lowercase()).or_default() += 1;
+    }
+    symbols
+}
+
+/// How relevant a file is: being named in the query counts most, then keywords in its name, then
+/// keywords among the identifiers of its contents, each keyword weighted by how rare it is.
+fn score(path: &str, symbols: &HashMap<String, usize>, query: &str, keywords: &[String], weights: &[f64]) -> f64 {
+    let lower_path = path.to_lowercase();
+    let name = lower_path.rsplit('/').next().unwrap_or(&lower_path);
+    let stem = name.split('.').next().unwrap_or(name);
+    let mut score = 0.0;
+    if query.contains(path) || query.split_whitespace().any(|word| word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase() == name) {
+        score += 100.0;
+    }
+    for (keyword, weight) in keywords.iter().zip(weights) {
+        if stem == keyword {
+            score += 30.0;
+        } else if name.contains(keyword.as_str()) {
+            score += 10.0;
+        }
+        // A few mentions say as much as many; a definition and a use or two is the typical case.
+        score += symbols.get(keyword).copied().unwrap_or(0).min(5) as f64 * weight;
+    }
+    score
+}
+
+/// The patterns of an `.aiignore` file, which follows the `.gitignore` syntax: `#` comments,
+/// `!` to include files again, a leading `/` to anchor a pattern at the top level and a trailing
+/// `/` for directories.
+struct IgnoreList {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    /// Matched against the whole path rather than against each part of it
+    anchored: bool,
+    directory: bool,
+}
+
+impl IgnoreList {
+    fn load(path: &Path) -> Result<IgnoreList> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(anyhow!("Could not read {}: {}", path.display(), e)),
+        };
+        let mut rules = Vec::new();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let (directory, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let pattern = Pattern::new(line.trim_start_matches('/'))
+                .map_err(|e| anyhow!("Invalid pattern '{}' in {}: {}", line, path.display(), e))?;
+            rules.push(Rule { pattern, negated, anchored, directory });
+        }
+        Ok(IgnoreList { rules })
+    }
+
+    /// Whether a path relative to the top level is ignored; the last matching rule decides.
+    fn matches(&self, path: &str) -> bool {
+        let options = MatchOptions { require_literal_separator: true, ..MatchOptions::new() };
+        let parts: Vec<&str> = path.split('/').collect();
+        let mut ignored = false;
+        for rule in &self.rules {
+            let matched = if rule.anchored {
+                // The path itself, or a directory it is in.
+                (1..=parts.len())
+                    .filter(|&end| !rule.directory || end < parts.len())
+                    .any(|end| rule.pattern.matches_with(&parts[..end].join("/"), options))
+            } else {
+                let candidates = if rule.directory { &parts[..parts.len() - 1] } else { &parts[..] };
+                candidates.iter().any(|part| rule.pattern.matches_with(part, options))
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+/// Rough credit cost per 1,000 tokens for previews; override with `[model_rates]` in the config.
+const MODEL_RATES: &[(&str, f64)] = &[
+    ("o1-preview", 150.0),
+    ("o3-mini", 11.0),
+    ("gpt-4o", 25.0),
+    ("gpt-4o-mini", 1.5),
+    ("deepseek-r1", 11.0),
+];
+const DEFAULT_RATE: f64 = 10.0;
+pub const DEFAULT_CONFIRM_ABOVE_TOKENS: usize = 8000;
+
+/// Rough credit cost per image; `[model_rates]` entries for image models are read as credits per image.
+const IMAGE_RATES: &[(&str, f64)] = &[
+    ("dall-e-2", 1000.0),
+    ("dall-e-3", 4000.0),
+    ("gpt-image-1", 5000.0),
+    ("flux-pro", 3000.0),
+    ("midjourney", 6000.0),
+];
+const DEFAULT_IMAGE_RATE: f64 = 3000.0;
+/// HD images cost about twice as much as standard ones
+const HD_FACTOR: f64 = 2.0;
+
+/// Approximates the token count of a text (about four characters per token).
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Approximates the tokens of a response of `words` words (roughly four tokens to three words).
+pub fn tokens_for_words(words: u32) -> u32 {
+    words.saturating_mul(4).div_ceil(3)
+}
+
+/// Approximates the words that fit in `tokens` tokens, at least one.
+pub fn words_for_tokens(tokens: u32) -> u32 {
+    (tokens.saturating_mul(3) / 4).max(1)
+}
+
+pub fn rate_for(model: &str, overrides: &BTreeMap<String, f64>) -> f64 {
+    overrides.get(model).copied()
+        .or_else(|| MODEL_RATES.iter().find(|(name, _)| *name == model).map(|(_, rate)| *rate))
+        .unwrap_or(DEFAULT_RATE)
+}
+
+pub fn estimate_credits(tokens: usize, model: &str, overrides: &BTreeMap<String, f64>) -> f64 {
+    tokens as f64 / 1000.0 * rate_for(model, overrides)
+}
+
+pub fn estimate_image_credits(model: &str, count: u32, quality: Option<&str>, overrides: &BTreeMap<String, f64>) -> f64 {
+    let rate = overrides.get(model).copied()
+        .or_else(|| IMAGE_RATES.iter().find(|(name, _)| *name == model).map(|(_, rate)| *rate))
+        .unwrap_or(DEFAULT_IMAGE_RATE);
+    let factor = if quality == Some("hd") { HD_FACTOR } else { 1.0 };
+    rate * factor * count as f64
+}
+
+/// Which limit an operation estimated at `credits` would break, if any: `max_per_request`, or
+/// `monthly_budget` given the `spent` credits of the month so far.
+pub fn over_limit(credits: f64, max_per_request: Option<f64>, monthly_budget: Option<f64>, spent: f64) -> Option<String> {
+    if let Some(max) = max_per_request.filter(|max| credits > *max) {
+        return Some(format!("~{:.0} credits is over max_cost_per_request ({:.0})", credits, max));
+    }
+    if let Some(budget) = monthly_budget.filter(|budget| spent + credits > *budget) {
+        return Some(format!(
+            "~{:.0} credits on top of the {:.0} spent this month is over monthly_budget ({:.0})",
+            credits, spent, budget
+        ));
+    }
+    None
+}
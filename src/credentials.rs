@@ -0,0 +1,205 @@
+use anyhow::{anyhow, Context, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Config};
+use crate::lock;
+
+const SERVICE_NAME: &str = "ai-cli";
+const USERNAME: &str = "user";
+const CREDENTIALS_FILE: &str = "credentials.json";
+/// Passphrase the credential file is encrypted with; without it, a key tied to this machine is used
+pub const PASSPHRASE_ENV: &str = "AI_CLI_PASSPHRASE";
+/// Where a machine id is found on Linux and other systems with systemd or D-Bus.
+const MACHINE_ID_FILES: &[&str] = &["/etc/machine-id", "/var/lib/dbus/machine-id"];
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Where API keys are saved: `credential_store` in the config file.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialStore {
+    /// The system keyring, falling back to the file when there is none (e.g. on a headless server)
+    #[default]
+    Keyring,
+    /// The encrypted file only
+    File,
+}
+
+/// Where a saved key was found or put.
+pub enum Location {
+    Keyring,
+    File(PathBuf),
+}
+
+/// What the encryption key of the credential file is derived from.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Secret {
+    Passphrase,
+    Machine,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CredentialFile {
+    secret: Secret,
+    /// Hex-encoded Argon2id salt, ChaCha20-Poly1305 nonce and encrypted API key
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+pub fn credentials_path() -> Result<PathBuf> {
+    Ok(config::config_dir()?.join(CREDENTIALS_FILE))
+}
+
+fn preferred_store() -> CredentialStore {
+    Config::load().map(|config| config.credential_store).unwrap_or_default()
+}
+
+/// Whether the keyring error means there is no usable keyring, rather than e.g. no entry in it.
+fn keyring_unavailable(error: &keyring::Error) -> bool {
+    matches!(error, keyring::Error::NoStorageAccess(_) | keyring::Error::PlatformFailure(_))
+}
+
+/// Whether the keyring is usable and holds an API key.
+pub fn keyring_status() -> Result<bool, keyring::Error> {
+    match keyring::Entry::new(SERVICE_NAME, USERNAME).and_then(|keyring| keyring.get_password()) {
+        Ok(_) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// The saved API key: from the keyring, or else the credential file, or only the file when the
+/// config says so.
+pub fn load() -> Result<Option<(String, Location)>> {
+    if preferred_store() == CredentialStore::Keyring {
+        if let Ok(key) = keyring::Entry::new(SERVICE_NAME, USERNAME).and_then(|keyring| keyring.get_password()) {
+            return Ok(Some((key, Location::Keyring)));
+        }
+    }
+    let path = credentials_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some((read_file(&path)?, Location::File(path))))
+}
+
+/// Saves the API key in the configured store. Without a usable keyring it goes to the
+/// credential file instead.
+pub fn save(api_key: &str) -> Result<Location> {
+    if preferred_store() == CredentialStore::Keyring {
+        match keyring::Entry::new(SERVICE_NAME, USERNAME).and_then(|keyring| keyring.set_password(api_key)) {
+            Ok(()) => return Ok(Location::Keyring),
+            Err(e) if keyring_unavailable(&e) => {
+                eprintln!("The system keyring is unavailable ({}); using the encrypted credential file instead.", e);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let path = credentials_path()?;
+    write_file(&path, api_key)?;
+    Ok(Location::File(path))
+}
+
+fn machine_id() -> Option<String> {
+    MACHINE_ID_FILES.iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|id| id.trim().to_string())
+        .find(|id| !id.is_empty())
+}
+
+/// The passphrase from the environment, or typed in when there is a terminal to ask at.
+fn passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(if confirm {
+            anyhow!("Encrypting the credential file needs a passphrase; set {}", PASSPHRASE_ENV)
+        } else {
+            anyhow!("The credential file is encrypted with a passphrase; set {} to unlock it", PASSPHRASE_ENV)
+        });
+    }
+    let mut prompt = dialoguer::Password::new().with_prompt("Passphrase for the credential file");
+    if confirm {
+        prompt = prompt.with_confirmation("Repeat the passphrase", "The passphrases don't match");
+    }
+    Ok(prompt.interact()?)
+}
+
+fn derive_key(secret: Secret, salt: &[u8], confirm: bool) -> Result<Key> {
+    let material = match secret {
+        Secret::Passphrase => passphrase(confirm)?,
+        // Tied to the user on this machine, so a copied file is useless elsewhere.
+        Secret::Machine => format!(
+            "{}:{}",
+            machine_id().ok_or_else(|| anyhow!("This machine has no machine id; set {} to use a passphrase", PASSPHRASE_ENV))?,
+            dirs::home_dir().unwrap_or_default().display()
+        ),
+    };
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(material.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Could not derive the credential file key: {}", e))?;
+    Ok(key)
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Could not read the credential file {}", path.display()))?;
+    let file: CredentialFile = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid credential file {}", path.display()))?;
+    let (salt, nonce, ciphertext) = (from_hex(&file.salt)?, from_hex(&file.nonce)?, from_hex(&file.ciphertext)?);
+    if nonce.len() != NONCE_LEN {
+        return Err(anyhow!("Invalid credential file {}", path.display()));
+    }
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(file.secret, &salt, false)?);
+    let plaintext = cipher.decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice()).map_err(|_| match file.secret {
+        Secret::Passphrase => anyhow!("Could not decrypt {}: wrong passphrase", path.display()),
+        Secret::Machine => anyhow!("Could not decrypt {}: it was saved on another machine or by another user", path.display()),
+    })?;
+    String::from_utf8(plaintext).map_err(|_| anyhow!("Invalid credential file {}", path.display()))
+}
+
+fn write_file(path: &Path, api_key: &str) -> Result<()> {
+    let secret = if std::env::var_os(PASSPHRASE_ENV).is_some() || machine_id().is_none() {
+        Secret::Passphrase
+    } else {
+        Secret::Machine
+    };
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(secret, &salt, true)?);
+    let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), api_key.as_bytes())
+        .map_err(|_| anyhow!("Could not encrypt the API key"))?;
+    let file = CredentialFile { secret, salt: to_hex(&salt), nonce: to_hex(&nonce), ciphertext: to_hex(&ciphertext) };
+
+    fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+    lock::write_private(path, serde_json::to_string_pretty(&file)?.as_bytes())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(anyhow!("Invalid hex in the credential file"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| anyhow!("Invalid hex in the credential file")))
+        .collect()
+}
@@ -0,0 +1,437 @@
+//! `ai-cli daemon`, which keeps the HTTP client, the API key and open conversations around for
+//! editor integrations and `--use-daemon`.
+//!
+//! The protocol is line-based JSON over a Unix socket. Each line a client sends is a
+//! [`Request`]; the daemon answers it with [`Event`]s, one per line: `chunk`s of the response as
+//! it streams in, then `done` (or `error`). A connection may send any number of requests, one
+//! after the other.
+
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use crate::auth::AuthManager;
+use crate::client::OneMinClient;
+use crate::error::AiCliError;
+use crate::history::{self, Turn};
+use crate::providers::redacting::Redacting;
+use crate::providers::{ChatRequest, Provider, RequestDefaults};
+use crate::redact::Redaction;
+use crate::sampling::{self, Sampling};
+use crate::{models, usage};
+
+/// Overrides where the daemon listens and clients connect
+pub const SOCKET_ENV: &str = "AI_CLI_DAEMON_SOCKET";
+const SOCKET_FILE: &str = "ai-cli.sock";
+
+#[derive(Serialize, Deserialize)]
+pub struct Request {
+    pub prompt: String,
+    /// Model to answer with (the daemon's default when omitted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_words: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(flatten)]
+    pub sampling: Sampling,
+    /// Name of a conversation to continue, started on its first use and kept open by the daemon.
+    /// Without one the prompt is sent on its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The next part of the response
+    Chunk { text: String },
+    /// The response is complete; `conversation` is the id it was stored under in local history
+    Done {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        conversation: Option<String>,
+    },
+    Error { message: String },
+}
+
+/// The socket the daemon listens on: `AI_CLI_DAEMON_SOCKET`, or `ai-cli.sock` in the runtime
+/// directory (the cache directory where there is none).
+pub fn socket_path() -> Result<PathBuf> {
+    if let Some(path) = std::env::var_os(SOCKET_ENV).filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    let dir = dirs::runtime_dir()
+        .or_else(|| dirs::cache_dir().map(|dir| dir.join("ai-cli")))
+        .ok_or_else(|| anyhow!("Could not determine a directory for the daemon socket"))?;
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir.join(SOCKET_FILE))
+}
+
+async fn write_line(writer: &mut (impl AsyncWrite + Unpin), message: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+/// What the daemon answers requests with.
+pub struct Daemon {
+    client: OneMinClient,
+    /// The provider to chat with, when it isn't 1min.ai (`client`)
+    other: Option<Box<dyn Provider>>,
+    model: String,
+    defaults: RequestDefaults,
+    model_rates: BTreeMap<String, f64>,
+    redaction: Redaction,
+    /// Reloads the API key when the API rejects it (see [`AuthManager::unattended`])
+    auth: AuthManager,
+    /// Ids of the conversations started so far, by the name clients gave them
+    conversations: Mutex<HashMap<String, String>>,
+}
+
+impl Daemon {
+    pub fn new(
+        client: OneMinClient,
+        other: Option<Box<dyn Provider>>,
+        model: String,
+        defaults: RequestDefaults,
+        model_rates: BTreeMap<String, f64>,
+        redaction: Redaction,
+        auth: AuthManager,
+    ) -> Self {
+        Daemon { client, other, model, defaults, model_rates, redaction, auth, conversations: Mutex::new(HashMap::new()) }
+    }
+
+    fn provider(&self) -> &dyn Provider {
+        self.other.as_deref().unwrap_or(&self.client)
+    }
+
+    /// Runs `call`, and once more if the API rejected the key and a new one could be loaded,
+    /// since the key may have been rotated while the daemon was running.
+    async fn with_auth<T, Fut>(&self, call: impl Fn() -> Fut) -> Result<T>
+    where
+        Fut: Future<Output = Result<T, AiCliError>>,
+    {
+        match call().await {
+            Err(e) if e.is_auth() => {
+                self.auth.recover(self.provider())?;
+                Ok(call().await?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// The id of the conversation named `name`, starting it on first use. The lock isn't held
+    /// while the conversation is created, so other requests don't wait on the API; if two
+    /// requests start the same conversation at once, the first to finish wins.
+    async fn conversation(&self, name: &str) -> Result<String> {
+        if let Some(id) = self.conversations.lock().await.get(name) {
+            return Ok(id.clone());
+        }
+
+        let id = if self.provider().hosts_conversations() {
+            self.with_auth(|| self.client.create_conversation(name)).await?
+        } else {
+            format!("{}{}", history::LOCAL_ID_PREFIX, Local::now().format("%Y%m%d%H%M%S%3f"))
+        };
+        match self.conversations.lock().await.entry(name.to_string()) {
+            Entry::Occupied(started) => Ok(started.get().clone()),
+            Entry::Vacant(entry) => {
+                history::start_conversation(&id, name)?;
+                Ok(entry.insert(id).clone())
+            }
+        }
+    }
+
+    /// Answers one request, writing the events to `writer`.
+    async fn answer(&self, request: Request, writer: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+        let provider = Redacting::new(self.provider(), self.redaction);
+        let model = request.model.as_deref().unwrap_or(&self.model);
+        models::validate(model)?;
+        let sampling = sampling::resolve(model, request.sampling, self.defaults.sampling)?;
+        let max_words = request.max_words.unwrap_or(self.defaults.max_words);
+        let max_tokens = request.max_tokens.or(self.defaults.max_tokens);
+        let conversation = match &request.conversation {
+            Some(name) => Some(self.conversation(name).await?),
+            None => None,
+        };
+        let hosted = provider.hosts_conversations();
+        let history = match &conversation {
+            Some(id) if !hosted => history::load(id)?.messages(),
+            _ => Vec::new(),
+        };
+        let prompt = match &request.system {
+            Some(system) => format!("{}\n\n{}", system, request.prompt),
+            None => request.prompt.clone(),
+        };
+
+        let mut stream = self.with_auth(|| provider.chat_stream(ChatRequest {
+            model,
+            prompt: &prompt,
+            max_words,
+            max_tokens,
+            sampling,
+            conversation_id: conversation.as_deref().filter(|_| hosted),
+            history: &history,
+        })).await?;
+        let mut response = String::new();
+        while let Some(chunk) = stream.next().await {
+            let text = chunk?;
+            response.push_str(&text);
+            write_line(writer, &Event::Chunk { text }).await?;
+        }
+
+        let usage = usage::UsageEntry::new(model, &prompt, &response, &self.model_rates);
+        usage::record(&usage)?;
+        if let Some(id) = &conversation {
+            history::record_turn(id, &Turn {
+                timestamp: Local::now(),
+                model: model.to_string(),
+                prompt: request.prompt.clone(),
+                response,
+                credits: Some(usage.credits),
+                retry_of: None,
+                interrupted: false,
+            }, request.system.as_deref())?;
+        }
+        write_line(writer, &Event::Done { conversation }).await
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
+
+    pub(super) async fn handle(daemon: Arc<Daemon>, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let result = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => daemon.answer(request, &mut writer).await,
+                Err(e) => Err(anyhow!("Invalid request: {}", e)),
+            };
+            if let Err(e) = result {
+                write_line(&mut writer, &Event::Error { message: format!("{:#}", e) }).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Listens on `path` until Ctrl-C, answering each connection's requests. The socket is
+    /// only accessible to the current user, since requests are sent with their API key.
+    pub async fn serve(daemon: Daemon, path: &Path) -> Result<()> {
+        if path.exists() {
+            if UnixStream::connect(path).await.is_ok() {
+                return Err(anyhow!("A daemon is already listening on {}", path.display()));
+            }
+            // Left behind by a daemon that didn't shut down cleanly.
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        println!("Listening on {} (Ctrl-C to stop).", path.display());
+
+        let daemon = Arc::new(daemon);
+        let result = loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        let daemon = Arc::clone(&daemon);
+                        tokio::spawn(async move {
+                            if let Err(e) = handle(daemon, stream).await {
+                                tracing::debug!("daemon connection ended: {:#}", e);
+                            }
+                        });
+                    }
+                    Err(e) => break Err(e.into()),
+                },
+                _ = tokio::signal::ctrl_c() => break Ok(()),
+            }
+        };
+        let _ = std::fs::remove_file(path);
+        result
+    }
+
+    /// Sends `request` to the daemon listening on `path`, passing each part of the response to
+    /// `on_chunk` as it arrives. Returns the id of the conversation it was stored under, if any.
+    pub async fn ask(path: &Path, request: &Request, mut on_chunk: impl FnMut(&str) -> Result<()>) -> Result<Option<String>> {
+        let stream = UnixStream::connect(path).await
+            .map_err(|e| anyhow!("Could not connect to the daemon at {} ({}); start it with `ai-cli daemon`", path.display(), e))?;
+        let (reader, mut writer) = stream.into_split();
+        write_line(&mut writer, request).await?;
+
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            match serde_json::from_str::<Event>(&line)? {
+                Event::Chunk { text } => on_chunk(&text)?,
+                Event::Done { conversation } => return Ok(conversation),
+                Event::Error { message } => return Err(anyhow!("{}", message)),
+            }
+        }
+        Err(anyhow!("The daemon closed the connection before the response was complete"))
+    }
+}
+
+#[cfg(unix)]
+pub use unix::{ask, serve};
+
+#[cfg(not(unix))]
+pub async fn serve(_daemon: Daemon, _path: &Path) -> Result<()> {
+    Err(crate::error::AiCliError::Unsupported("The daemon needs Unix sockets, which this platform lacks".to_string()).into())
+}
+
+#[cfg(not(unix))]
+pub async fn ask(_path: &Path, _request: &Request, _on_chunk: impl FnMut(&str) -> Result<()>) -> Result<Option<String>> {
+    Err(crate::error::AiCliError::Unsupported("The daemon needs Unix sockets, which this platform lacks".to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{KeySource, Recovery};
+    use crate::retry;
+    use mockito::{Matcher, ServerGuard};
+    use serde_json::json;
+
+    fn daemon(upstream: &ServerGuard, auth: AuthManager) -> Daemon {
+        usage::use_scratch_dirs();
+        retry::configure(0, 0);
+        let client = OneMinClient::new(reqwest::Client::new(), "old-key").with_base_url(upstream.url());
+        let defaults = RequestDefaults { max_words: 100, max_tokens: None, sampling: Sampling::default() };
+        Daemon::new(client, None, "gpt-4o".to_string(), defaults, BTreeMap::new(), Redaction::Off, auth)
+    }
+
+    fn failing_auth() -> AuthManager {
+        AuthManager::new(Recovery::Fail, KeySource::Env, "old-key")
+    }
+
+    fn request(prompt: &str, conversation: Option<&str>) -> Request {
+        Request {
+            prompt: prompt.to_string(),
+            model: None,
+            system: None,
+            max_words: None,
+            max_tokens: None,
+            sampling: Sampling::default(),
+            conversation: conversation.map(str::to_string),
+        }
+    }
+
+    async fn answer(daemon: &Daemon, request: Request) -> Result<Vec<Event>> {
+        let mut output = Vec::new();
+        daemon.answer(request, &mut output).await?;
+        Ok(String::from_utf8(output)?.lines().map(|line| serde_json::from_str(line).unwrap()).collect())
+    }
+
+    #[tokio::test]
+    async fn streams_chunks_then_done() {
+        let mut upstream = mockito::Server::new_async().await;
+        upstream.mock("POST", "/api/features")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::PartialJson(json!({ "model": "gpt-4o", "promptObject": { "prompt": "Hi", "maxWord": 100 } })))
+            .with_body("Hello")
+            .create_async()
+            .await;
+
+        let events = answer(&daemon(&upstream, failing_auth()), request("Hi", None)).await.unwrap();
+        assert!(matches!(&events[..], [Event::Chunk { text }, Event::Done { conversation: None }] if text == "Hello"));
+    }
+
+    #[tokio::test]
+    async fn keeps_named_conversations_open() {
+        let mut upstream = mockito::Server::new_async().await;
+        let created = upstream.mock("POST", "/api/conversations")
+            .match_body(Matcher::PartialJson(json!({ "title": "notes" })))
+            .with_body(json!({ "conversation": { "uuid": "conv-42" } }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let chats = upstream.mock("POST", "/api/features")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::PartialJson(json!({ "conversationId": "conv-42" })))
+            .with_body("Noted")
+            .expect(2)
+            .create_async()
+            .await;
+
+        let daemon = daemon(&upstream, failing_auth());
+        for prompt in ["First", "Second"] {
+            let events = answer(&daemon, request(prompt, Some("notes"))).await.unwrap();
+            assert!(matches!(events.last(), Some(Event::Done { conversation: Some(id) }) if id == "conv-42"));
+        }
+        created.assert_async().await;
+        chats.assert_async().await;
+        assert_eq!(history::load("conv-42").unwrap().turns.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reloads_a_rejected_key_and_tries_again_once() {
+        let mut upstream = mockito::Server::new_async().await;
+        upstream.mock("POST", "/api/features").match_query(Matcher::Any).match_header("API-KEY", "old-key").with_status(401).create_async().await;
+        let retried = upstream.mock("POST", "/api/features")
+            .match_query(Matcher::Any)
+            .match_header("API-KEY", "new-key")
+            .with_body("Hello again")
+            .expect(1)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("key");
+        std::fs::write(&key_file, "new-key\n").unwrap();
+        let rotated = daemon(&upstream, AuthManager::new(Recovery::Reload, KeySource::File(key_file.clone()), "old-key"));
+        let events = answer(&rotated, request("Hi", None)).await.unwrap();
+        assert!(matches!(&events[0], Event::Chunk { text } if text == "Hello again"));
+        retried.assert_async().await;
+
+        // A key that hasn't changed isn't tried again.
+        let mut upstream = mockito::Server::new_async().await;
+        let rejected = upstream.mock("POST", "/api/features").match_query(Matcher::Any).with_status(401).expect(1).create_async().await;
+        std::fs::write(&key_file, "old-key\n").unwrap();
+        let unchanged = daemon(&upstream, AuthManager::new(Recovery::Reload, KeySource::File(key_file), "old-key"));
+        let error = answer(&unchanged, request("Hi", None)).await.unwrap_err();
+        assert!(error.downcast_ref::<AiCliError>().is_some_and(AiCliError::is_auth));
+        rejected.assert_async().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn answers_each_line_of_a_connection() {
+        use std::sync::Arc;
+        use tokio::io::{AsyncBufReadExt, BufReader};
+        use tokio::net::UnixStream;
+
+        let mut upstream = mockito::Server::new_async().await;
+        upstream.mock("POST", "/api/features").match_query(Matcher::Any).with_body("Hello").create_async().await;
+        let (client, connection) = UnixStream::pair().unwrap();
+        tokio::spawn(unix::handle(Arc::new(daemon(&upstream, failing_auth())), connection));
+
+        let (reader, mut writer) = client.into_split();
+        writer.write_all(b"not json\n\n").await.unwrap();
+        write_line(&mut writer, &request("Hi", None)).await.unwrap();
+        let mut lines = BufReader::new(reader).lines();
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            events.push(serde_json::from_str::<Event>(&lines.next_line().await.unwrap().unwrap()).unwrap());
+        }
+        assert!(matches!(&events[0], Event::Error { message } if message.starts_with("Invalid request:")));
+        assert!(matches!(&events[1], Event::Chunk { text } if text == "Hello"));
+        assert!(matches!(&events[2], Event::Done { conversation: None }));
+    }
+}
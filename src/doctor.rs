@@ -0,0 +1,252 @@
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::Serialize;
+use std::io::IsTerminal;
+
+use crate::auth::{KeySource, API_KEY_ENV};
+use crate::client::OneMinClient;
+use crate::config::{self, Config};
+use crate::credentials;
+use crate::providers;
+use crate::status;
+use crate::voice;
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pass,
+    /// Works, but something is missing that some features need
+    Warn,
+    Fail,
+}
+
+/// The result of one diagnostic, with a suggested fix when it didn't pass.
+#[derive(Serialize)]
+pub struct Finding {
+    pub name: String,
+    pub outcome: Outcome,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    pub fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Finding { name: name.to_string(), outcome: Outcome::Pass, detail: detail.into(), fix: None }
+    }
+
+    pub fn warn(name: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Finding { name: name.to_string(), outcome: Outcome::Warn, detail: detail.into(), fix: Some(fix.into()) }
+    }
+
+    pub fn fail(name: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Finding { name: name.to_string(), outcome: Outcome::Fail, detail: detail.into(), fix: Some(fix.into()) }
+    }
+}
+
+/// Checks that the config file parses and names a known provider.
+pub fn check_config() -> Finding {
+    let name = "Config file";
+    let path = match config::config_path() {
+        Ok(path) => path,
+        Err(e) => return Finding::fail(name, e.to_string(), "Set HOME (or XDG_CONFIG_HOME) so the config directory can be found"),
+    };
+    if !path.exists() {
+        return Finding::pass(name, format!("none at {} (defaults in use)", path.display()));
+    }
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => return Finding::fail(name, format!("{:#}", e), format!("Fix or remove {}", path.display())),
+    };
+    let provider = config.provider.as_deref().unwrap_or(providers::ONEMIN);
+    match providers::resolve(provider, &config) {
+        Ok(_) => Finding::pass(name, format!("{} is valid", path.display())),
+        Err(e) => Finding::fail(name, format!("{:#}", e), format!("Fix the provider settings in {}", path.display())),
+    }
+}
+
+/// Checks where the API key would be stored. With `no_keyring`, it isn't looked up there at all.
+pub fn check_keyring(no_keyring: bool) -> Finding {
+    let name = "Keyring";
+    if no_keyring {
+        return Finding::pass(name, "not used (--no-keyring)");
+    }
+    let file = credentials::credentials_path().ok().filter(|path| path.exists());
+    let config = Config::load().unwrap_or_default();
+    if config.credential_store == credentials::CredentialStore::File {
+        return match file {
+            Some(path) => Finding::pass(name, format!("not used (credential_store = \"file\"), API key in {}", path.display())),
+            None => Finding::pass(name, "not used (credential_store = \"file\"), no API key stored"),
+        };
+    }
+    match (credentials::keyring_status(), file) {
+        (Ok(true), _) => Finding::pass(name, "available, API key stored"),
+        (Ok(false), _) => Finding::pass(name, "available, no API key stored"),
+        (Err(e), Some(path)) => Finding::pass(name, format!("unavailable ({}), API key in {}", e, path.display())),
+        (Err(e), None) => Finding::warn(
+            name,
+            format!("unavailable ({})", e),
+            format!("Run 'ai-cli config' to save the key in an encrypted file instead, or set {}", API_KEY_ENV),
+        ),
+    }
+}
+
+/// Reports where the API key was found, if anywhere, and returns it for the API checks.
+/// `key_file` says whether the key was to be read from `--api-key-file`.
+pub fn check_api_key(found: Result<Option<(String, KeySource)>>, key_file: bool) -> (Finding, Option<String>) {
+    let name = "API key";
+    match found {
+        Ok(Some((key, source))) => {
+            let origin = match source {
+                KeySource::File(path) => format!("from {}", path.display()),
+                KeySource::Env => format!("from {}", API_KEY_ENV),
+                KeySource::Keyring => "from the keyring".to_string(),
+                KeySource::CredentialFile(path) => format!("from {}", path.display()),
+                KeySource::Provider(_) => String::new(),
+            };
+            (Finding::pass(name, format!("found ({})", origin)), Some(key))
+        }
+        Ok(None) => (
+            Finding::fail(name, "none found", format!("Run 'ai-cli config', set {}, or pass --api-key-file", API_KEY_ENV)),
+            None,
+        ),
+        Err(e) => {
+            let fix = if key_file {
+                "Fix the API key file, or pass another one".to_string()
+            } else {
+                format!("Set {} for the credential file, or run 'ai-cli config' to save the key again", credentials::PASSPHRASE_ENV)
+            };
+            (Finding::fail(name, format!("{:#}", e), fix), None)
+        }
+    }
+}
+
+/// Probes the API endpoints as `ai-cli status` does.
+pub async fn check_api(client: &OneMinClient, api_key: Option<&str>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for check in status::run_checks(client, api_key).await {
+        let fix = if check.name == "API (authenticated)" {
+            "Run 'ai-cli config' to enter a new API key, or update the one in use".to_string()
+        } else {
+            format!("Check your connection and proxy settings (--proxy, HTTPS_PROXY) and the API base URL ({})", client.base_url())
+        };
+        findings.push(from_status_check(check, &fix));
+    }
+    findings
+}
+
+/// Checks a default provider other than 1min.ai by listing its models, which needs its API key.
+pub async fn check_provider(name: &str, config: &Config, http: Client) -> Option<Finding> {
+    if name == providers::ONEMIN {
+        return None;
+    }
+    let check_name = format!("Provider '{}'", name);
+    let result = match providers::resolve(name, config).and_then(|settings| providers::connect(&settings, http)) {
+        Ok(provider) => provider.list_models().await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    Some(match result {
+        Ok(_) => Finding::pass(&check_name, "reachable, models listed"),
+        Err(e) => Finding::fail(&check_name, format!("{:#}", e), "Check the provider's base_url and API key settings"),
+    })
+}
+
+/// Converts an endpoint probe from `ai-cli status` into a finding.
+pub fn from_status_check(check: status::Check, fix: &str) -> Finding {
+    let detail = match check.latency {
+        Some(latency) => format!("{} in {} ms", check.detail, latency.as_millis()),
+        None => check.detail,
+    };
+    if check.ok {
+        Finding::pass(check.name, detail)
+    } else {
+        Finding::fail(check.name, detail, fix)
+    }
+}
+
+/// Checks the programs behind voice output, `--tts-voice` playback and voice input.
+pub fn check_speech() -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    findings.push(if let Some(command) = voice::speech_command() {
+        Finding::pass("Voice output (-v)", format!("'{}' found", command))
+    } else if cfg!(target_os = "macos") {
+        Finding::fail("Voice output (-v)", "'say' not found", "'say' ships with macOS; check your PATH")
+    } else {
+        Finding::warn(
+            "Voice output (-v)",
+            format!("none of {} found", voice::SPEECH_COMMANDS.join(", ")),
+            "Install espeak-ng, or use --tts-voice to speak through the API instead",
+        )
+    });
+
+    let players = voice::installed_audio_players();
+    findings.push(match players.first() {
+        Some(player) => Finding::pass("Audio playback (--tts-voice)", format!("'{}' found", player)),
+        None => Finding::warn(
+            "Audio playback (--tts-voice)",
+            "no audio player found",
+            format!("Install one of {}", voice::audio_player_names().join(", ")),
+        ),
+    });
+
+    findings.push(match voice::RECORDERS.iter().find(|recorder| voice::is_installed(recorder)) {
+        Some(recorder) => Finding::pass("Voice input (--voice-input)", format!("'{}' found", recorder)),
+        None => Finding::warn(
+            "Voice input (--voice-input)",
+            "no recorder found",
+            format!("Install {}", voice::RECORDERS.join(" or ")),
+        ),
+    });
+    findings
+}
+
+/// Checks what the terminal supports: interactive use, colors and width.
+pub fn check_terminal() -> Finding {
+    let name = "Terminal";
+    let term = console::Term::stdout();
+    if !std::io::stdout().is_terminal() {
+        return Finding::pass(name, "output is not a terminal; responses are printed as plain text");
+    }
+    let colors = term.features().colors_supported();
+    let size = term.size_checked().map(|(rows, cols)| format!("{}x{}", cols, rows)).unwrap_or_else(|| "unknown size".to_string());
+    if colors {
+        Finding::pass(name, format!("{}, colors supported", size))
+    } else {
+        Finding::warn(
+            name,
+            format!("{}, no color support (TERM={})", size, std::env::var("TERM").unwrap_or_default()),
+            "Set TERM to e.g. xterm-256color, or use --plain",
+        )
+    }
+}
+
+pub fn print_report(findings: &[Finding]) {
+    let name_width = findings.iter().map(|f| f.name.len()).max().unwrap_or(0).max("CHECK".len());
+    println!("{:<name_width$}  {:<6}  DETAIL", "CHECK", "RESULT");
+    for finding in findings {
+        let outcome = match finding.outcome {
+            Outcome::Pass => "ok",
+            Outcome::Warn => "warn",
+            Outcome::Fail => "FAIL",
+        };
+        println!("{:<name_width$}  {:<6}  {}", finding.name, outcome, finding.detail);
+        if let Some(fix) = &finding.fix {
+            println!("{:<name_width$}  {:<6}  -> {}", "", "", fix);
+        }
+    }
+}
+
+/// Prints the findings, as JSON with `json`, and fails if any check did.
+pub fn report(findings: &[Finding], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(findings)?);
+    } else {
+        print_report(findings);
+    }
+    let failed = findings.iter().filter(|f| f.outcome == Outcome::Fail).count();
+    if failed > 0 {
+        return Err(anyhow!("{} of {} checks failed", failed, findings.len()));
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use indicatif::ProgressBar;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::providers::Provider;
+use crate::ui::show_progress;
+
+/// Inputs embedded per request
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+
+/// How `ai-cli embed` writes its output.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Json,
+    Ndjson,
+}
+
+/// One text to embed, and where it came from.
+pub struct Input {
+    /// `file:line`, the file name for a whole file, or `arg:<n>` for a command-line argument
+    pub source: String,
+    pub text: String,
+}
+
+/// An embedded input, as written by `ai-cli embed`.
+#[derive(Serialize)]
+pub struct Embedding {
+    pub index: usize,
+    pub source: String,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+/// Reads the inputs from `files` (`-` is stdin): one per non-empty line, or one per file when
+/// `whole` is set.
+pub fn read_inputs(files: &[String], whole: bool) -> Result<Vec<Input>> {
+    let mut inputs = Vec::new();
+    for file in files {
+        let (label, contents) = if file == "-" {
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents)?;
+            ("stdin", contents)
+        } else {
+            let contents = fs::read_to_string(Path::new(file)).map_err(|e| anyhow!("Could not read {}: {}", file, e))?;
+            (file.as_str(), contents)
+        };
+        if whole {
+            if !contents.trim().is_empty() {
+                inputs.push(Input { source: label.to_string(), text: contents });
+            }
+            continue;
+        }
+        for (index, line) in contents.lines().enumerate() {
+            if !line.trim().is_empty() {
+                inputs.push(Input { source: format!("{}:{}", label, index + 1), text: line.to_string() });
+            }
+        }
+    }
+    Ok(inputs)
+}
+
+/// Embeds every text, `batch_size` per request, calling `progress` with how many are done.
+pub async fn embed_all(
+    provider: &dyn Provider,
+    model: &str,
+    texts: &[String],
+    batch_size: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<Vec<f32>>> {
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(batch_size.max(1)) {
+        embeddings.extend(provider.embed(model, batch).await?);
+        progress(embeddings.len());
+    }
+    Ok(embeddings)
+}
+
+/// Embeds the inputs and writes them with their vectors, as a JSON array or one object per line.
+pub async fn embed_inputs(
+    provider: &dyn Provider,
+    inputs: Vec<Input>,
+    model: &str,
+    format: Format,
+    batch_size: usize,
+    output: Option<&Path>,
+) -> Result<()> {
+    if inputs.is_empty() {
+        return Err(anyhow!("There is no text to embed"));
+    }
+    let texts: Vec<String> = inputs.iter().map(|input| input.text.clone()).collect();
+    let progress = (show_progress() && texts.len() > batch_size).then(|| ProgressBar::new(texts.len() as u64));
+    let vectors = embed_all(provider, model, &texts, batch_size, |done| {
+        if let Some(progress) = &progress {
+            progress.set_position(done as u64);
+        }
+    }).await?;
+    if let Some(progress) = progress {
+        progress.finish_and_clear();
+    }
+
+    let embedded: Vec<Embedding> = inputs.into_iter()
+        .zip(vectors)
+        .enumerate()
+        .map(|(index, (input, embedding))| Embedding { index, source: input.source, text: input.text, embedding })
+        .collect();
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    match format {
+        Format::Json => writeln!(out, "{}", serde_json::to_string(&embedded)?)?,
+        Format::Ndjson => {
+            for embedding in &embedded {
+                writeln!(out, "{}", serde_json::to_string(embedding)?)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use reqwest::{Response, StatusCode};
+use serde_json::Value;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::retry;
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_API_ERROR: i32 = 2;
+pub const EXIT_AUTH: i32 = 3;
+pub const EXIT_QUOTA_EXCEEDED: i32 = 4;
+pub const EXIT_INVALID_MODEL: i32 = 5;
+pub const EXIT_CONTENT_POLICY: i32 = 6;
+pub const EXIT_SERVER_ERROR: i32 = 7;
+pub const EXIT_RATE_LIMITED: i32 = 8;
+pub const EXIT_NETWORK: i32 = 9;
+pub const EXIT_IO: i32 = 10;
+pub const EXIT_INPUT_REQUIRED: i32 = 11;
+pub const EXIT_TIMED_OUT: i32 = 12;
+pub const EXIT_BUDGET_EXCEEDED: i32 = 13;
+pub const EXIT_SECRETS_FOUND: i32 = 14;
+/// What shells report for a process ended by Ctrl-C
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Errors returned by [`crate::client::OneMinClient`] and the other [`crate::providers`].
+#[derive(Debug, Error)]
+pub enum AiCliError {
+    #[error("Authentication failed: {0}")]
+    Auth(String),
+    #[error("Rate limited by the API{}", match retry_after {
+        Some(delay) => format!(", retry after {}s", delay.as_secs()),
+        None => ". Try again later.".to_string(),
+    })]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("Out of credits or quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("Invalid model: {0}. Run 'ai-cli models' to see available models.")]
+    InvalidModel(String),
+    #[error("Request rejected by the content policy: {0}")]
+    ContentPolicy(String),
+    /// Any other error response; `code` is the provider's error code, when it sends one
+    #[error("{}{status} - {message}", if status.is_server_error() { "The API is having problems: " } else { "" })]
+    ApiError { status: StatusCode, code: Option<String>, message: String },
+    #[error("Network error: {0}")]
+    Network(reqwest::Error),
+    /// The API answered successfully but not in the expected shape
+    #[error("Unexpected API response: {0}")]
+    InvalidResponse(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// A prompt or confirmation was needed while running non-interactively
+    #[error("{0}")]
+    InputRequired(String),
+    /// The selected provider doesn't offer the requested feature
+    #[error("{0}")]
+    Unsupported(String),
+    /// Ctrl-C cancelled the request
+    #[error("Interrupted")]
+    Interrupted,
+    /// A long-running job (e.g. video generation) didn't finish in time
+    #[error("Timed out: {0}")]
+    TimedOut(String),
+    /// An operation would break `max_cost_per_request` or `monthly_budget`
+    #[error("Over budget: {0}")]
+    BudgetExceeded(String),
+    /// A prompt contained secrets while `redaction` is `block`
+    #[error("Not sent: {0}")]
+    SecretsFound(String),
+}
+
+/// Pulls the error code and human-readable message out of the provider's error body.
+///
+/// The API answers with `{"message": "..."}` (sometimes prefixed with a numeric code, e.g.
+/// `"400 Invalid model"`) or an OpenAI-style `{"error": {"message": "...", "code": "..."}}`.
+fn extract_error(body: &str) -> (Option<String>, String) {
+    let json = match serde_json::from_str::<Value>(body) {
+        Ok(json) => json,
+        Err(_) => return (None, body.trim().to_string()),
+    };
+
+    let code = json.get("code")
+        .or_else(|| json.get("error").and_then(|e| e.get("code")))
+        .and_then(|c| match c {
+            Value::String(code) => Some(code.clone()),
+            Value::Number(code) => Some(code.to_string()),
+            _ => None,
+        });
+
+    let message = json.get("message")
+        .or_else(|| json.get("error").and_then(|e| e.get("message")))
+        .or_else(|| json.get("error"))
+        .and_then(|m| m.as_str());
+
+    match message {
+        Some(message) => match message.split_once(' ') {
+            Some((prefix, rest)) if prefix.parse::<u32>().is_ok() => (code.or(Some(prefix.to_string())), rest.to_string()),
+            _ => (code, message.to_string()),
+        },
+        None => (code, body.trim().to_string()),
+    }
+}
+
+impl AiCliError {
+    /// Classifies an error response by its status code first, and then, for client errors, by
+    /// its error body. Rate limits and server errors are never taken for anything else, so they
+    /// stay retryable whatever their bodies say.
+    pub fn from_status(status: StatusCode, retry_after: Option<Duration>, body: &str) -> AiCliError {
+        let (code, message) = extract_error(body);
+        let lower = message.to_lowercase();
+        let mentions_quota = ["credit", "quota", "insufficient", "balance"].iter().any(|k| lower.contains(k));
+
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => AiCliError::RateLimited { retry_after },
+            _ if status.is_server_error() => AiCliError::ApiError { status, code, message },
+            StatusCode::PAYMENT_REQUIRED => AiCliError::QuotaExceeded(message),
+            StatusCode::BAD_REQUEST | StatusCode::FORBIDDEN if mentions_quota => AiCliError::QuotaExceeded(message),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => AiCliError::Auth(message),
+            _ if lower.contains("model")
+                && ["invalid", "not found", "not supported", "unsupported", "unknown", "does not exist"].iter().any(|k| lower.contains(k)) =>
+            {
+                AiCliError::InvalidModel(message)
+            }
+            _ if ["content policy", "safety", "moderation", "flagged"].iter().any(|k| lower.contains(k)) => {
+                AiCliError::ContentPolicy(message)
+            }
+            _ => AiCliError::ApiError { status, code, message },
+        }
+    }
+
+    /// Consumes an unsuccessful response and classifies it.
+    pub async fn from_response(response: Response) -> AiCliError {
+        let status = response.status();
+        let retry_after = retry::retry_after(&response);
+        match response.text().await {
+            Ok(body) => AiCliError::from_status(status, retry_after, &body),
+            Err(e) => e.into(),
+        }
+    }
+
+    pub fn is_auth(&self) -> bool {
+        matches!(self, AiCliError::Auth(_))
+    }
+
+    /// Whether trying the same request again later might succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AiCliError::RateLimited { .. } | AiCliError::Network(_) => true,
+            AiCliError::ApiError { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AiCliError::Auth(_) => EXIT_AUTH,
+            AiCliError::RateLimited { .. } => EXIT_RATE_LIMITED,
+            AiCliError::QuotaExceeded(_) => EXIT_QUOTA_EXCEEDED,
+            AiCliError::InvalidModel(_) => EXIT_INVALID_MODEL,
+            AiCliError::ContentPolicy(_) => EXIT_CONTENT_POLICY,
+            AiCliError::ApiError { status, .. } if status.is_server_error() => EXIT_SERVER_ERROR,
+            AiCliError::ApiError { .. } | AiCliError::InvalidResponse(_) => EXIT_API_ERROR,
+            AiCliError::Network(_) => EXIT_NETWORK,
+            AiCliError::Io(_) => EXIT_IO,
+            AiCliError::InputRequired(_) => EXIT_INPUT_REQUIRED,
+            AiCliError::Unsupported(_) => EXIT_FAILURE,
+            AiCliError::Interrupted => EXIT_INTERRUPTED,
+            AiCliError::TimedOut(_) => EXIT_TIMED_OUT,
+            AiCliError::BudgetExceeded(_) => EXIT_BUDGET_EXCEEDED,
+            AiCliError::SecretsFound(_) => EXIT_SECRETS_FOUND,
+        }
+    }
+}
+
+impl From<reqwest::Error> for AiCliError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            AiCliError::InvalidResponse(error.to_string())
+        } else {
+            AiCliError::Network(error)
+        }
+    }
+}
+
+/// Maps an error to the process exit code, so scripts can branch on the failure type.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    error.chain()
+        .find_map(|cause| cause.downcast_ref::<AiCliError>())
+        .map(AiCliError::exit_code)
+        .unwrap_or(EXIT_FAILURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(status: u16, body: &str) -> AiCliError {
+        AiCliError::from_status(StatusCode::from_u16(status).unwrap(), None, body)
+    }
+
+    #[test]
+    fn classifies_rate_limits_and_server_errors_by_status_alone() {
+        assert!(matches!(
+            AiCliError::from_status(StatusCode::TOO_MANY_REQUESTS, Some(Duration::from_secs(7)), r#"{"message": "Invalid model: quota"}"#),
+            AiCliError::RateLimited { retry_after: Some(delay) } if delay == Duration::from_secs(7)
+        ));
+        for status in [500, 502, 503] {
+            let error = classify(status, r#"{"message": "insufficient credits for model gpt-4o, not found"}"#);
+            assert!(matches!(error, AiCliError::ApiError { .. }), "{}: {:?}", status, error);
+            assert!(error.is_transient());
+            assert_eq!(error.exit_code(), EXIT_SERVER_ERROR);
+        }
+    }
+
+    #[test]
+    fn classifies_quota_and_auth_errors() {
+        assert!(matches!(classify(402, "Payment required"), AiCliError::QuotaExceeded(_)));
+        assert!(matches!(classify(400, r#"{"message": "Insufficient credit balance"}"#), AiCliError::QuotaExceeded(_)));
+        assert!(matches!(classify(403, r#"{"message": "Monthly quota exceeded"}"#), AiCliError::QuotaExceeded(_)));
+        assert!(matches!(classify(403, r#"{"message": "Forbidden"}"#), AiCliError::Auth(_)));
+        assert!(matches!(classify(401, r#"{"message": "Invalid credit card on file"}"#), AiCliError::Auth(_)));
+    }
+
+    #[test]
+    fn classifies_other_client_errors_by_their_message() {
+        assert!(matches!(classify(400, r#"{"message": "400 Model gpt-9 does not exist"}"#), AiCliError::InvalidModel(m) if m == "Model gpt-9 does not exist"));
+        assert!(matches!(classify(404, r#"{"error": {"message": "The model `x` is not supported"}}"#), AiCliError::InvalidModel(_)));
+        assert!(matches!(classify(400, r#"{"message": "Prompt was flagged by moderation"}"#), AiCliError::ContentPolicy(_)));
+        assert!(!classify(400, "flagged").is_transient());
+    }
+
+    #[test]
+    fn keeps_the_code_and_message_of_other_errors() {
+        match classify(422, r#"{"error": {"message": "Bad prompt", "code": "invalid_prompt"}}"#) {
+            AiCliError::ApiError { status, code, message } => {
+                assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+                assert_eq!(code.as_deref(), Some("invalid_prompt"));
+                assert_eq!(message, "Bad prompt");
+            }
+            error => panic!("unexpected {:?}", error),
+        }
+        match classify(400, "  not json  ") {
+            AiCliError::ApiError { code: None, message, .. } => assert_eq!(message, "not json"),
+            error => panic!("unexpected {:?}", error),
+        }
+    }
+
+    #[test]
+    fn finds_the_exit_code_behind_context() {
+        let error = anyhow::Error::new(AiCliError::Auth("no".to_string())).context("Could not chat");
+        assert_eq!(exit_code(&error), EXIT_AUTH);
+        assert_eq!(exit_code(&anyhow::anyhow!("plain")), EXIT_FAILURE);
+    }
+}
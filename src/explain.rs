@@ -0,0 +1,169 @@
+use anyhow::{anyhow, Context, Result};
+use clap_complete::Shell;
+use dialoguer::Confirm;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use crate::ui::ensure_interactive;
+
+const LAST_COMMAND_FILE: &str = "last_command";
+/// Only the end of long output is sent; that's where errors usually are.
+const MAX_OUTPUT_BYTES: usize = 8 * 1024;
+
+/// A command that was run, with everything it printed.
+pub struct CommandRun {
+    pub command: String,
+    pub stdout: String,
+    pub stderr: String,
+    /// Exit code, or `None` when the command was killed by a signal
+    pub code: Option<i32>,
+}
+
+fn last_command_path() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| anyhow!("Could not determine the local data directory"))?
+        .join("ai-cli");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(LAST_COMMAND_FILE))
+}
+
+/// The shell snippet that records each command line for `explain --last`. Commands starting
+/// with `ai-cli explain` are not recorded, so `--last` keeps pointing at the command before it.
+pub fn hook_script(shell: Shell) -> Result<String> {
+    let path = last_command_path()?.display().to_string().replace('\'', r"'\''");
+    match shell {
+        Shell::Bash => Ok(format!(
+            "__ai_cli_record_last() {{\n\
+             \x20   local cmd\n\
+             \x20   cmd=$(HISTTIMEFORMAT= history 1 | sed 's/^ *[0-9]* *//')\n\
+             \x20   case \"$cmd\" in \"ai-cli explain\"*) ;; *) printf '%s\\n' \"$cmd\" > '{path}' ;; esac\n\
+             }}\n\
+             PROMPT_COMMAND=\"__ai_cli_record_last${{PROMPT_COMMAND:+; $PROMPT_COMMAND}}\"\n"
+        )),
+        Shell::Zsh => Ok(format!(
+            "__ai_cli_record_last() {{\n\
+             \x20   [[ \"$1\" == \"ai-cli explain\"* ]] || print -r -- \"$1\" > '{path}'\n\
+             }}\n\
+             autoload -Uz add-zsh-hook\n\
+             add-zsh-hook preexec __ai_cli_record_last\n"
+        )),
+        Shell::Fish => Ok(format!(
+            "function __ai_cli_record_last --on-event fish_preexec\n\
+             \x20   string match -q 'ai-cli explain*' -- $argv[1]; or printf '%s\\n' $argv[1] > '{path}'\n\
+             end\n"
+        )),
+        other => Err(anyhow!("No history hook is available for {}; use bash, zsh or fish", other)),
+    }
+}
+
+/// The previous command line recorded by the shell hook.
+pub fn last_command() -> Result<String> {
+    let path = last_command_path()?;
+    let command = fs::read_to_string(&path).unwrap_or_default().trim().to_string();
+    if command.is_empty() {
+        return Err(anyhow!(
+            "No previous command recorded. Install the shell hook first, e.g. add\n  \
+             eval \"$(ai-cli explain --hook bash)\"\nto your ~/.bashrc"
+        ));
+    }
+    Ok(command)
+}
+
+fn finish(command: String, output: Output) -> CommandRun {
+    CommandRun {
+        command,
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        code: output.status.code(),
+    }
+}
+
+/// Runs a program with arguments, capturing its output.
+pub fn run(args: &[String]) -> Result<CommandRun> {
+    let (program, rest) = args.split_first().ok_or_else(|| anyhow!("No command given"))?;
+    let command = args.join(" ");
+    match Command::new(program).args(rest).output() {
+        Ok(output) => Ok(finish(command, output)),
+        // A command that can't even start is worth explaining too.
+        Err(e) => Ok(CommandRun { command, stdout: String::new(), stderr: format!("{}: {}", program, e), code: None }),
+    }
+}
+
+/// Runs a command line through the user's shell, capturing its output.
+pub fn run_shell(command: &str) -> Result<CommandRun> {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+    let output = Command::new(&shell)
+        .arg("-c")
+        .arg(command)
+        .output()
+        .with_context(|| format!("Could not run {}", shell))?;
+    Ok(finish(command.to_string(), output))
+}
+
+fn tail(text: &str) -> String {
+    if text.len() <= MAX_OUTPUT_BYTES {
+        return text.trim_end().to_string();
+    }
+    let mut start = text.len() - MAX_OUTPUT_BYTES;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("[... earlier output omitted]\n{}", text[start..].trim_end())
+}
+
+/// Builds the prompt asking the model to explain what happened and how to fix it.
+pub fn explain_prompt(run: &CommandRun) -> String {
+    let status = match run.code {
+        Some(code) => format!("exited with status {}", code),
+        None => "did not exit normally".to_string(),
+    };
+    let os = std::env::consts::OS;
+
+    let mut prompt = format!(
+        "I ran this command on {} and it {}:\n\n```\n$ {}\n```\n\n",
+        os, status, run.command
+    );
+    for (name, text) in [("stdout", &run.stdout), ("stderr", &run.stderr)] {
+        if !text.trim().is_empty() {
+            prompt.push_str(&format!("{}:\n```\n{}\n```\n\n", name, tail(text)));
+        }
+    }
+    prompt.push_str(if run.code == Some(0) {
+        "Explain what the command did and what its output means."
+    } else {
+        "Explain why it failed and suggest how to fix it, with the corrected command if there is one."
+    });
+    prompt
+}
+
+/// Runs the command to explain and builds the prompt from its output, first printing the output
+/// when `echo` is set. Returns `None` if the
+/// user declines to re-run the previous shell command.
+pub fn prepare(command: &[String], last: bool, echo: bool) -> Result<Option<String>> {
+    let run = if last {
+        let command = last_command()?;
+        // Shell history can hold anything, so this is always asked, even with --yes.
+        ensure_interactive("Re-running the previous command", "give the command to explain after `--` instead")?;
+        let confirmed = Confirm::new()
+            .with_prompt(format!("Re-run `{}` to capture its output?", command))
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            return Ok(None);
+        }
+        run_shell(&command)?
+    } else if command.is_empty() {
+        return Err(anyhow!("Give the command to explain after `--`, or use --last"));
+    } else {
+        run(command)?
+    };
+
+    if echo {
+        print!("{}", run.stdout);
+        eprint!("{}", run.stderr);
+        io::stdout().flush()?;
+    }
+    Ok(Some(explain_prompt(&run)))
+}
@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Result};
+use dialoguer::{Editor, MultiSelect};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::history::{ConversationRecord, Turn};
+
+const HTML_STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:50rem;margin:2rem auto;padding:0 1rem;line-height:1.5}\
+    .meta{color:#666}.turn{border-top:1px solid #ddd;padding:1rem 0}.time{color:#666;font-size:.9em}\
+    .prompt,.response{white-space:pre-wrap}.prompt{font-weight:600}";
+
+fn render_turn(turn: &Turn) -> String {
+    format!(
+        "\n## {} ({})\n\n**You:** {}\n\n**AI:** {}\n",
+        turn.timestamp.format("%Y-%m-%d %H:%M:%S"),
+        turn.model,
+        turn.prompt,
+        turn.response.trim_end()
+    )
+}
+
+pub fn render_markdown(record: &ConversationRecord) -> String {
+    let mut out = format!("# {}\n\n", record.title);
+    out.push_str(&format!("- Conversation: `{}`\n", record.id));
+    out.push_str(&format!("- Created: {}\n", record.created_at.format("%Y-%m-%d %H:%M:%S")));
+    if !record.tags.is_empty() {
+        out.push_str(&format!("- Tags: {}\n", record.tags.join(", ")));
+    }
+
+    for turn in &record.turns {
+        out.push_str(&render_turn(turn));
+    }
+
+    out
+}
+
+pub fn render_json(record: &ConversationRecord) -> Result<String> {
+    Ok(serde_json::to_string_pretty(record)?)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders a standalone HTML page, keeping prompts and responses as preformatted text.
+pub fn render_html(record: &ConversationRecord) -> String {
+    let title = escape_html(&record.title);
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        title, HTML_STYLE, title
+    );
+    out.push_str(&format!(
+        "<p class=\"meta\">Conversation <code>{}</code>, created {}",
+        escape_html(&record.id),
+        record.created_at.format("%Y-%m-%d %H:%M:%S")
+    ));
+    if !record.tags.is_empty() {
+        out.push_str(&format!(", tags: {}", escape_html(&record.tags.join(", "))));
+    }
+    out.push_str("</p>\n");
+
+    for turn in &record.turns {
+        out.push_str(&format!(
+            "<section class=\"turn\">\n<p class=\"time\">{} ({})</p>\n<p class=\"prompt\">{}</p>\n<div class=\"response\">{}</div>\n</section>\n",
+            turn.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            escape_html(&turn.model),
+            escape_html(&turn.prompt),
+            escape_html(turn.response.trim_end())
+        ));
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Appends one exchange to a Markdown transcript, as `--save` does after every response.
+pub fn append_turn(path: &Path, turn: &Turn) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(render_turn(turn).as_bytes())?;
+    Ok(())
+}
+
+/// Where `--save-response` writes responses when neither it nor `responses_dir` in the config
+/// file names a directory.
+pub fn default_responses_dir() -> Result<PathBuf> {
+    let documents = dirs::document_dir()
+        .or_else(dirs::home_dir)
+        .ok_or_else(|| anyhow!("Could not determine the documents directory"))?;
+    Ok(documents.join("ai-cli"))
+}
+
+/// Renders one exchange as a Markdown note, as `--save-response` writes it: YAML front matter
+/// with the model, conversation and options the response was generated with, then the prompt
+/// and the response.
+pub fn render_response(turn: &Turn, conversation: &str, options: &[(&str, String)]) -> String {
+    // JSON strings are valid YAML, and quote whatever a prompt may contain.
+    let quote = |text: &str| serde_json::to_string(text).expect("strings serialize");
+    let first_line = turn.prompt.lines().find(|line| !line.trim().is_empty()).unwrap_or("");
+    let mut out = String::from("---\n");
+    out.push_str(&format!("date: {}\n", turn.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)));
+    out.push_str(&format!("model: {}\n", quote(&turn.model)));
+    out.push_str(&format!("conversation: {}\n", quote(conversation)));
+    out.push_str(&format!("prompt: {}\n", quote(first_line.trim())));
+    if !options.is_empty() {
+        out.push_str("options:\n");
+        for (name, value) in options {
+            out.push_str(&format!("  {}: {}\n", name, quote(value)));
+        }
+    }
+    out.push_str("---\n\n");
+    out.push_str(&format!("## Prompt\n\n{}\n\n## Response\n\n{}\n", turn.prompt.trim(), turn.response.trim_end()));
+    out
+}
+
+fn turn_label(index: usize, prompt: &str) -> String {
+    let first_line = prompt.lines().next().unwrap_or("");
+    let preview: String = first_line.chars().take(60).collect();
+    if preview.len() < first_line.len() || prompt.lines().count() > 1 {
+        format!("{}. {}...", index + 1, preview)
+    } else {
+        format!("{}. {}", index + 1, preview)
+    }
+}
+
+/// Lets the user choose which turns to keep, returning a redacted copy of the record.
+pub fn redact_turns(record: &ConversationRecord) -> Result<ConversationRecord> {
+    let labels: Vec<String> = record.turns.iter()
+        .enumerate()
+        .map(|(i, turn)| turn_label(i, &turn.prompt))
+        .collect();
+    let defaults = vec![true; labels.len()];
+
+    let keep = MultiSelect::new()
+        .with_prompt("Select the turns to keep (space to toggle, enter to confirm)")
+        .items(&labels)
+        .defaults(&defaults)
+        .interact()?;
+
+    let mut redacted = record.clone();
+    redacted.turns = keep.into_iter().map(|i| record.turns[i].clone()).collect();
+    Ok(redacted)
+}
+
+/// Opens the rendered markdown in `$EDITOR` and returns the edited text.
+pub fn redact_in_editor(markdown: &str) -> Result<String> {
+    Editor::new()
+        .extension(".md")
+        .edit(markdown)?
+        .ok_or_else(|| anyhow!("Export aborted: editor closed without saving"))
+}
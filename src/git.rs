@@ -0,0 +1,163 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// Largest diff sent to the model; bigger diffs are cut off with a note.
+const MAX_DIFF_BYTES: usize = 60 * 1024;
+/// Git's well-known empty tree, used to diff against when amending a root commit.
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+const COMMIT_PROMPT: &str = "Write a git commit message for the staged changes below. \
+    Use a concise subject line in the imperative mood, at most 72 characters, then a blank line and a \
+    short body explaining what changed and why if the change isn't trivial. \
+    Respond with only the commit message, with no code fences or commentary.";
+
+const CONVENTIONAL_PROMPT: &str = "The subject line must follow the Conventional Commits format: \
+    `type(optional scope): description`, where type is one of feat, fix, docs, style, refactor, perf, \
+    test, build, ci or chore. Mark breaking changes with `!` after the type and a `BREAKING CHANGE:` footer.";
+
+fn git_output(args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .context("Could not run git")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The staged diff. When amending, the diff is taken against the parent of HEAD so it covers
+/// everything the amended commit will contain.
+pub fn staged_diff(amend: bool) -> Result<String> {
+    let diff = if amend {
+        let base = match git_output(&["rev-parse", "--verify", "--quiet", "HEAD^"]) {
+            Ok(parent) => parent.trim().to_string(),
+            Err(_) => EMPTY_TREE.to_string(),
+        };
+        git_output(&["diff", "--cached", &base])?
+    } else {
+        git_output(&["diff", "--cached"])?
+    };
+
+    if diff.trim().is_empty() {
+        return Err(anyhow!("Nothing is staged; stage changes with `git add` first"));
+    }
+    Ok(diff)
+}
+
+/// Which changes `ai-cli review` looks at.
+pub enum DiffTarget<'a> {
+    /// Everything not yet committed, staged or not
+    Uncommitted,
+    Staged,
+    /// The current branch's changes since it diverged from this one
+    Branch(&'a str),
+    /// A commit range such as `HEAD~3..HEAD`, or a single commit
+    Range(&'a str),
+}
+
+/// The diff of `target`, failing when there are no changes.
+pub fn diff(target: &DiffTarget) -> Result<String> {
+    let diff = match target {
+        DiffTarget::Uncommitted => git_output(&["diff", "HEAD"])?,
+        DiffTarget::Staged => git_output(&["diff", "--cached"])?,
+        DiffTarget::Branch(base) => git_output(&["diff", &format!("{}...HEAD", base)])?,
+        DiffTarget::Range(range) if range.contains("..") => git_output(&["diff", range])?,
+        // A single commit is reviewed against its parent.
+        DiffTarget::Range(commit) => git_output(&["show", "--format=", commit])?,
+    };
+    if diff.trim().is_empty() {
+        return Err(anyhow!("There are no changes to review"));
+    }
+    Ok(diff)
+}
+
+/// The branch a pull request goes into when none is given: the remote's default branch, or `main`.
+pub fn default_base() -> String {
+    git_output(&["symbolic-ref", "--quiet", "--short", "refs/remotes/origin/HEAD"])
+        .ok()
+        .and_then(|head| head.trim().strip_prefix("origin/").map(str::to_string))
+        .unwrap_or_else(|| "main".to_string())
+}
+
+/// The subjects and bodies of the commits on the current branch since it left `base`, oldest first.
+pub fn branch_log(base: &str) -> Result<String> {
+    git_output(&["log", "--reverse", "--format=- %s%n%w(0,2,2)%b", &format!("{}..HEAD", base)])
+}
+
+/// The top-level directory of the repository.
+pub fn toplevel() -> Result<std::path::PathBuf> {
+    Ok(git_output(&["rev-parse", "--show-toplevel"])?.trim().into())
+}
+
+/// The files of the repository at `root`, tracked or not, that aren't ignored, relative to it.
+pub fn list_files(root: &Path) -> Result<Vec<String>> {
+    let root = root.display().to_string();
+    let output = git_output(&["-C", &root, "ls-files", "--cached", "--others", "--exclude-standard"])?;
+    let mut files: Vec<String> = output.lines().map(str::to_string).collect();
+    // A file with merge conflicts is listed once per stage.
+    files.dedup();
+    Ok(files)
+}
+
+/// Builds the prompt asking for a commit message, truncating very large diffs.
+pub fn commit_prompt(diff: &str, conventional: bool) -> String {
+    let mut prompt = COMMIT_PROMPT.to_string();
+    if conventional {
+        prompt.push(' ');
+        prompt.push_str(CONVENTIONAL_PROMPT);
+    }
+
+    prompt.push_str("\n\n");
+    prompt.push_str(&fenced_diff(diff));
+    prompt
+}
+
+/// The diff in a code fence, truncated when very large.
+pub fn fenced_diff(diff: &str) -> String {
+    let mut end = diff.len().min(MAX_DIFF_BYTES);
+    while !diff.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut fenced = format!("```diff\n{}", &diff[..end]);
+    if end < diff.len() {
+        fenced.push_str("\n[diff truncated]");
+    }
+    fenced.push_str("\n```");
+    fenced
+}
+
+/// Cleans up the model's answer: strips surrounding code fences and blank lines.
+pub fn clean_message(response: &str) -> String {
+    let trimmed = response.trim();
+    let unfenced = match trimmed.strip_prefix("```") {
+        Some(rest) => rest
+            .split_once('\n')
+            .map(|(_, body)| body.trim_end().trim_end_matches("```"))
+            .unwrap_or(rest),
+        None => trimmed,
+    };
+    unfenced.trim().to_string()
+}
+
+/// Runs `git commit` with the message, amending HEAD when asked.
+pub fn commit(message: &str, amend: bool) -> Result<()> {
+    let mut command = Command::new("git");
+    command.args(["commit", "-m", message]);
+    if amend {
+        command.arg("--amend");
+    }
+
+    let status = command.status().context("Could not run git")?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("git commit failed with {}", status))
+    }
+}
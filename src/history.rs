@@ -0,0 +1,426 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::lock;
+use crate::providers::Message;
+
+const HISTORY_DIR: &str = "conversations";
+/// Prefix of the ids of conversations kept only in local history
+pub const LOCAL_ID_PREFIX: &str = "local-";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConversationRecord {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub model: String,
+    /// System prompt the latest turn was sent with, restored when the conversation is resumed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub created_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub turns: Vec<Turn>,
+    /// The uploaded document a CHAT_WITH_PDF conversation is about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document: Option<Document>,
+    /// Named points in the conversation, each the number of turns recorded when it was set
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checkpoints: BTreeMap<String, usize>,
+    /// Id of the conversation this one was branched or compacted from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branched_from: Option<String>,
+    /// Summary of the exchanges that came before `turns`, when it was compacted from a longer one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+/// A document uploaded to the asset store for chatting about it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Document {
+    /// File name it was uploaded from
+    pub name: String,
+    /// Asset path returned by the upload
+    pub asset: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Turn {
+    pub timestamp: DateTime<Local>,
+    pub model: String,
+    pub prompt: String,
+    pub response: String,
+    #[serde(default)]
+    pub credits: Option<f64>,
+    /// Index of the turn this one regenerates, when it is a retry of an earlier prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_of: Option<usize>,
+    /// Set when Ctrl-C cut the response short, so `response` is only its beginning
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+}
+
+#[derive(Serialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub title: String,
+    pub model: String,
+    pub turns: usize,
+    pub last_activity: DateTime<Local>,
+    pub tags: Vec<String>,
+    pub estimated_cost: Option<f64>,
+}
+
+impl ConversationRecord {
+    /// The conversation so far, for providers that don't keep conversations themselves: the
+    /// summary it was compacted to, if any, then each prompt with its original answer
+    /// (regenerated responses are left out).
+    pub fn messages(&self) -> Vec<Message> {
+        let summary = self.summary.iter()
+            .map(|summary| Message::new("system", format!("Summary of the earlier conversation: {}", summary)));
+        summary
+            .chain(self.turns.iter()
+                .filter(|turn| turn.retry_of.is_none())
+                .flat_map(|turn| [Message::new("user", &turn.prompt), Message::new("assistant", &turn.response)]))
+            .collect()
+    }
+
+    /// Index of the last prompt asked (not counting retries), for regenerating its response.
+    pub fn last_original_turn(&self) -> Option<usize> {
+        let last = self.turns.len().checked_sub(1)?;
+        Some(self.turns[last].retry_of.unwrap_or(last))
+    }
+
+    /// Renders the conversation for `history show`, listing every response to a prompt
+    /// (the original and its regenerations) together.
+    pub fn render_with_retries(&self) -> String {
+        let mut out = format!("{}\n{}\n", self.title, self.id);
+
+        let originals = self.turns.iter().enumerate().filter(|(_, turn)| turn.retry_of.is_none());
+        for (number, (index, turn)) in originals.enumerate() {
+            out.push_str(&format!("\n[{}] You: {}\n", number + 1, turn.prompt));
+
+            let responses: Vec<&Turn> = std::iter::once(turn)
+                .chain(self.turns.iter().filter(|t| t.retry_of == Some(index)))
+                .collect();
+            for (attempt, response) in responses.iter().enumerate() {
+                let label = if responses.len() > 1 {
+                    format!("Response {} of {}", attempt + 1, responses.len())
+                } else {
+                    "Response".to_string()
+                };
+                out.push_str(&format!(
+                    "\n{} ({}, {}):\n{}\n",
+                    label,
+                    response.model,
+                    response.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                    response.response.trim_end()
+                ));
+            }
+        }
+        out
+    }
+
+    pub fn summary(&self) -> ConversationSummary {
+        let costs: Vec<f64> = self.turns.iter().filter_map(|turn| turn.credits).collect();
+
+        ConversationSummary {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            model: self.model.clone(),
+            turns: self.turns.len(),
+            last_activity: self.updated_at,
+            tags: self.tags.clone(),
+            estimated_cost: if costs.is_empty() { None } else { Some(costs.iter().sum()) },
+        }
+    }
+}
+
+fn history_dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| anyhow!("Could not determine the local data directory"))?
+        .join("ai-cli")
+        .join(HISTORY_DIR);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn record_path(id: &str) -> Result<PathBuf> {
+    check_id(id)?;
+    Ok(history_dir()?.join(format!("{}.json", id)))
+}
+
+/// Conversation ids come from the command line and from daemon clients, so anything that could
+/// name a file outside the history directory (`../x`, an absolute path) is refused.
+fn check_id(id: &str) -> Result<()> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow!("Invalid conversation id '{}': ids contain only letters, digits, '-' and '_'", id));
+    }
+    Ok(())
+}
+
+pub fn load(id: &str) -> Result<ConversationRecord> {
+    let path = record_path(id)?;
+    let contents = fs::read_to_string(&path)
+        .map_err(|_| anyhow!("No conversation with id '{}' found in local history", id))?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+pub fn save(record: &ConversationRecord) -> Result<()> {
+    let path = record_path(&record.id)?;
+    lock::write_atomic(&path, serde_json::to_string_pretty(record)?.as_bytes())
+}
+
+/// Runs a read-modify-write cycle on the history store while holding its lock.
+fn locked<T>(update: impl FnOnce() -> Result<T>) -> Result<T> {
+    let _lock = lock::lock_dir(&history_dir()?)?;
+    update()
+}
+
+pub fn start_conversation(id: &str, title: &str) -> Result<()> {
+    let now = Local::now();
+    locked(|| save(&ConversationRecord {
+        id: id.to_string(),
+        title: title.to_string(),
+        model: String::new(),
+        system: None,
+        created_at: now,
+        updated_at: now,
+        tags: Vec::new(),
+        turns: Vec::new(),
+        document: None,
+        checkpoints: BTreeMap::new(),
+        branched_from: None,
+        summary: None,
+    }))
+}
+
+/// Records the document a conversation is about, so follow-up questions can reuse the upload.
+pub fn set_document(id: &str, document: &Document) -> Result<()> {
+    locked(|| {
+        let mut record = load(id)?;
+        record.document = Some(document.clone());
+        save(&record)
+    })
+}
+
+/// Names the conversation's current state and returns it, with every turn recorded so far.
+pub fn add_checkpoint(id: &str, name: &str) -> Result<ConversationRecord> {
+    locked(|| {
+        let mut record = load(id)?;
+        record.checkpoints.insert(name.to_string(), record.turns.len());
+        save(&record)?;
+        Ok(record)
+    })
+}
+
+/// Fills the new conversation `id` with the turns of `from` up to its checkpoint `name`, so it
+/// continues from there. Earlier checkpoints come along; `from` is left unchanged.
+pub fn branch(id: &str, from: &ConversationRecord, name: &str) -> Result<ConversationRecord> {
+    let turns = *from.checkpoints.get(name)
+        .ok_or_else(|| anyhow!("No checkpoint named '{}' in this conversation", name))?;
+    locked(|| {
+        let mut record = load(id)?;
+        record.model = from.model.clone();
+        record.system = from.system.clone();
+        record.turns = from.turns[..turns.min(from.turns.len())].to_vec();
+        record.checkpoints = from.checkpoints.iter()
+            .filter(|(_, at)| **at <= turns)
+            .map(|(name, at)| (name.clone(), *at))
+            .collect();
+        record.branched_from = Some(from.id.clone());
+        record.summary = from.summary.clone();
+        save(&record)?;
+        Ok(record)
+    })
+}
+
+/// Fills the new conversation `id` as the continuation of `from`: a summary of its earlier
+/// exchanges followed by the most recent ones. Checkpoints stay with `from`.
+pub fn compacted(id: &str, from: &ConversationRecord, summary: &str, recent: Vec<Turn>) -> Result<ConversationRecord> {
+    locked(|| {
+        let mut record = load(id)?;
+        record.model = from.model.clone();
+        record.system = from.system.clone();
+        record.turns = recent;
+        record.branched_from = Some(from.id.clone());
+        record.summary = Some(summary.to_string());
+        save(&record)?;
+        Ok(record)
+    })
+}
+
+/// Adds a turn, sent with the system prompt `system`, to a stored conversation and returns its index.
+pub fn record_turn(id: &str, turn: &Turn, system: Option<&str>) -> Result<usize> {
+    locked(|| {
+        let mut record = load(id)?;
+        record.model = turn.model.clone();
+        record.system = system.map(str::to_string);
+        record.updated_at = turn.timestamp;
+        record.turns.push(turn.clone());
+        save(&record)?;
+        Ok(record.turns.len() - 1)
+    })
+}
+
+pub fn list() -> Result<Vec<ConversationRecord>> {
+    let mut records = Vec::new();
+
+    for entry in fs::read_dir(history_dir()?)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let contents = fs::read_to_string(&path)?;
+        if let Ok(record) = serde_json::from_str::<ConversationRecord>(&contents) {
+            records.push(record);
+        }
+    }
+
+    records.sort_by_key(|record| std::cmp::Reverse(record.updated_at));
+    Ok(records)
+}
+
+pub fn rename(id: &str, title: &str) -> Result<()> {
+    locked(|| {
+        let mut record = load(id)?;
+        record.title = title.to_string();
+        save(&record)
+    })
+}
+
+pub fn add_tags(id: &str, tags: &[String]) -> Result<()> {
+    locked(|| {
+        let mut record = load(id)?;
+        for tag in tags {
+            if !record.tags.contains(tag) {
+                record.tags.push(tag.clone());
+            }
+        }
+        save(&record)
+    })
+}
+
+fn format_cost(cost: Option<f64>) -> String {
+    match cost {
+        Some(cost) => format!("{:.0}", cost),
+        None => "-".to_string(),
+    }
+}
+
+pub fn print_table(summaries: &[ConversationSummary]) {
+    let headers = ["ID", "TITLE", "MODEL", "TURNS", "LAST ACTIVITY", "TAGS", "EST. COST"];
+    let rows: Vec<[String; 7]> = summaries.iter().map(|s| [
+        s.id.clone(),
+        s.title.clone(),
+        s.model.clone(),
+        s.turns.to_string(),
+        s.last_activity.format("%Y-%m-%d %H:%M").to_string(),
+        s.tags.join(","),
+        format_cost(s.estimated_cost),
+    ]).collect();
+
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells.iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+
+    print_row(headers.to_vec());
+    for row in &rows {
+        print_row(row.iter().map(|cell| cell.as_str()).collect());
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn print_csv(summaries: &[ConversationSummary]) {
+    println!("id,title,model,turns,last_activity,tags,estimated_cost");
+    for s in summaries {
+        let fields = [
+            csv_field(&s.id),
+            csv_field(&s.title),
+            csv_field(&s.model),
+            s.turns.to_string(),
+            s.last_activity.to_rfc3339(),
+            csv_field(&s.tags.join(";")),
+            s.estimated_cost.map(|c| c.to_string()).unwrap_or_default(),
+        ];
+        println!("{}", fields.join(","));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_that_could_leave_the_history_directory_are_refused() {
+        for id in ["", "..", "../../x", "/etc/passwd", "a/b", "a\\b", "a.json", "id with spaces"] {
+            assert!(check_id(id).is_err(), "{:?} was accepted", id);
+        }
+        for id in ["local-5f0c2a7e-1b2d-4c3e-9f8a-0123456789ab", "abc_DEF-123"] {
+            check_id(id).unwrap();
+        }
+    }
+
+    fn turn(prompt: &str, response: &str, retry_of: Option<usize>) -> Turn {
+        Turn {
+            timestamp: Local::now(),
+            model: "gpt-4o".to_string(),
+            prompt: prompt.to_string(),
+            response: response.to_string(),
+            credits: None,
+            retry_of,
+            interrupted: false,
+        }
+    }
+
+    #[test]
+    fn messages_start_from_the_summary_and_skip_regenerated_responses() {
+        let record = ConversationRecord {
+            id: "local-1".to_string(),
+            title: "notes".to_string(),
+            model: String::new(),
+            system: None,
+            created_at: Local::now(),
+            updated_at: Local::now(),
+            tags: Vec::new(),
+            turns: vec![turn("Hi", "Hello", None), turn("Hi", "Hey there", Some(0)), turn("Bye", "Goodbye", None)],
+            document: None,
+            checkpoints: BTreeMap::new(),
+            branched_from: None,
+            summary: Some("They talked about the weather.".to_string()),
+        };
+        let messages: Vec<(String, String)> = record.messages().into_iter().map(|m| (m.role, m.content)).collect();
+        let expected = [
+            ("system", "Summary of the earlier conversation: They talked about the weather."),
+            ("user", "Hi"),
+            ("assistant", "Hello"),
+            ("user", "Bye"),
+            ("assistant", "Goodbye"),
+        ];
+        assert_eq!(messages, expected.map(|(role, content)| (role.to_string(), content.to_string())));
+    }
+}
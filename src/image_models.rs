@@ -0,0 +1,260 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::client::ImageGeneration;
+
+/// The image generation options a family of models accepts. An empty list of values means the
+/// option isn't supported; the first value is the default sent when it isn't given.
+pub struct ImageModel {
+    pub family: &'static str,
+    /// Model ids starting with one of these belong to the family
+    prefixes: &'static [&'static str],
+    sizes: &'static [&'static str],
+    qualities: &'static [&'static str],
+    styles: &'static [&'static str],
+    aspect_ratios: &'static [&'static str],
+    negative_prompt: bool,
+    steps: Option<(u32, u32)>,
+    cfg_scale: Option<(f32, f32)>,
+    seed: bool,
+}
+
+const COMMON_ASPECT_RATIOS: &[&str] = &["1:1", "16:9", "9:16", "4:3", "3:4", "3:2", "2:3", "21:9", "9:21"];
+
+pub const IMAGE_MODELS: &[ImageModel] = &[
+    ImageModel {
+        family: "DALL·E 3",
+        prefixes: &["dall-e-3"],
+        sizes: &["1024x1024", "1024x1792", "1792x1024"],
+        qualities: &["standard", "hd"],
+        styles: &["vivid", "natural"],
+        aspect_ratios: &[],
+        negative_prompt: false,
+        steps: None,
+        cfg_scale: None,
+        seed: false,
+    },
+    ImageModel {
+        family: "DALL·E 2",
+        prefixes: &["dall-e-2"],
+        sizes: &["1024x1024", "512x512", "256x256"],
+        qualities: &[],
+        styles: &[],
+        aspect_ratios: &[],
+        negative_prompt: false,
+        steps: None,
+        cfg_scale: None,
+        seed: false,
+    },
+    ImageModel {
+        family: "GPT Image",
+        prefixes: &["gpt-image"],
+        sizes: &["1024x1024", "1024x1536", "1536x1024", "auto"],
+        qualities: &["auto", "low", "medium", "high"],
+        styles: &[],
+        aspect_ratios: &[],
+        negative_prompt: false,
+        steps: None,
+        cfg_scale: None,
+        seed: false,
+    },
+    ImageModel {
+        family: "FLUX",
+        prefixes: &["flux", "black-forest-labs/flux"],
+        sizes: &[],
+        qualities: &[],
+        styles: &[],
+        aspect_ratios: COMMON_ASPECT_RATIOS,
+        negative_prompt: false,
+        steps: Some((1, 50)),
+        cfg_scale: Some((1.0, 10.0)),
+        seed: true,
+    },
+    ImageModel {
+        family: "Midjourney",
+        prefixes: &["midjourney"],
+        sizes: &[],
+        qualities: &[],
+        styles: &[],
+        aspect_ratios: COMMON_ASPECT_RATIOS,
+        negative_prompt: true,
+        steps: None,
+        cfg_scale: None,
+        seed: true,
+    },
+    ImageModel {
+        family: "Stable Diffusion",
+        prefixes: &["stable-diffusion", "stable-image", "sdxl", "sd3", "stability"],
+        sizes: &[],
+        qualities: &[],
+        styles: &[],
+        aspect_ratios: COMMON_ASPECT_RATIOS,
+        negative_prompt: true,
+        steps: Some((10, 150)),
+        cfg_scale: Some((1.0, 35.0)),
+        seed: true,
+    },
+];
+
+/// The known family of an image model, if any.
+pub fn lookup(model: &str) -> Option<&'static ImageModel> {
+    let model = model.to_lowercase();
+    IMAGE_MODELS.iter().find(|family| family.prefixes.iter().any(|prefix| model.starts_with(prefix)))
+}
+
+impl ImageModel {
+    /// The flags this family accepts, with the values they take.
+    pub fn supported_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        for (flag, values) in [("--size", self.sizes), ("--quality", self.qualities), ("--style", self.styles), ("--aspect-ratio", self.aspect_ratios)] {
+            if !values.is_empty() {
+                flags.push(format!("{} ({})", flag, values.join(", ")));
+            }
+        }
+        if self.negative_prompt {
+            flags.push("--negative-prompt".to_string());
+        }
+        if let Some((min, max)) = self.steps {
+            flags.push(format!("--steps ({}-{})", min, max));
+        }
+        if let Some((min, max)) = self.cfg_scale {
+            flags.push(format!("--cfg-scale ({}-{})", min, max));
+        }
+        if self.seed {
+            flags.push("--seed".to_string());
+        }
+        flags
+    }
+
+    fn unsupported(&self, model: &str, flag: &str) -> anyhow::Error {
+        anyhow!(
+            "{} ({}) doesn't support {}. It supports: {}",
+            model,
+            self.family,
+            flag,
+            self.supported_flags().join(", ")
+        )
+    }
+
+    fn choose<'a>(&self, model: &str, flag: &str, values: &'static [&'static str], given: Option<&'a str>) -> Result<Option<&'a str>> {
+        match given {
+            None => Ok(values.first().copied()),
+            Some(_) if values.is_empty() => Err(self.unsupported(model, flag)),
+            Some(value) => values.iter()
+                .find(|allowed| allowed.eq_ignore_ascii_case(value))
+                .map(|allowed| Some(*allowed))
+                .ok_or_else(|| anyhow!("{} doesn't accept {} {}; choose one of {}", model, flag, value, values.join(", "))),
+        }
+    }
+}
+
+/// Checks the options against what the model supports and fills in the defaults for those it
+/// accepts but that weren't given. Options for models not in the table are sent as given.
+/// Models that take a seed get a random one, so the images record what they were made with.
+pub fn resolve(options: &mut ImageGeneration<'_>) -> Result<()> {
+    let Some(family) = lookup(options.model) else {
+        return Ok(());
+    };
+    let model = options.model;
+
+    options.size = family.choose(model, "--size", family.sizes, options.size)?;
+    options.quality = family.choose(model, "--quality", family.qualities, options.quality)?;
+    options.style = family.choose(model, "--style", family.styles, options.style)?;
+    if options.aspect_ratio.is_some() {
+        options.aspect_ratio = family.choose(model, "--aspect-ratio", family.aspect_ratios, options.aspect_ratio)?;
+    }
+    if options.negative_prompt.is_some() && !family.negative_prompt {
+        return Err(family.unsupported(model, "--negative-prompt"));
+    }
+    if let Some(steps) = options.steps {
+        let (min, max) = family.steps.ok_or_else(|| family.unsupported(model, "--steps"))?;
+        if !(min..=max).contains(&steps) {
+            return Err(anyhow!("{} takes --steps from {} to {}, not {}", model, min, max, steps));
+        }
+    }
+    if let Some(cfg_scale) = options.cfg_scale {
+        let (min, max) = family.cfg_scale.ok_or_else(|| family.unsupported(model, "--cfg-scale"))?;
+        if !(min..=max).contains(&cfg_scale) {
+            return Err(anyhow!("{} takes --cfg-scale from {} to {}, not {}", model, min, max, cfg_scale));
+        }
+    }
+    if options.seed.is_some() && !family.seed {
+        return Err(family.unsupported(model, "--seed"));
+    }
+    if family.seed && options.seed.is_none() {
+        options.seed = Some(random_seed());
+    }
+    Ok(())
+}
+
+/// A seed every model that takes one accepts (they stop at 2^32 - 1).
+fn random_seed() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    (now.as_nanos() % u128::from(u32::MAX)) as u64
+}
+
+/// What an image was generated with, written into saved PNGs and a `.json` file next to every
+/// image so it can be made again.
+#[derive(Serialize)]
+pub struct Parameters<'a> {
+    pub model: &'a str,
+    /// The prompt as given
+    pub prompt: &'a str,
+    /// The prompt actually sent, when `--enhance-prompt` rewrote it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enhanced_prompt: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negative_prompt: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub steps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cfg_scale: Option<f32>,
+}
+
+impl<'a> Parameters<'a> {
+    /// The parameters of a request, whose prompt is `prompt` enhanced when it differs.
+    pub fn new(options: &ImageGeneration<'a>, prompt: &'a str) -> Parameters<'a> {
+        Parameters {
+            model: options.model,
+            prompt,
+            enhanced_prompt: Some(options.prompt).filter(|sent| *sent != prompt),
+            seed: options.seed,
+            size: options.size,
+            quality: options.quality,
+            style: options.style,
+            aspect_ratio: options.aspect_ratio,
+            negative_prompt: options.negative_prompt,
+            steps: options.steps,
+            cfg_scale: options.cfg_scale,
+        }
+    }
+
+    /// The parameters as PNG text entries (keyword and text).
+    pub fn text_entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![("Model", self.model.to_string()), ("Prompt", self.prompt.to_string())];
+        let optional = [
+            ("Enhanced Prompt", self.enhanced_prompt.map(str::to_string)),
+            ("Seed", self.seed.map(|seed| seed.to_string())),
+            ("Size", self.size.map(str::to_string)),
+            ("Quality", self.quality.map(str::to_string)),
+            ("Style", self.style.map(str::to_string)),
+            ("Aspect Ratio", self.aspect_ratio.map(str::to_string)),
+            ("Negative Prompt", self.negative_prompt.map(str::to_string)),
+            ("Steps", self.steps.map(|steps| steps.to_string())),
+            ("CFG Scale", self.cfg_scale.map(|scale| scale.to_string())),
+        ];
+        entries.extend(optional.into_iter().filter_map(|(keyword, value)| Some((keyword, value?))));
+        entries
+    }
+}
@@ -0,0 +1,73 @@
+//! Enhancing short image prompts with a chat model before generating images.
+
+use anyhow::{anyhow, Result};
+use dialoguer::Input;
+
+use crate::config::Config;
+use crate::providers::Provider;
+use crate::ui::non_interactive;
+use crate::{auth, models, usage};
+
+/// Template that turns a short idea into a prompt image models do well with.
+const ENHANCE_TEMPLATE: &str = "Rewrite the following idea as a detailed prompt for an image generation model. \
+    Describe the subject, setting, composition, lighting, colors, mood and art style or medium in one \
+    paragraph of vivid, concrete language, keeping everything the idea asks for. Respond with only the \
+    prompt, without a title, quotes or commentary.\n\nIdea: {{input}}";
+
+/// The request asking a chat model to enhance an image prompt.
+pub fn enhance_request(prompt: &str) -> String {
+    ENHANCE_TEMPLATE.replace("{{input}}", prompt.trim())
+}
+
+/// The enhanced prompt in a chat response, without the labels and quotes models like to add.
+pub fn clean_enhanced(response: &str) -> String {
+    let text = response.trim();
+    let text = ["Prompt:", "Enhanced prompt:", "**Prompt:**"].iter()
+        .find_map(|label| text.strip_prefix(label))
+        .unwrap_or(text)
+        .trim();
+    text.strip_prefix('"').and_then(|text| text.strip_suffix('"')).unwrap_or(text).trim().to_string()
+}
+
+/// Has a chat model rewrite a short image prompt in detail and lets the user use, edit or reject
+/// the result, or with `yes` takes it as it is. Returns the prompt to generate with, or None when
+/// aborted.
+pub async fn enhance(provider: &dyn Provider, prompt: &str, model: &str, max_words: u32, yes: bool) -> Result<Option<String>> {
+    models::validate(model)?;
+    println!("Enhancing the prompt with {}...", model);
+    let request = enhance_request(prompt);
+    let response = auth::with_recovery(provider, || provider.chat(model, &request, max_words)).await?;
+    usage::record(&usage::UsageEntry::new(model, &request, &response, &Config::load()?.model_rates))?;
+    let mut enhanced = clean_enhanced(&response);
+    if enhanced.is_empty() {
+        return Err(anyhow!("The model returned an empty prompt"));
+    }
+
+    loop {
+        println!("\n  {}\n", enhanced.replace('\n', "\n  "));
+        // Nobody can approve it, so go ahead with what was shown.
+        if yes || non_interactive() {
+            return Ok(Some(enhanced));
+        }
+        let choice: String = Input::new()
+            .with_prompt("[u]se / [e]dit / [o]riginal / [a]bort")
+            .default("u".to_string())
+            .interact_text()?;
+
+        match choice.trim().to_lowercase().as_str() {
+            "u" | "use" => return Ok(Some(enhanced)),
+            "e" | "edit" => {
+                enhanced = Input::new()
+                    .with_prompt("Prompt")
+                    .with_initial_text(enhanced.clone())
+                    .interact_text()?;
+            }
+            "o" | "original" => return Ok(Some(prompt.to_string())),
+            "a" | "abort" => {
+                println!("Aborted.");
+                return Ok(None);
+            }
+            other => println!("Unknown choice '{}'.", other),
+        }
+    }
+}
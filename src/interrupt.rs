@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use tokio::sync::Notify;
+
+use crate::error::EXIT_INTERRUPTED;
+
+static INSTALL: Once = Once::new();
+/// Whether a request that Ctrl-C can cancel is in flight
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static NOTIFY: Notify = Notify::const_new();
+
+/// Marks a cancellable request; Ctrl-C exits the process again once it is dropped.
+pub struct Request(());
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Starts a request that the first Ctrl-C cancels instead of ending the process; a second one
+/// (or one while no request is in flight) still exits. Must be called within the Tokio runtime.
+pub fn begin() -> Request {
+    INSTALL.call_once(|| {
+        tokio::spawn(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if ACTIVE.load(Ordering::SeqCst) && !CANCELLED.swap(true, Ordering::SeqCst) {
+                    NOTIFY.notify_waiters();
+                } else {
+                    eprintln!();
+                    std::process::exit(EXIT_INTERRUPTED);
+                }
+            }
+        });
+    });
+    CANCELLED.store(false, Ordering::SeqCst);
+    ACTIVE.store(true, Ordering::SeqCst);
+    Request(())
+}
+
+/// Whether Ctrl-C cancelled the last request, clearing it so it is only reported once.
+pub fn take_cancelled() -> bool {
+    !ACTIVE.load(Ordering::SeqCst) && CANCELLED.swap(false, Ordering::SeqCst)
+}
+
+/// Completes once Ctrl-C cancels the current request.
+pub async fn cancelled() {
+    loop {
+        let notified = NOTIFY.notified();
+        if CANCELLED.load(Ordering::SeqCst) {
+            return;
+        }
+        notified.await;
+    }
+}
@@ -0,0 +1,64 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::error::AiCliError;
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+pub const DEFAULT_JOB_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Statuses 1min.ai reports for a feature request that is still being processed.
+const PENDING_STATUSES: &[&str] = &["PENDING", "QUEUED", "PROCESSING", "IN_PROGRESS", "RUNNING"];
+
+/// How often a long-running job is checked on, and how long it may take.
+#[derive(Clone, Copy, Debug)]
+pub struct Polling {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Polling {
+    fn default() -> Self {
+        Polling { interval: DEFAULT_POLL_INTERVAL, timeout: DEFAULT_JOB_TIMEOUT }
+    }
+}
+
+impl Polling {
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Polling { timeout, ..Polling::default() }
+    }
+}
+
+/// Whether a feature request with this status hasn't finished yet.
+pub fn is_pending(status: &str) -> bool {
+    PENDING_STATUSES.iter().any(|pending| status.eq_ignore_ascii_case(pending))
+}
+
+/// Checks on a job with `check` until `pending` says it is done, calling `progress` with its
+/// latest state and the time spent waiting before each check. Fails once `polling.timeout` has
+/// passed.
+pub async fn poll<T, F, Fut>(
+    mut job: T,
+    polling: Polling,
+    pending: impl Fn(&T) -> bool,
+    mut check: F,
+    mut progress: impl FnMut(&T, Duration),
+) -> Result<T, AiCliError>
+where
+    F: FnMut(&T) -> Fut,
+    Fut: Future<Output = Result<T, AiCliError>>,
+{
+    let started = Instant::now();
+    while pending(&job) {
+        let elapsed = started.elapsed();
+        if elapsed >= polling.timeout {
+            return Err(AiCliError::TimedOut(format!(
+                "the job was still running after {} seconds",
+                polling.timeout.as_secs()
+            )));
+        }
+        progress(&job, elapsed);
+        tokio::time::sleep(polling.interval.min(polling.timeout - elapsed)).await;
+        job = check(&job).await?;
+    }
+    Ok(job)
+}
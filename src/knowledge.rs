@@ -0,0 +1,413 @@
+//! Local document indexes for `--knowledge`: `ai-cli index` splits the files of a directory into
+//! chunks and embeds them, and each prompt is sent with the chunks closest to it.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::{cost, embeddings};
+use crate::providers::Provider;
+use crate::ui::{show_progress, waiting_spinner};
+
+const INDEX_FILE: &str = "knowledge.db";
+/// Roughly how many tokens of a file go into one chunk
+const CHUNK_TOKENS: usize = 300;
+/// A chunk that is one very long line is cut off here, to stay within embedding model limits
+const MAX_CHUNK_CHARS: usize = 4000;
+/// Files larger than this are skipped; they are rarely prose or code worth retrieving
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+/// Directories of build output and dependencies, skipped like hidden ones
+const SKIPPED_DIRS: &[&str] = &["node_modules", "target"];
+/// How many chunks `--knowledge` adds to a prompt unless told otherwise
+pub const DEFAULT_TOP_K: usize = 5;
+/// How long to wait for another ai-cli process that is updating an index
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An index as listed by `ai-cli index --list`.
+pub struct IndexInfo {
+    pub name: String,
+    /// Directory the files were read from
+    pub root: PathBuf,
+    /// Provider and model the chunks were embedded with; prompts must be embedded the same way
+    pub provider: String,
+    pub model: String,
+    pub files: usize,
+    pub chunks: usize,
+    pub updated_at: DateTime<Local>,
+}
+
+/// A part of an indexed file.
+pub struct Chunk {
+    /// Path relative to the index's directory
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    embedding: Vec<f32>,
+}
+
+impl Chunk {
+    /// Where the chunk comes from, e.g. `guide/setup.md:12-40`.
+    pub fn citation(&self) -> String {
+        if self.start_line == self.end_line {
+            format!("{}:{}", self.path, self.start_line)
+        } else {
+            format!("{}:{}-{}", self.path, self.start_line, self.end_line)
+        }
+    }
+}
+
+/// What `update` did.
+pub struct Update {
+    pub indexed: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub chunks: usize,
+    /// Set when the index was embedded with another provider or model before, so every file was
+    /// embedded again
+    pub rebuilt: bool,
+}
+
+fn index_path() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| anyhow!("Could not determine the local data directory"))?
+        .join("ai-cli");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(INDEX_FILE))
+}
+
+fn open() -> Result<Connection> {
+    let connection = Connection::open(index_path()?)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS indexes (
+             name TEXT PRIMARY KEY,
+             root TEXT NOT NULL,
+             provider TEXT NOT NULL,
+             model TEXT NOT NULL,
+             updated_at TEXT NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS files (
+             index_name TEXT NOT NULL,
+             path TEXT NOT NULL,
+             modified INTEGER NOT NULL,
+             PRIMARY KEY (index_name, path)
+         );
+         CREATE TABLE IF NOT EXISTS chunks (
+             index_name TEXT NOT NULL,
+             path TEXT NOT NULL,
+             start_line INTEGER NOT NULL,
+             end_line INTEGER NOT NULL,
+             text TEXT NOT NULL,
+             embedding BLOB NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS chunks_by_file ON chunks (index_name, path);",
+    )?;
+    Ok(connection)
+}
+
+/// The text files under `root`, relative to it, skipping hidden files and directories, build
+/// output and files too large to index.
+pub(crate) fn collect_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = dir.join(&*name);
+            let metadata = fs::metadata(entry.path())?;
+            if metadata.is_dir() {
+                if !SKIPPED_DIRS.contains(&name.as_ref()) {
+                    pending.push(path);
+                }
+            } else if metadata.is_file() && metadata.len() <= MAX_FILE_BYTES {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn modified(path: &Path) -> Result<i64> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(modified.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as i64))
+}
+
+/// Splits text into chunks of whole lines of about `CHUNK_TOKENS` each, returning each chunk
+/// with its first and last line number.
+fn split(text: &str) -> Vec<(usize, usize, String)> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut start = 1;
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        if !current.is_empty() && cost::estimate_tokens(&current) + cost::estimate_tokens(line) > CHUNK_TOKENS {
+            chunks.push((start, number - 1, std::mem::take(&mut current)));
+        }
+        if current.is_empty() {
+            start = number;
+        } else {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    chunks.push((start, text.lines().count(), current));
+    chunks.into_iter()
+        .filter(|(_, _, text)| !text.trim().is_empty())
+        .map(|(start, end, text)| (start, end.max(start), truncate(&text).to_string()))
+        .collect()
+}
+
+fn truncate(text: &str) -> &str {
+    match text.char_indices().nth(MAX_CHUNK_CHARS) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+fn to_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect()
+}
+
+/// Creates the index `name` from the files under `root`, or brings it up to date: only files
+/// that changed since they were indexed are embedded again, and deleted ones are dropped.
+/// `progress` is called with each file before it is embedded.
+pub async fn update(
+    name: &str,
+    root: &Path,
+    provider: &dyn Provider,
+    model: &str,
+    mut progress: impl FnMut(&Path),
+) -> Result<Update> {
+    let root = fs::canonicalize(root).map_err(|e| anyhow!("Could not read {}: {}", root.display(), e))?;
+    if !root.is_dir() {
+        return Err(anyhow!("{} is not a directory", root.display()));
+    }
+    let mut connection = open()?;
+    let existing: Option<(String, String)> = connection
+        .query_row("SELECT provider, model FROM indexes WHERE name = ?1", params![name], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()?;
+    let rebuilt = existing.as_ref().is_some_and(|(p, m)| p != provider.name() || m != model);
+    if rebuilt {
+        remove_from(&connection, name)?;
+    }
+    let indexed: HashMap<String, i64> = connection
+        .prepare("SELECT path, modified FROM files WHERE index_name = ?1")?
+        .query_map(params![name], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let files = collect_files(&root)?;
+    let mut update = Update { indexed: 0, unchanged: 0, removed: 0, chunks: 0, rebuilt };
+    for file in &files {
+        let path = file.to_string_lossy().into_owned();
+        let absolute = root.join(file);
+        let modified = modified(&absolute)?;
+        if indexed.get(&path) == Some(&modified) {
+            update.unchanged += 1;
+            continue;
+        }
+        let bytes = fs::read(&absolute)?;
+        if bytes.contains(&0) {
+            continue;
+        }
+        progress(file);
+        let chunks = split(&String::from_utf8_lossy(&bytes));
+        let texts: Vec<String> = chunks.iter().map(|(_, _, text)| text.clone()).collect();
+        let embeddings = embeddings::embed_all(provider, model, &texts, embeddings::DEFAULT_BATCH_SIZE, |_| {}).await?;
+
+        // Each file is stored as it is embedded, so an interrupted run keeps what it did.
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM chunks WHERE index_name = ?1 AND path = ?2", params![name, path])?;
+        for ((start, end, text), embedding) in chunks.iter().zip(&embeddings) {
+            transaction.execute(
+                "INSERT INTO chunks (index_name, path, start_line, end_line, text, embedding) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![name, path, *start as i64, *end as i64, text, to_blob(embedding)],
+            )?;
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO files (index_name, path, modified) VALUES (?1, ?2, ?3)",
+            params![name, path, modified],
+        )?;
+        transaction.commit()?;
+        update.indexed += 1;
+        update.chunks += chunks.len();
+    }
+
+    let transaction = connection.transaction()?;
+    let present: Vec<String> = files.iter().map(|file| file.to_string_lossy().into_owned()).collect();
+    for path in indexed.keys().filter(|path| !present.contains(path)) {
+        transaction.execute("DELETE FROM chunks WHERE index_name = ?1 AND path = ?2", params![name, path])?;
+        transaction.execute("DELETE FROM files WHERE index_name = ?1 AND path = ?2", params![name, path])?;
+        update.removed += 1;
+    }
+    transaction.execute(
+        "INSERT OR REPLACE INTO indexes (name, root, provider, model, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![name, root.to_string_lossy(), provider.name(), model, Local::now().to_rfc3339()],
+    )?;
+    transaction.commit()?;
+    Ok(update)
+}
+
+fn remove_from(connection: &Connection, name: &str) -> Result<()> {
+    connection.execute("DELETE FROM chunks WHERE index_name = ?1", params![name])?;
+    connection.execute("DELETE FROM files WHERE index_name = ?1", params![name])?;
+    connection.execute("DELETE FROM indexes WHERE name = ?1", params![name])?;
+    Ok(())
+}
+
+/// Deletes the index `name`.
+pub fn remove(name: &str) -> Result<()> {
+    let connection = open()?;
+    if info(&connection, name)?.is_none() {
+        return Err(anyhow!("No index named '{}'", name));
+    }
+    remove_from(&connection, name)
+}
+
+fn info(connection: &Connection, name: &str) -> Result<Option<IndexInfo>> {
+    connection
+        .query_row(
+            "SELECT name, root, provider, model, updated_at,
+                    (SELECT COUNT(*) FROM files WHERE index_name = indexes.name),
+                    (SELECT COUNT(*) FROM chunks WHERE index_name = indexes.name)
+             FROM indexes WHERE name = ?1",
+            params![name],
+            row_info,
+        )
+        .optional()?
+        .transpose()
+}
+
+fn row_info(row: &rusqlite::Row) -> rusqlite::Result<Result<IndexInfo>> {
+    let updated_at: String = row.get(4)?;
+    Ok(DateTime::parse_from_rfc3339(&updated_at)
+        .map_err(Into::into)
+        .and_then(|updated_at| {
+            Ok(IndexInfo {
+                name: row.get(0)?,
+                root: PathBuf::from(row.get::<_, String>(1)?),
+                provider: row.get(2)?,
+                model: row.get(3)?,
+                updated_at: updated_at.with_timezone(&Local),
+                files: row.get::<_, i64>(5)? as usize,
+                chunks: row.get::<_, i64>(6)? as usize,
+            })
+        }))
+}
+
+/// Every index, by name.
+pub fn list() -> Result<Vec<IndexInfo>> {
+    let connection = open()?;
+    let mut statement = connection.prepare(
+        "SELECT name, root, provider, model, updated_at,
+                (SELECT COUNT(*) FROM files WHERE index_name = indexes.name),
+                (SELECT COUNT(*) FROM chunks WHERE index_name = indexes.name)
+         FROM indexes ORDER BY name",
+    )?;
+    let rows = statement.query_map([], row_info)?;
+    rows.map(|row| row?).collect()
+}
+
+/// An index loaded for answering prompts.
+pub struct Index {
+    pub info: IndexInfo,
+    chunks: Vec<Chunk>,
+}
+
+impl Index {
+    pub fn load(name: &str) -> Result<Index> {
+        let connection = open()?;
+        let info = info(&connection, name)?.ok_or_else(|| {
+            anyhow!("No index named '{}'. Create it with `ai-cli index <DIR> --name {}`", name, name)
+        })?;
+        let chunks = connection
+            .prepare("SELECT path, start_line, end_line, text, embedding FROM chunks WHERE index_name = ?1")?
+            .query_map(params![name], |row| {
+                Ok(Chunk {
+                    path: row.get(0)?,
+                    start_line: row.get::<_, i64>(1)? as usize,
+                    end_line: row.get::<_, i64>(2)? as usize,
+                    text: row.get(3)?,
+                    embedding: from_blob(&row.get::<_, Vec<u8>>(4)?),
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Index { info, chunks })
+    }
+
+    /// The `k` chunks most similar to the prompt, most similar first.
+    pub async fn retrieve(&self, provider: &dyn Provider, prompt: &str, k: usize) -> Result<Vec<&Chunk>> {
+        let query = provider.embed(&self.info.model, &[truncate(prompt).to_string()]).await?
+            .pop()
+            .ok_or_else(|| anyhow!("No embedding returned for the prompt"))?;
+        let mut scored: Vec<(f32, &Chunk)> = self.chunks.iter()
+            .map(|chunk| (cosine_similarity(&query, &chunk.embedding), chunk))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored.into_iter().take(k).map(|(_, chunk)| chunk).collect())
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms = a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// The prompt with the retrieved chunks placed ahead of it, numbered for citing.
+pub fn augment(prompt: &str, chunks: &[&Chunk]) -> String {
+    if chunks.is_empty() {
+        return prompt.to_string();
+    }
+    let excerpts: Vec<String> = chunks.iter()
+        .enumerate()
+        .map(|(index, chunk)| format!("[{}] {}\n```\n{}\n```", index + 1, chunk.citation(), chunk.text))
+        .collect();
+    format!(
+        "Excerpts from the user's documents that may help with the request below. Where you use \
+         one, cite it by its number, like [1]. Ignore those that aren't relevant.\n\n{}\n\n---\n\n{}",
+        excerpts.join("\n\n"),
+        prompt
+    )
+}
+
+/// Embeds the files of a directory into a `--knowledge` index, showing which file is being embedded.
+pub async fn index_directory(provider: &dyn Provider, dir: &Path, name: Option<&str>, model: &str) -> Result<()> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => std::fs::canonicalize(dir)?
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| anyhow!("Give the index a name with --name"))?,
+    };
+    let spinner = show_progress().then(waiting_spinner);
+    let update = update(&name, dir, provider, model, |file| {
+        if let Some(spinner) = &spinner {
+            spinner.set_message(format!("embedding {}…", file.display()));
+        }
+    }).await?;
+    drop(spinner);
+
+    if update.rebuilt {
+        println!("Index '{}' was embedded with another model before, so every file was embedded again.", name);
+    }
+    println!(
+        "Index '{}': {} files embedded ({} chunks), {} unchanged, {} removed. Use it with --knowledge {}",
+        name, update.indexed, update.chunks, update.unchanged, update.removed, name
+    );
+    Ok(())
+}
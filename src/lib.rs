@@ -0,0 +1,72 @@
+//! Library behind the `ai-cli` binary: a typed 1min.ai client, other chat providers, and the local
+//! stores (history, caches, config) the CLI is built on.
+
+pub mod alias;
+pub mod assistant;
+pub mod auth;
+pub mod batch;
+pub mod bulk;
+pub mod cache;
+pub mod chunk;
+pub mod client;
+pub mod clipboard;
+pub mod code;
+pub mod compact;
+pub mod compare;
+pub mod completions;
+pub mod config;
+pub mod context;
+pub mod cost;
+pub mod credentials;
+pub mod daemon;
+pub mod doctor;
+pub mod embeddings;
+pub mod error;
+pub mod explain;
+pub mod export;
+pub mod git;
+pub mod history;
+pub mod image_models;
+pub mod image_prompt;
+pub mod interrupt;
+pub mod jobs;
+pub mod knowledge;
+pub mod line_editor;
+pub mod lock;
+pub mod mcp;
+pub mod media;
+pub mod models;
+pub mod notification;
+pub mod pager;
+pub mod patch;
+pub mod png_text;
+pub mod pr;
+pub mod preview;
+pub mod providers;
+pub mod rate_limit;
+pub mod redact;
+pub mod references;
+pub mod render;
+pub mod repl;
+pub mod retry;
+pub mod review;
+pub mod sampling;
+pub mod schema;
+pub mod search;
+pub mod server;
+pub mod session;
+pub mod share;
+pub mod shell;
+pub mod status;
+pub mod stream;
+pub mod summarize;
+pub mod tasks;
+pub mod templates;
+pub mod tools;
+pub mod translate;
+pub mod tui;
+pub mod ui;
+pub mod usage;
+pub mod voice;
+pub mod watch;
+pub mod web;
@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Result};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::interrupt;
+
+const PROMPT_HISTORY_FILE: &str = "history";
+const CONTINUATION_PROMPT: &str = "... ";
+
+/// Readline-style prompt for interactive mode: arrow-key history, Ctrl-R search, and
+/// multi-line input, with the history persisted across sessions.
+pub struct LineEditor {
+    editor: DefaultEditor,
+    history_path: Option<PathBuf>,
+}
+
+fn history_path() -> Option<PathBuf> {
+    let dir = dirs::data_dir()?.join("ai-cli");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir.join(PROMPT_HISTORY_FILE))
+}
+
+/// Whether the input so far asks for another line: a trailing backslash, or an unclosed code fence.
+fn needs_continuation(input: &str) -> bool {
+    input.ends_with('\\') || input.matches("```").count() % 2 == 1
+}
+
+impl LineEditor {
+    pub fn new() -> Result<LineEditor> {
+        let mut editor = DefaultEditor::new()
+            .map_err(|e| anyhow!("Could not initialize the line editor: {}", e))?;
+        let history_path = history_path();
+        if let Some(path) = history_path.as_ref().filter(|p| p.exists()) {
+            // A corrupt history file shouldn't stop the session from starting.
+            let _ = editor.load_history(path);
+        }
+
+        Ok(LineEditor { editor, history_path })
+    }
+
+    /// Reads one prompt, which may span several lines. Returns `None` on Ctrl-D, or on a
+    /// second Ctrl-C in a row.
+    ///
+    /// End a line with `\` or open a ``` fence to continue on the next line. Ctrl-C
+    /// discards the current input and starts over; right after Ctrl-C cancelled a response,
+    /// it counts as the second one.
+    pub fn read(&mut self, prompt: &str) -> Result<Option<String>> {
+        let mut input = String::new();
+        let mut interrupted = interrupt::take_cancelled();
+
+        loop {
+            let line_prompt = if input.is_empty() { prompt } else { CONTINUATION_PROMPT };
+            match self.editor.readline(line_prompt) {
+                Ok(line) => {
+                    interrupted = false;
+                    if let Some(stripped) = input.strip_suffix('\\') {
+                        input.truncate(stripped.len());
+                    }
+                    if !input.is_empty() {
+                        input.push('\n');
+                    }
+                    input.push_str(&line);
+
+                    if !needs_continuation(&input) {
+                        break;
+                    }
+                }
+                Err(ReadlineError::Interrupted) if interrupted => return Ok(None),
+                Err(ReadlineError::Interrupted) => {
+                    input.clear();
+                    interrupted = true;
+                }
+                Err(ReadlineError::Eof) if input.is_empty() => return Ok(None),
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(anyhow!("Could not read input: {}", e)),
+            }
+        }
+
+        if !input.trim().is_empty() {
+            let _ = self.editor.add_history_entry(input.as_str());
+            if let Some(path) = &self.history_path {
+                let _ = self.editor.save_history(path);
+            }
+        }
+        Ok(Some(input))
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::Result;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const LOCK_FILE: &str = ".lock";
+
+/// An exclusive advisory lock on a local store directory, released when dropped.
+pub struct StoreLock {
+    _file: File,
+}
+
+/// Blocks until this process holds the lock for `dir`, so concurrent ai-cli
+/// processes take turns on read-modify-write cycles instead of clobbering each other.
+pub fn lock_dir(dir: &Path) -> Result<StoreLock> {
+    fs::create_dir_all(dir)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+    file.lock()?;
+    Ok(StoreLock { _file: file })
+}
+
+/// Writes a file by renaming a fully written temporary file over it, so readers
+/// never observe a half-written store.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    replace(path, contents, OpenOptions::new().write(true).create_new(true))
+}
+
+/// Like [`write_atomic`], for secrets: the file is readable by its owner alone from the moment
+/// it is created, not just once it is in place.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    replace(path, contents, &options)
+}
+
+fn replace(path: &Path, contents: &[u8], options: &OpenOptions) -> Result<()> {
+    let tmp = temp_path(path);
+    let written = options.open(&tmp).and_then(|mut file| file.write_all(contents)).and_then(|()| fs::rename(&tmp, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// A temporary name next to `path`, unique to this call: concurrent writers, whether
+/// processes or tasks of one process, never share one.
+fn temp_path(path: &Path) -> PathBuf {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("store");
+    let write = WRITES.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}.{}.tmp", file_name, std::process::id(), write))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn concurrent_writes_leave_one_complete_file_and_no_temporaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.json");
+        let writers: Vec<_> = (0..16)
+            .map(|writer| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        write_atomic(&path, format!("{:02}", writer).repeat(1000).as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, contents[..2].repeat(1000));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_files_are_never_readable_by_others() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        write_atomic(&path, b"public").unwrap();
+        write_private(&path, b"secret").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "secret");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn temporary_names_are_unique_per_call() {
+        let path = Path::new("/tmp/store.json");
+        assert_ne!(temp_path(path), temp_path(path));
+    }
+
+    #[test]
+    fn lock_dir_serializes_read_modify_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("counter");
+        fs::write(&counter, "0").unwrap();
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let (dir, counter) = (dir.path().to_path_buf(), counter.clone());
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        let _lock = lock_dir(&dir).unwrap();
+                        let count: u32 = fs::read_to_string(&counter).unwrap().parse().unwrap();
+                        write_atomic(&counter, (count + 1).to_string().as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "200");
+    }
+}
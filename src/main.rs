@@ -1,39 +1,186 @@
-use anyhow::{anyhow, Result};
-use chrono::Local;
-use clap::{CommandFactory, Parser, Subcommand};
-use dialoguer::Input;
-use futures_util::StreamExt;
-use keyring::Entry;
-use reqwest::{Client, header};
-use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::{self, Write};
-use std::path::Path;
-use std::process::Command;
-use serde_json;
-
-const CONVERSATION_API_URL: &str = "https://api.1min.ai/api/conversations";
-const STREAMING_FEATURES_API_URL: &str = "https://api.1min.ai/api/features?isStreaming=true";
-const IMAGE_GENERATION_API_URL: &str = "https://api.1min.ai/api/features";
+use ai_cli::auth::{self, AuthManager, KeySource, Recovery, API_KEY_ENV};
+use ai_cli::client::{ImageGeneration, ImageProcessing, ImageTool, OneMinClient, VideoGeneration, DEFAULT_TEXT_TO_SPEECH_MODEL, DEFAULT_TTS_VOICE};
+use ai_cli::line_editor::LineEditor;
+use ai_cli::notification::{self, Notify};
+use ai_cli::providers::{self, Provider, ProviderKind, RequestDefaults};
+use ai_cli::providers::redacting::Redacting;
+use ai_cli::sampling::{self, Sampling};
+use ai_cli::assistant::{Assistant, Execution, ReviewOutput, TaskFormat};
+use ai_cli::repl;
+use ai_cli::session::{ask, compare_models, follow_stdin, initialize_conversation, initialize_document_conversation, prompt_from_editor, resume_in, run_slash_command, send_interactive, upload_document, watch_files, CompareLayout, CopyTarget, Knowledge, OutputFormat, Session, TextStyle};
+use ai_cli::ui::{self, check_budget, confirm_large_prompt, ensure_interactive, non_interactive};
+use ai_cli::{alias, batch, bulk, cache, chunk, clipboard, compact, completions, config, context, cost, credentials, daemon, doctor, embeddings, error, explain, export, git, history, image_models, image_prompt, knowledge, mcp, media, models, pager, preview, rate_limit, references, retry, review, schema, search, server, share, status, templates, tools, tui, usage};
+use anyhow::{anyhow, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::{ArgValueCandidates, CompleteEnv, Shell};
+use dialoguer::{Confirm, Input};
+use reqwest::Client;
+use std::ffi::OsString;
+use std::io::{self, IsTerminal, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
 const DEFAULT_MODEL: &str = "o3-mini";
 const DEFAULT_IMAGE_MODEL: &str = "dall-e-3";
 const DEFAULT_IMAGE_SIZE: &str = "1024x1024";
-const DEFAULT_IMAGE_QUALITY: &str = "standard";
-const DEFAULT_IMAGE_STYLE: &str = "vivid";
+const DEFAULT_IMAGE_TOOL_MODEL: &str = "dall-e-2";
+const DEFAULT_IMAGE_PROCESSING_MODEL: &str = "clipdrop";
+const DEFAULT_VIDEO_MODEL: &str = "klingai";
+const DEFAULT_VIDEO_DURATION_SECS: u32 = 5;
+/// Video generation usually takes minutes, longer than the other background features
+const DEFAULT_VIDEO_WAIT_SECS: u64 = 900;
 const MAX_WORDS: u32 = 500;
-const SERVICE_NAME: &str = "ai-cli";
-const USERNAME: &str = "user";
-const DEFAULT_IMAGE_FILENAME: &str = "1minAI_output.png";
-
+const BASE_URL_ENV: &str = "AI_CLI_BASE_URL";
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_STALL_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_FOLLOW_BATCH_LINES: usize = 50;
+const DEFAULT_FOLLOW_BATCH_SECS: u64 = 10;
+const DEFAULT_SCHEMA_RETRIES: u32 = 2;
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+const DEFAULT_SERVE_HOST: &str = "127.0.0.1";
+const DEFAULT_SERVE_PORT: u16 = 8080;
 #[derive(Parser)]
 #[command(author, version, about = "CLI tool for interacting with 1min.ai API")]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// The prompt to send to the AI
+    /// The prompt to send to the AI (the same as `ai-cli chat <PROMPT>`)
     prompt: Option<String>,
 
+    #[command(flatten)]
+    chat: ChatArgs,
+
+    /// Generate images from the prompt (the same as `ai-cli image generate`)
+    #[arg(short = 'g', long, conflicts_with_all = ["regen", "compare"])]
+    image_generation: bool,
+
+    #[command(flatten, next_help_heading = "Image generation options (with -g)")]
+    generation: ImageGenerationArgs,
+
+    /// Overall timeout in seconds for each API request, including streaming the response
+    #[arg(long, global = true, default_value_t = DEFAULT_TIMEOUT_SECS)]
+    timeout: u64,
+
+    /// Timeout in seconds for establishing a connection
+    #[arg(long, global = true, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS)]
+    connect_timeout: u64,
+
+    /// Number of times to retry network failures and 429/5xx responses
+    #[arg(long, global = true, default_value_t = retry::DEFAULT_RETRIES)]
+    retries: u32,
+
+    /// Base delay in milliseconds for exponential retry backoff
+    #[arg(long, global = true, default_value_t = retry::DEFAULT_RETRY_DELAY_MS)]
+    retry_delay: u64,
+
+    /// Log requests to stderr: once for URLs and statuses, twice adds headers (API keys redacted), payload sizes and stream timing, three times adds raw payloads (-d, as -v is voice output)
+    #[arg(short = 'd', long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Write the --verbose log to this file instead of stderr (logs requests even without --verbose)
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Send at most this many requests per minute, queueing the rest (e.g. in batch and compare mode)
+    #[arg(long, global = true, value_name = "RPM")]
+    rate_limit: Option<u32>,
+
+    /// Chat provider: 1min (default), openai, ollama, or a [providers] entry of the config file
+    #[arg(long, global = true, value_name = "NAME")]
+    provider: Option<String>,
+
+    /// API base URL, e.g. a proxy or gateway (default: AI_CLI_BASE_URL, base_url in the config file, or https://api.1min.ai)
+    #[arg(long, global = true, value_name = "URL")]
+    base_url: Option<String>,
+
+    /// Send requests through this proxy (http://, https:// or socks5://); HTTPS_PROXY and HTTP_PROXY are used otherwise
+    #[arg(long, global = true, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// Trust this PEM certificate (or bundle) in addition to the system's, e.g. for a TLS-intercepting proxy
+    #[arg(long, global = true, value_name = "PEM")]
+    ca_cert: Option<PathBuf>,
+
+    /// Don't verify TLS certificates at all. Anyone on the network path can read and change your traffic
+    #[arg(long, global = true)]
+    insecure: bool,
+
+    /// Read the API key from this file (takes precedence over AI_CLI_API_KEY and the keyring)
+    #[arg(long, global = true, value_name = "PATH")]
+    api_key_file: Option<PathBuf>,
+
+    /// Never use the keyring (or credential file) or prompt for an API key; fail if none is given by file or environment
+    #[arg(long, global = true)]
+    no_keyring: bool,
+
+    /// Never prompt; fail with an error instead (automatic when stdin or stdout is not a terminal)
+    #[arg(long, global = true)]
+    non_interactive: bool,
+
+    /// What to do when the API rejects the API key: prompt for a new one (the default when interactive), fail, or reload it from its file, environment variable or keyring
+    #[arg(long, global = true, value_enum, value_name = "STRATEGY")]
+    auth_recovery: Option<Recovery>,
+
+    /// Skip confirmation prompts for large or expensive requests
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Announce when the command finishes or fails, with a desktop notification, the terminal bell or both (the default)
+    #[arg(long, global = true, value_enum, value_name = "HOW", num_args = 0..=1, require_equals = true, default_missing_value = "both")]
+    notify: Option<Notify>,
+}
+
+impl Cli {
+    /// The model to use: `--model`, or the task's default once `run` has resolved it.
+    fn model(&self) -> &str {
+        self.chat.model.as_deref().unwrap_or(DEFAULT_MODEL)
+    }
+
+    /// What the one-off commands ask `provider` with.
+    fn assistant<'a>(&'a self, provider: &'a dyn Provider) -> Assistant<'a> {
+        Assistant { provider, model: self.model(), max_words: self.chat.max_words(), yes: self.yes, output: self.chat.output }
+    }
+}
+
+impl ChatArgs {
+    fn verbosity(&self) -> Option<Verbosity> {
+        match (self.brief, self.detailed) {
+            (true, _) => Some(Verbosity::Brief),
+            (_, true) => Some(Verbosity::Detailed),
+            _ => None,
+        }
+    }
+
+    /// Response length limit in words: `--max-words`, or what `--max-tokens` allows, or that of
+    /// `--brief` or `--detailed`.
+    fn max_words(&self) -> u32 {
+        self.words
+            .or(self.max_tokens.map(cost::words_for_tokens))
+            .or(self.verbosity().map(Verbosity::words))
+            .unwrap_or(MAX_WORDS)
+    }
+
+    /// Response length limit in tokens, only when a length was asked for; providers otherwise
+    /// answer at whatever length the model chooses.
+    fn max_tokens(&self) -> Option<u32> {
+        self.max_tokens
+            .or(self.words.map(cost::tokens_for_words))
+            .or(self.verbosity().map(|verbosity| cost::tokens_for_words(verbosity.words())))
+    }
+
+    /// The sampling parameters given on the command line.
+    fn sampling(&self) -> Sampling {
+        Sampling { temperature: self.temperature, top_p: self.top_p, frequency_penalty: self.frequency_penalty }
+    }
+}
+
+/// Options for chatting with a model, taken by `ai-cli chat` and by a bare `ai-cli <PROMPT>`.
+#[derive(clap::Args)]
+struct ChatArgs {
     /// Enable interactive mode
     #[arg(short, long)]
     interactive: bool,
@@ -42,487 +189,2298 @@ struct Cli {
     #[arg(short, long)]
     voice_output: bool,
 
+    /// Speak voice output with this 1min.ai text-to-speech voice (e.g. alloy) instead of `say`
+    #[arg(long, value_name = "VOICE", requires = "voice_output")]
+    tts_voice: Option<String>,
+
+    /// Record prompts from the microphone and transcribe them (requires sox or arecord)
+    #[arg(long)]
+    voice_input: bool,
+
+    /// Attach a text file (or glob, e.g. "src/**/*.rs") to the prompt; can be repeated
+    #[arg(long = "file", value_name = "PATH")]
+    files: Vec<String>,
+
+    /// Send an image (local path or URL) with the prompt to a vision-capable model; can be repeated
+    #[arg(long = "image", value_name = "PATH_OR_URL")]
+    images: Vec<String>,
+
+    /// Copy each response to the clipboard; --copy=code copies only its code block
+    #[arg(long, value_enum, value_name = "WHAT", num_args = 0..=1, require_equals = true, default_missing_value = "response")]
+    copy: Option<CopyTarget>,
+
+    /// Print only the fenced code blocks of each response; --code-only=LANG keeps just that language's
+    #[arg(long, value_name = "LANG", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    code_only: Option<String>,
+
+    /// Also write each response (only its code with --code-only) to this file
+    #[arg(long, value_name = "FILE")]
+    out: Option<PathBuf>,
+
+    /// Make the model answer with JSON matching this JSON Schema, and print only that JSON
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["code_only", "compare"])]
+    schema: Option<PathBuf>,
+
+    /// Let the model call tools: all of them, or a comma-separated list of shell, read_file, web_fetch and calculator
+    #[arg(long, value_name = "TOOLS", num_args = 0..=1, require_equals = true, value_delimiter = ',',
+          conflicts_with_all = ["schema", "compare"])]
+    tools: Option<Vec<tools::Tool>>,
+
+    /// Offer the model the tools and resources of MCP servers: all configured ones, or a comma-separated list of names
+    #[arg(long, value_name = "SERVERS", num_args = 0..=1, require_equals = true, value_delimiter = ',',
+          conflicts_with_all = ["schema", "compare"])]
+    mcp: Option<Vec<String>>,
+
+    /// Add the most relevant chunks of a local document index (see `ai-cli index`) to each prompt
+    #[arg(long, value_name = "INDEX", conflicts_with_all = ["compare", "use_daemon"])]
+    knowledge: Option<String>,
+
+    /// Add the files of the current git repository most relevant to each prompt, as many as fit in the context
+    #[arg(long, conflicts_with_all = ["compare", "use_daemon"])]
+    repo: bool,
+
+    /// How many chunks --knowledge adds to each prompt
+    #[arg(long, value_name = "K", default_value_t = knowledge::DEFAULT_TOP_K, requires = "knowledge")]
+    top_k: usize,
+
+    /// How many times to ask again when a response doesn't match --schema
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_SCHEMA_RETRIES, requires = "schema")]
+    schema_retries: u32,
+
+    /// Write the prompt in $EDITOR (starting from the prompt argument, if given)
+    #[arg(long)]
+    editor: bool,
+
+    /// Use the clipboard contents as the prompt, or append them to the prompt as context
+    #[arg(long)]
+    paste: bool,
+
+    /// Append each exchange to this Markdown transcript
+    #[arg(long, value_name = "FILE")]
+    save: Option<PathBuf>,
+
+    /// Write each response to its own Markdown file named after the date and prompt, with its metadata as front matter
+    #[arg(long, value_name = "DIR", num_args = 0..=1, require_equals = true)]
+    save_response: Option<Option<PathBuf>>,
+
+    /// Keep reading stdin and send new lines in batches, using the prompt as the instruction
+    #[arg(long)]
+    follow: bool,
+
+    /// Send a follow batch once this many lines have arrived
+    #[arg(long, default_value_t = DEFAULT_FOLLOW_BATCH_LINES, requires = "follow")]
+    batch_lines: usize,
+
+    /// Send a follow batch this many seconds after its first line arrived
+    #[arg(long, default_value_t = DEFAULT_FOLLOW_BATCH_SECS, requires = "follow")]
+    batch_secs: u64,
+
     /// Do not print AI responses (only works with voice output)
     #[arg(short, long)]
     quiet: bool,
 
-    /// The AI model to use
-    #[arg(short, long, default_value = DEFAULT_MODEL)]
-    model: String,
+    /// Print responses as raw text instead of rendering markdown
+    #[arg(long)]
+    plain: bool,
 
-    /// Maximum number of words for web search
-    #[arg(short, long, default_value_t = MAX_WORDS)]
-    words: u32,
-    
-    /// Enable image generation mode (incompatible with interactive and voice modes)
-    #[arg(short = 'g', long)]
-    image_generation: bool,
-    
-    /// Image size (1024x1024, 1024x1792, 1792x1024)
-    #[arg(short, long, default_value = DEFAULT_IMAGE_SIZE)]
-    size: String,
-    
-    /// Image quality (standard, hd)
-    #[arg(long, default_value = DEFAULT_IMAGE_QUALITY)]
-    quality: String,
-    
-    /// Image style (vivid, natural)
-    #[arg(long, default_value = DEFAULT_IMAGE_STYLE)]
-    style: String,
+    /// Show each response again in $PAGER (less -R by default) once it is complete; responses taller than the terminal are paged anyway
+    #[arg(long, conflicts_with = "no_pager")]
+    pager: bool,
+
+    /// Don't page responses that don't fit on the screen
+    #[arg(long)]
+    no_pager: bool,
+
+    /// Give up on a streaming response after this many seconds without new data
+    #[arg(long, default_value_t = DEFAULT_STALL_TIMEOUT_SECS)]
+    stall_timeout: u64,
+
+    /// Summarize earlier exchanges once an interactive conversation reaches about this many tokens (0: never)
+    #[arg(long, value_name = "TOKENS")]
+    context_limit: Option<usize>,
+
+    /// Send the last prompt of a stored conversation (the most recent one by default) again, keeping both responses
+    #[arg(long, value_name = "ID", num_args = 0..=1, require_equals = true, default_missing_value = "",
+          conflicts_with_all = ["prompt", "interactive", "follow"],
+          add = ArgValueCandidates::new(completions::conversation_candidates))]
+    regen: Option<String>,
+
+    /// Continue a stored conversation (the most recent one by default) with the model and system prompt it used
+    #[arg(long, value_name = "ID", num_args = 0..=1, require_equals = true, default_missing_value = "",
+          conflicts_with_all = ["regen", "follow", "compare"],
+          add = ArgValueCandidates::new(completions::conversation_candidates))]
+    resume: Option<String>,
+
+    /// Send the prompt to several models at once (comma-separated) and show their responses together
+    #[arg(long, value_name = "MODELS", value_delimiter = ',', num_args = 1,
+          conflicts_with_all = ["interactive", "follow", "regen", "model", "images"],
+          add = ArgValueCandidates::new(completions::model_candidates))]
+    compare: Vec<String>,
+
+    /// How --compare shows the responses
+    #[arg(long, value_enum, default_value_t = CompareLayout::Sequential, requires = "compare")]
+    layout: CompareLayout,
+
+    /// Title for the new conversation, shown in the history list and the 1min.ai web app
+    #[arg(long, conflicts_with = "auto_title")]
+    title: Option<String>,
+
+    /// Name the conversation after its first exchange, using a short title from the model
+    #[arg(long)]
+    auto_title: bool,
+
+    /// Send prompts on their own instead of creating a conversation (the default for one-shot prompts)
+    #[arg(long, conflicts_with_all = ["conversation", "tools", "mcp"])]
+    no_conversation: bool,
+
+    /// Create a conversation on the API even for a one-shot prompt
+    #[arg(long)]
+    conversation: bool,
+
+    /// Don't ask for confirmation when the same prompt is sent twice in a row in interactive mode
+    #[arg(long)]
+    no_dup_guard: bool,
+
+    /// Screen-reader friendly output: no colors or terminal effects, with explicit response markers
+    #[arg(long)]
+    screen_reader: bool,
+
+    /// Answer a repeated identical prompt (same model and options) from the local cache instead of the API
+    #[arg(long, conflicts_with = "no_cache")]
+    cache: bool,
+
+    /// Don't use the response cache, even if the config file turns it on
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Print time to first token, total time and approximate tokens/sec after each response
+    #[arg(long)]
+    stats: bool,
+
+    /// Output format for chat responses
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// The AI model to use (default: the task's model from [default] in the config file, or o3-mini)
+    #[arg(short, long, add = ArgValueCandidates::new(completions::model_candidates))]
+    model: Option<String>,
+
+    /// System prompt to prepend to every request
+    #[arg(long, conflicts_with = "persona")]
+    system: Option<String>,
+
+    /// Use a persona (named system prompt) defined in the config file
+    #[arg(long)]
+    persona: Option<String>,
+
+    /// Fill in and send a prompt template; the prompt argument or piped stdin becomes {{input}}
+    #[arg(short, long, value_name = "NAME")]
+    template: Option<String>,
+
+    /// Response style preset (concise, detailed, bullet, eli5, or one defined in the config file)
+    #[arg(long)]
+    response_style: Option<String>,
+
+    /// Response length limit in words (default: 500, or that of --brief or --detailed)
+    #[arg(short, long = "max-words", visible_alias = "words", value_name = "N")]
+    words: Option<u32>,
+
+    /// Response length limit in tokens, for providers that take one; the word limit follows from it
+    #[arg(long, value_name = "N")]
+    max_tokens: Option<u32>,
+
+    /// Ask for a short answer, and limit it to about 150 words
+    #[arg(long, conflicts_with = "detailed")]
+    brief: bool,
+
+    /// Ask for a thorough answer, and allow up to about 2000 words
+    #[arg(long)]
+    detailed: bool,
+
+    /// Sampling temperature; 0 gives the most deterministic answers, higher values more varied ones
+    #[arg(long, value_name = "T")]
+    temperature: Option<f32>,
+
+    /// Only consider the most likely tokens making up this share of the probability (0 to 1)
+    #[arg(long, value_name = "P")]
+    top_p: Option<f32>,
+
+    /// Discourage tokens by how often they already appeared (-2 to 2), against repetition
+    #[arg(long, value_name = "F", allow_negative_numbers = true)]
+    frequency_penalty: Option<f32>,
+
+    /// Send the prompt to a running `ai-cli daemon` instead of connecting to the API
+    #[arg(long, conflicts_with_all = ["interactive", "follow", "regen", "resume", "compare"])]
+    use_daemon: bool,
+
+    /// With --use-daemon, continue the daemon's conversation of this name (started on first use)
+    #[arg(long, value_name = "NAME", requires = "use_daemon")]
+    daemon_conversation: Option<String>,
+}
+
+/// Options for generating images, taken by `ai-cli image generate` and by `ai-cli -g`.
+#[derive(clap::Args)]
+struct ImageGenerationArgs {
+    /// Image size for DALL·E and GPT Image models (e.g. 1024x1024, 1024x1792, 1792x1024)
+    #[arg(short, long)]
+    size: Option<String>,
+
+    /// Number of images to generate
+    #[arg(short = 'n', long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=10))]
+    count: u32,
+
+    /// Save the generated image to this path (indexed when generating several)
+    #[arg(short = 'o', long)]
+    output_file: Option<PathBuf>,
+
+    /// Directory to save generated images in (created if missing)
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// Open the saved images in the default viewer
+    #[arg(long)]
+    open: bool,
+
+    /// Show the saved images in the terminal; --preview=PROTOCOL forces kitty, iterm2, sixel or blocks
+    #[arg(long, value_name = "PROTOCOL", num_args = 0..=1, require_equals = true)]
+    preview: Option<Option<preview::Protocol>>,
+
+    /// Image quality (standard, hd for DALL·E 3)
+    #[arg(long)]
+    quality: Option<String>,
+
+    /// Image style (vivid, natural for DALL·E 3)
+    #[arg(long)]
+    style: Option<String>,
+
+    /// Image aspect ratio, e.g. 16:9 (FLUX, Midjourney, Stable Diffusion)
+    #[arg(long)]
+    aspect_ratio: Option<String>,
+
+    /// What the image should not contain (Midjourney, Stable Diffusion)
+    #[arg(long)]
+    negative_prompt: Option<String>,
+
+    /// Number of diffusion steps (FLUX, Stable Diffusion)
+    #[arg(long)]
+    steps: Option<u32>,
+
+    /// How closely the image follows the prompt (FLUX, Stable Diffusion)
+    #[arg(long)]
+    cfg_scale: Option<f32>,
+
+    /// Seed for reproducible images (FLUX, Midjourney, Stable Diffusion); a random one is used when not given
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Have a chat model rewrite the prompt as a detailed image prompt, and confirm it before generating
+    #[arg(long)]
+    enhance_prompt: bool,
+
+    /// Chat model that enhances the prompt (the provider's default model unless given)
+    #[arg(long, value_name = "MODEL", requires = "enhance_prompt", add = ArgValueCandidates::new(completions::model_candidates))]
+    enhance_model: Option<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Configure API key
-    Config,
+    /// Chat with a model (the default: `ai-cli <PROMPT>` is short for `ai-cli chat <PROMPT>`)
+    Chat {
+        /// The prompt to send to the AI
+        prompt: Option<String>,
+        #[command(flatten)]
+        options: Box<ChatArgs>,
+    },
+    /// Chat in a full-screen terminal interface, with the stored conversations beside the chat
+    Tui,
+    /// Configure the API key, or change a setting of the config file
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigCommands>,
+    },
+    /// Manage aliases: shortcuts that run a longer invocation, such as `ai-cli critique <file>`
+    Alias {
+        #[command(subcommand)]
+        action: AliasCommands,
+    },
+    /// Manage MCP servers, whose tools and resources `--mcp` offers to the model
+    Mcp {
+        #[command(subcommand)]
+        action: McpCommands,
+    },
+    /// Export a stored conversation as Markdown, JSON or HTML
+    Export {
+        /// Conversation ID
+        #[arg(add = ArgValueCandidates::new(completions::conversation_candidates))]
+        id: String,
+        /// Choose which turns to strip before exporting (the stored conversation is left untouched)
+        #[arg(long)]
+        redact: bool,
+        /// Redact by editing the rendered Markdown in $EDITOR instead of selecting turns
+        #[arg(long, requires = "redact")]
+        editor: bool,
+        /// Export format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Md)]
+        format: ExportFormat,
+        /// Write the export to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Upload a conversation as a secret GitHub gist or to a paste service and print its URL
+    Share {
+        /// Conversation ID
+        #[arg(add = ArgValueCandidates::new(completions::conversation_candidates))]
+        id: String,
+        /// Where to upload it (default: a gist when a GitHub token is available, else paste_url)
+        #[arg(long, value_enum)]
+        to: Option<share::Target>,
+        /// Strip file paths and secrets (API keys, tokens, private keys, passwords) before uploading
+        #[arg(long)]
+        redact: bool,
+    },
+    /// List available models and refresh the local model cache
+    Models,
+    /// Generate, edit or process images
+    Image {
+        #[command(subcommand)]
+        action: ImageCommands,
+    },
+    /// Manage the prompt library and run prompt templates (alias: tpl)
+    #[command(alias = "tpl")]
+    Templates {
+        #[command(subcommand)]
+        action: TemplatesCommands,
+    },
+    /// Check whether the API is reachable and the API key works
+    Status,
+    /// Diagnose the setup: config, API key and keyring, connectivity, speech programs and terminal
+    Doctor {
+        /// Print the findings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate completions for
+        shell: Shell,
+    },
+    /// Extract action items from a stored conversation
+    Tasks {
+        /// Conversation ID
+        #[arg(add = ArgValueCandidates::new(completions::conversation_candidates))]
+        id: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = TaskFormat::Markdown)]
+        format: TaskFormat,
+        /// Also append the tasks as Markdown checkboxes to this TODO file
+        #[arg(long)]
+        append: Option<PathBuf>,
+    },
+    /// Generate code for a task, printing or saving only the code
+    Code {
+        /// What the code should do
+        task: String,
+        /// Language to write it in (inferred from --out when omitted)
+        #[arg(long)]
+        lang: Option<String>,
+        /// Write the code to this file instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Run the generated code after confirming (scripting languages only)
+        #[arg(long)]
+        exec: bool,
+        /// Run the generated code without asking first, for unattended runs; --yes never does that
+        #[arg(long, conflicts_with = "exec")]
+        exec_unconfirmed: bool,
+    },
+    /// Review code changes: the uncommitted ones, the staged ones, a branch's, or a commit range
+    Review {
+        /// Commit range (e.g. HEAD~3..HEAD) or single commit to review
+        #[arg(conflicts_with_all = ["staged", "branch"])]
+        range: Option<String>,
+        /// Review the staged changes
+        #[arg(long, conflicts_with = "branch")]
+        staged: bool,
+        /// Review the current branch's changes since it diverged from this branch
+        #[arg(long, value_name = "BASE")]
+        branch: Option<String>,
+        /// Print the findings grouped by file, or as JSON for CI
+        #[arg(long, value_enum, default_value_t = ReviewOutput::Text)]
+        output: ReviewOutput,
+        /// Exit with status 1 if there are findings of this severity or worse
+        #[arg(long, value_enum, value_name = "SEVERITY")]
+        fail_on: Option<review::Severity>,
+        /// Largest part of the diff (in estimated tokens) sent in one request
+        #[arg(long, default_value_t = chunk::DEFAULT_CHUNK_TOKENS)]
+        chunk_tokens: usize,
+        /// Send the files of the repository most relevant to each part along with it
+        #[arg(long)]
+        repo: bool,
+    },
+    /// Change a file as instructed: the model proposes a diff, which is previewed and applied once confirmed
+    Edit {
+        /// File to change
+        file: PathBuf,
+        /// What to change (e.g. "make this function iterative")
+        instruction: String,
+        /// Only show the diff, without applying it
+        #[arg(long)]
+        dry_run: bool,
+        /// Send the other files of the repository most relevant to the change along with it
+        #[arg(long)]
+        repo: bool,
+    },
+    /// Watch files and, each time one is saved, send what changed to the model with the instruction
+    Watch {
+        /// What to do with each change (e.g. "summarize what changed and flag bugs")
+        instruction: String,
+        /// File to watch; can be repeated
+        #[arg(short = 'f', long = "file", value_name = "PATH", required = true)]
+        files: Vec<PathBuf>,
+        /// Send the whole file on every save instead of the diff from the previous version
+        #[arg(long)]
+        full: bool,
+    },
+    /// Write a pull request title and description for the current branch, and optionally create it with gh
+    Pr {
+        /// Branch the pull request goes into (default: the remote's default branch, or main)
+        #[arg(long)]
+        base: Option<String>,
+        /// Markdown template the description follows (default: pr_template in the config file, or the repository's)
+        #[arg(long, value_name = "FILE")]
+        template: Option<PathBuf>,
+        /// Create the pull request with the GitHub CLI (gh) after confirming
+        #[arg(long)]
+        push_gh: bool,
+        /// Create it as a draft
+        #[arg(long, requires = "push_gh")]
+        draft: bool,
+    },
+    /// Write a commit message for the staged changes and commit them
+    Commit {
+        /// Follow the Conventional Commits format
+        #[arg(long)]
+        conventional: bool,
+        /// Amend the previous commit, describing its changes together with the staged ones
+        #[arg(long)]
+        amend: bool,
+    },
+    /// Run a command and explain its output or failure
+    Explain {
+        /// Command to run, after `--` (e.g. ai-cli explain -- cargo build)
+        #[arg(last = true)]
+        command: Vec<String>,
+        /// Re-run and explain the previous command from your shell (needs the --hook snippet)
+        #[arg(long, conflicts_with = "command")]
+        last: bool,
+        /// Print the shell snippet that records commands for --last
+        #[arg(long, value_name = "SHELL", conflicts_with_all = ["command", "last"])]
+        hook: Option<Shell>,
+    },
+    /// Suggest a shell command for a task, then run, edit or copy it
+    Sh {
+        /// What the command should do
+        task: String,
+    },
+    /// Run many prompts from a file concurrently and write the results as JSONL
+    Batch {
+        /// One prompt per line, or a .jsonl file of {"prompt": ..., "id", "model", "system", "max_words"} objects
+        file: PathBuf,
+        /// Number of requests to run at the same time
+        #[arg(long, default_value_t = batch::DEFAULT_CONCURRENCY)]
+        concurrency: usize,
+        /// Write the results to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Summarize a file, a web page, stdin (-) or each file of a directory, splitting long texts into parts
+    Summarize {
+        /// File or directory path, http(s) URL, or - for stdin
+        source: String,
+        /// How long the summary should be
+        #[arg(long, value_enum, default_value_t = SummaryLength::Medium)]
+        length: SummaryLength,
+        /// Summarize as a bulleted list
+        #[arg(long)]
+        bullets: bool,
+        /// Largest part (in estimated tokens) sent in one request
+        #[arg(long, default_value_t = chunk::DEFAULT_CHUNK_TOKENS)]
+        chunk_tokens: usize,
+        #[command(flatten)]
+        directory: DirectoryArgs,
+    },
+    /// Translate a file, stdin or each file of a directory, keeping formatting and code blocks
+    Translate {
+        /// File or directory to translate (reads stdin when omitted)
+        file: Option<PathBuf>,
+        /// Language to translate into (e.g. de, French)
+        #[arg(long)]
+        to: String,
+        /// Language of the text (detected automatically when omitted)
+        #[arg(long)]
+        from: Option<String>,
+        /// Replace the file's contents (each file's, for a directory) with the translation
+        #[arg(long, requires = "file", conflicts_with = "output_dir")]
+        in_place: bool,
+        /// Largest part (in estimated tokens) sent in one request
+        #[arg(long, default_value_t = chunk::DEFAULT_CHUNK_TOKENS)]
+        chunk_tokens: usize,
+        #[command(flatten)]
+        directory: DirectoryArgs,
+    },
+    /// Turn text into speech with 1min.ai's text-to-speech models
+    Tts {
+        /// Text to speak (read from stdin when omitted)
+        text: Option<String>,
+        /// Voice to use (alloy, echo, fable, onyx, nova or shimmer)
+        #[arg(long, default_value = DEFAULT_TTS_VOICE)]
+        voice: String,
+        /// The text-to-speech model to use
+        #[arg(short, long, default_value = DEFAULT_TEXT_TO_SPEECH_MODEL, add = ArgValueCandidates::new(completions::model_candidates))]
+        model: String,
+        /// Save the audio to this file (default: speech.mp3, unless --play is given)
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Play the audio once it is ready
+        #[arg(long)]
+        play: bool,
+    },
+    /// Generate a short video from a prompt and download it as an MP4
+    Video {
+        /// What the video should show
+        prompt: String,
+        /// The video model to use
+        #[arg(short, long, default_value = DEFAULT_VIDEO_MODEL, add = ArgValueCandidates::new(completions::model_candidates))]
+        model: String,
+        /// Length of the video in seconds
+        #[arg(long, default_value_t = DEFAULT_VIDEO_DURATION_SECS, value_parser = clap::value_parser!(u32).range(1..=60))]
+        duration: u32,
+        /// Give up waiting for the video after this many seconds
+        #[arg(long, value_name = "SECS", default_value_t = DEFAULT_VIDEO_WAIT_SECS)]
+        max_wait: u64,
+        /// Save the video to this path (default: named after the prompt)
+        #[arg(short = 'o', long)]
+        output_file: Option<PathBuf>,
+        /// Open the video in the default player once it is saved
+        #[arg(long)]
+        open: bool,
+    },
+    /// Chat about documents such as PDFs
+    Doc {
+        #[command(subcommand)]
+        action: DocCommands,
+    },
+    /// Inspect or clear the response cache used by --cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommands,
+    },
+    /// Summarize recorded usage and estimated spend per model and per day
+    Usage {
+        /// Period to summarize: 12h, 7d, 4w, or a date like 2024-05-01
+        #[arg(long, default_value = "30d")]
+        since: String,
+        /// Print the summary as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect locally stored conversations
+    History {
+        #[command(subcommand)]
+        action: HistoryCommands,
+    },
+    /// Serve requests from editors and `--use-daemon` over a Unix socket, keeping the client and conversations warm
+    Daemon {
+        /// Listen on this socket instead of the default (also `AI_CLI_DAEMON_SOCKET`)
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+    },
+    /// Serve an OpenAI-compatible API (/v1/chat/completions) backed by the configured provider
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = DEFAULT_SERVE_PORT)]
+        port: u16,
+        /// Address to listen on; anyone who can reach it can use your account
+        #[arg(long, default_value = DEFAULT_SERVE_HOST)]
+        host: IpAddr,
+    },
+    /// Full-text search the prompts and responses of locally stored conversations
+    Search {
+        /// Words to look for; exchanges containing all of them match
+        query: String,
+        /// Show at most this many matching exchanges
+        #[arg(long, default_value_t = DEFAULT_SEARCH_LIMIT)]
+        limit: usize,
+        /// Continue the conversation of a hit interactively: the hit's number, or ask when omitted
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true)]
+        resume: Option<Option<usize>>,
+    },
+    /// Print embedding vectors for lines of text, or whole files, as JSON for vector databases
+    Embed {
+        /// Texts to embed, one input each (reads --input, or stdin, when omitted)
+        text: Vec<String>,
+        /// File whose lines are embedded, one input per non-empty line (- for stdin); can be repeated
+        #[arg(long, value_name = "FILE")]
+        input: Vec<String>,
+        /// Embed each --input file as a single input instead of line by line
+        #[arg(long)]
+        whole: bool,
+        /// Embedding model (default: embedding_model from [default] in the config file, or text-embedding-3-small)
+        #[arg(short, long)]
+        model: Option<String>,
+        /// One JSON array, or one JSON object per line
+        #[arg(long, value_enum, default_value_t = embeddings::Format::Json)]
+        format: embeddings::Format,
+        /// Inputs sent per request
+        #[arg(long, default_value_t = embeddings::DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+        /// Write the embeddings to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Index the files of a directory for `--knowledge`, or update an existing index
+    Index {
+        /// Directory to index
+        #[arg(required_unless_present_any = ["list", "remove"])]
+        dir: Option<PathBuf>,
+        /// Name to use the index by (default: the directory's name)
+        #[arg(long)]
+        name: Option<String>,
+        /// Embedding model (default: embedding_model from [default] in the config file, or text-embedding-3-small)
+        #[arg(short, long)]
+        model: Option<String>,
+        /// List the indexes instead
+        #[arg(long, conflicts_with_all = ["dir", "remove"])]
+        list: bool,
+        /// Delete this index instead
+        #[arg(long, value_name = "NAME", conflicts_with = "dir")]
+        remove: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Change a setting of the config file, e.g. `config set default.image_model flux-pro`
+    Set {
+        /// Setting to change, with tables separated by dots (e.g. default.chat_model)
+        key: String,
+        /// New value; numbers and true/false are stored as such
+        value: String,
+    },
 }
 
-#[derive(Serialize)]
-struct ConversationRequest {
-    #[serde(rename = "type")]
-    request_type: String,
-    title: String,
+#[derive(Subcommand)]
+enum AliasCommands {
+    /// List the aliases defined in the config file
+    List,
+    /// Define an alias, e.g. `alias add critique "chat -m gpt-4o --system 'You are a strict code reviewer' --file"`
+    Add {
+        /// Name to invoke it by
+        name: String,
+        /// What it stands for: arguments to ai-cli, as one quoted string or as separate words
+        #[arg(required = true, num_args = 1.., trailing_var_arg = true, allow_hyphen_values = true)]
+        definition: Vec<String>,
+    },
+    /// Remove an alias
+    Rm {
+        name: String,
+    },
 }
 
-#[derive(Deserialize)]
-struct ConversationResponse {
-    conversation: Conversation,
+#[derive(Subcommand)]
+enum McpCommands {
+    /// List the MCP servers in the config file
+    List,
+    /// Register an MCP server, e.g. `mcp add files npx -y @modelcontextprotocol/server-filesystem ~/notes`
+    Add {
+        /// Name its tools are called by, as `<name>.<tool>`
+        name: String,
+        /// Program that starts the server, followed by its arguments
+        #[arg(required = true, num_args = 1.., trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+        /// Environment variable to set for the server, as KEY=VALUE (repeatable)
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Whether its tool calls run without asking
+        #[arg(long, value_enum, default_value_t = tools::Policy::Ask)]
+        policy: tools::Policy,
+    },
+    /// Remove an MCP server
+    Rm {
+        name: String,
+    },
 }
 
-#[derive(Deserialize)]
-struct Conversation {
-    uuid: String,
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Remove every cached response
+    Clear,
+    /// Show how many responses are cached and how much space they take
+    Stats {
+        /// Print the statistics as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
-#[derive(Serialize)]
-struct ChatRequest {
-    #[serde(rename = "type")]
-    request_type: String,
-    #[serde(rename = "conversationId")]
-    conversation_id: String,
-    model: String,
-    #[serde(rename = "promptObject")]
-    prompt_object: PromptObject,
-}
-
-#[derive(Serialize)]
-struct PromptObject {
-    prompt: String,
-    #[serde(rename = "isMixed")]
-    is_mixed: bool,
-    #[serde(rename = "webSearch")]
-    web_search: bool,
-    #[serde(rename = "numOfSite")]
-    num_of_site: u32,
-    #[serde(rename = "maxWord")]
-    max_word: u32,
-}
-
-#[derive(Serialize)]
-struct ImageGenerationRequest {
-    #[serde(rename = "type")]
-    request_type: String,
-    model: String,
-    #[serde(rename = "promptObject")]
-    prompt_object: ImagePromptObject,
+#[derive(Subcommand)]
+enum HistoryCommands {
+    /// List stored conversations
+    List {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+    },
+    /// Show a stored conversation, with regenerated responses next to the original
+    Show {
+        /// Conversation ID
+        #[arg(add = ArgValueCandidates::new(completions::conversation_candidates))]
+        id: String,
+    },
+    /// Add tags to a stored conversation
+    Tag {
+        /// Conversation ID
+        #[arg(add = ArgValueCandidates::new(completions::conversation_candidates))]
+        id: String,
+        /// Tags to add
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
 }
 
-#[derive(Serialize)]
-struct ImagePromptObject {
-    #[serde(rename = "prompt")]
-    prompt: String,
-    #[serde(rename = "n")]
-    n: u32,
-    #[serde(rename = "size")]
-    size: String,
-    #[serde(rename = "quality")]
-    quality: String,
-    #[serde(rename = "style")]
-    style: String,
+#[derive(Subcommand)]
+enum DocCommands {
+    /// Upload a document and ask about it; without a question, starts interactive mode
+    Chat {
+        /// Document to upload (e.g. a PDF)
+        file: PathBuf,
+        /// Question about the document
+        prompt: Option<String>,
+    },
 }
 
-#[derive(Deserialize, Debug)]
-#[allow(non_snake_case)]
-struct ImageGenerationResponse {
-    aiRecord: AIRecord,
+#[derive(Subcommand)]
+enum ImageCommands {
+    /// Generate images from a prompt
+    Generate {
+        /// What the image should show
+        prompt: String,
+        /// The image model to use (default: dall-e-3)
+        #[arg(short, long, add = ArgValueCandidates::new(completions::model_candidates))]
+        model: Option<String>,
+        #[command(flatten)]
+        options: ImageGenerationArgs,
+    },
+    /// Edit an image according to a prompt, optionally limited to a mask
+    Edit {
+        /// What to change in the image
+        prompt: String,
+        /// Image to edit
+        #[arg(long)]
+        input: PathBuf,
+        /// PNG mask whose transparent areas mark the region to edit
+        #[arg(long)]
+        mask: Option<PathBuf>,
+        #[command(flatten)]
+        options: ImageToolArgs,
+    },
+    /// Create variations of an image
+    Variations {
+        /// Image to create variations of
+        #[arg(long)]
+        input: PathBuf,
+        #[command(flatten)]
+        options: ImageToolArgs,
+    },
+    /// Upscale an image; the result is saved next to it as NAME-upscaled-<SCALE>x
+    Upscale {
+        /// How many times larger to make the image
+        #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(2..=4))]
+        scale: u32,
+        #[command(flatten)]
+        options: ImageProcessingArgs,
+    },
+    /// Remove the background of an image; the result is saved next to it as NAME-no-bg.png
+    RemoveBg {
+        #[command(flatten)]
+        options: ImageProcessingArgs,
+    },
 }
 
-#[derive(Deserialize, Debug)]
-#[allow(non_snake_case, dead_code)]
-struct AIRecord {
-    #[serde(default)]
-    temporaryUrl: String,
-    status: String,
-    #[serde(default)]
-    aiRecordDetail: Option<AIRecordDetail>,
+#[derive(clap::Args)]
+struct ImageProcessingArgs {
+    /// Image to process
+    input: PathBuf,
+    /// The model to process it with
+    #[arg(short, long, default_value = DEFAULT_IMAGE_PROCESSING_MODEL, add = ArgValueCandidates::new(completions::model_candidates))]
+    model: String,
+    /// Save the result to this path instead of next to the original
+    #[arg(short = 'o', long)]
+    output_file: Option<PathBuf>,
+    /// Open the result in the default viewer
+    #[arg(long)]
+    open: bool,
+    /// Show the result in the terminal; --preview=PROTOCOL forces kitty, iterm2, sixel or blocks
+    #[arg(long, value_name = "PROTOCOL", num_args = 0..=1, require_equals = true)]
+    preview: Option<Option<preview::Protocol>>,
 }
 
-#[derive(Deserialize, Debug)]
-#[allow(non_snake_case, dead_code)]
-struct AIRecordDetail {
-    #[serde(default)]
-    resultObject: Option<Vec<String>>,
+#[derive(clap::Args)]
+struct ImageToolArgs {
+    /// The image model to use
+    #[arg(short, long, default_value = DEFAULT_IMAGE_TOOL_MODEL, add = ArgValueCandidates::new(completions::model_candidates))]
+    model: String,
+    /// Number of images to create
+    #[arg(short = 'n', long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=10))]
+    count: u32,
+    /// Image size (256x256, 512x512, 1024x1024)
+    #[arg(short, long, default_value = DEFAULT_IMAGE_SIZE)]
+    size: String,
+    /// Save the image to this path (indexed when creating several)
+    #[arg(short = 'o', long)]
+    output_file: Option<PathBuf>,
+    /// Directory to save images in (created if missing)
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+    /// Open the saved images in the default viewer
+    #[arg(long)]
+    open: bool,
+    /// Show the saved images in the terminal; --preview=PROTOCOL forces kitty, iterm2, sixel or blocks
+    #[arg(long, value_name = "PROTOCOL", num_args = 0..=1, require_equals = true)]
+    preview: Option<Option<preview::Protocol>>,
 }
 
-async fn get_api_key() -> Result<String> {
-    let keyring = Entry::new(SERVICE_NAME, USERNAME)?;
-    
-    match keyring.get_password() {
-        Ok(key) => Ok(key),
-        Err(_) => {
-            let api_key: String = Input::<String>::new()
-                .with_prompt("API key not found. Please enter your 1min.ai API key")
-                .allow_empty(false)
-                .interact()?;
-            
-            keyring.set_password(&api_key)?;
-            Ok(api_key)
+impl ImageProcessingArgs {
+    /// Where the result goes: next to the input unless `--output-file` says otherwise.
+    fn output(&self) -> media::ImageOutput<'_> {
+        media::ImageOutput {
+            file: self.output_file.as_deref(),
+            fit_extension: false,
+            dir: None,
+            open: self.open,
+            preview: self.preview,
+            parameters: None,
         }
     }
 }
 
-async fn set_api_key() -> Result<()> {
-    let api_key: String = Input::<String>::new()
-        .with_prompt("Please enter your 1min.ai API key")
-        .allow_empty(false)
-        .interact()?;
-    
-    let keyring = Entry::new(SERVICE_NAME, USERNAME)?;
-    keyring.set_password(&api_key)?;
-    println!("API key saved successfully!");
-    Ok(())
+impl ImageToolArgs {
+    fn edit<'a>(&'a self, tool: ImageTool, input: &'a Path, mask: Option<&'a Path>, prompt: Option<&'a str>) -> media::ImageEdit<'a> {
+        media::ImageEdit { tool, input, mask, prompt, model: &self.model, count: self.count, size: &self.size }
+    }
+
+    fn output(&self) -> media::ImageOutput<'_> {
+        media::ImageOutput {
+            file: self.output_file.as_deref(),
+            fit_extension: false,
+            dir: self.output_dir.as_deref(),
+            open: self.open,
+            preview: self.preview,
+            parameters: None,
+        }
+    }
 }
 
-async fn initialize_conversation(client: &Client, api_key: &str, prompt: &str) -> Result<String> {
-    let now = Local::now().format("%Y/%m/%d at %I:%M:%S %p").to_string();
-    
-    let request = ConversationRequest {
-        request_type: "CHAT_WITH_AI".to_string(),
-        title: format!("API - {}", now),
-    };
+/// Options for `summarize` and `translate` runs over a directory, taken by both.
+#[derive(clap::Args)]
+struct DirectoryArgs {
+    /// With a directory: write the results under this directory, in the same layout
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+    /// With a directory: number of files to process at the same time
+    #[arg(long, default_value_t = bulk::DEFAULT_CONCURRENCY)]
+    concurrency: usize,
+    /// With a directory: write a JSON report on every file to this path
+    #[arg(long)]
+    report: Option<PathBuf>,
+}
 
-    let response = client
-        .post(CONVERSATION_API_URL)
-        .header("API-KEY", api_key)
-        .header(header::CONTENT_TYPE, "application/json")
-        .json(&request)
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        let conversation: ConversationResponse = response.json().await?;
-        Ok(conversation.conversation.uuid)
-    } else {
-        let status = response.status();
-        let text = response.text().await?;
-        
-        if status.as_u16() == 401 {
-            let new_api_key: String = Input::<String>::new()
-                .with_prompt("Invalid API key. Please enter a new one")
-                .allow_empty(false)
-                .interact()?;
-            
-            let keyring = Entry::new(SERVICE_NAME, USERNAME)?;
-            keyring.set_password(&new_api_key)?;
-            
-            Box::pin(initialize_conversation(client, &new_api_key, prompt)).await
-        } else {
-            Err(anyhow!("Error communicating with conversation API: {} - {}", status, text))
+impl DirectoryArgs {
+    /// Fails if options that only apply to a directory were given for something else.
+    fn ensure_unused(&self) -> Result<()> {
+        if self.output_dir.is_some() || self.report.is_some() {
+            return Err(anyhow!("--output-dir and --report only apply to a directory"));
+        }
+        Ok(())
+    }
+
+    fn options<'a>(&'a self, cli: &'a Cli, chunk_tokens: usize) -> bulk::Options<'a> {
+        bulk::Options {
+            model: cli.model(),
+            max_words: cli.chat.max_words(),
+            chunk_tokens,
+            output_dir: self.output_dir.as_deref(),
+            concurrency: self.concurrency,
+            report: self.report.as_deref(),
+            print_report: cli.chat.output != OutputFormat::Text,
+            yes: cli.yes,
         }
     }
 }
 
-async fn chat_with_ai(
-    client: &Client, 
-    api_key: &str,
-    conversation_uuid: &str, 
-    prompt: &str,
-    model: &str,
-    max_words: u32,
-    quiet: bool,
-    voice_output: bool,
-) -> Result<()> {
-    let request = ChatRequest {
-        request_type: "CHAT_WITH_AI".to_string(),
-        conversation_id: conversation_uuid.to_string(),
-        model: model.to_string(),
-        prompt_object: PromptObject {
-            prompt: prompt.to_string(),
-            is_mixed: false,
-            web_search: false,
-            num_of_site: 0,
-            max_word: max_words,
-        },
-    };
+#[derive(Subcommand)]
+enum TemplatesCommands {
+    /// Clone or update the prompt library from its git repository
+    Sync {
+        /// Repository to clone from (defaults to templates_repo in the config file)
+        #[arg(long)]
+        repo: Option<String>,
+    },
+    /// List prompt templates with their placeholders
+    List,
+    /// Create a prompt template and open it in $EDITOR
+    New {
+        /// Template name
+        name: String,
+    },
+    /// Open a prompt template in $EDITOR
+    Edit {
+        /// Template name
+        name: String,
+    },
+    /// Run a template: <name> --placeholder=value ...
+    #[command(external_subcommand)]
+    Run(Vec<String>),
+}
 
-    let response = client
-        .post(STREAMING_FEATURES_API_URL)
-        .header("API-KEY", api_key)
-        .header(header::CONTENT_TYPE, "application/json")
-        .json(&request)
-        .send()
-        .await?;
+#[derive(Clone, Copy, ValueEnum)]
+enum SummaryLength {
+    Short,
+    Medium,
+    Long,
+}
 
-    if response.status().is_success() {
-        if !quiet {
-            print!("AI({}): ", model);
-            io::stdout().flush()?;
+impl SummaryLength {
+    fn guidance(self) -> &'static str {
+        match self {
+            SummaryLength::Short => "keeping it short, at most about 60 words",
+            SummaryLength::Medium => "in about 200 words",
+            SummaryLength::Long => "in detail, in up to about 600 words",
         }
+    }
+}
 
-        let mut stream = response.bytes_stream();
-        let mut full_response = String::with_capacity(1024);
+/// How long answers should be, from `--brief` or `--detailed`.
+#[derive(Clone, Copy)]
+enum Verbosity {
+    Brief,
+    Detailed,
+}
 
-        while let Some(item) = stream.next().await {
-            let chunk = item?;
-            let text_chunk = String::from_utf8_lossy(&chunk);
-            
-            if !quiet {
-                print!("{}", text_chunk);
-                io::stdout().flush()?;
-            }
-            
-            full_response.push_str(&text_chunk);
+impl Verbosity {
+    fn words(self) -> u32 {
+        match self {
+            Verbosity::Brief => 150,
+            Verbosity::Detailed => 2000,
         }
+    }
 
-        if !quiet {
-            println!();
+    /// Added to the system prompt, so the answer fits the limit rather than being cut off by it.
+    fn instruction(self) -> &'static str {
+        match self {
+            Verbosity::Brief => "Be brief: answer in a few sentences, without preamble, caveats or repetition.",
+            Verbosity::Detailed => "Be thorough: explain your reasoning step by step, cover edge cases and include examples where they help.",
         }
+    }
+}
 
-        if voice_output {
-            speak_response(&full_response)?;
-        }
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ExportFormat {
+    Md,
+    Json,
+    Html,
+}
 
-        Ok(())
-    } else {
-        let status = response.status();
-        let text = response.text().await?;
-        
-        if status.as_u16() == 401 {
-            let new_api_key: String = Input::<String>::new()
-                .with_prompt("Invalid API key. Please enter a new one")
-                .allow_empty(false)
-                .interact()?;
-            
-            let keyring = Entry::new(SERVICE_NAME, USERNAME)?;
-            keyring.set_password(&new_api_key)?;
-            
-            Box::pin(chat_with_ai(
-                client, 
-                &new_api_key, 
-                conversation_uuid, 
-                prompt, 
-                model, 
-                max_words, 
-                quiet, 
-                voice_output
-            )).await
-        } else {
-            Err(anyhow!("Error communicating with features API: {} - {}", status, text))
+#[derive(Clone, Copy, ValueEnum)]
+enum ListFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+
+/// Folds `ai-cli chat` and `ai-cli image generate` into the top-level options they stand for, so
+/// that the rest of `run` handles them like a bare prompt and `-g`.
+fn normalize_subcommands(cli: &mut Cli) {
+    match cli.command.take() {
+        Some(Commands::Chat { prompt, options }) => {
+            cli.prompt = prompt;
+            cli.chat = *options;
         }
+        Some(Commands::Image { action: ImageCommands::Generate { prompt, model, options } }) => {
+            cli.prompt = Some(prompt);
+            cli.chat.model = model;
+            cli.generation = options;
+            cli.image_generation = true;
+        }
+        command => cli.command = command,
     }
 }
 
-fn speak_response(text: &str) -> Result<()> {
-    Command::new("say")
-        .arg(text)
-        .spawn()?
-        .wait()?;
-    Ok(())
+/// Looks up the API key without prompting: `--api-key-file`, then `AI_CLI_API_KEY`, then the
+/// keyring or credential file (unless `--no-keyring`).
+fn find_api_key(cli: &Cli) -> Result<Option<(String, KeySource)>> {
+    if let Some(path) = &cli.api_key_file {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the API key file {}", path.display()))?;
+        let key = contents.trim();
+        if key.is_empty() {
+            return Err(anyhow!("The API key file {} is empty", path.display()));
+        }
+        return Ok(Some((key.to_string(), KeySource::File(path.clone()))));
+    }
+
+    if let Some(key) = std::env::var(API_KEY_ENV).ok().filter(|key| !key.trim().is_empty()) {
+        return Ok(Some((key.trim().to_string(), KeySource::Env)));
+    }
+
+    if cli.no_keyring {
+        return Ok(None);
+    }
+    Ok(credentials::load()?.map(|(key, location)| (key, key_source(location))))
 }
 
-async fn generate_image(client: &Client, api_key: &str, prompt: &str, model: &str, size: &str, quality: &str, style: &str) -> Result<()> {
-    println!("Generating image with {} model for prompt \"{}\"...", model, prompt);
-    
-    let request = ImageGenerationRequest {
-        request_type: "IMAGE_GENERATOR".to_string(),
-        model: model.to_string(),
-        prompt_object: ImagePromptObject {
-            prompt: prompt.to_string(),
-            n: 1,
-            size: size.to_string(),
-            quality: quality.to_string(),
-            style: style.to_string(),
-        },
-    };
+fn key_source(location: credentials::Location) -> KeySource {
+    match location {
+        credentials::Location::Keyring => KeySource::Keyring,
+        credentials::Location::File(path) => KeySource::CredentialFile(path),
+    }
+}
 
-    let response = client
-        .post(IMAGE_GENERATION_API_URL)
-        .header("API-KEY", api_key)
-        .header(header::CONTENT_TYPE, "application/json")
-        .json(&request)
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        let response_text = response.text().await?;
-        let image_response: ImageGenerationResponse = serde_json::from_str(&response_text)?;
-        
-        if image_response.aiRecord.status != "SUCCESS" {
-            return Err(anyhow!("Image generation failed with status: {}", image_response.aiRecord.status));
+async fn get_api_key(cli: &Cli) -> Result<String> {
+    let (api_key, source) = match find_api_key(cli)? {
+        Some(found) => found,
+        None if cli.no_keyring || non_interactive() => {
+            return Err(error::AiCliError::Auth(format!(
+                "no API key found. Set {} or pass --api-key-file <path>",
+                API_KEY_ENV
+            )).into());
         }
-        
-        println!("Image generated successfully. Downloading...");
-        
-        if image_response.aiRecord.temporaryUrl.is_empty() {
-            return Err(anyhow!("No image URL found in response"));
-        }
-        
-        let url = &image_response.aiRecord.temporaryUrl;
-        let filename = url.split('?').next()
-            .and_then(|path| path.split('/').last())
-            .unwrap_or(DEFAULT_IMAGE_FILENAME);
-        
-        let image_bytes = client
-            .get(url)
-            .send()
-            .await?
-            .bytes()
-            .await?;
-            
-        let path = Path::new(filename);
-        let mut file = File::create(path)?;
-        file.write_all(&image_bytes)?;
-        
-        println!("Image saved to {}", filename);
-        Ok(())
-    } else {
-        let status = response.status();
-        let text = response.text().await?;
-        
-        if status.as_u16() == 401 {
-            let new_api_key: String = Input::<String>::new()
-                .with_prompt("Invalid API key. Please enter a new one")
+        None => {
+            let api_key: String = Input::<String>::new()
+                .with_prompt("API key not found. Please enter your 1min.ai API key")
                 .allow_empty(false)
                 .interact()?;
-            
-            let keyring = Entry::new(SERVICE_NAME, USERNAME)?;
-            keyring.set_password(&new_api_key)?;
-            
-            Box::pin(generate_image(client, &new_api_key, prompt, model, size, quality, style)).await
-        } else {
-            let error_message = match serde_json::from_str::<serde_json::Value>(&text) {
-                Ok(json) => {
-                    if let Some(message) = json.get("message").and_then(|m| m.as_str()) {
-                        if let Some(space_pos) = message.find(' ') {
-                            if message[..space_pos].parse::<u32>().is_ok() {
-                                message[space_pos+1..].to_string()
-                            } else {
-                                message.to_string()
-                            }
-                        } else {
-                            message.to_string()
-                        }
-                    } else {
-                        text
-                    }
-                },
-                Err(_) => text,
-            };
-            
-            Err(anyhow!("{} - {}", status, error_message))
+
+            let location = credentials::save(&api_key)?;
+            (api_key, key_source(location))
         }
-    }
+    };
+
+    auth::install(AuthManager::new(auth_recovery(cli)?, source, &api_key));
+    Ok(api_key)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let client = Client::new();
+/// How to recover from a rejected API key: `--auth-recovery`, else `auth_recovery` in the config
+/// file. Asking for a new key is impossible when running non-interactively, so that fails instead.
+fn auth_recovery(cli: &Cli) -> Result<Recovery> {
+    let recovery = match cli.auth_recovery {
+        Some(recovery) => recovery,
+        None => config::Config::load()?.auth_recovery,
+    };
+    Ok(match recovery {
+        Recovery::Prompt if non_interactive() => Recovery::Fail,
+        recovery => recovery,
+    })
+}
 
-    match &cli.command {
-        Some(Commands::Config) => {
-            set_api_key().await?;
-            return Ok(());
+/// Sets up the `--verbose` log. Only this crate's events are logged, not those of the HTTP stack.
+fn init_logging(verbose: u8, log_file: Option<&Path>) -> Result<()> {
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+
+    let level = match verbose {
+        0 if log_file.is_none() => return Ok(()),
+        0 | 1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let filter = Targets::new().with_target("ai_cli", level);
+    let layer = tracing_subscriber::fmt::layer().with_target(false);
+    match log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(path)
+                .with_context(|| format!("Could not open the log file {}", path.display()))?;
+            tracing_subscriber::registry()
+                .with(layer.with_ansi(false).with_writer(std::sync::Mutex::new(file)).with_filter(filter))
+                .init();
         }
-        None => {}
+        None => tracing_subscriber::registry().with(layer.with_writer(io::stderr).with_filter(filter)).init(),
     }
+    Ok(())
+}
 
-    let api_key = get_api_key().await?;
+/// Builds the HTTP client with the timeout, proxy and TLS options. Without `--proxy`, reqwest
+/// picks up `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and `NO_PROXY` from the environment.
+fn http_client(cli: &Cli) -> Result<Client> {
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_secs(cli.connect_timeout))
+        .timeout(Duration::from_secs(cli.timeout));
 
-    let mut errors = Vec::with_capacity(3);
-    
-    if cli.quiet && !cli.voice_output {
-        errors.push("Quiet mode requires voice output to be enabled.");
+    if let Some(proxy) = &cli.proxy {
+        let proxy = reqwest::Proxy::all(proxy)
+            .map_err(|_| anyhow!("Invalid proxy URL '{}': use e.g. http://host:port or socks5://host:port", proxy))?;
+        builder = builder.proxy(proxy);
     }
-    
-    if cli.image_generation && cli.interactive {
-        errors.push("Image generation is not compatible with interactive mode.");
-    }
-    
-    if cli.image_generation && cli.voice_output {
-        errors.push("Image generation is not compatible with voice output mode.");
+    if let Some(path) = &cli.ca_cert {
+        let pem = std::fs::read(path).with_context(|| format!("Could not read the CA certificate {}", path.display()))?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("{} is not a valid PEM certificate", path.display()))?;
+        if certificates.is_empty() {
+            return Err(anyhow!("{} contains no PEM certificates", path.display()));
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
     }
-    
-    if !errors.is_empty() {
-        return Err(anyhow!("{}", errors.join("\nError: ")));
+    if cli.insecure {
+        eprintln!(
+            "WARNING: --insecure turns off TLS certificate verification. Your API key and every \
+             request can be read or changed by anyone between you and the server. Prefer --ca-cert."
+        );
+        builder = builder.danger_accept_invalid_certs(true);
     }
+    Ok(builder.build()?)
+}
 
-    if cli.image_generation {
-        match &cli.prompt {
-            Some(prompt) => {
-                let model = if cli.model == DEFAULT_MODEL {
-                    DEFAULT_IMAGE_MODEL
-                } else {
-                    &cli.model
-                };
-                
-                generate_image(&client, &api_key, prompt, model, &cli.size, &cli.quality, &cli.style).await?;
-                return Ok(());
-            }
-            None => {
-                return Err(anyhow!("Error: No prompt provided for image generation."));
-            }
+/// Creates the API client, pointed at `--base-url`, `AI_CLI_BASE_URL` or the config file's
+/// `base_url`, in that order, falling back to the public API.
+fn build_client(http: Client, api_key: String, cli: &Cli) -> Result<OneMinClient> {
+    let config = config::Config::load()?;
+    let mut client = OneMinClient::new(http, api_key);
+
+    let base_url = cli.base_url.clone()
+        .or_else(|| std::env::var(BASE_URL_ENV).ok().filter(|url| !url.trim().is_empty()))
+        .or(config.base_url);
+    if let Some(base_url) = base_url {
+        let base_url = base_url.trim();
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            return Err(anyhow!("Invalid API base URL '{}': it must start with http:// or https://", base_url));
         }
+        client = client.with_base_url(base_url);
+    }
+    if let Some(asset_base_url) = config.asset_base_url {
+        client = client.with_asset_base_url(asset_base_url.trim());
     }
+    Ok(client)
+}
 
-    let prompt = match &cli.prompt {
+async fn set_api_key() -> Result<()> {
+    ensure_interactive("Saving an API key", &format!("set {} or pass --api-key-file instead", API_KEY_ENV))?;
+    let api_key: String = Input::<String>::new()
+        .with_prompt("Please enter your 1min.ai API key")
+        .allow_empty(false)
+        .interact()?;
+
+    match credentials::save(&api_key)? {
+        credentials::Location::Keyring => println!("API key saved successfully!"),
+        credentials::Location::File(path) => println!("API key saved to {}", path.display()),
+    }
+    Ok(())
+}
+
+/// The first feature asked for that only the 1min.ai API offers, if any.
+fn onemin_only_feature(cli: &Cli) -> Option<&'static str> {
+    match &cli.command {
+        Some(Commands::Image { .. }) => Some("Editing images"),
+        Some(Commands::Doc { .. }) => Some("Document chat"),
+        Some(Commands::Tts { .. }) => Some("Text-to-speech"),
+        Some(Commands::Video { .. }) => Some("Video generation"),
+        _ if !cli.chat.images.is_empty() => Some("Attaching images with --image"),
+        _ if cli.chat.voice_input => Some("Voice input"),
+        _ if cli.chat.tts_voice.is_some() => Some("--tts-voice"),
+        _ => None,
+    }
+}
+
+/// The length limits and sampling parameters the daemon and the server answer with.
+fn request_defaults(cli: &Cli, config: &config::Config) -> RequestDefaults {
+    RequestDefaults {
+        max_words: cli.chat.max_words(),
+        max_tokens: cli.chat.max_tokens(),
+        sampling: cli.chat.sampling().or(config.sampling),
+    }
+}
+
+/// The options given for a chat that shape its responses, for the front matter of saved responses.
+fn response_options(cli: &Cli) -> Vec<(&'static str, String)> {
+    let chat = &cli.chat;
+    let mut options = Vec::new();
+    if let Some(provider) = &cli.provider {
+        options.push(("provider", provider.clone()));
+    }
+    if let Some(persona) = &chat.persona {
+        options.push(("persona", persona.clone()));
+    }
+    if let Some(system) = &chat.system {
+        options.push(("system", system.clone()));
+    }
+    if let Some(style) = &chat.response_style {
+        options.push(("response_style", style.clone()));
+    }
+    if let Some(template) = &chat.template {
+        options.push(("template", template.clone()));
+    }
+    if let Some(knowledge) = &chat.knowledge {
+        options.push(("knowledge", knowledge.clone()));
+    }
+    if chat.repo {
+        options.push(("repo", "true".to_string()));
+    }
+    if chat.brief {
+        options.push(("brief", "true".to_string()));
+    }
+    if chat.detailed {
+        options.push(("detailed", "true".to_string()));
+    }
+    if let Some(words) = chat.words {
+        options.push(("max_words", words.to_string()));
+    }
+    if let Some(tokens) = chat.max_tokens {
+        options.push(("max_tokens", tokens.to_string()));
+    }
+    if let Some(temperature) = chat.temperature {
+        options.push(("temperature", temperature.to_string()));
+    }
+    if let Some(top_p) = chat.top_p {
+        options.push(("top_p", top_p.to_string()));
+    }
+    if let Some(penalty) = chat.frequency_penalty {
+        options.push(("frequency_penalty", penalty.to_string()));
+    }
+    options
+}
+
+/// The provider an index was embedded with, which its prompts must be embedded with too.
+async fn embedding_provider(name: &str, config: &config::Config, client: &OneMinClient, http: &Client, cli: &Cli) -> Result<Box<dyn Provider>> {
+    let settings = providers::resolve(name, config)?;
+    if settings.kind != ProviderKind::OneMin {
+        return providers::connect(&settings, http.clone());
+    }
+    let api_key = match client.api_key() {
+        key if key.is_empty() => get_api_key(cli).await?,
+        key => key,
+    };
+    Ok(Box::new(build_client(http.clone(), api_key, cli)?))
+}
+
+/// Runs every diagnostic of `ai-cli doctor` and prints the findings with suggested fixes.
+async fn run_doctor(http: Client, cli: &Cli, json: bool) -> Result<()> {
+    let mut findings = vec![doctor::check_config(), doctor::check_keyring(cli.no_keyring)];
+    let (finding, api_key) = doctor::check_api_key(find_api_key(cli), cli.api_key_file.is_some());
+    findings.push(finding);
+
+    match build_client(http.clone(), api_key.clone().unwrap_or_default(), cli) {
+        Ok(client) => findings.extend(doctor::check_api(&client, api_key.as_deref()).await),
+        Err(e) => findings.push(doctor::Finding::fail("API", format!("{:#}", e), "Fix --base-url, AI_CLI_BASE_URL or base_url in the config file")),
+    }
+
+    let config = config::Config::load().unwrap_or_default();
+    let provider_name = cli.provider.clone().or(config.provider.clone()).unwrap_or_else(|| providers::ONEMIN.to_string());
+    findings.extend(doctor::check_provider(&provider_name, &config, http).await);
+
+    findings.extend(doctor::check_speech());
+    findings.push(doctor::check_terminal());
+    doctor::report(&findings, json)
+}
+
+/// Asks before resending the previous prompt verbatim, which is usually an accidental double Enter.
+fn confirm_duplicate_prompt() -> Result<bool> {
+    Ok(Confirm::new()
+        .with_prompt("You just sent this exact prompt. Send it again?")
+        .default(false)
+        .interact()?)
+}
+
+/// Sends the prompt to a running `ai-cli daemon` and prints the response as it streams in.
+async fn ask_daemon(cli: &Cli) -> Result<()> {
+    if cli.command.is_some() || cli.image_generation {
+        return Err(anyhow!("--use-daemon only works with chat prompts"));
+    }
+    let prompt = match &cli.prompt {
+        Some(prompt) => prompt.clone(),
+        None if !io::stdin().is_terminal() => io::read_to_string(io::stdin())?,
+        None => return Err(anyhow!("No prompt provided for the daemon.")),
+    };
+    let request = daemon::Request {
+        prompt,
+        model: cli.chat.model.clone(),
+        system: match (&cli.chat.system, cli.chat.verbosity()) {
+            (Some(system), Some(verbosity)) => Some(format!("{}\n\n{}", system, verbosity.instruction())),
+            (system, verbosity) => system.clone().or(verbosity.map(|verbosity| verbosity.instruction().to_string())),
+        },
+        max_words: Some(cli.chat.max_words()),
+        max_tokens: cli.chat.max_tokens(),
+        sampling: cli.chat.sampling(),
+        conversation: cli.chat.daemon_conversation.clone(),
+    };
+
+    let mut stdout = io::stdout();
+    daemon::ask(&daemon::socket_path()?, &request, |text| {
+        stdout.write_all(text.as_bytes())?;
+        Ok(stdout.flush()?)
+    }).await?;
+    println!();
+    Ok(())
+}
+
+/// Whether `name` is one of ai-cli's own subcommands, which take precedence over aliases.
+fn is_subcommand(name: &str) -> bool {
+    name == "help" || Cli::command().find_subcommand(name).is_some()
+}
+
+/// Expands an alias from the config file at the start of the command line, before it is parsed.
+fn expand_aliases(mut args: Vec<OsString>) -> Result<Vec<OsString>> {
+    // Only a word that could be an alias is worth reading the config file for.
+    match args.get(1).and_then(|arg| arg.to_str()) {
+        Some(first) if !first.starts_with('-') && !is_subcommand(first) => {}
+        _ => return Ok(args),
+    }
+    let mut aliases = config::Config::load()?.aliases;
+    aliases.retain(|name, _| !is_subcommand(name));
+    let rest = args.split_off(1);
+    args.extend(alias::expand(rest, &aliases)?);
+    Ok(args)
+}
+
+/// Checks that an alias can be invoked and its definition parsed before it is saved.
+fn validate_alias(name: &str, definition: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('-') || name.contains(char::is_whitespace) {
+        return Err(anyhow!("Invalid alias name '{}': use a single word that doesn't start with -", name));
+    }
+    if is_subcommand(name) {
+        return Err(anyhow!("'{}' is an ai-cli subcommand and can't be an alias", name));
+    }
+    if alias::split_words(definition)?.is_empty() {
+        return Err(anyhow!("The definition of alias '{}' is empty", name));
+    }
+    Ok(())
+}
+
+/// Parses a `KEY=VALUE` environment variable argument.
+fn parse_env_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("'{}' is not of the form KEY=VALUE", arg)),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    CompleteEnv::with_factory(Cli::command)
+        .var(completions::COMPLETE_VAR)
+        .complete();
+
+    if let Err(e) = run().await {
+        eprintln!("Error: {:#}", e);
+        let code = error::exit_code(&e);
+        let hint = auth::hint();
+        if code == error::EXIT_AUTH && !hint.is_empty() {
+            eprintln!("{}", hint);
+        }
+        std::process::exit(code);
+    }
+}
+
+async fn run() -> Result<()> {
+    let mut cli = Cli::parse_from(expand_aliases(std::env::args_os().collect())?);
+    normalize_subcommands(&mut cli);
+    let notify = cli.notify.map(|how| (how, job_name(&cli)));
+    let result = run_command(cli).await;
+    if let Some((how, job)) = notify {
+        notification::job_finished(how, job, result.as_ref().err());
+    }
+    result
+}
+
+/// What `--notify` calls the command's job.
+fn job_name(cli: &Cli) -> &'static str {
+    match &cli.command {
+        _ if cli.image_generation => "Image generation",
+        Some(Commands::Image { .. }) => "Image processing",
+        Some(Commands::Video { .. }) => "Video generation",
+        Some(Commands::Batch { .. }) => "Batch",
+        Some(Commands::Tts { .. }) => "Text to speech",
+        Some(Commands::Summarize { .. }) => "Summary",
+        Some(Commands::Translate { .. }) => "Translation",
+        Some(Commands::Review { .. }) => "Review",
+        Some(Commands::Embed { .. }) | Some(Commands::Index { .. }) => "Embedding",
+        _ => "Request",
+    }
+}
+
+async fn run_command(mut cli: Cli) -> Result<()> {
+    init_logging(cli.verbose, cli.log_file.as_deref())?;
+    // Asking about a document without a question starts a chat about it, and so does resuming a
+    // conversation without a follow-up or one found by a search.
+    let interactive = cli.chat.interactive
+        || matches!(&cli.command, Some(Commands::Doc { action: DocCommands::Chat { prompt: None, .. } }))
+        || (cli.chat.resume.is_some() && cli.command.is_none() && cli.prompt.is_none())
+        || matches!(&cli.command, Some(Commands::Search { resume: Some(_), .. }));
+    let has_terminal = io::stdin().is_terminal() && io::stdout().is_terminal();
+    ui::configure(cli.non_interactive || (!interactive && !has_terminal), cli.chat.screen_reader);
+    let http = http_client(&cli)?;
+    retry::configure(cli.retries, cli.retry_delay);
+
+    let mut resumed = None;
+    match &cli.command {
+        Some(Commands::Config { action: None }) => {
+            set_api_key().await?;
+            return Ok(());
+        }
+        Some(Commands::Config { action: Some(ConfigCommands::Set { key, value }) }) => {
+            let path = config::set(key, value)?;
+            println!("Set {} to {} in {}", key, value, path.display());
+            return Ok(());
+        }
+        Some(Commands::Alias { action }) => {
+            match action {
+                AliasCommands::List => {
+                    let aliases = config::Config::load()?.aliases;
+                    if aliases.is_empty() {
+                        println!("No aliases yet. Define one with `ai-cli alias add <name> \"<arguments>\"`.");
+                    }
+                    for (name, definition) in aliases {
+                        println!("{:<20} {}", name, definition);
+                    }
+                }
+                AliasCommands::Add { name, definition } => {
+                    // A single argument is the definition as typed; several are joined back into one.
+                    let definition = match definition.as_slice() {
+                        [definition] => definition.clone(),
+                        words => alias::join_words(words),
+                    };
+                    validate_alias(name, &definition)?;
+                    let path = config::set_alias(name, &definition)?;
+                    println!("Alias '{}' saved to {}", name, path.display());
+                }
+                AliasCommands::Rm { name } => {
+                    let path = config::remove_alias(name)?;
+                    println!("Alias '{}' removed from {}", name, path.display());
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::Mcp { action }) => {
+            match action {
+                McpCommands::List => {
+                    let servers = config::Config::load()?.mcp_servers;
+                    if servers.is_empty() {
+                        println!("No MCP servers yet. Register one with `ai-cli mcp add <name> <command>`.");
+                    }
+                    for (name, server) in servers {
+                        let command = alias::join_words(&[vec![server.command.clone()], server.args.clone()].concat());
+                        let policy = server.policy.to_possible_value().expect("no policy is skipped");
+                        println!("{:<20} {:<6} {}", name, policy.get_name(), command);
+                    }
+                }
+                McpCommands::Add { name, command, env, policy } => {
+                    let server = mcp::ServerConfig {
+                        command: command[0].clone(),
+                        args: command[1..].to_vec(),
+                        env: env.iter().cloned().collect(),
+                        policy: *policy,
+                    };
+                    let path = config::set_mcp_server(name, &server)?;
+                    println!("MCP server '{}' saved to {}", name, path.display());
+                }
+                McpCommands::Rm { name } => {
+                    let path = config::remove_mcp_server(name)?;
+                    println!("MCP server '{}' removed from {}", name, path.display());
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::History { action }) => {
+            match action {
+                HistoryCommands::List { format } => {
+                    let summaries: Vec<_> = history::list()?.iter().map(|r| r.summary()).collect();
+                    match format {
+                        ListFormat::Table => history::print_table(&summaries),
+                        ListFormat::Json => println!("{}", serde_json::to_string_pretty(&summaries)?),
+                        ListFormat::Csv => history::print_csv(&summaries),
+                    }
+                }
+                HistoryCommands::Show { id } => print!("{}", history::load(id)?.render_with_retries()),
+                HistoryCommands::Tag { id, tags } => {
+                    history::add_tags(id, tags)?;
+                    println!("Tagged conversation {}", id);
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::Export { id, redact, editor, format, output }) => {
+            if *editor && *format != ExportFormat::Md {
+                return Err(anyhow!("--editor only works with Markdown exports"));
+            }
+            if *redact {
+                ensure_interactive("Redacting an export", "export without --redact, or run it in a terminal")?;
+            }
+            let mut record = history::load(id)?;
+            if *redact && !*editor {
+                record = export::redact_turns(&record)?;
+            }
+
+            let mut rendered = match format {
+                ExportFormat::Md => export::render_markdown(&record),
+                ExportFormat::Json => export::render_json(&record)? + "\n",
+                ExportFormat::Html => export::render_html(&record),
+            };
+            if *editor {
+                rendered = export::redact_in_editor(&rendered)?;
+            }
+
+            match output {
+                Some(path) => {
+                    std::fs::write(path, rendered)?;
+                    println!("Conversation exported to {}", path.display());
+                }
+                None => print!("{}", rendered),
+            }
+            return Ok(());
+        }
+        Some(Commands::Share { id, to, redact }) => {
+            share::publish(&http, id, *to, *redact, cli.yes).await?;
+            return Ok(());
+        }
+        Some(Commands::Templates { action: TemplatesCommands::Run(_) }) => {}
+        Some(Commands::Templates { action }) => {
+            match action {
+                TemplatesCommands::Sync { repo } => {
+                    let config = config::Config::load()?;
+                    templates::sync(repo.as_deref().or(config.templates_repo.as_deref()))?;
+                    println!("Prompt library is up to date.");
+                }
+                TemplatesCommands::List => {
+                    let templates = templates::list_templates()?;
+                    if templates.is_empty() {
+                        println!("No templates yet. Create one with `ai-cli templates new <name>`.");
+                    }
+                    for (name, template) in templates {
+                        let placeholders: Vec<String> = template.placeholders().iter().map(|p| format!("--{}", p)).collect();
+                        println!("{:<20} {}", name, template.description);
+                        if !placeholders.is_empty() {
+                            println!("{:<20} {}", "", placeholders.join(" "));
+                        }
+                    }
+                }
+                TemplatesCommands::New { name } => {
+                    let path = templates::create_template(name)?;
+                    templates::edit_template(name)?;
+                    println!("Template saved to {}", path.display());
+                }
+                TemplatesCommands::Edit { name } => {
+                    templates::edit_template(name)?;
+                    println!("Template '{}' saved.", name);
+                }
+                TemplatesCommands::Run(_) => unreachable!("templates are run as chat prompts"),
+            }
+            return Ok(());
+        }
+        Some(Commands::Cache { action }) => {
+            match action {
+                CacheCommands::Clear => println!("Removed {} cached responses.", cache::clear()?),
+                CacheCommands::Stats { json: true } => println!("{}", serde_json::to_string_pretty(&cache::stats()?)?),
+                CacheCommands::Stats { json: false } => cache::print_stats(&cache::stats()?),
+            }
+            return Ok(());
+        }
+        Some(Commands::Completions { shell }) => {
+            completions::write_registration(*shell, Cli::command().get_name())?;
+            return Ok(());
+        }
+        Some(Commands::Usage { since, json }) => {
+            let since = usage::parse_since(since)?;
+            let summary = usage::summarize(&usage::load_since(since)?, since);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            } else {
+                usage::print_summary(&summary);
+                if let Some(budget) = config::Config::load()?.monthly_budget {
+                    println!();
+                    usage::print_budget(budget)?;
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::Explain { hook: Some(shell), .. }) => {
+            print!("{}", explain::hook_script(*shell)?);
+            return Ok(());
+        }
+        Some(Commands::Status) => {
+            let api_key = find_api_key(&cli)?.map(|(key, _)| key);
+            let client = build_client(http.clone(), api_key.clone().unwrap_or_default(), &cli)?;
+            return status::report(&client, api_key.as_deref()).await;
+        }
+        Some(Commands::Doctor { json }) => return run_doctor(http, &cli, *json).await,
+        Some(Commands::Index { list: true, .. }) => {
+            let indexes = knowledge::list()?;
+            if indexes.is_empty() {
+                println!("No indexes yet. Create one with `ai-cli index <DIR> --name <NAME>`.");
+            }
+            for index in indexes {
+                println!(
+                    "{:<20} {:>6} files {:>7} chunks  {}  {} ({})",
+                    index.name, index.files, index.chunks, index.root.display(), index.model, index.provider
+                );
+            }
+            return Ok(());
+        }
+        Some(Commands::Index { remove: Some(name), .. }) => {
+            knowledge::remove(name)?;
+            println!("Index '{}' removed.", name);
+            return Ok(());
+        }
+        Some(Commands::Search { query, limit, resume }) => {
+            let hits = search::search(query, *limit)?;
+            if hits.is_empty() {
+                println!("No stored exchanges match '{}'.", query);
+                return Ok(());
+            }
+            search::print_hits(&hits);
+            match resume {
+                Some(number) => resumed = Some(search::pick_hit(&hits, *number)?),
+                None => return Ok(()),
+            }
+        }
+        Some(Commands::Chat { .. }) | Some(Commands::Models) | Some(Commands::Image { .. }) | Some(Commands::Tasks { .. }) | Some(Commands::Code { .. }) | Some(Commands::Commit { .. }) | Some(Commands::Explain { .. }) | Some(Commands::Sh { .. }) | Some(Commands::Batch { .. }) | Some(Commands::Doc { .. }) | Some(Commands::Tts { .. }) | Some(Commands::Video { .. }) | Some(Commands::Summarize { .. }) | Some(Commands::Translate { .. }) | Some(Commands::Daemon { .. }) | Some(Commands::Serve { .. }) | Some(Commands::Index { .. }) | Some(Commands::Embed { .. }) | Some(Commands::Review { .. }) | Some(Commands::Edit { .. }) | Some(Commands::Watch { .. }) | Some(Commands::Pr { .. }) | Some(Commands::Tui) | None => {}
+    }
+
+    if cli.chat.use_daemon {
+        return ask_daemon(&cli).await;
+    }
+
+    let config = config::Config::load()?;
+    let provider_name = cli.provider.clone()
+        .or_else(|| config.provider.clone())
+        .unwrap_or_else(|| providers::ONEMIN.to_string());
+    let settings = providers::resolve(&provider_name, &config)?;
+    models::use_provider(&settings.name);
+    rate_limit::configure(cli.rate_limit.or(settings.requests_per_minute));
+    // Without --model, the task's default from the config file is used, then the provider's.
+    let model_given = cli.chat.model.is_some();
+    let (task, builtin) = match &cli.command {
+        Some(Commands::Code { .. }) | Some(Commands::Review { .. }) | Some(Commands::Edit { .. }) | Some(Commands::Watch { .. }) => (config::Task::Code, DEFAULT_MODEL),
+        Some(Commands::Summarize { .. }) => (config::Task::Summarize, DEFAULT_MODEL),
+        Some(Commands::Translate { .. }) => (config::Task::Translate, DEFAULT_MODEL),
+        _ if cli.image_generation => (config::Task::Image, DEFAULT_IMAGE_MODEL),
+        _ => (config::Task::Chat, DEFAULT_MODEL),
+    };
+    if !model_given {
+        cli.chat.model = Some(config.task_model(task, settings.default_model.as_deref(), builtin));
+    }
+
+    let onemin = settings.kind == ProviderKind::OneMin;
+    if let Some(feature) = onemin_only_feature(&cli).filter(|_| !onemin) {
+        return Err(error::AiCliError::Unsupported(format!(
+            "{} is only available with the 1min.ai provider, not '{}'",
+            feature, settings.name
+        )).into());
+    }
+    let api_key = if onemin {
+        get_api_key(&cli).await?
+    } else {
+        auth::install(AuthManager::new(auth_recovery(&cli)?, KeySource::Provider(settings.api_key_env.clone()), ""));
+        String::new()
+    };
+    let client = build_client(http.clone(), api_key, &cli)?;
+    let other_provider = if onemin { None } else { Some(providers::connect(&settings, http.clone())?) };
+    let provider = Redacting::new(other_provider.as_deref().unwrap_or(&client), config.redaction);
+    let provider: &dyn Provider = &provider;
+
+    if let Some(Commands::Image { action }) = &cli.command {
+        match action {
+            ImageCommands::Generate { .. } => unreachable!("image generation runs as -g"),
+            ImageCommands::Edit { prompt, input, mask, options } => {
+                let edit = options.edit(ImageTool::Edit, input, mask.as_deref(), Some(prompt));
+                media::edit_image(&client, &edit, &options.output()).await?;
+            }
+            ImageCommands::Variations { input, options } => {
+                let edit = options.edit(ImageTool::Variations, input, None, None);
+                media::edit_image(&client, &edit, &options.output()).await?;
+            }
+            ImageCommands::Upscale { scale, options } => {
+                media::process_image(&client, ImageProcessing::Upscale { scale: *scale }, &options.model, &options.input, &options.output()).await?;
+            }
+            ImageCommands::RemoveBg { options } => {
+                media::process_image(&client, ImageProcessing::RemoveBackground, &options.model, &options.input, &options.output()).await?;
+            }
+        }
+        return Ok(());
+    }
+
+    let embedding_model = |model: &Option<String>| {
+        model.clone().unwrap_or_else(|| config.task_model(config::Task::Embedding, None, settings.kind.default_embedding_model()))
+    };
+    if let Some(Commands::Index { dir: Some(dir), name, model, .. }) = &cli.command {
+        return knowledge::index_directory(provider, dir, name.as_deref(), &embedding_model(model)).await;
+    }
+
+    if let Some(Commands::Embed { text, input, whole, model, format, batch_size, output }) = &cli.command {
+        let inputs = match (text.as_slice(), input.as_slice()) {
+            ([], []) if io::stdin().is_terminal() => return Err(anyhow!("Give the texts to embed, --input files, or pipe them in")),
+            ([], []) => embeddings::read_inputs(&["-".to_string()], *whole)?,
+            (texts, files) => texts.iter()
+                .enumerate()
+                .map(|(index, text)| embeddings::Input { source: format!("arg:{}", index + 1), text: text.clone() })
+                .chain(embeddings::read_inputs(files, *whole)?)
+                .collect(),
+        };
+        return embeddings::embed_inputs(provider, inputs, &embedding_model(model), *format, *batch_size, output.as_deref()).await;
+    }
+
+    if let Some(Commands::Tasks { id, format, append }) = &cli.command {
+        cli.assistant(provider).extract_tasks(id, *format, append.as_deref()).await?;
+        return Ok(());
+    }
+
+    if let Some(Commands::Code { task, lang, out, exec, exec_unconfirmed }) = &cli.command {
+        let exec = match (exec, exec_unconfirmed) {
+            (_, true) => Execution::Unconfirmed,
+            (true, _) => Execution::Confirmed,
+            _ => Execution::None,
+        };
+        return cli.assistant(provider).generate_code(task, lang.as_deref(), out.as_deref(), exec).await;
+    }
+
+    let context_limit = cli.chat.context_limit.or(config.context_limit).unwrap_or(compact::DEFAULT_CONTEXT_LIMIT_TOKENS);
+    if let Some(Commands::Review { range, staged, branch, output, fail_on, chunk_tokens, repo }) = &cli.command {
+        let target = match (range, branch) {
+            (Some(range), _) => git::DiffTarget::Range(range),
+            (None, Some(base)) => git::DiffTarget::Branch(base),
+            (None, None) if *staged => git::DiffTarget::Staged,
+            (None, None) => git::DiffTarget::Uncommitted,
+        };
+        let repo = repo.then(context::Repo::load).transpose()?;
+        return cli.assistant(provider).review_changes(&target, *output, *fail_on, *chunk_tokens, repo.as_ref().map(|repo| (repo, context_limit))).await;
+    }
+
+    if let Some(Commands::Edit { file, instruction, dry_run, repo }) = &cli.command {
+        let repo = repo.then(context::Repo::load).transpose()?;
+        return cli.assistant(provider).edit_file(file, instruction, *dry_run, repo.as_ref().map(|repo| (repo, context_limit))).await;
+    }
+
+    if let Some(Commands::Pr { base, template, push_gh, draft }) = &cli.command {
+        let base = base.clone().unwrap_or_else(git::default_base);
+        let template = template.clone().or(config.pr_template.clone());
+        return cli.assistant(provider).write_pull_request(&base, template.as_deref(), *push_gh, *draft).await;
+    }
+
+    if let Some(Commands::Commit { conventional, amend }) = &cli.command {
+        return cli.assistant(provider).commit_staged(*conventional, *amend).await;
+    }
+
+    if let Some(Commands::Batch { file, concurrency, output }) = &cli.command {
+        let options = batch::BatchOptions {
+            model: cli.model().to_string(),
+            system: match (&cli.chat.system, &cli.chat.persona) {
+                (Some(system), _) => Some(system.clone()),
+                (None, Some(persona)) => Some(config.persona(persona)?),
+                (None, None) => None,
+            },
+            max_words: cli.chat.max_words(),
+            concurrency: *concurrency,
+        };
+        return batch::run_file(provider, file, &options, output.as_deref(), &config, cli.yes).await;
+    }
+
+    if let Some(Commands::Summarize { source, length, bullets, chunk_tokens, directory }) = &cli.command {
+        if Path::new(source).is_dir() {
+            let task = bulk::Task::Summarize { length: length.guidance(), bullets: *bullets };
+            return bulk::process_directory(provider, Path::new(source), task, &directory.options(&cli, *chunk_tokens)).await;
+        }
+        directory.ensure_unused()?;
+        return cli.assistant(provider).summarize_source(&http, source, length.guidance(), *bullets, *chunk_tokens).await;
+    }
+
+    if let Some(Commands::Translate { file, to, from, in_place, chunk_tokens, directory }) = &cli.command {
+        if let Some(dir) = file.as_deref().filter(|file| file.is_dir()) {
+            let task = bulk::Task::Translate { to, from: from.as_deref(), in_place: *in_place };
+            return bulk::process_directory(provider, dir, task, &directory.options(&cli, *chunk_tokens)).await;
+        }
+        directory.ensure_unused()?;
+        return cli.assistant(provider).translate_text(file.as_deref(), to, from.as_deref(), *in_place, *chunk_tokens).await;
+    }
+
+    if let Some(Commands::Tts { text, voice, model, out, play }) = &cli.command {
+        let text = match text {
+            Some(text) => text.clone(),
+            None if !io::stdin().is_terminal() => io::read_to_string(io::stdin())?,
+            None => return Err(anyhow!("Give the text to speak, or pipe it in")),
+        };
+        return media::synthesize_speech(&client, &text, model, voice, out.as_deref(), *play).await;
+    }
+
+    if let Some(Commands::Video { prompt, model, duration, max_wait, output_file, open }) = &cli.command {
+        let options = VideoGeneration { prompt, model, duration: *duration };
+        return media::generate_video(&client, &options, Duration::from_secs(*max_wait), output_file.as_deref(), *open).await;
+    }
+
+    if let Some(Commands::Daemon { socket }) = &cli.command {
+        let path = match socket {
+            Some(path) => path.clone(),
+            None => daemon::socket_path()?,
+        };
+        let auth = auth::installed().expect("installed along with the API key").unattended();
+        let daemon = daemon::Daemon::new(client, other_provider, cli.model().to_string(), request_defaults(&cli, &config), config.model_rates, config.redaction, auth);
+        return daemon::serve(daemon, &path).await;
+    }
+
+    if let Some(Commands::Serve { port, host }) = &cli.command {
+        let provider: Arc<dyn Provider> = match other_provider {
+            Some(provider) => Arc::from(provider),
+            None => Arc::new(client),
+        };
+        let api_key = match config.serve_api_key.clone().filter(|key| !key.is_empty()) {
+            Some(key) => key,
+            None => {
+                let key = server::generate_api_key();
+                println!("Clients must send the API key {} (set serve_api_key in the config file to keep one across restarts).", key);
+                key
+            }
+        };
+        let server = server::Server::new(provider, cli.model().to_string(), request_defaults(&cli, &config), config.model_rates, config.redaction, api_key);
+        return server::serve(server, SocketAddr::new(*host, *port)).await;
+    }
+
+    if let Some(Commands::Tui) = &cli.command {
+        ensure_interactive("The TUI", "run it in a terminal")?;
+        return tui::run(tui::Options {
+            provider: other_provider.as_deref().unwrap_or(&client),
+            client: onemin.then_some(&client),
+            model: cli.model().to_string(),
+            max_words: cli.chat.max_words(),
+            max_tokens: cli.chat.max_tokens(),
+            sampling: cli.chat.sampling(),
+            default_sampling: config.sampling,
+            model_rates: config.model_rates,
+            redaction: config.redaction,
+        }).await;
+    }
+
+    if let Some(Commands::Sh { task }) = &cli.command {
+        return cli.assistant(provider).suggest_command(task).await;
+    }
+
+    if let Some(Commands::Models) = &cli.command {
+        let models = models::fetch_models(provider).await?;
+        models::print_table(&models);
+        return Ok(());
+    }
+
+    let mut errors = Vec::with_capacity(5);
+    
+    if cli.chat.quiet && !cli.chat.voice_output {
+        errors.push("Quiet mode requires voice output to be enabled.");
+    }
+    
+    if cli.image_generation && cli.chat.interactive {
+        errors.push("Image generation is not compatible with interactive mode.");
+    }
+    
+    if cli.image_generation && cli.chat.voice_output {
+        errors.push("Image generation is not compatible with voice output mode.");
+    }
+    
+    if cli.image_generation && cli.chat.output != OutputFormat::Text {
+        errors.push("Image generation does not support JSON output.");
+    }
+    
+    if cli.image_generation && !cli.chat.images.is_empty() {
+        errors.push("Image generation does not support --image; use 'ai-cli image edit' instead.");
+    }
+    
+    if cli.image_generation && !cli.chat.files.is_empty() {
+        errors.push("Image generation does not support attaching files.");
+    }
+    
+    if cli.generation.enhance_prompt && !cli.image_generation {
+        errors.push("--enhance-prompt only works with image generation.");
+    }
+    
+    if cli.chat.interactive && cli.non_interactive {
+        errors.push("Interactive mode is not compatible with --non-interactive.");
+    }
+    
+    if cli.chat.follow && (cli.chat.interactive || cli.image_generation) {
+        errors.push("Follow mode is not compatible with interactive or image generation mode.");
+    }
+    
+    let doc_chat = matches!(&cli.command, Some(Commands::Doc { .. }));
+    if doc_chat && (cli.image_generation || cli.chat.follow || !cli.chat.compare.is_empty() || cli.chat.regen.is_some()) {
+        errors.push("Document chat is not compatible with image generation, follow, --compare or --regen.");
+    }
+
+    if cli.chat.resume.is_some() && (cli.image_generation || cli.command.is_some()) {
+        errors.push("--resume only continues a chat; it is not compatible with image generation or subcommands.");
+    }
+
+    if doc_chat && !cli.chat.images.is_empty() {
+        errors.push("Document chat does not support --image.");
+    }
+
+    if !errors.is_empty() {
+        return Err(anyhow!("{}", errors.join("\nError: ")));
+    }
+
+    if let Some(id) = &cli.chat.resume {
+        resumed = Some(if id.is_empty() {
+            history::list()?.into_iter().next().ok_or_else(|| anyhow!("There are no stored conversations to resume"))?
+        } else {
+            history::load(id)?
+        });
+    }
+
+    let regen = match &cli.chat.regen {
+        Some(id) => {
+            let record = if id.is_empty() {
+                history::list()?.into_iter().next().ok_or_else(|| anyhow!("There are no stored conversations to regenerate"))?
+            } else {
+                history::load(id)?
+            };
+            let turn = record.last_original_turn()
+                .ok_or_else(|| anyhow!("Conversation {} has no prompt to regenerate", record.id))?;
+            Some((record, turn))
+        }
+        None => None,
+    };
+
+    let initial_prompt = match (&cli.command, &cli.chat.template, &cli.prompt) {
+        _ if regen.is_some() => regen.as_ref().map(|(record, turn)| record.turns[*turn].prompt.clone()),
+        (Some(Commands::Explain { command, last, .. }), _, _) => match explain::prepare(command, *last, cli.chat.output == OutputFormat::Text)? {
+            Some(prompt) => Some(prompt),
+            None => {
+                println!("Aborted.");
+                return Ok(());
+            }
+        },
+        (Some(Commands::Doc { action: DocCommands::Chat { prompt, .. } }), _, _) => prompt.clone(),
+        (Some(Commands::Watch { instruction, .. }), _, _) => Some(instruction.clone()),
+        (Some(Commands::Templates { action: TemplatesCommands::Run(args) }), _, _) => {
+            let (name, vars) = args.split_first().ok_or_else(|| anyhow!("No template name given"))?;
+            Some(templates::render_template(name, vars, None)?)
+        }
+        (_, Some(name), prompt) => Some(templates::render_template(name, &[], prompt.as_deref())?),
+        (_, None, Some(p)) => Some(p.clone()),
+        (_, None, None) if cli.chat.voice_input && !interactive => Some(media::voice_prompt(&client).await?),
+        (_, None, None) => None,
+    };
+
+    let initial_prompt = match (cli.chat.editor, initial_prompt) {
+        (false, prompt) => prompt,
+        (true, prompt) => match prompt_from_editor(prompt.as_deref().unwrap_or_default())? {
+            Some(prompt) => Some(prompt),
+            None => {
+                println!("Aborted: the prompt is empty.");
+                return Ok(());
+            }
+        },
+    };
+
+    let initial_prompt = match (cli.chat.paste, initial_prompt) {
+        (false, prompt) => prompt,
+        (true, None) => Some(clipboard::paste()?),
+        (true, Some(prompt)) => Some(format!("{}\n\n{}", prompt, clipboard::paste()?)),
+    };
+
+    if cli.image_generation {
+        match &initial_prompt {
+            Some(prompt) => {
+                if !provider.generates_images() {
+                    return Err(error::AiCliError::Unsupported(format!("The '{}' provider can't generate images", provider.name())).into());
+                }
+                let model = cli.model();
+                models::validate(model)?;
+                
+                let mut options = ImageGeneration {
+                    prompt,
+                    model,
+                    count: cli.generation.count,
+                    size: cli.generation.size.as_deref(),
+                    quality: cli.generation.quality.as_deref(),
+                    style: cli.generation.style.as_deref(),
+                    aspect_ratio: cli.generation.aspect_ratio.as_deref(),
+                    negative_prompt: cli.generation.negative_prompt.as_deref(),
+                    steps: cli.generation.steps,
+                    cfg_scale: cli.generation.cfg_scale,
+                    seed: cli.generation.seed,
+                };
+                image_models::resolve(&mut options)?;
+                let credits = cost::estimate_image_credits(options.model, options.count, options.quality, &config.model_rates);
+                let what = format!("Generating {} image(s) with {}", options.count, options.model);
+                if !check_budget(&what, credits, &config)? {
+                    println!("Aborted.");
+                    return Ok(());
+                }
+
+                let enhanced = if cli.generation.enhance_prompt {
+                    let enhance_model = cli.generation.enhance_model.clone()
+                        .unwrap_or_else(|| config.task_model(config::Task::Chat, settings.default_model.as_deref(), DEFAULT_MODEL));
+                    match image_prompt::enhance(provider, prompt, &enhance_model, cli.chat.max_words(), cli.yes).await? {
+                        Some(enhanced) => Some(enhanced),
+                        None => return Ok(()),
+                    }
+                } else {
+                    None
+                };
+                if let Some(enhanced) = &enhanced {
+                    options.prompt = enhanced;
+                }
+                let parameters = image_models::Parameters::new(&options, prompt);
+
+                let output = media::ImageOutput {
+                    file: cli.generation.output_file.as_deref(),
+                    fit_extension: false,
+                    dir: cli.generation.output_dir.as_deref(),
+                    open: cli.generation.open,
+                    preview: cli.generation.preview,
+                    parameters: Some(&parameters),
+                };
+                media::generate_image(&client, provider, &options, &output).await?;
+                usage::record(&usage::UsageEntry::image(options.model, options.prompt, credits))?;
+                return Ok(());
+            }
+            None => {
+                return Err(anyhow!("No prompt provided for image generation."));
+            }
+        }
+    }
+
+    // A regenerated response keeps the original model unless another one is asked for, and so
+    // does a resumed conversation.
+    let model = match (&regen, &resumed) {
+        (Some((record, turn)), _) if !model_given => record.turns[*turn].model.clone(),
+        (_, Some(record)) if !model_given && !record.model.is_empty() => record.model.clone(),
+        _ => cli.model().to_string(),
+    };
+    models::validate(&model)?;
+    sampling::resolve(&model, cli.chat.sampling(), config.sampling)?;
+
+    let text_style = if cli.chat.screen_reader {
+        TextStyle::ScreenReader
+    } else if cli.chat.plain || !io::stdout().is_terminal() {
+        TextStyle::Plain
+    } else {
+        TextStyle::Markdown
+    };
+
+    let mut system = match (&cli.chat.system, &cli.chat.persona) {
+        (Some(system), _) => Some(system.clone()),
+        (None, Some(persona)) => Some(config.persona(persona)?),
+        (None, None) => None,
+    };
+    // Like the model, a resumed or regenerated conversation keeps its system prompt unless
+    // another one is asked for.
+    let system_given = system.is_some() || cli.chat.response_style.is_some() || cli.chat.verbosity().is_some();
+    let stored = regen.as_ref().map(|(record, _)| record).or(resumed.as_ref());
+    if let Some(record) = stored.filter(|_| !system_given) {
+        system = record.system.clone();
+    }
+
+    if let Some(style) = &cli.chat.response_style {
+        let style = config.style(style)?;
+        system = Some(match system {
+            Some(system) => format!("{}\n\n{}", system, style),
+            None => style,
+        });
+    }
+    if let Some(verbosity) = cli.chat.verbosity() {
+        system = Some(match system {
+            Some(system) => format!("{}\n\n{}", system, verbosity.instruction()),
+            None => verbosity.instruction().to_string(),
+        });
+    }
+
+    let attachments = match cli.chat.files.as_slice() {
+        [] => None,
+        files => Some(references::attach_files(files, config.max_file_bytes.unwrap_or(references::MAX_FILE_BYTES))?),
+    };
+
+    let mut expanded_prompt = initial_prompt.as_deref().map(references::expand).transpose()?;
+    if let (Some(prompt), Some(attachments)) = (expanded_prompt.as_mut(), &attachments) {
+        prompt.push_str("\n\n");
+        prompt.push_str(attachments);
+    }
+    let prompt = match &expanded_prompt {
         Some(p) => p.as_str(),
         None => "",
     };
+
+    // A comparison costs one request per model; the estimate is for the first of them.
+    let confirm_model = cli.chat.compare.first().unwrap_or(&model);
+    let watching = matches!(&cli.command, Some(Commands::Watch { .. }));
+    if !interactive && !cli.chat.follow && !watching && !confirm_large_prompt(prompt, confirm_model, &config, cli.yes)? {
+        println!("Aborted.");
+        return Ok(());
+    }
     
-    let conversation_uuid = initialize_conversation(&client, &api_key, prompt).await?;
+    // Tool results go back to the model as follow-up prompts, which need the conversation
+    // (hence --no-conversation conflicts with --tools and --mcp).
+    let remote = if interactive || cli.chat.follow || watching { !cli.chat.no_conversation } else { cli.chat.conversation || cli.chat.tools.is_some() || cli.chat.mcp.is_some() };
+    let document = match (&cli.command, &regen, &resumed) {
+        (Some(Commands::Doc { action: DocCommands::Chat { file, .. } }), _, _) => Some(upload_document(&client, file).await?),
+        (_, Some((record, _)), _) | (_, None, Some(record)) => record.document.clone(),
+        _ => None,
+    };
+    let use_cache = (cli.chat.cache || config.cache) && !cli.chat.no_cache;
+    let context_limit = cli.chat.context_limit.or(config.context_limit).unwrap_or(compact::DEFAULT_CONTEXT_LIMIT_TOKENS);
+    let mcp_servers = config.mcp_servers.clone();
+    let knowledge = match &cli.chat.knowledge {
+        Some(name) => {
+            let index = knowledge::Index::load(name)?;
+            let embedder = embedding_provider(&index.info.provider, &config, &client, &http, &cli).await?;
+            Some(Knowledge { index, embedder, top_k: cli.chat.top_k })
+        }
+        None => None,
+    };
+    let save_responses = match &cli.chat.save_response {
+        Some(Some(dir)) => Some(dir.clone()),
+        Some(None) => Some(match &config.responses_dir {
+            Some(dir) => dir.clone(),
+            None => export::default_responses_dir()?,
+        }),
+        None => None,
+    };
+    let mut session = Session {
+        client: &client,
+        provider,
+        config,
+        conversation_uuid: match (&regen, &document) {
+            (Some((record, _)), _) => record.id.clone(),
+            // Set up by `resume_in` below.
+            _ if resumed.is_some() => String::new(),
+            // Each compared model gets a conversation of its own instead.
+            _ if !cli.chat.compare.is_empty() => String::new(),
+            (None, Some(document)) => initialize_document_conversation(&client, document, cli.chat.title.as_deref()).await?,
+            (None, None) => initialize_conversation(&client, remote && provider.hosts_conversations(), cli.chat.title.as_deref()).await?,
+        },
+        remote: (remote && regen.is_none()) || document.is_some(),
+        model,
+        system,
+        max_words: cli.chat.max_words(),
+        max_tokens: cli.chat.max_tokens(),
+        sampling: cli.chat.sampling(),
+        quiet: cli.chat.quiet,
+        voice_output: cli.chat.voice_output,
+        tts_voice: cli.chat.tts_voice.clone(),
+        text_style,
+        output: cli.chat.output,
+        paging: match (cli.chat.pager, cli.chat.no_pager) {
+            (true, _) => pager::Paging::Always,
+            // A pager opening unasked would get in a screen reader's way.
+            (false, false) if text_style != TextStyle::ScreenReader => pager::Paging::Auto,
+            _ => pager::Paging::Never,
+        },
+        stall_timeout: Duration::from_secs(cli.chat.stall_timeout),
+        yes: cli.yes,
+        pending_context: attachments.filter(|_| interactive),
+        pending_images: media::upload_images(&client, &cli.chat.images).await?,
+        replay: None,
+        context_tokens: 0,
+        context_limit,
+        copy: cli.chat.copy,
+        code_only: cli.chat.code_only.clone(),
+        out: cli.chat.out.clone(),
+        schema: cli.chat.schema.as_deref().map(schema::Schema::load).transpose()?.map(Arc::new),
+        schema_retries: cli.chat.schema_retries,
+        transcript: cli.chat.save.clone(),
+        save_responses,
+        response_options: response_options(&cli),
+        auto_title: cli.chat.auto_title,
+        needs_title: cli.chat.auto_title,
+        stats: cli.chat.stats,
+        cache: use_cache,
+        document,
+        last_prompt: None,
+        last_response: None,
+        last_original_turn: None,
+        regenerate: regen.as_ref().map(|(_, turn)| *turn),
+        tools: match &cli.chat.tools {
+            Some(tools) if tools.is_empty() => tools::Tool::value_variants().to_vec(),
+            Some(tools) => tools.clone(),
+            None => Vec::new(),
+        },
+        mcp: match &cli.chat.mcp {
+            Some(names) => mcp::connect_all(&mcp_servers, names).await?,
+            None => Vec::new(),
+        },
+        knowledge,
+        repo: if cli.chat.repo { Some(context::Repo::load()?) } else { None },
+        http: http.clone(),
+    };
+    if let Some(record) = &resumed {
+        resume_in(&mut session, record);
+        if !model_given && session.model == record.model {
+            eprintln!("Using {} as before.", session.model);
+        }
+        if !system_given && session.system.is_some() {
+            eprintln!("Using the conversation's system prompt. Pass --system to change it.");
+        }
+    }
 
-    if cli.interactive {
-        println!("Starting interactive mode. Type 'exit' to quit.");
+    if interactive {
+        if let Some(document) = &session.document {
+            println!("Chatting about {}.", document.name);
+        }
+        println!("Starting interactive mode. Type 'exit' to quit or /help for commands.");
         
-        let mut prompt = match &cli.prompt {
-            Some(p) => {
-                println!("You: {}", p);
-                p.clone()
+        let mut editor = LineEditor::new()?;
+        let mut pending = initial_prompt.clone();
+        if let Some(p) = &pending {
+            println!("You: {}", p);
+        }
+
+        loop {
+            let prompt = match pending.take() {
+                Some(p) => p,
+                None if cli.chat.voice_input => media::voice_prompt(&client).await?,
+                None => match editor.read("You: ")? {
+                    Some(p) => p,
+                    None => break,
+                },
+            };
+
+            if prompt.trim().is_empty() {
+                continue;
             }
-            None => {
-                let input: String = Input::new().with_prompt("You").interact_text()?;
-                input
+            if prompt.trim().eq_ignore_ascii_case("exit") {
+                break;
             }
-        };
 
-        while !prompt.is_empty() && prompt.to_lowercase() != "exit" {
-            chat_with_ai(
-                &client,
-                &api_key,
-                &conversation_uuid,
-                &prompt,
-                &cli.model,
-                cli.words,
-                cli.quiet,
-                cli.voice_output,
-            ).await?;
-
-            prompt = Input::new().with_prompt("You").interact_text()?;
+            match repl::parse(&prompt) {
+                Some(Ok(command)) => {
+                    if let Err(e) = run_slash_command(&mut session, command).await {
+                        eprintln!("Error: {:#}", e);
+                    }
+                }
+                Some(Err(e)) => eprintln!("Error: {:#}", e),
+                None => {
+                    let duplicate = !cli.chat.no_dup_guard && session.last_prompt.as_deref() == Some(prompt.as_str());
+                    if !duplicate || confirm_duplicate_prompt()? {
+                        send_interactive(&mut session, &prompt).await?;
+                    }
+                }
+            }
+        }
+    } else if let Some(Commands::Watch { files, full, .. }) = &cli.command {
+        let instruction = expanded_prompt.as_deref().expect("watch takes an instruction");
+        watch_files(&mut session, instruction, files, *full).await?;
+    } else if cli.chat.follow {
+        match &expanded_prompt {
+            Some(instruction) => {
+                follow_stdin(&mut session, instruction, cli.chat.batch_lines, cli.chat.batch_secs).await?;
+            }
+            None => {
+                return Err(anyhow!("No instruction provided for follow mode."));
+            }
         }
     } else {
-        match &cli.prompt {
+        match &expanded_prompt {
+            Some(prompt) if !cli.chat.compare.is_empty() => {
+                compare_models(&session, prompt, &cli.chat.compare, cli.chat.layout).await?;
+            }
             Some(prompt) => {
-                chat_with_ai(
-                    &client,
-                    &api_key,
-                    &conversation_uuid,
-                    prompt,
-                    &cli.model,
-                    cli.words,
-                    cli.quiet,
-                    cli.voice_output,
-                ).await?;
+                ask(&mut session, prompt).await?;
             }
             None => {
                 Cli::command().print_help()?;
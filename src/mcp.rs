@@ -0,0 +1,300 @@
+//! A client for [Model Context Protocol](https://modelcontextprotocol.io) servers, whose tools and
+//! resources are offered to the model with `--mcp`.
+//!
+//! Servers are started as child processes and spoken to with JSON-RPC over their stdin and
+//! stdout, one message per line.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+use crate::tools::Policy;
+
+const PROTOCOL_VERSION: &str = "2025-06-18";
+/// How long a server gets to answer a request, including starting up
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// Resources beyond this many are left out of the instructions (they can still be read)
+const MAX_LISTED_RESOURCES: usize = 50;
+/// Name of the tool that reads a server's resources
+pub const READ_RESOURCE: &str = "read_resource";
+
+/// An MCP server in the `[mcp_servers]` table of the config file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerConfig {
+    /// Program that starts the server
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Environment variables to set for it, such as access tokens
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Whether its tools run without asking (reading its resources is only refused by `deny`)
+    #[serde(default = "default_policy")]
+    pub policy: Policy,
+}
+
+fn default_policy() -> Policy {
+    Policy::Ask
+}
+
+/// A tool offered by a server.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub input_schema: Value,
+}
+
+/// A resource offered by a server.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    pub uri: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+struct Io {
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+}
+
+/// A running MCP server, stopped when dropped.
+pub struct Connection {
+    pub name: String,
+    pub policy: Policy,
+    pub tools: Vec<RemoteTool>,
+    pub resources: Vec<Resource>,
+    io: Mutex<Io>,
+    _child: Child,
+}
+
+impl Connection {
+    /// Starts the server `name` and asks it for its tools and resources.
+    pub async fn connect(name: &str, config: &ServerConfig) -> Result<Connection> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("Could not start MCP server '{}' ({}): {}", name, config.command, e))?;
+        let io = Io {
+            stdin: child.stdin.take().expect("stdin is piped"),
+            stdout: BufReader::new(child.stdout.take().expect("stdout is piped")).lines(),
+            next_id: 1,
+        };
+        let mut connection = Connection {
+            name: name.to_string(),
+            policy: config.policy,
+            tools: Vec::new(),
+            resources: Vec::new(),
+            io: Mutex::new(io),
+            _child: child,
+        };
+
+        let initialized = connection.request("initialize", json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "ai-cli", "version": env!("CARGO_PKG_VERSION") },
+        })).await?;
+        connection.notify("notifications/initialized").await?;
+
+        let capabilities = &initialized["capabilities"];
+        if capabilities.get("tools").is_some() {
+            connection.tools = connection.list("tools/list", "tools").await?;
+        }
+        if capabilities.get("resources").is_some() {
+            connection.resources = connection.list("resources/list", "resources").await?;
+        }
+        Ok(connection)
+    }
+
+    /// Sends a request and waits for its result. Requests from the server in the meantime are
+    /// answered: `ping` as required, anything else as unsupported.
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let mut io = self.io.lock().await;
+        let id = io.next_id;
+        io.next_id += 1;
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        write_message(&mut io.stdin, &message).await?;
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, async {
+            loop {
+                let line = io.stdout.next_line().await?
+                    .ok_or_else(|| anyhow!("MCP server '{}' exited", self.name))?;
+                let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                    tracing::debug!("ignoring non-JSON output of MCP server '{}': {}", self.name, line);
+                    continue;
+                };
+                match (message.get("id"), message.get("method")) {
+                    (Some(request_id), Some(method)) => {
+                        let reply = if method == "ping" {
+                            json!({ "jsonrpc": "2.0", "id": request_id, "result": {} })
+                        } else {
+                            json!({ "jsonrpc": "2.0", "id": request_id, "error": { "code": -32601, "message": "Method not found" } })
+                        };
+                        write_message(&mut io.stdin, &reply).await?;
+                    }
+                    (Some(response_id), None) if response_id.as_u64() == Some(id) => return Ok::<_, anyhow::Error>(message),
+                    // Notifications, and responses to requests that timed out
+                    _ => {}
+                }
+            }
+        }).await
+        .map_err(|_| anyhow!("MCP server '{}' didn't answer {} within {}s", self.name, method, REQUEST_TIMEOUT.as_secs()))??;
+
+        if let Some(error) = response.get("error") {
+            let message = error["message"].as_str().unwrap_or("unknown error");
+            return Err(anyhow!("MCP server '{}' failed {}: {}", self.name, method, message));
+        }
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+
+    async fn notify(&self, method: &str) -> Result<()> {
+        let mut io = self.io.lock().await;
+        write_message(&mut io.stdin, &json!({ "jsonrpc": "2.0", "method": method })).await
+    }
+
+    /// Fetches every page of a list method's results.
+    async fn list<T: for<'de> Deserialize<'de>>(&self, method: &str, field: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let mut result = self.request(method, params).await?;
+            let page: Vec<T> = serde_json::from_value(result[field].take())
+                .map_err(|e| anyhow!("MCP server '{}' sent an invalid {} result: {}", self.name, method, e))?;
+            items.extend(page);
+            cursor = result["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+
+    /// Describes the server's tools for the model, named `<server>.<tool>`, plus a
+    /// `<server>.read_resource` tool when it has resources.
+    pub fn descriptions(&self) -> Vec<String> {
+        let mut descriptions: Vec<String> = self.tools.iter()
+            .map(|tool| {
+                let arguments = tool.input_schema.get("properties").cloned().unwrap_or_else(|| json!({}));
+                let description = tool.description.as_deref().unwrap_or("").trim().replace('\n', " ");
+                format!("{}.{} {}: {}", self.name, tool.name, arguments, description)
+            })
+            .collect();
+        if !self.resources.is_empty() {
+            let mut listed: Vec<String> = self.resources.iter()
+                .take(MAX_LISTED_RESOURCES)
+                .map(|resource| match resource.name.as_deref().or(resource.description.as_deref()) {
+                    Some(name) => format!("{} ({})", resource.uri, name),
+                    None => resource.uri.clone(),
+                })
+                .collect();
+            if self.resources.len() > MAX_LISTED_RESOURCES {
+                listed.push(format!("and {} more", self.resources.len() - MAX_LISTED_RESOURCES));
+            }
+            descriptions.push(format!(
+                "{}.{} {{\"uri\": string}}: returns a resource of the {} server: {}",
+                self.name, READ_RESOURCE, self.name, listed.join(", ")
+            ));
+        }
+        descriptions
+    }
+
+    /// Calls one of the server's tools and returns its output.
+    pub async fn call_tool(&self, tool: &str, args: &Value) -> Result<String> {
+        let args = if args.is_null() { json!({}) } else { args.clone() };
+        let result = self.request("tools/call", json!({ "name": tool, "arguments": args })).await?;
+        let output = content_text(&result["content"]);
+        if result["isError"].as_bool() == Some(true) {
+            return Err(anyhow!("{}", output));
+        }
+        Ok(output)
+    }
+
+    /// Reads one of the server's resources.
+    pub async fn read_resource(&self, uri: &str) -> Result<String> {
+        let result = self.request("resources/read", json!({ "uri": uri })).await?;
+        let contents = result["contents"].as_array().map(Vec::as_slice).unwrap_or_default();
+        Ok(contents.iter()
+            .map(|content| match content.get("text").and_then(Value::as_str) {
+                Some(text) => text.to_string(),
+                None => format!("[binary {} content]", content["mimeType"].as_str().unwrap_or("unknown")),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+async fn write_message(stdin: &mut ChildStdin, message: &Value) -> Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    stdin.write_all(line.as_bytes()).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+/// The text of a tool result's content, with placeholders for what isn't text.
+fn content_text(content: &Value) -> String {
+    let items = content.as_array().map(Vec::as_slice).unwrap_or_default();
+    items.iter()
+        .map(|item| match item["type"].as_str() {
+            Some("text") => item["text"].as_str().unwrap_or("").to_string(),
+            Some("resource") => match item["resource"]["text"].as_str() {
+                Some(text) => text.to_string(),
+                None => format!("[resource {}]", item["resource"]["uri"].as_str().unwrap_or("")),
+            },
+            Some("resource_link") => format!("[resource {}]", item["uri"].as_str().unwrap_or("")),
+            Some(kind) => format!("[{} content]", kind),
+            None => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Starts the configured servers named in `names` (all of them when empty).
+pub async fn connect_all(servers: &BTreeMap<String, ServerConfig>, names: &[String]) -> Result<Vec<Connection>> {
+    if servers.is_empty() {
+        return Err(anyhow!("No MCP servers are configured. Add one with `ai-cli mcp add <name> <command>`"));
+    }
+    let selected: Vec<(&String, &ServerConfig)> = if names.is_empty() {
+        servers.iter().collect()
+    } else {
+        names.iter()
+            .map(|name| {
+                servers.get_key_value(name).ok_or_else(|| {
+                    let known: Vec<&str> = servers.keys().map(String::as_str).collect();
+                    anyhow!("Unknown MCP server '{}'. Configured servers: {}", name, known.join(", "))
+                })
+            })
+            .collect::<Result<_>>()?
+    };
+    futures_util::future::try_join_all(selected.into_iter().map(|(name, config)| Connection::connect(name, config))).await
+}
+
+/// The server and tool a call named `<server>.<tool>` is for.
+pub fn find<'a>(connections: &'a [Connection], tool: &'a str) -> Option<(&'a Connection, &'a str)> {
+    let (server, tool) = tool.split_once('.')?;
+    let connection = connections.iter().find(|connection| connection.name == server)?;
+    let known = tool == READ_RESOURCE && !connection.resources.is_empty()
+        || connection.tools.iter().any(|remote| remote.name == tool);
+    known.then_some((connection, tool))
+}
@@ -0,0 +1,459 @@
+//! Sending images and recordings to the 1min.ai API, and saving the images, video and speech it
+//! generates.
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::StreamExt;
+use indicatif::ProgressBar;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::client::{ImageGeneration, ImageProcessing, ImageTool, ImageToolOptions, OneMinClient, VideoGeneration, DEFAULT_SPEECH_TO_TEXT_MODEL};
+use crate::providers::Provider;
+use crate::ui::{show_progress, waiting_spinner};
+use crate::{auth, error, image_models, jobs, models, png_text, preview, retry, voice};
+
+const DEFAULT_IMAGE_FILENAME: &str = "1minAI_output.png";
+/// Name of a downloaded image whose URL doesn't end in a file name, before its format's extension
+const DEFAULT_IMAGE_STEM: &str = "1minAI_output";
+/// Generated images downloaded at the same time
+const MAX_PARALLEL_DOWNLOADS: usize = 4;
+const DEFAULT_SPEECH_FILENAME: &str = "speech.mp3";
+
+/// Uploads a local file, asking for a new API key if the current one is rejected.
+pub async fn upload_asset(client: &OneMinClient, path: &Path) -> Result<String> {
+    auth::with_recovery(client, || client.upload_asset(path)).await
+}
+
+/// Uploads the `--image` inputs, downloading URLs first, and returns their asset paths.
+pub async fn upload_images(client: &OneMinClient, images: &[String]) -> Result<Vec<String>> {
+    let mut paths = Vec::with_capacity(images.len());
+    for image in images {
+        eprintln!("Uploading {}...", image);
+        let path = if image.starts_with("http://") || image.starts_with("https://") {
+            let filename = image.split('?').next()
+                .and_then(|path| path.split('/').next_back())
+                .filter(|name| !name.is_empty())
+                .unwrap_or(DEFAULT_IMAGE_FILENAME);
+            let bytes = client.download(image).await?;
+            auth::with_recovery(client, || client.upload_bytes(filename, bytes.clone())).await?
+        } else {
+            upload_asset(client, Path::new(image)).await
+                .with_context(|| format!("Could not upload {}", image))?
+        };
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Turns a prompt into a short filename-safe slug, e.g. "a cute anime cat" -> "a-cute-anime-cat".
+pub fn slugify(text: &str) -> String {
+    let slug = text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let slug: String = slug.chars().take(40).collect();
+    let slug = slug.trim_end_matches('-');
+
+    if slug.is_empty() { "image".to_string() } else { slug.to_string() }
+}
+
+/// Appends a counter to the file name until it no longer collides with an existing file.
+pub fn unique_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let extension = path.extension().and_then(|e| e.to_str());
+    (1..)
+        .map(|n| match extension {
+            Some(ext) => path.with_file_name(format!("{}-{}.{}", stem, n, ext)),
+            None => path.with_file_name(format!("{}-{}", stem, n)),
+        })
+        .find(|candidate| !candidate.exists())
+        .expect("an unused file name")
+}
+
+/// Where downloaded images are saved and how they are shown afterwards.
+#[derive(Clone, Copy)]
+pub struct ImageOutput<'a> {
+    pub file: Option<&'a Path>,
+    /// Whether `file` is a name ai-cli made up, whose extension becomes that of the format the
+    /// image was downloaded in
+    pub fit_extension: bool,
+    pub dir: Option<&'a Path>,
+    pub open: bool,
+    /// Draw the images in the terminal, with the detected protocol unless one is given
+    pub preview: Option<Option<preview::Protocol>>,
+    /// What the images were generated with, written into saved PNG images and a `.json` file
+    /// next to each image
+    pub parameters: Option<&'a image_models::Parameters<'a>>,
+}
+
+/// Downloads an image, trying again when the download fails or isn't an image, as a truncated
+/// or garbled transfer might succeed the next time.
+async fn download_image(client: &OneMinClient, url: &str) -> Result<(Vec<u8>, &'static str), error::AiCliError> {
+    let mut attempt = 0;
+    loop {
+        match client.download_image(url).await {
+            Err(e) if attempt < retry::max_retries() && (e.is_transient() || matches!(e, error::AiCliError::InvalidResponse(_))) => {
+                debug!("Download of {} failed ({}), trying again", url, e);
+                tokio::time::sleep(retry::delay(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Downloads every generated image and saves it, honoring the output path options. Images that
+/// can't be downloaded are skipped with a warning as long as one of them can.
+pub async fn save_images(
+    client: &OneMinClient,
+    urls: Vec<String>,
+    name_hint: &str,
+    output: &ImageOutput<'_>,
+) -> Result<()> {
+    let (output_file, output_dir) = (output.file, output.dir);
+    if urls.is_empty() {
+        return Err(anyhow!("No image URL found in response"));
+    }
+
+    // Downloaded a few at a time but saved in order, so numbered names follow the API's order.
+    let progress = (urls.len() > 1 && show_progress()).then(|| ProgressBar::new(urls.len() as u64));
+    let downloads: Vec<Result<(Vec<u8>, &str), error::AiCliError>> = futures_util::stream::iter(&urls)
+        .map(|url| async {
+            let result = download_image(client, url).await;
+            if let Some(progress) = &progress {
+                progress.inc(1);
+            }
+            result
+        })
+        .buffered(MAX_PARALLEL_DOWNLOADS)
+        .collect()
+        .await;
+    if let Some(progress) = progress {
+        progress.finish_and_clear();
+    }
+
+    let mut saved = Vec::with_capacity(urls.len());
+    let mut failed = 0;
+    for (i, (url, download)) in urls.iter().zip(downloads).enumerate() {
+        let (image_bytes, format) = match download {
+            Ok(download) => download,
+            // The other images are still worth keeping.
+            Err(e) if urls.len() > 1 => {
+                eprintln!("Warning: could not download image {}: {}", i + 1, e);
+                failed += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let url_filename = url.split('?').next()
+            .and_then(|path| path.split('/').next_back())
+            .filter(|name| !name.is_empty() && Path::new(name).extension().is_some())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}.{}", DEFAULT_IMAGE_STEM, format));
+        let extension = Path::new(&url_filename).extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or(format);
+        let filename = match output_file {
+            Some(file) if urls.len() > 1 => {
+                let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+                let ext = file.extension().and_then(|e| e.to_str()).filter(|_| !output.fit_extension).unwrap_or(extension);
+                file.with_file_name(format!("{}-{}.{}", stem, i + 1, ext))
+            }
+            Some(file) if output.fit_extension => file.with_extension(format),
+            Some(file) => file.to_path_buf(),
+            None if urls.len() > 1 => PathBuf::from(format!("{}-{}.{}", slugify(name_hint), i + 1, extension)),
+            None => PathBuf::from(&url_filename),
+        };
+        let path = unique_path(&match output_dir {
+            Some(dir) => dir.join(filename),
+            None => filename,
+        });
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&path)?;
+        file.write_all(&image_bytes)?;
+        drop(file);
+        if let Some(parameters) = output.parameters {
+            let entries = parameters.text_entries();
+            let entries: Vec<(&str, &str)> = entries.iter().map(|(keyword, text)| (*keyword, text.as_str())).collect();
+            png_text::add_text(&path, &entries)?;
+            let mut sidecar = path.clone().into_os_string();
+            sidecar.push(".json");
+            std::fs::write(sidecar, serde_json::to_string_pretty(parameters)? + "\n")?;
+        }
+        saved.push(std::fs::canonicalize(&path)?);
+    }
+    if saved.is_empty() {
+        return Err(anyhow!("None of the {} images could be downloaded", failed));
+    }
+
+    if let [path] = saved.as_slice() {
+        println!("Image saved to {}", path.display());
+    } else {
+        println!("Saved {} images:", saved.len());
+        for path in &saved {
+            println!("  {}", path.display());
+        }
+    }
+
+    if let Some(protocol) = output.preview {
+        if std::io::stdout().is_terminal() {
+            let protocol = protocol.unwrap_or_else(preview::detect);
+            for path in &saved {
+                if let Err(e) = preview::show(path, protocol) {
+                    eprintln!("Warning: {:#}", e);
+                }
+            }
+        } else {
+            eprintln!("Warning: --preview needs a terminal; skipping the preview.");
+        }
+    }
+    if output.open {
+        for path in &saved {
+            if let Err(e) = preview::open(path) {
+                eprintln!("Warning: {:#}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Generates images with the selected provider; the client downloads them, since result URLs
+/// need no API key.
+pub async fn generate_image(
+    client: &OneMinClient,
+    provider: &dyn Provider,
+    options: &ImageGeneration<'_>,
+    output: &ImageOutput<'_>,
+) -> Result<()> {
+    match options.seed {
+        Some(seed) => println!("Generating image with {} model (seed {}) for prompt \"{}\"...", options.model, seed, options.prompt),
+        None => println!("Generating image with {} model for prompt \"{}\"...", options.model, options.prompt),
+    }
+
+    let urls = auth::with_recovery(provider, || provider.generate_image(options)).await?;
+
+    println!("Image generated successfully. Downloading...");
+
+    save_images(client, urls, options.prompt, output).await
+}
+
+/// An image to edit or make variations of, and what to make of it.
+pub struct ImageEdit<'a> {
+    pub tool: ImageTool,
+    pub input: &'a Path,
+    /// Which parts of the input an edit may change
+    pub mask: Option<&'a Path>,
+    pub prompt: Option<&'a str>,
+    pub model: &'a str,
+    pub count: u32,
+    pub size: &'a str,
+}
+
+/// Uploads the input image (and mask) and runs an image edit or variations request.
+pub async fn edit_image(client: &OneMinClient, edit: &ImageEdit<'_>, output: &ImageOutput<'_>) -> Result<()> {
+    println!("Uploading {}...", edit.input.display());
+    let image = upload_asset(client, edit.input).await?;
+    let mask = match edit.mask {
+        Some(mask) => Some(upload_asset(client, mask).await?),
+        None => None,
+    };
+
+    let options = ImageToolOptions {
+        tool: edit.tool,
+        model: edit.model,
+        image: &image,
+        mask: mask.as_deref(),
+        prompt: edit.prompt,
+        count: edit.count,
+        size: edit.size,
+    };
+    let record = auth::with_recovery(client, || client.image_tool(&options)).await?;
+
+    if record.status != "SUCCESS" {
+        return Err(anyhow!("Image processing failed with status: {}", record.status));
+    }
+
+    let name_hint = edit.input.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let urls = record.result_urls(client.asset_base_url());
+    save_images(client, urls, name_hint, output).await
+}
+
+/// Uploads the input image, upscales it or removes its background, and saves the result next to
+/// the original with a suffix, unless `output` names a file.
+pub async fn process_image(
+    client: &OneMinClient,
+    processing: ImageProcessing,
+    model: &str,
+    input: &Path,
+    output: &ImageOutput<'_>,
+) -> Result<()> {
+    println!("Uploading {}...", input.display());
+    let image = upload_asset(client, input).await?;
+
+    println!("Processing the image with {}...", model);
+    let record = auth::with_recovery(client, || client.process_image(processing, model, &image)).await?;
+    if record.status != "SUCCESS" {
+        return Err(anyhow!("Image processing failed with status: {}", record.status));
+    }
+
+    let stem = input.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    // The extension is that of the format the result comes in, which needn't be the input's:
+    // a removed background needs transparency, for one.
+    let name = match processing {
+        ImageProcessing::Upscale { scale } => format!("{}-upscaled-{}x.png", stem, scale),
+        ImageProcessing::RemoveBackground => format!("{}-no-bg.png", stem),
+    };
+    let next_to_input = input.with_file_name(name);
+    let output = ImageOutput {
+        file: Some(output.file.unwrap_or(&next_to_input)),
+        fit_extension: output.file.is_none(),
+        ..*output
+    };
+    let urls = record.result_urls(client.asset_base_url());
+    save_images(client, urls, stem, &output).await
+}
+
+/// Submits a video generation job, waits for it with a progress indicator, and downloads the MP4.
+pub async fn generate_video(
+    client: &OneMinClient,
+    options: &VideoGeneration<'_>,
+    max_wait: Duration,
+    output_file: Option<&Path>,
+    open: bool,
+) -> Result<()> {
+    models::validate(options.model)?;
+    println!("Generating a {}s video with {} for prompt \"{}\"...", options.duration, options.model, options.prompt);
+    let record = auth::with_recovery(client, || client.generate_video(options)).await?;
+
+    let spinner = show_progress().then(waiting_spinner);
+    let result = client.wait_for(record, jobs::Polling::with_timeout(max_wait), |record, elapsed| {
+        if let Some(spinner) = &spinner {
+            spinner.set_message(format!("video {} ({}s)…", record.status.to_lowercase(), elapsed.as_secs()));
+        }
+    }).await;
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+    let record = match result {
+        Err(e @ error::AiCliError::TimedOut(_)) => {
+            return Err(anyhow::Error::from(e).context("The video wasn't ready in time; pass a longer --max-wait to wait for it"));
+        }
+        result => result?,
+    };
+    if record.status != "SUCCESS" {
+        return Err(anyhow!("Video generation failed with status: {}", record.status));
+    }
+
+    let url = record.result_urls(client.asset_base_url()).into_iter().next()
+        .ok_or_else(|| anyhow!("No video URL found in response"))?;
+    println!("Video generated successfully. Downloading...");
+    let video = client.download(&url).await?;
+
+    let path = match output_file {
+        Some(path) => path.to_path_buf(),
+        None => unique_path(Path::new(&format!("{}.mp4", slugify(options.prompt)))),
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, video).with_context(|| format!("Could not write {}", path.display()))?;
+    println!("Video saved to {}", std::fs::canonicalize(&path)?.display());
+
+    if open {
+        if let Err(e) = preview::open(&path) {
+            eprintln!("Warning: {:#}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Synthesizes speech with the API and saves it to `out`, or to a temporary file when it is only played.
+pub async fn synthesize_speech(client: &OneMinClient, text: &str, model: &str, voice: &str, out: Option<&Path>, play: bool) -> Result<()> {
+    if text.trim().is_empty() {
+        return Err(anyhow!("No text to speak"));
+    }
+    let audio = auth::with_recovery(client, || client.text_to_speech(text, model, voice)).await?;
+
+    let path = match out {
+        Some(path) => path.to_path_buf(),
+        None if play => std::env::temp_dir().join(format!("ai-cli-speech-{}.mp3", std::process::id())),
+        None => unique_path(Path::new(DEFAULT_SPEECH_FILENAME)),
+    };
+    std::fs::write(&path, audio).with_context(|| format!("Could not write {}", path.display()))?;
+    if out.is_some() || !play {
+        eprintln!("Audio saved to {}", path.display());
+    }
+
+    if play {
+        let played = voice::play_audio(&path);
+        if out.is_none() {
+            let _ = std::fs::remove_file(&path);
+        }
+        played?;
+    }
+    Ok(())
+}
+
+pub async fn voice_prompt(client: &OneMinClient) -> Result<String> {
+    let path = std::env::temp_dir().join(voice::VOICE_INPUT_FILENAME);
+    voice::record_voice_input(&path)?;
+
+    let asset_path = upload_asset(client, &path).await;
+    let _ = std::fs::remove_file(&path);
+    let asset_path = asset_path?;
+    let transcript = auth::with_recovery(client, || client.transcribe(&asset_path, DEFAULT_SPEECH_TO_TEXT_MODEL)).await?;
+
+    println!("You: {}", transcript);
+    Ok(transcript)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_files_after_prompts() {
+        assert_eq!(slugify("A cute anime cat, sitting!"), "a-cute-anime-cat-sitting");
+        assert_eq!(slugify("?!"), "image");
+        assert!(slugify(&"word ".repeat(20)).len() <= 40);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cat.png");
+        assert_eq!(unique_path(&path), path);
+        std::fs::write(&path, "").unwrap();
+        std::fs::write(dir.path().join("cat-1.png"), "").unwrap();
+        assert_eq!(unique_path(&path), dir.path().join("cat-2.png"));
+    }
+
+    #[tokio::test]
+    async fn saves_the_images_that_download() {
+        let mut server = mockito::Server::new_async().await;
+        let _missing = server.mock("GET", "/missing").with_status(404).create_async().await;
+        let _gif = server.mock("GET", "/result")
+            .with_header("content-type", "image/gif")
+            .with_body("GIF89a....")
+            .create_async().await;
+        let client = OneMinClient::new(reqwest::Client::new(), "key".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let output = ImageOutput { file: None, fit_extension: false, dir: Some(dir.path()), open: false, preview: None, parameters: None };
+
+        let urls = vec![format!("{}/missing", server.url()), format!("{}/result", server.url())];
+        save_images(&client, urls, "a red fox", &output).await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("a-red-fox-2.gif")).unwrap(), b"GIF89a....");
+        assert!(!dir.path().join("a-red-fox-1.gif").exists());
+
+        let error = save_images(&client, vec![format!("{}/missing", server.url())], "a red fox", &output).await.unwrap_err();
+        assert!(error.to_string().contains("404"), "{}", error);
+    }
+}
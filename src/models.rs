@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::lock;
+use crate::providers::{Provider, ONEMIN};
+
+const MODELS_CACHE_FILE: &str = "models.json";
+
+static PROVIDER: OnceLock<String> = OnceLock::new();
+
+/// Selects the provider whose model list is cached and validated against, for the rest of the
+/// process. Each provider other than 1min.ai has a cache file of its own.
+pub fn use_provider(name: &str) {
+    let _ = PROVIDER.set(name.to_string());
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ModelInfo {
+    pub id: String,
+    pub name: String,
+    pub kind: String,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+fn cache_path() -> Result<PathBuf> {
+    let dir = dirs::cache_dir()
+        .ok_or_else(|| anyhow!("Could not determine the local cache directory"))?
+        .join("ai-cli");
+    fs::create_dir_all(&dir)?;
+    match PROVIDER.get() {
+        Some(name) if name != ONEMIN => Ok(dir.join(format!("models-{}.json", name))),
+        _ => Ok(dir.join(MODELS_CACHE_FILE)),
+    }
+}
+
+pub fn load_cache() -> Option<Vec<ModelInfo>> {
+    let contents = fs::read_to_string(cache_path().ok()?).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn save_cache(models: &[ModelInfo]) -> Result<()> {
+    lock::write_atomic(&cache_path()?, serde_json::to_string_pretty(models)?.as_bytes())
+}
+
+fn parse_model(item: &Value) -> Option<ModelInfo> {
+    let id = ["modelId", "id", "model"].iter()
+        .find_map(|key| item.get(*key).and_then(|v| v.as_str()))?
+        .to_string();
+    let name = item.get("name").and_then(|v| v.as_str()).unwrap_or(&id).to_string();
+
+    let feature = ["feature", "type", "category"].iter()
+        .find_map(|key| item.get(*key).and_then(|v| v.as_str()))
+        .unwrap_or("")
+        .to_uppercase();
+    let kind = if feature.contains("IMAGE") {
+        "image"
+    } else if feature.contains("VIDEO") {
+        "video"
+    } else if feature.contains("SPEECH") || feature.contains("AUDIO") {
+        "audio"
+    } else {
+        "chat"
+    }.to_string();
+
+    let mut capabilities = Vec::new();
+    if let Some(features) = item.get("features").and_then(|v| v.as_array()) {
+        capabilities.extend(features.iter().filter_map(|f| f.as_str()).map(|f| f.to_lowercase()));
+    }
+    for (key, label) in [("vision", "vision"), ("webSearch", "web-search"), ("streaming", "streaming")] {
+        if item.get(key).and_then(|v| v.as_bool()) == Some(true) {
+            capabilities.push(label.to_string());
+        }
+    }
+
+    Some(ModelInfo { id, name, kind, capabilities })
+}
+
+fn parse_models(json: &Value) -> Vec<ModelInfo> {
+    let items = json.as_array()
+        .or_else(|| json.get("models").and_then(|v| v.as_array()))
+        .or_else(|| json.get("data").and_then(|v| v.as_array()));
+
+    let mut models: Vec<ModelInfo> = items
+        .map(|items| items.iter().filter_map(parse_model).collect())
+        .unwrap_or_default();
+    models.sort_by(|a, b| a.kind.cmp(&b.kind).then(a.id.cmp(&b.id)));
+    models.dedup_by(|a, b| a.id == b.id);
+    models
+}
+
+pub async fn fetch_models(provider: &dyn Provider) -> Result<Vec<ModelInfo>> {
+    let json = provider.list_models().await?;
+    let models = parse_models(&json);
+    if models.is_empty() {
+        return Err(anyhow!("No models found in models API response"));
+    }
+
+    save_cache(&models)?;
+    Ok(models)
+}
+
+pub fn print_table(models: &[ModelInfo]) {
+    let id_width = models.iter().map(|m| m.id.len()).max().unwrap_or(0).max("MODEL".len());
+    let name_width = models.iter().map(|m| m.name.len()).max().unwrap_or(0).max("NAME".len());
+
+    println!("{:<id_width$}  {:<name_width$}  {:<5}  CAPABILITIES", "MODEL", "NAME", "TYPE");
+    for model in models {
+        println!(
+            "{:<id_width$}  {:<name_width$}  {:<5}  {}",
+            model.id,
+            model.name,
+            model.kind,
+            model.capabilities.join(", ")
+        );
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Checks a model name against the cached model list, if one exists.
+pub fn validate(model: &str) -> Result<()> {
+    let models = match load_cache() {
+        Some(models) if !models.is_empty() => models,
+        _ => return Ok(()),
+    };
+
+    // Ollama lists "llama3.2:latest" for a model pulled as "llama3.2".
+    if models.iter().any(|m| m.id == model || m.id.strip_suffix(":latest") == Some(model)) {
+        return Ok(());
+    }
+
+    let suggestion = models.iter()
+        .map(|m| (edit_distance(&model.to_lowercase(), &m.id.to_lowercase()), &m.id))
+        .min_by_key(|(distance, _)| *distance)
+        .filter(|(distance, _)| *distance <= model.len().max(3) / 2);
+
+    match suggestion {
+        Some((_, id)) => Err(anyhow!("Unknown model '{}'. Did you mean '{}'?", model, id)),
+        None => Err(anyhow!("Unknown model '{}'. Run 'ai-cli models' to see available models.", model)),
+    }
+}
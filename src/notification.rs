@@ -0,0 +1,49 @@
+//! Telling the user that a long job finished (`--notify`), so they can switch away while waiting.
+
+use clap::ValueEnum;
+use std::io::{self, IsTerminal, Write};
+use tracing::debug;
+
+/// How to announce that a job finished.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+#[value(rename_all = "lowercase")]
+pub enum Notify {
+    /// A desktop notification, or the terminal bell where none can be shown
+    Desktop,
+    /// The terminal bell
+    Bell,
+    /// A desktop notification and the terminal bell
+    Both,
+}
+
+/// Announces that `job` (e.g. "Video generation") succeeded or failed with `error`.
+pub fn job_finished(how: Notify, job: &str, error: Option<&anyhow::Error>) {
+    let (summary, body) = match error {
+        None => (format!("{} finished", job), "ai-cli is done.".to_string()),
+        Some(e) => (format!("{} failed", job), format!("{:#}", e)),
+    };
+    let shown = matches!(how, Notify::Desktop | Notify::Both) && show(&summary, &body);
+    if how != Notify::Desktop || !shown {
+        bell();
+    }
+}
+
+/// Shows a desktop notification, returning whether that worked (there may be no notification
+/// service, e.g. over SSH).
+fn show(summary: &str, body: &str) -> bool {
+    match notify_rust::Notification::new().appname("ai-cli").summary(summary).body(body).show() {
+        Ok(_) => true,
+        Err(e) => {
+            debug!("Could not show a desktop notification: {}", e);
+            false
+        }
+    }
+}
+
+fn bell() {
+    let mut stderr = io::stderr();
+    if stderr.is_terminal() {
+        let _ = stderr.write_all(b"\x07");
+        let _ = stderr.flush();
+    }
+}
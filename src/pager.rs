@@ -0,0 +1,64 @@
+//! Showing a complete response again in a pager (`--pager`), once it has streamed past, so a long
+//! answer can be read from the top instead of scrolling off the screen.
+
+use anyhow::{anyhow, Result};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Used when `$PAGER` is not set; `-R` shows the colors of rendered Markdown.
+const DEFAULT_PAGER: &str = "less -R";
+
+/// When responses are shown in the pager.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Paging {
+    /// When they don't fit on the screen
+    Auto,
+    Always,
+    Never,
+}
+
+impl Paging {
+    /// Whether `shown`, the response as it was printed, goes to the pager. Never when stdout is
+    /// not a terminal.
+    pub fn wants(self, shown: &str) -> bool {
+        let term = console::Term::stdout();
+        if self == Paging::Never || !term.is_term() {
+            return false;
+        }
+        match term.size_checked() {
+            // The line with the prompt stays in view above the response.
+            Some((rows, columns)) if self == Paging::Auto => screen_lines(shown, columns as usize) >= rows as usize,
+            _ => self == Paging::Always,
+        }
+    }
+}
+
+/// Lines `text` takes up on a screen `columns` wide, counting wrapped lines.
+fn screen_lines(text: &str, columns: usize) -> usize {
+    text.lines()
+        .map(|line| console::measure_text_width(line).div_ceil(columns.max(1)).max(1))
+        .sum()
+}
+
+/// Pipes `text` through `$PAGER` (or `less -R`) and waits for it to be closed.
+pub fn page(text: &str) -> Result<()> {
+    let pager = std::env::var("PAGER").ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+    let mut parts = pager.split_whitespace();
+    let program = parts.next().unwrap_or("less");
+    let mut child = Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Could not start pager '{}': {}", pager, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Quitting the pager before reaching the end closes the pipe early, which is fine.
+        match stdin.write_all(text.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Result};
+
+use crate::code;
+
+/// How many lines away from where its header says a hunk may be found, like `patch` allows
+const MAX_OFFSET: usize = 200;
+
+/// Builds the prompt asking for a unified diff that makes the requested change to a file, with
+/// other files of the project (`--repo`) after it.
+pub fn edit_prompt(path: &str, contents: &str, instruction: &str, context: &str) -> String {
+    format!(
+        "Make this change to the file {}: {}\n\n\
+         Respond with only a unified diff of the file in a ```diff code block: `--- a/{}` and `+++ b/{}` headers, \
+         then hunks starting with `@@ -start,count +start,count @@` with 3 lines of unchanged context around each \
+         change. Copy context and removed lines exactly, including their indentation. Change nothing beyond what \
+         was asked.\n\n\
+         ```\n{}\n```{}",
+        path,
+        instruction.trim(),
+        path,
+        path,
+        contents.trim_end_matches('\n'),
+        context
+    )
+}
+
+/// The prompt asking again after a diff didn't apply.
+pub fn retry_prompt(prompt: &str, response: &str, error: &anyhow::Error) -> String {
+    format!(
+        "{}\n\nYour previous diff was:\n\n{}\n\nIt does not apply to the file: {}. Answer again with a corrected diff.",
+        prompt,
+        response.trim(),
+        error
+    )
+}
+
+/// One `@@` section of a unified diff.
+struct Hunk {
+    /// First line of the hunk in the original file, 1-based (0 for an insertion into an empty file)
+    old_start: usize,
+    /// The hunk's lines, each starting with ' ', '-' or '+'
+    lines: Vec<String>,
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines.iter().filter(|line| !line.starts_with('+')).map(|line| &line[1..]).collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines.iter().filter(|line| !line.starts_with('-')).map(|line| &line[1..]).collect()
+    }
+}
+
+/// A unified diff of a single file.
+pub struct Patch {
+    hunks: Vec<Hunk>,
+}
+
+impl Patch {
+    /// Reads the diff in a response (the first fenced block, or the whole response).
+    pub fn parse(response: &str) -> Result<Patch> {
+        let diff = if response.contains("```") { code::strip_fences(response) } else { response.to_string() };
+        let mut hunks: Vec<Hunk> = Vec::new();
+        for line in diff.lines() {
+            if let Some(header) = line.strip_prefix("@@ ") {
+                hunks.push(Hunk { old_start: parse_old_start(header)?, lines: Vec::new() });
+                continue;
+            }
+            let Some(hunk) = hunks.last_mut() else {
+                // `diff`, `---` and `+++` headers, or text before the diff
+                continue;
+            };
+            match line.chars().next() {
+                Some(' ' | '-' | '+') if !line.starts_with("--- ") && !line.starts_with("+++ ") => hunk.lines.push(line.to_string()),
+                // Models often drop the space of blank context lines.
+                None => hunk.lines.push(" ".to_string()),
+                // "\ No newline at end of file", or the end of the block
+                _ => {}
+            }
+        }
+        hunks.retain(|hunk| hunk.lines.iter().any(|line| !line.starts_with(' ')));
+        if hunks.is_empty() {
+            return Err(anyhow!("the response contains no diff hunks"));
+        }
+        Ok(Patch { hunks })
+    }
+
+    /// Applies the patch to the file's contents. Each hunk's context and removed lines must match
+    /// the file exactly, though a hunk may be found some lines away from where it says it is.
+    pub fn apply(&self, original: &str) -> Result<String> {
+        let lines: Vec<&str> = original.lines().collect();
+        let mut result: Vec<&str> = Vec::with_capacity(lines.len());
+        let mut position = 0;
+        for (number, hunk) in self.hunks.iter().enumerate() {
+            let old = hunk.old_lines();
+            let expected = hunk.old_start.saturating_sub(1).max(position);
+            let start = find(&lines, &old, expected, position)
+                .ok_or_else(|| anyhow!("hunk {} (at line {}) doesn't match the file", number + 1, hunk.old_start))?;
+            result.extend_from_slice(&lines[position..start]);
+            result.extend(hunk.new_lines());
+            position = start + old.len();
+        }
+        result.extend_from_slice(&lines[position..]);
+
+        let mut patched = result.join("\n");
+        if !patched.is_empty() && (original.ends_with('\n') || original.is_empty()) {
+            patched.push('\n');
+        }
+        Ok(patched)
+    }
+
+    /// The patch with removed lines in red, added ones in green and hunk headers in cyan.
+    pub fn render_colored(&self) -> String {
+        let mut text = String::new();
+        for hunk in &self.hunks {
+            text.push_str(&format!("{}\n", console::style(format!("@@ line {} @@", hunk.old_start)).cyan()));
+            for line in &hunk.lines {
+                let styled = match line.chars().next() {
+                    Some('-') => console::style(line.as_str()).red().to_string(),
+                    Some('+') => console::style(line.as_str()).green().to_string(),
+                    _ => line.clone(),
+                };
+                text.push_str(&styled);
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    /// How many lines the patch adds and removes.
+    pub fn stats(&self) -> (usize, usize) {
+        let lines = self.hunks.iter().flat_map(|hunk| &hunk.lines);
+        lines.fold((0, 0), |(added, removed), line| match line.chars().next() {
+            Some('+') => (added + 1, removed),
+            Some('-') => (added, removed + 1),
+            _ => (added, removed),
+        })
+    }
+}
+
+/// The `-start` of a hunk header such as `-12,7 +12,9 @@`.
+fn parse_old_start(header: &str) -> Result<usize> {
+    header.split_whitespace()
+        .next()
+        .and_then(|old| old.strip_prefix('-'))
+        .and_then(|old| old.split(',').next())
+        .and_then(|start| start.parse().ok())
+        .ok_or_else(|| anyhow!("invalid hunk header '@@ {}'", header))
+}
+
+/// Where `needle` occurs in `lines` at or after `min`, trying `expected` first and then ever
+/// further away from it.
+fn find(lines: &[&str], needle: &[&str], expected: usize, min: usize) -> Option<usize> {
+    let matches = |start: usize| start + needle.len() <= lines.len() && lines[start..start + needle.len()] == *needle;
+    if needle.is_empty() {
+        return Some(expected.min(lines.len()));
+    }
+    (0..=MAX_OFFSET).find_map(|offset| {
+        let after = expected + offset;
+        let before = expected.checked_sub(offset).filter(|&start| start >= min);
+        if matches(after) {
+            Some(after)
+        } else {
+            before.filter(|&start| matches(start))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n";
+
+    #[test]
+    fn applies_a_fenced_diff() {
+        let response = "Here is the change:\n\n```diff\n--- a/main.rs\n+++ b/main.rs\n@@ -1,4 +1,4 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n     println!(\"{}\", x);\n }\n```\n";
+        let patch = Patch::parse(response).unwrap();
+        assert_eq!(patch.stats(), (1, 1));
+        assert_eq!(patch.apply(ORIGINAL).unwrap(), ORIGINAL.replace("= 1", "= 2"));
+    }
+
+    #[test]
+    fn finds_hunks_away_from_their_stated_line() {
+        let patch = Patch::parse("@@ -10,2 +10,3 @@\n     let x = 1;\n+    let y = x;\n     println!(\"{}\", x);\n").unwrap();
+        assert_eq!(
+            patch.apply(ORIGINAL).unwrap(),
+            "fn main() {\n    let x = 1;\n    let y = x;\n    println!(\"{}\", x);\n}\n"
+        );
+    }
+
+    #[test]
+    fn applies_several_hunks_in_order() {
+        let original = "a\nb\nc\nd\ne\nf\n";
+        let patch = Patch::parse("@@ -1,2 +1,2 @@\n-a\n+A\n b\n@@ -5,2 +5,1 @@\n e\n-f\n").unwrap();
+        assert_eq!(patch.apply(original).unwrap(), "A\nb\nc\nd\ne\n");
+    }
+
+    #[test]
+    fn accepts_blank_context_lines_without_their_space() {
+        let original = "first\n\nlast\n";
+        let patch = Patch::parse("@@ -1,3 +1,3 @@\n first\n\n-last\n+final\n").unwrap();
+        assert_eq!(patch.apply(original).unwrap(), "first\n\nfinal\n");
+    }
+
+    #[test]
+    fn keeps_a_missing_final_newline_missing() {
+        let patch = Patch::parse("@@ -1 +1 @@\n-old\n+new\n\\ No newline at end of file\n").unwrap();
+        assert_eq!(patch.apply("old").unwrap(), "new");
+    }
+
+    #[test]
+    fn inserts_into_an_empty_file() {
+        let patch = Patch::parse("@@ -0,0 +1,2 @@\n+one\n+two\n").unwrap();
+        assert_eq!(patch.apply("").unwrap(), "one\ntwo\n");
+    }
+
+    #[test]
+    fn rejects_hunks_that_do_not_match() {
+        let patch = Patch::parse("@@ -2 +2 @@\n-    let x = 3;\n+    let x = 4;\n").unwrap();
+        let error = patch.apply(ORIGINAL).unwrap_err();
+        assert_eq!(error.to_string(), "hunk 1 (at line 2) doesn't match the file");
+    }
+
+    #[test]
+    fn rejects_responses_without_changes() {
+        assert!(Patch::parse("No changes are needed.").is_err());
+        assert!(Patch::parse("@@ -1 +1 @@\n unchanged\n").is_err());
+        assert!(Patch::parse("@@ nonsense @@\n-a\n").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::lock;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// The IHDR chunk always comes first: 4 bytes length, 4 type, 13 data and 4 CRC.
+const IHDR_END: usize = SIGNATURE.len() + 4 + 4 + 13 + 4;
+
+fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    chunk.extend_from_slice(&hasher.finalize().to_be_bytes());
+    chunk
+}
+
+/// A tEXt chunk when the text fits in Latin-1, as the PNG spec requires, and an iTXt chunk
+/// (UTF-8) otherwise.
+fn text_chunk(keyword: &str, text: &str) -> Vec<u8> {
+    let latin1: Option<Vec<u8>> = text.chars().map(|c| u8::try_from(u32::from(c)).ok()).collect();
+    match latin1 {
+        Some(bytes) => chunk(b"tEXt", &[keyword.as_bytes(), &[0], &bytes].concat()),
+        // Keyword, then no compression, no language tag and no translated keyword.
+        None => chunk(b"iTXt", &[keyword.as_bytes(), &[0, 0, 0, 0, 0], text.as_bytes()].concat()),
+    }
+}
+
+/// Adds text entries (keyword and text) to a PNG file, right after its header. Other images
+/// are left alone, returning false.
+pub fn add_text(path: &Path, entries: &[(&str, &str)]) -> Result<bool> {
+    let bytes = fs::read(path)?;
+    if !bytes.starts_with(SIGNATURE) {
+        return Ok(false);
+    }
+    if bytes.len() < IHDR_END || &bytes[12..16] != b"IHDR" {
+        return Err(anyhow!("{} is not a valid PNG image", path.display()));
+    }
+    let mut out = bytes[..IHDR_END].to_vec();
+    for (keyword, text) in entries {
+        out.extend(text_chunk(keyword, text));
+    }
+    out.extend_from_slice(&bytes[IHDR_END..]);
+    lock::write_atomic(path, &out)?;
+    Ok(true)
+}
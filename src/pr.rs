@@ -0,0 +1,90 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::{git, voice};
+
+/// Where GitHub looks for a repository's pull request template, relative to its top level.
+const REPO_TEMPLATES: &[&str] = &[
+    ".github/pull_request_template.md",
+    ".github/PULL_REQUEST_TEMPLATE.md",
+    "PULL_REQUEST_TEMPLATE.md",
+    "docs/pull_request_template.md",
+];
+
+/// Used when neither `--template`, `pr_template` nor the repository provides one.
+const DEFAULT_TEMPLATE: &str = "## Summary\n\n\
+    What the change does and why.\n\n\
+    ## Changes\n\n\
+    - The notable changes, one per bullet\n\n\
+    ## Testing\n\n\
+    How the change was verified.\n";
+
+/// The template the description follows: `explicit` (from `--template` or `pr_template` in the
+/// config file), else the repository's pull request template, else a built-in one.
+pub fn load_template(explicit: Option<&Path>) -> Result<String> {
+    if let Some(path) = explicit {
+        return fs::read_to_string(path).with_context(|| format!("Could not read the PR template {}", path.display()));
+    }
+    let top = git::toplevel()?;
+    Ok(REPO_TEMPLATES.iter()
+        .map(|template| top.join(template))
+        .find_map(|path| fs::read_to_string(path).ok())
+        .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()))
+}
+
+/// Builds the prompt asking for a pull request title and description.
+pub fn pr_prompt(base: &str, log: &str, diff: &str, template: &str) -> String {
+    format!(
+        "Write a pull request title and description for the changes of this branch, which is to be merged into {}.\n\
+         The title goes on the first line: concise, in the imperative mood, at most 72 characters, with no prefix \
+         such as \"Title:\". Then a blank line and the description in Markdown, filling in the sections of the \
+         template below; leave out sections that don't apply. Describe what changed and why, not every line. \
+         Respond with only the title and description, with no code fences or commentary.\n\n\
+         Template:\n\n{}\n\nCommits:\n\n{}\n\nDiff:\n\n{}",
+        base,
+        template.trim(),
+        log.trim(),
+        git::fenced_diff(diff)
+    )
+}
+
+/// Splits the model's answer into the title and the description.
+pub fn parse_pr(response: &str) -> Result<(String, String)> {
+    let message = git::clean_message(response);
+    let (title, body) = message.split_once('\n').unwrap_or((&message, ""));
+    let title = title.trim().trim_start_matches('#').trim();
+    let title = title.strip_prefix("Title:").unwrap_or(title).trim().trim_matches('*').trim();
+    if title.is_empty() {
+        return Err(anyhow!("The model returned an empty pull request title"));
+    }
+    Ok((title.to_string(), body.trim().to_string()))
+}
+
+/// Fails unless the GitHub CLI is installed, before any request is made for `--push-gh`.
+pub fn ensure_gh() -> Result<()> {
+    if voice::is_installed("gh") {
+        Ok(())
+    } else {
+        Err(anyhow!("--push-gh needs the GitHub CLI (gh) on the PATH: https://cli.github.com"))
+    }
+}
+
+/// Creates the pull request with the GitHub CLI, which prints its URL.
+pub fn create_with_gh(base: &str, title: &str, body: &str, draft: bool) -> Result<()> {
+    let mut command = Command::new("gh");
+    command.args(["pr", "create", "--base", base, "--title", title, "--body-file", "-"]);
+    if draft {
+        command.arg("--draft");
+    }
+    let mut child = command.stdin(Stdio::piped()).spawn().context("Could not run gh")?;
+    child.stdin.take().expect("stdin is piped").write_all(body.as_bytes())?;
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("gh pr create failed with {}", status))
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, RgbImage};
+use std::collections::BTreeSet;
+use std::io::{Cursor, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Widest a preview gets, in terminal columns; it is a thumbnail, not a viewer.
+const PREVIEW_COLUMNS: u32 = 48;
+/// Pixel width that images sent to graphics-capable terminals are scaled down to.
+const PREVIEW_PIXELS: u32 = 512;
+/// Kitty takes base64 image data in chunks of at most this many bytes.
+const KITTY_CHUNK: usize = 4096;
+
+/// How an image is drawn in the terminal.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Protocol {
+    /// Kitty graphics protocol (kitty, Ghostty)
+    Kitty,
+    /// iTerm2 inline images (iTerm2, WezTerm)
+    Iterm2,
+    /// Sixel graphics (foot, mlterm, Windows Terminal, xterm -ti vt340)
+    Sixel,
+    /// Unicode half blocks in 24-bit color, which any modern terminal shows
+    Blocks,
+}
+
+/// Guesses the best protocol the terminal supports from its environment. Inside tmux or
+/// screen, which don't pass graphics through by default, half blocks are used.
+pub fn detect() -> Protocol {
+    let var = |name: &str| std::env::var(name).unwrap_or_default();
+    let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+    if std::env::var_os("TMUX").is_some() || term.starts_with("screen") || term.starts_with("tmux") {
+        Protocol::Blocks
+    } else if std::env::var_os("KITTY_WINDOW_ID").is_some() || term == "xterm-kitty" || program == "ghostty" || term == "xterm-ghostty" {
+        Protocol::Kitty
+    } else if program == "iTerm.app" || program == "WezTerm" || var("LC_TERMINAL") == "iTerm2" {
+        Protocol::Iterm2
+    } else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") || program == "contour"
+        || std::env::var_os("WT_SESSION").is_some()
+    {
+        Protocol::Sixel
+    } else {
+        Protocol::Blocks
+    }
+}
+
+/// Opens a file in the default viewer without waiting for it to close.
+pub fn open(path: &Path) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    command.arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Could not open {} in the default viewer", path.display()))?;
+    Ok(())
+}
+
+/// Draws a thumbnail of the image file on stdout.
+pub fn show(path: &Path, protocol: Protocol) -> Result<()> {
+    // Result URLs don't always end in the right extension, so the format is read from the file.
+    let image = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()
+        .with_context(|| format!("Could not read {} for the preview", path.display()))?;
+    let columns = console::Term::stdout().size_checked()
+        .map(|(_, columns)| u32::from(columns))
+        .unwrap_or(PREVIEW_COLUMNS)
+        .min(PREVIEW_COLUMNS);
+
+    let output = match protocol {
+        Protocol::Kitty => kitty(&image, columns)?,
+        Protocol::Iterm2 => iterm2(&image, columns)?,
+        Protocol::Sixel => sixel(&flatten(&image.thumbnail(PREVIEW_PIXELS.min(columns * 10), u32::MAX))),
+        Protocol::Blocks => half_blocks(&image, columns),
+    };
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(output.as_bytes())?;
+    writeln!(stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn png_base64(image: &DynamicImage) -> Result<String> {
+    let mut png = Vec::new();
+    image.thumbnail(PREVIEW_PIXELS, u32::MAX).write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(png))
+}
+
+fn kitty(image: &DynamicImage, columns: u32) -> Result<String> {
+    let data = png_base64(image)?;
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut output = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+        if i == 0 {
+            output.push_str(&format!("\x1b_Ga=T,f=100,c={},m={};{}\x1b\\", columns, more, chunk));
+        } else {
+            output.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk));
+        }
+    }
+    Ok(output)
+}
+
+fn iterm2(image: &DynamicImage, columns: u32) -> Result<String> {
+    Ok(format!("\x1b]1337;File=inline=1;width={};preserveAspectRatio=1:{}\x07", columns, png_base64(image)?))
+}
+
+/// The image without transparency, over a black background.
+fn flatten(image: &DynamicImage) -> RgbImage {
+    let mut rgb = RgbImage::new(image.width(), image.height());
+    for (x, y, pixel) in image.to_rgba8().enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        let blend = |channel: u8| (u16::from(channel) * u16::from(a) / 255) as u8;
+        rgb.put_pixel(x, y, image::Rgb([blend(r), blend(g), blend(b)]));
+    }
+    rgb
+}
+
+/// Encodes the image as sixels, with colors reduced to a 6x6x6 cube.
+fn sixel(image: &RgbImage) -> String {
+    let (width, height) = image.dimensions();
+    let level = |channel: u8| (u32::from(channel) * 5 + 127) / 255;
+    let colors: Vec<u32> = image.pixels().map(|pixel| level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2])).collect();
+
+    let mut output = format!("\x1bPq\"1;1;{};{}", width, height);
+    for color in colors.iter().collect::<BTreeSet<_>>() {
+        output.push_str(&format!("#{};2;{};{};{}", color, color / 36 * 20, color / 6 % 6 * 20, color % 6 * 20));
+    }
+    for top in (0..height).step_by(6) {
+        let rows = top..(top + 6).min(height);
+        let band: BTreeSet<u32> = rows.clone().flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| colors[(y * width + x) as usize]).collect();
+        for color in band {
+            output.push_str(&format!("#{}", color));
+            let sixels = (0..width).map(|x| {
+                let bits = rows.clone()
+                    .filter(|y| colors[(y * width + x) as usize] == color)
+                    .fold(0u8, |bits, y| bits | 1 << (y - top));
+                char::from(63 + bits)
+            });
+            push_runs(&mut output, sixels);
+            output.push('$');
+        }
+        output.push('-');
+    }
+    output.push_str("\x1b\\");
+    output
+}
+
+/// Appends sixel characters, writing runs of the same one with the repeat introducer.
+fn push_runs(output: &mut String, sixels: impl Iterator<Item = char>) {
+    let mut run: Option<(char, usize)> = None;
+    for sixel in sixels.map(Some).chain([None]) {
+        match (run, sixel) {
+            (Some((current, count)), Some(next)) if current == next => run = Some((current, count + 1)),
+            (previous, next) => {
+                if let Some((current, count)) = previous {
+                    if count > 3 {
+                        output.push_str(&format!("!{}{}", count, current));
+                    } else {
+                        output.extend(std::iter::repeat_n(current, count));
+                    }
+                }
+                run = next.map(|next| (next, 1));
+            }
+        }
+    }
+}
+
+/// Draws the image with upper half blocks, two pixels per character: the top one in the
+/// foreground color and the bottom one in the background color.
+fn half_blocks(image: &DynamicImage, columns: u32) -> String {
+    let (width, height) = (image.width().max(1), image.height().max(1));
+    let rows = (columns * height / width).max(2).next_multiple_of(2);
+    let image = flatten(&image.resize_exact(columns, rows, FilterType::Triangle));
+
+    let mut output = String::new();
+    for y in (0..rows).step_by(2) {
+        for x in 0..columns {
+            let ([tr, tg, tb], [br, bg, bb]) = (image.get_pixel(x, y).0, image.get_pixel(x, y + 1).0);
+            output.push_str(&format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀", tr, tg, tb, br, bg, bb));
+        }
+        output.push_str("\x1b[0m");
+        if y + 2 < rows {
+            output.push('\n');
+        }
+    }
+    output
+}
@@ -0,0 +1,236 @@
+//! Chat backends. 1min.ai is the default; OpenAI-compatible endpoints and a local Ollama can be
+//! selected with `--provider` or the `provider` setting.
+
+use anyhow::{anyhow, Result};
+use futures_util::future::BoxFuture;
+use reqwest::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::client::{ChatStream, ImageGeneration};
+use crate::config::Config;
+use crate::error::AiCliError;
+use crate::retry;
+use crate::sampling::Sampling;
+
+pub mod ollama;
+pub mod onemin;
+pub mod openai;
+pub mod redacting;
+
+/// Name of the built-in 1min.ai provider.
+pub const ONEMIN: &str = "1min";
+pub const OPENAI: &str = "openai";
+pub const OLLAMA: &str = "ollama";
+
+const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_OPENAI_KEY_ENV: &str = "OPENAI_API_KEY";
+const DEFAULT_OLLAMA_BASE_URL: &str = "http://localhost:11434";
+const DEFAULT_OLLAMA_MODEL: &str = "llama3.2";
+/// Embedding models used when none is chosen
+pub const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+pub const DEFAULT_OLLAMA_EMBEDDING_MODEL: &str = "nomic-embed-text";
+
+/// One earlier message of a conversation, sent along by providers that don't keep
+/// conversations themselves.
+#[derive(Serialize, Clone)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+}
+
+impl Message {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Message { role: role.to_string(), content: content.into() }
+    }
+}
+
+pub struct ChatRequest<'a> {
+    pub model: &'a str,
+    pub prompt: &'a str,
+    /// Response length limit in words, for providers that take one (1min.ai)
+    pub max_words: u32,
+    /// Response length limit in tokens, for providers that take one; unlimited when None
+    pub max_tokens: Option<u32>,
+    /// Checked against the model with [`crate::sampling::resolve`] beforehand
+    pub sampling: Sampling,
+    /// Conversation the provider keeps server-side (see [`Provider::hosts_conversations`])
+    pub conversation_id: Option<&'a str>,
+    /// Earlier messages, for providers that don't keep conversations
+    pub history: &'a [Message],
+}
+
+/// What the daemon and the server answer requests with where the request doesn't say.
+#[derive(Clone, Copy)]
+pub struct RequestDefaults {
+    pub max_words: u32,
+    pub max_tokens: Option<u32>,
+    /// Sent where the model accepts them
+    pub sampling: Sampling,
+}
+
+/// A backend that answers chat prompts, and possibly generates images.
+pub trait Provider: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Whether the provider keeps conversations server-side, keyed by the conversation id.
+    /// Otherwise the earlier messages are sent with every request.
+    fn hosts_conversations(&self) -> bool;
+
+    fn chat_stream<'a>(&'a self, request: ChatRequest<'a>) -> BoxFuture<'a, Result<ChatStream, AiCliError>>;
+
+    /// Whether [`Provider::generate_image`] is supported at all.
+    fn generates_images(&self) -> bool {
+        true
+    }
+
+    /// Generates images and returns their download URLs.
+    fn generate_image<'a>(&'a self, options: &'a ImageGeneration<'a>) -> BoxFuture<'a, Result<Vec<String>, AiCliError>>;
+
+    /// Embeds each input with an embedding model and returns the vectors, in the same order.
+    fn embed<'a>(&'a self, _model: &'a str, _inputs: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, AiCliError>> {
+        Box::pin(async move {
+            Err(AiCliError::Unsupported(format!("The '{}' provider can't create embeddings", self.name())))
+        })
+    }
+
+    /// Lists the available models, as returned by the provider.
+    fn list_models(&self) -> BoxFuture<'_, Result<serde_json::Value, AiCliError>>;
+
+    /// Replaces the API key used for subsequent requests, after the user entered a new one.
+    fn set_api_key(&self, _api_key: String) {}
+
+    /// Sends a one-off prompt and waits for the complete response.
+    fn chat<'a>(&'a self, model: &'a str, prompt: &'a str, max_words: u32) -> BoxFuture<'a, Result<String, AiCliError>> {
+        Box::pin(async move {
+            let request = ChatRequest { model, prompt, max_words, max_tokens: None, sampling: Sampling::default(), conversation_id: None, history: &[] };
+            self.chat_stream(request).await?.collect().await
+        })
+    }
+}
+
+/// Sends a request with retries, turning error responses into [`AiCliError`]s.
+async fn send(request: RequestBuilder) -> Result<Response, AiCliError> {
+    let response = retry::send(request).await?;
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(AiCliError::from_response(response).await)
+    }
+}
+
+/// The API a provider speaks.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProviderKind {
+    OneMin,
+    OpenAi,
+    Ollama,
+}
+
+impl ProviderKind {
+    /// The embedding model used when none is chosen.
+    pub fn default_embedding_model(self) -> &'static str {
+        match self {
+            ProviderKind::OneMin | ProviderKind::OpenAi => DEFAULT_EMBEDDING_MODEL,
+            ProviderKind::Ollama => DEFAULT_OLLAMA_EMBEDDING_MODEL,
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            ONEMIN | "1min.ai" => Some(ProviderKind::OneMin),
+            OPENAI | "openai-compatible" => Some(ProviderKind::OpenAi),
+            OLLAMA => Some(ProviderKind::Ollama),
+            _ => None,
+        }
+    }
+}
+
+/// A `[providers.NAME]` entry of the config file.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ProviderConfig {
+    /// API the provider speaks: `1min`, `openai` or `ollama`; defaults to the entry's name
+    #[serde(default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Environment variable holding the API key
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    /// Model used when `--model` isn't given
+    #[serde(default)]
+    pub default_model: Option<String>,
+    /// Requests per minute to stay under; overrides the top-level `requests_per_minute`
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+}
+
+/// A provider chosen by name, with the config entry's settings filled in with the defaults of
+/// its kind.
+pub struct ProviderSettings {
+    pub name: String,
+    pub kind: ProviderKind,
+    pub base_url: Option<String>,
+    pub api_key_env: Option<String>,
+    pub default_model: Option<String>,
+    pub requests_per_minute: Option<u32>,
+}
+
+/// Looks up a provider by name: `1min`, `openai`, `ollama`, or an entry under `[providers]`.
+pub fn resolve(name: &str, config: &Config) -> Result<ProviderSettings> {
+    let entry = config.providers.get(name).cloned().unwrap_or_default();
+    let kind_name = entry.kind.as_deref().unwrap_or(name);
+    let kind = ProviderKind::parse(kind_name).ok_or_else(|| {
+        if config.providers.contains_key(name) {
+            anyhow!("Unknown kind '{}' for provider '{}'. Use 1min, openai or ollama", kind_name, name)
+        } else {
+            let mut names = vec![ONEMIN, OPENAI, OLLAMA];
+            names.extend(config.providers.keys().map(|k| k.as_str()));
+            anyhow!("Unknown provider '{}'. Available providers: {}", name, names.join(", "))
+        }
+    })?;
+
+    let (base_url, api_key_env, default_model) = match kind {
+        ProviderKind::OneMin => (None, None, None),
+        ProviderKind::OpenAi if name == OPENAI => (
+            Some(DEFAULT_OPENAI_BASE_URL),
+            Some(DEFAULT_OPENAI_KEY_ENV),
+            None,
+        ),
+        ProviderKind::OpenAi => (None, None, None),
+        ProviderKind::Ollama => (Some(DEFAULT_OLLAMA_BASE_URL), None, Some(DEFAULT_OLLAMA_MODEL)),
+    };
+    Ok(ProviderSettings {
+        name: name.to_string(),
+        kind,
+        base_url: entry.base_url.or(base_url.map(String::from)),
+        api_key_env: entry.api_key_env.or(api_key_env.map(String::from)),
+        default_model: entry.default_model.or(default_model.map(String::from)),
+        requests_per_minute: entry.requests_per_minute.or(config.requests_per_minute),
+    })
+}
+
+/// Connects to an OpenAI-compatible or Ollama provider. 1min.ai needs its API key looked up
+/// first, so it is built from a [`crate::client::OneMinClient`] instead.
+pub fn connect(settings: &ProviderSettings, http: Client) -> Result<Box<dyn Provider>> {
+    let base_url = settings.base_url.as_deref()
+        .ok_or_else(|| anyhow!("Provider '{}' needs a base_url in the config file", settings.name))?;
+    if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+        return Err(anyhow!("Invalid base URL '{}' for provider '{}': it must start with http:// or https://", base_url, settings.name));
+    }
+    let api_key = match &settings.api_key_env {
+        Some(env) => match std::env::var(env) {
+            Ok(key) if !key.trim().is_empty() => Some(key.trim().to_string()),
+            _ => return Err(AiCliError::Auth(format!(
+                "{} is not set; the '{}' provider reads its API key from it",
+                env, settings.name
+            )).into()),
+        },
+        None => None,
+    };
+
+    match settings.kind {
+        ProviderKind::OpenAi => Ok(Box::new(openai::OpenAiCompatible::new(http, &settings.name, base_url, api_key))),
+        ProviderKind::Ollama => Ok(Box::new(ollama::Ollama::new(http, &settings.name, base_url))),
+        ProviderKind::OneMin => Err(anyhow!("The 1min.ai provider is built from its client")),
+    }
+}
@@ -0,0 +1,64 @@
+use futures_util::future::BoxFuture;
+use reqwest::Client;
+
+use crate::client::{ChatStream, ImageGeneration};
+use crate::error::AiCliError;
+use crate::providers::openai::OpenAiCompatible;
+use crate::providers::{send, ChatRequest, Provider};
+
+/// Ollama's OpenAI-compatible API lives under this prefix of the server URL.
+const OPENAI_PREFIX: &str = "/v1";
+/// Ollama's own model listing, which includes models pulled but not yet loaded.
+const TAGS_URL: &str = "/api/tags";
+
+/// A local (or self-hosted) Ollama server. Chats and embeddings go through its OpenAI-compatible endpoint;
+/// models are listed with Ollama's own API. No API key is needed.
+pub struct Ollama {
+    http: Client,
+    base_url: String,
+    chat: OpenAiCompatible,
+}
+
+impl Ollama {
+    /// `base_url` is the server URL, e.g. `http://localhost:11434`.
+    pub fn new(http: Client, name: &str, base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/').trim_end_matches(OPENAI_PREFIX).to_string();
+        let chat = OpenAiCompatible::new(http.clone(), name, &format!("{}{}", base_url, OPENAI_PREFIX), None);
+        Ollama { http, base_url, chat }
+    }
+}
+
+impl Provider for Ollama {
+    fn name(&self) -> &str {
+        self.chat.name()
+    }
+
+    fn hosts_conversations(&self) -> bool {
+        false
+    }
+
+    fn chat_stream<'a>(&'a self, request: ChatRequest<'a>) -> BoxFuture<'a, Result<ChatStream, AiCliError>> {
+        self.chat.chat_stream(request)
+    }
+
+    fn generates_images(&self) -> bool {
+        false
+    }
+
+    fn generate_image<'a>(&'a self, _options: &'a ImageGeneration<'a>) -> BoxFuture<'a, Result<Vec<String>, AiCliError>> {
+        Box::pin(async move {
+            Err(AiCliError::Unsupported(format!("The '{}' provider (Ollama) can't generate images", self.name())))
+        })
+    }
+
+    fn embed<'a>(&'a self, model: &'a str, inputs: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, AiCliError>> {
+        self.chat.embed(model, inputs)
+    }
+
+    fn list_models(&self) -> BoxFuture<'_, Result<serde_json::Value, AiCliError>> {
+        Box::pin(async move {
+            let request = self.http.get(format!("{}{}", self.base_url, TAGS_URL));
+            Ok(send(request).await?.json().await?)
+        })
+    }
+}
@@ -0,0 +1,49 @@
+use futures_util::future::BoxFuture;
+
+use crate::client::{ChatStream, ImageGeneration, OneMinClient};
+use crate::error::AiCliError;
+use crate::providers::{ChatRequest, Provider, ONEMIN};
+
+/// 1min.ai keeps conversations itself, so only the conversation id is sent, never the history.
+impl Provider for OneMinClient {
+    fn name(&self) -> &str {
+        ONEMIN
+    }
+
+    fn hosts_conversations(&self) -> bool {
+        true
+    }
+
+    fn chat_stream<'a>(&'a self, request: ChatRequest<'a>) -> BoxFuture<'a, Result<ChatStream, AiCliError>> {
+        Box::pin(OneMinClient::chat_stream(
+            self,
+            request.conversation_id,
+            request.model,
+            request.prompt,
+            request.max_words,
+            request.sampling,
+        ))
+    }
+
+    fn generate_image<'a>(&'a self, options: &'a ImageGeneration<'a>) -> BoxFuture<'a, Result<Vec<String>, AiCliError>> {
+        Box::pin(async move {
+            let record = OneMinClient::generate_image(self, options).await?;
+            if record.status != "SUCCESS" {
+                return Err(AiCliError::InvalidResponse(format!("image generation failed with status {}", record.status)));
+            }
+            Ok(record.result_urls(self.asset_base_url()))
+        })
+    }
+
+    fn embed<'a>(&'a self, model: &'a str, inputs: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, AiCliError>> {
+        Box::pin(OneMinClient::embed(self, model, inputs))
+    }
+
+    fn list_models(&self) -> BoxFuture<'_, Result<serde_json::Value, AiCliError>> {
+        Box::pin(OneMinClient::list_models(self))
+    }
+
+    fn set_api_key(&self, api_key: String) {
+        OneMinClient::set_api_key(self, api_key);
+    }
+}
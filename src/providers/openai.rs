@@ -0,0 +1,183 @@
+use futures_util::future::BoxFuture;
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::client::{ChatStream, ImageGeneration};
+use crate::error::AiCliError;
+use crate::providers::{send, ChatRequest, Message, Provider};
+use crate::sampling::Sampling;
+
+const CHAT_COMPLETIONS_PATH: &str = "/chat/completions";
+const IMAGE_GENERATIONS_PATH: &str = "/images/generations";
+const MODELS_PATH: &str = "/models";
+const EMBEDDINGS_PATH: &str = "/embeddings";
+
+#[derive(Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<Message>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(flatten)]
+    sampling: Sampling,
+}
+
+#[derive(Serialize)]
+struct ImageRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    n: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aspect_ratio: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    negative_prompt: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    steps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cfg_scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    response_format: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ImageResponse {
+    #[serde(default)]
+    data: Vec<ImageData>,
+}
+
+#[derive(Deserialize)]
+struct ImageData {
+    #[serde(default)]
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    #[serde(default)]
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Any endpoint speaking the OpenAI chat completions API: OpenAI itself, Azure-style gateways,
+/// vLLM, LM Studio, OpenRouter and the like.
+pub struct OpenAiCompatible {
+    http: Client,
+    name: String,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl OpenAiCompatible {
+    /// `base_url` includes the version prefix, e.g. `https://api.openai.com/v1`.
+    pub fn new(http: Client, name: &str, base_url: &str, api_key: Option<String>) -> Self {
+        OpenAiCompatible {
+            http,
+            name: name.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+
+    fn post<T: Serialize>(&self, path: &str, body: &T) -> RequestBuilder {
+        self.authorize(self.http.post(format!("{}{}", self.base_url, path)).json(body))
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.authorize(self.http.get(format!("{}{}", self.base_url, path)))
+    }
+}
+
+impl Provider for OpenAiCompatible {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn hosts_conversations(&self) -> bool {
+        false
+    }
+
+    fn chat_stream<'a>(&'a self, request: ChatRequest<'a>) -> BoxFuture<'a, Result<ChatStream, AiCliError>> {
+        Box::pin(async move {
+            let mut messages = request.history.to_vec();
+            messages.push(Message::new("user", request.prompt));
+            let body = CompletionRequest { model: request.model, messages, stream: true, max_tokens: request.max_tokens, sampling: request.sampling };
+            let response = send(self.post(CHAT_COMPLETIONS_PATH, &body)).await?;
+            Ok(ChatStream::from_response(response))
+        })
+    }
+
+    fn generate_image<'a>(&'a self, options: &'a ImageGeneration<'a>) -> BoxFuture<'a, Result<Vec<String>, AiCliError>> {
+        Box::pin(async move {
+            let body = ImageRequest {
+                model: options.model,
+                prompt: options.prompt,
+                n: options.count,
+                size: options.size,
+                quality: options.quality,
+                style: options.style,
+                aspect_ratio: options.aspect_ratio,
+                negative_prompt: options.negative_prompt,
+                steps: options.steps,
+                cfg_scale: options.cfg_scale,
+                seed: options.seed,
+                response_format: "url",
+            };
+            let response = send(self.post(IMAGE_GENERATIONS_PATH, &body)).await?;
+            let images: ImageResponse = response.json().await?;
+            let urls: Vec<String> = images.data.into_iter().filter_map(|image| image.url).collect();
+            if urls.is_empty() {
+                return Err(AiCliError::InvalidResponse("no image URLs in the image generation response".to_string()));
+            }
+            Ok(urls)
+        })
+    }
+
+    fn embed<'a>(&'a self, model: &'a str, inputs: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, AiCliError>> {
+        Box::pin(async move {
+            let response = send(self.post(EMBEDDINGS_PATH, &EmbeddingRequest { model, input: inputs })).await?;
+            let mut embeddings: EmbeddingResponse = response.json().await?;
+            if embeddings.data.len() != inputs.len() {
+                return Err(AiCliError::InvalidResponse(format!(
+                    "{} embeddings returned for {} inputs",
+                    embeddings.data.len(),
+                    inputs.len()
+                )));
+            }
+            embeddings.data.sort_by_key(|data| data.index);
+            Ok(embeddings.data.into_iter().map(|data| data.embedding).collect())
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'_, Result<serde_json::Value, AiCliError>> {
+        Box::pin(async move {
+            Ok(send(self.get(MODELS_PATH)).await?.json().await?)
+        })
+    }
+}
@@ -0,0 +1,66 @@
+use futures_util::future::BoxFuture;
+use std::borrow::Cow;
+
+use crate::client::{ChatStream, ImageGeneration};
+use crate::error::AiCliError;
+use crate::providers::{ChatRequest, Message, Provider};
+use crate::redact::{self, Redaction};
+
+/// Another provider whose chat prompts go through the `redaction` policy before being sent.
+/// Earlier messages of the conversation were reported when they were sent, so they are only
+/// masked again (history is kept as typed); image prompts and embedding inputs are passed on
+/// as they are.
+pub struct Redacting<'a> {
+    inner: &'a dyn Provider,
+    policy: Redaction,
+}
+
+impl<'a> Redacting<'a> {
+    pub fn new(inner: &'a dyn Provider, policy: Redaction) -> Redacting<'a> {
+        Redacting { inner, policy }
+    }
+}
+
+impl Provider for Redacting<'_> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn hosts_conversations(&self) -> bool {
+        self.inner.hosts_conversations()
+    }
+
+    fn chat_stream<'a>(&'a self, request: ChatRequest<'a>) -> BoxFuture<'a, Result<ChatStream, AiCliError>> {
+        Box::pin(async move {
+            let prompt = redact::filter(request.prompt, self.policy)?;
+            let history: Cow<[Message]> = if self.policy == Redaction::Mask {
+                Cow::Owned(request.history.iter()
+                    .map(|message| Message::new(&message.role, redact::mask_secrets(&message.content)))
+                    .collect())
+            } else {
+                Cow::Borrowed(request.history)
+            };
+            self.inner.chat_stream(ChatRequest { prompt: &prompt, history: &history, ..request }).await
+        })
+    }
+
+    fn generates_images(&self) -> bool {
+        self.inner.generates_images()
+    }
+
+    fn generate_image<'a>(&'a self, options: &'a ImageGeneration<'a>) -> BoxFuture<'a, Result<Vec<String>, AiCliError>> {
+        self.inner.generate_image(options)
+    }
+
+    fn embed<'a>(&'a self, model: &'a str, inputs: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, AiCliError>> {
+        self.inner.embed(model, inputs)
+    }
+
+    fn list_models(&self) -> BoxFuture<'_, Result<serde_json::Value, AiCliError>> {
+        self.inner.list_models()
+    }
+
+    fn set_api_key(&self, api_key: String) {
+        self.inner.set_api_key(api_key);
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Seconds' worth of requests that may be sent in a burst before the limit spaces them out.
+const BURST_SECS: f64 = 10.0;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Set after a 429: nothing is sent before then
+    paused_until: Option<Instant>,
+}
+
+/// A token bucket shared by every request of the process, so concurrent batch and compare
+/// requests queue up instead of tripping the API's limit.
+struct Limiter {
+    /// Tokens added per second; `None` when requests aren't limited, only paused after a 429
+    rate: Option<f64>,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+static LIMITER: OnceLock<Limiter> = OnceLock::new();
+
+/// Sets the request rate for the rest of the process; `None` leaves it unlimited.
+pub fn configure(requests_per_minute: Option<u32>) {
+    let rate = requests_per_minute.filter(|rpm| *rpm > 0).map(|rpm| f64::from(rpm) / 60.0);
+    let capacity = rate.map(|rate| (rate * BURST_SECS).max(1.0)).unwrap_or(1.0);
+    let _ = LIMITER.set(Limiter {
+        rate,
+        capacity,
+        bucket: Mutex::new(Bucket { tokens: capacity, updated: Instant::now(), paused_until: None }),
+    });
+}
+
+fn limiter() -> &'static Limiter {
+    LIMITER.get_or_init(|| Limiter {
+        rate: None,
+        capacity: 1.0,
+        bucket: Mutex::new(Bucket { tokens: 1.0, updated: Instant::now(), paused_until: None }),
+    })
+}
+
+/// How long to wait before the next request may go out, taking its token if it may go now.
+fn reserve(limiter: &Limiter) -> Option<Duration> {
+    let mut bucket = limiter.bucket.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let now = Instant::now();
+    if let Some(until) = bucket.paused_until {
+        if until > now {
+            return Some(until - now);
+        }
+        bucket.paused_until = None;
+    }
+
+    let rate = limiter.rate?;
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * rate).min(limiter.capacity);
+    bucket.updated = now;
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        None
+    } else {
+        Some(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+/// Waits until the rate limit (and any pause after a 429) lets another request go out.
+pub async fn acquire() {
+    while let Some(wait) = reserve(limiter()) {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Holds back every request for `delay`, after the API answered one of them with a 429.
+pub fn pause(delay: Duration) {
+    let mut bucket = limiter().bucket.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let until = Instant::now() + delay;
+    if bucket.paused_until.is_none_or(|paused| paused < until) {
+        bucket.paused_until = Some(until);
+    }
+}
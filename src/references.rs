@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::Path;
+
+/// Largest file that can be referenced with `@path`.
+pub const MAX_FILE_BYTES: u64 = 100 * 1024;
+/// Limit on the combined size of all files referenced by one prompt.
+pub const MAX_TOTAL_BYTES: u64 = 400 * 1024;
+/// How much of a file is inspected when deciding whether it is binary.
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+/// Punctuation that commonly follows a reference in prose, e.g. "look at @src/main.rs."
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '"', '\''];
+
+/// Finds the file a `@token` refers to, allowing for trailing punctuation.
+fn resolve(token: &str) -> Option<&str> {
+    let mut candidate = token;
+    loop {
+        if !candidate.is_empty() && Path::new(candidate).is_file() {
+            return Some(candidate);
+        }
+        let trimmed = candidate.strip_suffix(TRAILING_PUNCTUATION)?;
+        candidate = trimmed;
+    }
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(BINARY_SNIFF_BYTES).any(|b| *b == 0)
+}
+
+/// Formats a file as a path-labeled code block, using a longer fence if the file contains one.
+pub fn fenced_block(path: &str, contents: &str) -> String {
+    let language = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("");
+    let fence = if contents.contains("```") { "````" } else { "```" };
+    let newline = if contents.ends_with('\n') { "" } else { "\n" };
+    format!("{}:\n{}{}\n{}{}{}", path, fence, language, contents, newline, fence)
+}
+
+pub fn read_text(path: &str) -> Result<String> {
+    let size = fs::metadata(path)?.len();
+    if size > MAX_FILE_BYTES {
+        return Err(anyhow!(
+            "@{} is too large to include ({} KB, limit is {} KB)",
+            path, size / 1024, MAX_FILE_BYTES / 1024
+        ));
+    }
+
+    let bytes = fs::read(path)?;
+    if is_binary(&bytes) {
+        return Err(anyhow!("@{} looks like a binary file and cannot be included", path));
+    }
+    String::from_utf8(bytes).map_err(|_| anyhow!("@{} is not valid UTF-8 text and cannot be included", path))
+}
+
+/// Expands `@path/to/file` references in a prompt into fenced, path-labeled code blocks.
+///
+/// The reference itself is replaced by the bare path and the file contents are appended after
+/// the prompt. Tokens that don't name an existing file (e.g. `@someone`) are left untouched.
+pub fn expand(prompt: &str) -> Result<String> {
+    let mut text = String::with_capacity(prompt.len());
+    let mut files: Vec<(String, String)> = Vec::new();
+    let mut total = 0;
+    let mut rest = prompt;
+
+    while let Some(at) = rest.find('@') {
+        text.push_str(&rest[..at]);
+        let at_word_start = text.chars().next_back().is_none_or(char::is_whitespace);
+        let after = &rest[at + 1..];
+        let token_len = after.find(char::is_whitespace).unwrap_or(after.len());
+        let token = &after[..token_len];
+
+        match resolve(token).filter(|_| at_word_start) {
+            Some(path) => {
+                if !files.iter().any(|(p, _)| p == path) {
+                    let contents = read_text(path)?;
+                    total += contents.len() as u64;
+                    if total > MAX_TOTAL_BYTES {
+                        return Err(anyhow!(
+                            "Referenced files exceed the combined limit of {} KB",
+                            MAX_TOTAL_BYTES / 1024
+                        ));
+                    }
+                    files.push((path.to_string(), contents));
+                }
+                text.push_str(path);
+                text.push_str(&token[path.len()..]);
+            }
+            None => {
+                text.push('@');
+                text.push_str(token);
+            }
+        }
+        rest = &after[token_len..];
+    }
+    text.push_str(rest);
+
+    for (path, contents) in &files {
+        text.push_str("\n\n");
+        text.push_str(&fenced_block(path, contents));
+    }
+
+    Ok(text)
+}
+
+/// Expands a `--file` argument, which may be a plain path or a glob like `src/**/*.rs`.
+fn matching_files(pattern: &str) -> Result<Vec<String>> {
+    if !pattern.contains(['*', '?', '[']) {
+        if !Path::new(pattern).is_file() {
+            return Err(anyhow!("File not found: {}", pattern));
+        }
+        return Ok(vec![pattern.to_string()]);
+    }
+
+    let paths = glob::glob(pattern).map_err(|e| anyhow!("Invalid file pattern '{}': {}", pattern, e))?;
+    let mut files: Vec<String> = paths
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .map(|path| path.display().to_string())
+        .collect();
+    files.sort();
+
+    if files.is_empty() {
+        return Err(anyhow!("No files match '{}'", pattern));
+    }
+    Ok(files)
+}
+
+/// Reads the files given with `--file` into path-labeled code blocks to add to a prompt.
+///
+/// Files over `max_bytes` are truncated with a warning; binary files are skipped.
+pub fn attach_files(patterns: &[String], max_bytes: u64) -> Result<String> {
+    let mut paths: Vec<String> = Vec::new();
+    for pattern in patterns {
+        for path in matching_files(pattern)? {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    let mut blocks = Vec::with_capacity(paths.len());
+    for path in &paths {
+        let bytes = fs::read(path)?;
+        if is_binary(&bytes) {
+            eprintln!("Warning: skipping {}, which looks like a binary file", path);
+            continue;
+        }
+
+        let mut contents = String::from_utf8_lossy(&bytes).into_owned();
+        if contents.len() as u64 > max_bytes {
+            let mut end = max_bytes as usize;
+            while !contents.is_char_boundary(end) {
+                end -= 1;
+            }
+            contents.truncate(end);
+            contents.push_str("\n[... truncated]\n");
+            eprintln!(
+                "Warning: {} is {} KB, only the first {} KB is included",
+                path, bytes.len() / 1024, max_bytes / 1024
+            );
+        }
+        blocks.push(fenced_block(path, &contents));
+    }
+
+    Ok(blocks.join("\n\n"))
+}
@@ -0,0 +1,170 @@
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::as_24_bit_terminal_escaped;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const CYAN: &str = "\x1b[36m";
+const CODE_THEME: &str = "base16-ocean.dark";
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults();
+        themes.themes.remove(CODE_THEME).unwrap_or_default()
+    })
+}
+
+/// Renders markdown to ANSI-styled terminal output as it streams in.
+///
+/// Text is buffered until a full line is available, so headings, list markers
+/// and code fences can be recognized before anything is printed.
+pub struct MarkdownRenderer {
+    buffer: String,
+    highlighter: Option<HighlightLines<'static>>,
+    in_code_block: bool,
+}
+
+impl Default for MarkdownRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarkdownRenderer {
+    pub fn new() -> Self {
+        MarkdownRenderer {
+            buffer: String::new(),
+            highlighter: None,
+            in_code_block: false,
+        }
+    }
+
+    /// Feeds a streamed chunk, returning the rendered form of any completed lines.
+    pub fn push(&mut self, chunk: &str) -> String {
+        self.buffer.push_str(chunk);
+        let mut out = String::new();
+
+        while let Some(pos) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=pos).collect();
+            out.push_str(&self.render_line(&line));
+        }
+
+        out
+    }
+
+    /// Renders whatever is left in the buffer once the stream has ended.
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.buffer);
+        let out = if rest.is_empty() { String::new() } else { self.render_line(&rest) };
+        self.in_code_block = false;
+        self.highlighter = None;
+        out
+    }
+
+    fn render_line(&mut self, line: &str) -> String {
+        let trimmed = line.trim_start();
+
+        if trimmed.starts_with("```") {
+            if self.in_code_block {
+                self.in_code_block = false;
+                self.highlighter = None;
+            } else {
+                self.in_code_block = true;
+                let lang = trimmed.trim_start_matches('`').trim();
+                let syntaxes = syntax_set();
+                let syntax = syntaxes.find_syntax_by_token(lang)
+                    .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+                self.highlighter = Some(HighlightLines::new(syntax, theme()));
+            }
+            return format!("{}{}{}", DIM, line, RESET);
+        }
+
+        if self.in_code_block {
+            if let Some(highlighter) = self.highlighter.as_mut() {
+                if let Ok(ranges) = highlighter.highlight_line(line, syntax_set()) {
+                    return format!("{}{}", as_24_bit_terminal_escaped(&ranges, false), RESET);
+                }
+            }
+            return line.to_string();
+        }
+
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        let indent = &content[..content.len() - content.trim_start().len()];
+        let body = content.trim_start();
+
+        let rendered = if let Some(heading) = heading_text(body) {
+            format!("{}{}{}{}", BOLD, UNDERLINE, render_inline(heading), RESET)
+        } else if let Some(item) = body.strip_prefix("- ").or_else(|| body.strip_prefix("* ")) {
+            format!("{}• {}", indent, render_inline(item))
+        } else if let Some(quote) = body.strip_prefix('>') {
+            format!("{}{}│ {}{}", indent, DIM, render_inline(quote.trim_start()), RESET)
+        } else if is_rule(body) {
+            format!("{}{}{}", DIM, "─".repeat(40), RESET)
+        } else {
+            format!("{}{}", indent, render_inline(body))
+        };
+
+        format!("{}{}", rendered, newline)
+    }
+}
+
+fn heading_text(line: &str) -> Option<&str> {
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+        Some(line[hashes..].trim())
+    } else {
+        None
+    }
+}
+
+fn is_rule(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 3 && (line.chars().all(|c| c == '-') || line.chars().all(|c| c == '*'))
+}
+
+fn render_inline(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    'outer: while let Some(c) = rest.chars().next() {
+        if c == '`' {
+            if let Some(end) = rest[1..].find('`') {
+                out.push_str(&format!("{}{}{}", CYAN, &rest[1..end + 1], RESET));
+                rest = &rest[end + 2..];
+                continue;
+            }
+        }
+
+        for (marker, style) in [("**", BOLD), ("__", BOLD), ("*", ITALIC)] {
+            if let Some(after) = rest.strip_prefix(marker) {
+                if after.starts_with(char::is_whitespace) {
+                    continue;
+                }
+                if let Some(end) = after.find(marker).filter(|end| *end > 0) {
+                    out.push_str(&format!("{}{}{}", style, render_inline(&after[..end]), RESET));
+                    rest = &after[end + marker.len()..];
+                    continue 'outer;
+                }
+            }
+        }
+
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    out
+}
@@ -0,0 +1,78 @@
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+
+pub const HELP: &str = "\
+Commands:
+  /model [name]       Show or switch the model for the next messages
+  /new                Start a new conversation
+  /save <file>        Save the conversation so far as Markdown
+  /system [text]      Set the system prompt, or clear it when no text is given
+  /clear              Clear the screen
+  /retry [model]      Send the last prompt again, optionally to another model
+  /edit               Write the next prompt in $EDITOR, starting from the last one
+  /copy [code]        Copy the last response (or just its code block) to the clipboard
+  /code [lang]        Print only the code blocks of the last response, optionally in one language
+  /checkpoint [name]  Mark the conversation so far as a checkpoint, or list the checkpoints
+  /branch <name>      Continue from a checkpoint in a new conversation, keeping this one as it is
+  /compact            Summarize earlier exchanges to free up context, continuing in a new conversation
+  /help               Show this help
+  exit                Leave interactive mode";
+
+/// A slash command typed at the interactive prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SlashCommand {
+    Model(Option<String>),
+    New,
+    Save(PathBuf),
+    System(Option<String>),
+    Clear,
+    /// Resend the last prompt, optionally to another model for this one response
+    Retry(Option<String>),
+    Edit,
+    /// Copy the last response, or only its code block when `code` is set
+    Copy { code: bool },
+    /// Print the code blocks of the last response, only those in the given language if any
+    Code(Option<String>),
+    /// Name the current state of the conversation, or list the named states when no name is given
+    Checkpoint(Option<String>),
+    /// Start a new conversation from a checkpoint
+    Branch(String),
+    /// Summarize the earlier exchanges and continue from the summary
+    Compact,
+    Help,
+}
+
+/// Parses a line of interactive input.
+///
+/// Returns `None` for ordinary prompts, which are sent to the model as typed.
+pub fn parse(input: &str) -> Option<Result<SlashCommand>> {
+    let input = input.trim();
+    let rest = input.strip_prefix('/')?;
+    let (name, argument) = match rest.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, Some(argument.trim()).filter(|a| !a.is_empty())),
+        None => (rest, None),
+    };
+
+    let command = match (name, argument) {
+        ("model", argument) => Ok(SlashCommand::Model(argument.map(|a| a.to_string()))),
+        ("new", None) => Ok(SlashCommand::New),
+        ("save", Some(path)) => Ok(SlashCommand::Save(PathBuf::from(path))),
+        ("save", None) => Err(anyhow!("Usage: /save <file>")),
+        ("system", argument) => Ok(SlashCommand::System(argument.map(|a| a.to_string()))),
+        ("clear", None) => Ok(SlashCommand::Clear),
+        ("retry", argument) => Ok(SlashCommand::Retry(argument.map(|a| a.to_string()))),
+        ("edit", None) => Ok(SlashCommand::Edit),
+        ("copy", None) => Ok(SlashCommand::Copy { code: false }),
+        ("copy", Some("code")) => Ok(SlashCommand::Copy { code: true }),
+        ("copy", Some(_)) => Err(anyhow!("Usage: /copy [code]")),
+        ("code", argument) => Ok(SlashCommand::Code(argument.map(|a| a.to_string()))),
+        ("checkpoint", argument) => Ok(SlashCommand::Checkpoint(argument.map(|a| a.to_string()))),
+        ("branch", Some(name)) => Ok(SlashCommand::Branch(name.to_string())),
+        ("branch", None) => Err(anyhow!("Usage: /branch <checkpoint>")),
+        ("compact", None) => Ok(SlashCommand::Compact),
+        ("help", None) => Ok(SlashCommand::Help),
+        ("new" | "clear" | "edit" | "compact" | "help", Some(_)) => Err(anyhow!("/{} does not take an argument", name)),
+        _ => Err(anyhow!("Unknown command '/{}'. Type /help for a list of commands.", name)),
+    };
+    Some(command)
+}
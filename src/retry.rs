@@ -0,0 +1,159 @@
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+use crate::rate_limit;
+
+pub const DEFAULT_RETRIES: u32 = 3;
+pub const DEFAULT_RETRY_DELAY_MS: u64 = 500;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Total time a request may spend paused by 429s before the rate limit is reported as an error
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(600);
+
+struct RetryPolicy {
+    retries: u32,
+    base_delay: Duration,
+}
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// Sets the retry policy used by every API call for the rest of the process.
+pub fn configure(retries: u32, base_delay_ms: u64) {
+    let _ = POLICY.set(RetryPolicy {
+        retries,
+        base_delay: Duration::from_millis(base_delay_ms),
+    });
+}
+
+fn policy() -> &'static RetryPolicy {
+    POLICY.get_or_init(|| RetryPolicy {
+        retries: DEFAULT_RETRIES,
+        base_delay: Duration::from_millis(DEFAULT_RETRY_DELAY_MS),
+    })
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || matches!(status.as_u16(), 500 | 502 | 503 | 504)
+}
+
+/// Reads the delay a server asked for in its `Retry-After` header, if any.
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    response.headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Number of retries allowed by the configured policy.
+pub fn max_retries() -> u32 {
+    policy().retries
+}
+
+/// Backoff delay before retry number `attempt` (counting from 0) under the configured policy.
+pub fn delay(attempt: u32) -> Duration {
+    backoff(attempt, policy().base_delay)
+}
+
+fn backoff(attempt: u32, base: Duration) -> Duration {
+    let exponential = base.saturating_mul(2u32.saturating_pow(attempt));
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let jitter = base.mul_f64(f64::from(nanos % 1000) / 1000.0);
+    (exponential + jitter).min(MAX_RETRY_DELAY)
+}
+
+/// Headers whose values are never logged.
+const SECRET_HEADERS: &[&str] = &["api-key", "authorization", "proxy-authorization", "cookie", "x-api-key"];
+
+/// Sends one attempt, logging the request and the response status at `--verbose` levels.
+async fn send_logged(request: RequestBuilder) -> reqwest::Result<Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    info!("{} {}", request.method(), request.url());
+    for (name, value) in request.headers() {
+        let value = if SECRET_HEADERS.contains(&name.as_str()) {
+            "[redacted]"
+        } else {
+            value.to_str().unwrap_or("[binary]")
+        };
+        debug!("  {}: {}", name, value);
+    }
+    match request.body().map(|body| body.as_bytes()) {
+        Some(Some(bytes)) => debug!("  request body: {} bytes", bytes.len()),
+        Some(None) => debug!("  request body: streamed"),
+        None => {}
+    }
+
+    let started = Instant::now();
+    let result = client.execute(request).await;
+    match &result {
+        Ok(response) => {
+            info!("  {} after {}ms", response.status(), started.elapsed().as_millis());
+            for name in [header::CONTENT_TYPE, header::CONTENT_LENGTH, header::RETRY_AFTER] {
+                if let Some(value) = response.headers().get(&name).and_then(|value| value.to_str().ok()) {
+                    debug!("  {}: {}", name, value);
+                }
+            }
+        }
+        Err(e) => info!("  failed after {}ms: {}", started.elapsed().as_millis(), e),
+    }
+    result
+}
+
+/// Sends a request, retrying network failures and 5xx responses with exponential backoff.
+///
+/// A 429 pauses every request of the process (see [`rate_limit::pause`]) for as long as the
+/// server asks, without using up a retry, until [`MAX_RATE_LIMIT_WAIT`] has passed. Requests
+/// whose body cannot be cloned (e.g. multipart uploads) are sent once. When retries are
+/// exhausted the last response is returned so callers can report the error as usual.
+pub async fn send(request: RequestBuilder) -> reqwest::Result<Response> {
+    let policy = policy();
+    let mut attempt = 0;
+    let mut rate_limited_for = Duration::ZERO;
+    let mut pauses = 0;
+
+    loop {
+        rate_limit::acquire().await;
+        let retryable = if attempt < policy.retries { request.try_clone() } else { None };
+        let Some(next) = retryable else {
+            return send_logged(request).await;
+        };
+
+        let delay = match send_logged(next).await {
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS && rate_limited_for < MAX_RATE_LIMIT_WAIT => {
+                let delay = retry_after(&response).unwrap_or_else(|| backoff(pauses, policy.base_delay).max(Duration::from_secs(1)));
+                eprintln!("Rate limited, resuming in {}s...", delay.as_secs_f64().ceil());
+                rate_limit::pause(delay);
+                rate_limited_for += delay;
+                pauses += 1;
+                // The pause is waited out by the next acquire, together with every other request.
+                continue;
+            }
+            Ok(response) if is_transient(response.status()) => {
+                let delay = retry_after(&response).unwrap_or_else(|| backoff(attempt, policy.base_delay));
+                eprintln!(
+                    "Request failed with {}, retrying in {:.1}s (attempt {}/{})...",
+                    response.status(), delay.as_secs_f64(), attempt + 1, policy.retries
+                );
+                delay
+            }
+            Ok(response) => return Ok(response),
+            Err(e) if e.is_connect() || e.is_timeout() || e.is_request() => {
+                let delay = backoff(attempt, policy.base_delay);
+                eprintln!(
+                    "Request failed ({}), retrying in {:.1}s (attempt {}/{})...",
+                    e, delay.as_secs_f64(), attempt + 1, policy.retries
+                );
+                delay
+            }
+            Err(e) => return Err(e),
+        };
+
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{chunk, cost};
+
+/// How serious a review finding is, most serious first.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Critical,
+    Major,
+    Minor,
+    Nit,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Critical => "critical",
+            Severity::Major => "major",
+            Severity::Minor => "minor",
+            Severity::Nit => "nit",
+        }
+    }
+}
+
+/// A problem the model found in the diff.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Issue {
+    pub file: String,
+    /// Line in the new version of the file, when the issue is about a particular one
+    #[serde(default)]
+    pub line: Option<u32>,
+    pub severity: Severity,
+    pub title: String,
+    #[serde(default)]
+    pub explanation: String,
+    /// A unified diff fixing the issue, when the model suggests one
+    #[serde(default)]
+    pub patch: Option<String>,
+}
+
+/// The diff of one file.
+pub struct FileDiff {
+    pub path: String,
+    pub diff: String,
+}
+
+/// Splits a `git diff` into the diffs of each file.
+pub fn split_files(diff: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    for line in diff.split_inclusive('\n') {
+        if let Some(header) = line.strip_prefix("diff --git ") {
+            // `a/old b/new`: the new path is what the review is about.
+            let path = header.trim_end()
+                .rsplit_once(" b/")
+                .map_or(header.trim_end(), |(_, path)| path)
+                .to_string();
+            files.push(FileDiff { path, diff: String::new() });
+        }
+        if let Some(file) = files.last_mut() {
+            file.diff.push_str(line);
+        }
+    }
+    files
+}
+
+/// Groups file diffs into parts of at most `max_tokens` (estimated), each reviewed in one
+/// request. A file too big on its own is split, its parts each labeled with its path.
+pub fn parts(files: &[FileDiff], max_tokens: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    for file in files {
+        let pieces = if cost::estimate_tokens(&file.diff) > max_tokens {
+            chunk::split(&file.diff, max_tokens)
+                .into_iter()
+                .enumerate()
+                .map(|(index, piece)| match index {
+                    0 => piece,
+                    _ => format!("(continued diff of {})\n{}", file.path, piece),
+                })
+                .collect()
+        } else {
+            vec![file.diff.clone()]
+        };
+        for piece in pieces {
+            if !current.is_empty() && cost::estimate_tokens(&current) + cost::estimate_tokens(&piece) > max_tokens {
+                parts.push(std::mem::take(&mut current));
+            }
+            current.push_str(&piece);
+            if !current.ends_with('\n') {
+                current.push('\n');
+            }
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Builds the prompt asking the model to review part of a diff as JSON, followed by files of the
+/// project (`--repo`) that help understand it.
+pub fn review_prompt(part: &str, context: &str) -> String {
+    format!(
+        "Review the code changes in the git diff below like a careful senior engineer. Look for bugs, \
+         security problems, performance issues, missing error handling and unclear code; don't comment \
+         on what is fine.\n\
+         Respond with only a JSON array, no prose and no code fences. Each element must be an object with:\n\
+         - \"file\": the path of the file, as in the diff\n\
+         - \"line\": the line number in the new version of the file, or null\n\
+         - \"severity\": one of \"critical\", \"major\", \"minor\" or \"nit\"\n\
+         - \"title\": a one-line summary of the issue\n\
+         - \"explanation\": why it is a problem and how to fix it\n\
+         - \"patch\": a unified diff that fixes it, if a small one does, otherwise null\n\
+         If there is nothing to point out, respond with [].\n\n\
+         ```diff\n{}```{}",
+        part,
+        context
+    )
+}
+
+/// Parses the model's answer, tolerating code fences or text around the JSON array.
+pub fn parse_issues(response: &str) -> Result<Vec<Issue>> {
+    let start = response.find('[');
+    let end = response.rfind(']');
+    let json = match (start, end) {
+        (Some(start), Some(end)) if start < end => &response[start..=end],
+        _ => return Err(anyhow!("The model did not return a list of review findings:\n{}", response.trim())),
+    };
+    serde_json::from_str(json).map_err(|e| anyhow!("Could not parse the review findings: {}", e))
+}
+
+/// Renders the findings grouped by file, most serious first within each file.
+pub fn render_text(issues: &[Issue]) -> String {
+    let mut by_file: BTreeMap<&str, Vec<&Issue>> = BTreeMap::new();
+    for issue in issues {
+        by_file.entry(issue.file.as_str()).or_default().push(issue);
+    }
+    let mut text = String::new();
+    for (file, mut issues) in by_file {
+        issues.sort_by_key(|issue| (issue.severity, issue.line));
+        text.push_str(&format!("{}\n", file));
+        for issue in issues {
+            let location = issue.line.map(|line| format!("line {}: ", line)).unwrap_or_default();
+            text.push_str(&format!("  [{}] {}{}\n", issue.severity.name(), location, issue.title));
+            for line in issue.explanation.lines().filter(|line| !line.trim().is_empty()) {
+                text.push_str(&format!("      {}\n", line));
+            }
+            if let Some(patch) = issue.patch.as_deref().filter(|patch| !patch.trim().is_empty()) {
+                text.push_str("      ```diff\n");
+                for line in patch.trim_end().lines() {
+                    text.push_str(&format!("      {}\n", line));
+                }
+                text.push_str("      ```\n");
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
@@ -0,0 +1,168 @@
+//! Sampling parameters of chat requests (`--temperature`, `--top-p`, `--frequency-penalty`) and
+//! the ranges the known model families accept them in.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// How a response is sampled. Parameters left out are the model's own defaults.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct Sampling {
+    /// Randomness of the answer; 0 makes it as deterministic as the model allows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Only the most likely tokens making up this share of the probability are considered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Discourages tokens by how often they already appeared, against repetition
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+}
+
+impl Sampling {
+    pub fn is_empty(&self) -> bool {
+        *self == Sampling::default()
+    }
+
+    /// These parameters, with the ones not set taken from `defaults`.
+    pub fn or(self, defaults: Sampling) -> Sampling {
+        Sampling {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            frequency_penalty: self.frequency_penalty.or(defaults.frequency_penalty),
+        }
+    }
+
+    /// The parameters given, as `flag value` pairs.
+    fn flags(&self) -> impl Iterator<Item = (&'static str, f32)> {
+        [("--temperature", self.temperature), ("--top-p", self.top_p), ("--frequency-penalty", self.frequency_penalty)]
+            .into_iter()
+            .filter_map(|(flag, value)| Some((flag, value?)))
+    }
+}
+
+/// The sampling parameters a family of chat models accepts, as the range of each; None means
+/// the parameter isn't supported.
+pub struct ChatModel {
+    pub family: &'static str,
+    /// Model ids starting with one of these belong to the family
+    prefixes: &'static [&'static str],
+    temperature: Option<(f32, f32)>,
+    top_p: Option<(f32, f32)>,
+    frequency_penalty: Option<(f32, f32)>,
+}
+
+/// Listed most specific first: the first family with a matching prefix is the model's.
+pub const CHAT_MODELS: &[ChatModel] = &[
+    ChatModel {
+        family: "OpenAI reasoning",
+        prefixes: &["o1", "o3", "o4", "gpt-5"],
+        temperature: None,
+        top_p: None,
+        frequency_penalty: None,
+    },
+    ChatModel {
+        family: "GPT",
+        prefixes: &["gpt-", "chatgpt"],
+        temperature: Some((0.0, 2.0)),
+        top_p: Some((0.0, 1.0)),
+        frequency_penalty: Some((-2.0, 2.0)),
+    },
+    ChatModel {
+        family: "Claude",
+        prefixes: &["claude"],
+        temperature: Some((0.0, 1.0)),
+        top_p: Some((0.0, 1.0)),
+        frequency_penalty: None,
+    },
+    ChatModel {
+        family: "Gemini",
+        prefixes: &["gemini"],
+        temperature: Some((0.0, 2.0)),
+        top_p: Some((0.0, 1.0)),
+        frequency_penalty: Some((-2.0, 2.0)),
+    },
+    ChatModel {
+        family: "Mistral",
+        prefixes: &["mistral", "open-mistral", "codestral", "pixtral"],
+        temperature: Some((0.0, 1.5)),
+        top_p: Some((0.0, 1.0)),
+        frequency_penalty: Some((-2.0, 2.0)),
+    },
+    ChatModel {
+        family: "DeepSeek",
+        prefixes: &["deepseek"],
+        temperature: Some((0.0, 2.0)),
+        top_p: Some((0.0, 1.0)),
+        frequency_penalty: Some((-2.0, 2.0)),
+    },
+];
+
+/// Any model accepts no more than this; models not in the table are checked against it.
+const ANY_MODEL: ChatModel = ChatModel {
+    family: "",
+    prefixes: &[],
+    temperature: Some((0.0, 2.0)),
+    top_p: Some((0.0, 1.0)),
+    frequency_penalty: Some((-2.0, 2.0)),
+};
+
+/// The known family of a chat model, if any.
+pub fn lookup(model: &str) -> Option<&'static ChatModel> {
+    let model = model.to_lowercase();
+    // Provider prefixes, as in "openai/gpt-4o", don't change what the model accepts.
+    let name = model.rsplit('/').next().unwrap_or(&model);
+    CHAT_MODELS.iter().find(|family| family.prefixes.iter().any(|prefix| name.starts_with(prefix)))
+}
+
+impl ChatModel {
+    fn range(&self, flag: &str) -> Option<(f32, f32)> {
+        match flag {
+            "--temperature" => self.temperature,
+            "--top-p" => self.top_p,
+            _ => self.frequency_penalty,
+        }
+    }
+
+    /// The flags this family accepts, with their ranges.
+    pub fn supported_flags(&self) -> Vec<String> {
+        ["--temperature", "--top-p", "--frequency-penalty"].into_iter()
+            .filter_map(|flag| self.range(flag).map(|(min, max)| format!("{} ({} to {})", flag, min, max)))
+            .collect()
+    }
+
+    fn accepts(&self, flag: &str, value: f32) -> bool {
+        self.range(flag).is_some_and(|(min, max)| (min..=max).contains(&value))
+    }
+}
+
+/// The parameters to send to `model`: those given, which must be ones it accepts, and the
+/// `defaults` from the config file it accepts. Defaults it doesn't are left out rather than
+/// refused, so a default temperature doesn't stand in the way of reasoning models.
+pub fn resolve(model: &str, given: Sampling, defaults: Sampling) -> Result<Sampling> {
+    let known = lookup(model).unwrap_or(&ANY_MODEL);
+    for (flag, value) in given.flags() {
+        match known.range(flag) {
+            None => {
+                let supported = known.supported_flags();
+                return Err(anyhow!(
+                    "{} ({}) doesn't support {}{}",
+                    model,
+                    known.family,
+                    flag,
+                    if supported.is_empty() { String::new() } else { format!(". It supports: {}", supported.join(", ")) }
+                ));
+            }
+            Some((min, max)) if !(min..=max).contains(&value) => {
+                return Err(anyhow!("{} takes {} from {} to {}, not {}", model, flag, min, max, value));
+            }
+            Some(_) => {}
+        }
+    }
+
+    let keep = |flag: &str, value: Option<f32>| value.filter(|value| known.accepts(flag, *value));
+    Ok(given.or(Sampling {
+        temperature: keep("--temperature", defaults.temperature),
+        top_p: keep("--top-p", defaults.top_p),
+        frequency_penalty: keep("--frequency-penalty", defaults.frequency_penalty),
+    }))
+}
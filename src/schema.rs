@@ -0,0 +1,64 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::path::Path;
+
+use crate::code;
+
+/// Validation errors listed back to the model when asking again; more rarely help it.
+const MAX_LISTED_ERRORS: usize = 10;
+
+/// A JSON Schema that responses must match, loaded from `--schema`.
+pub struct Schema {
+    source: Value,
+    validator: jsonschema::Validator,
+}
+
+impl Schema {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the schema {}", path.display()))?;
+        let source: Value = serde_json::from_str(&contents)
+            .with_context(|| format!("{} is not valid JSON", path.display()))?;
+        let validator = jsonschema::validator_for(&source)
+            .map_err(|e| anyhow!("{} is not a valid JSON Schema: {}", path.display(), e))?;
+        Ok(Schema { source, validator })
+    }
+
+    /// What is added to the prompt so the model answers with matching JSON.
+    pub fn instructions(&self) -> String {
+        format!(
+            "Answer only with JSON that matches this JSON Schema, without any other text:\n\n{}",
+            serde_json::to_string_pretty(&self.source).unwrap_or_default()
+        )
+    }
+
+    /// The JSON in a response if it matches the schema, otherwise what is wrong with it. A
+    /// response wrapped in a code fence is accepted, since models often add one anyway.
+    pub fn check(&self, response: &str) -> Result<Value, Vec<String>> {
+        let json = code::strip_fences(response);
+        let value: Value = serde_json::from_str(json.trim()).map_err(|e| vec![format!("not valid JSON: {}", e)])?;
+        let errors: Vec<String> = self.validator.iter_errors(&value)
+            .take(MAX_LISTED_ERRORS)
+            .map(|error| match error.instance_path().to_string() {
+                path if path.is_empty() => error.to_string(),
+                path => format!("at {}: {}", path, error),
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(value)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// A prompt asking for the answer again, showing the model its previous response and why it
+    /// was rejected. It stands on its own, so it works without a conversation.
+    pub fn retry_prompt(&self, prompt: &str, response: &str, errors: &[String]) -> String {
+        format!(
+            "{}\n\nYour previous answer was:\n\n{}\n\nIt does not match the schema:\n- {}\n\nAnswer again with only the corrected JSON.",
+            prompt,
+            response.trim(),
+            errors.join("\n- ")
+        )
+    }
+}
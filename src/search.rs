@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, TransactionBehavior};
+use std::collections::HashMap;
+use dialoguer::Input;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::history::{self, ConversationRecord};
+use crate::ui::ensure_interactive;
+
+const INDEX_FILE: &str = "search.db";
+/// Marks the matched words in a snippet, for highlighting
+pub const MATCH_START: &str = "\u{1}";
+pub const MATCH_END: &str = "\u{2}";
+/// Roughly how many words of each side of an exchange a hit shows
+const SNIPPET_WORDS: u32 = 16;
+/// How long to wait for another ai-cli process that is updating the index
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A stored exchange that matches the query.
+pub struct Hit {
+    pub conversation_id: String,
+    pub title: String,
+    /// Index of the turn in the conversation
+    pub turn: usize,
+    pub timestamp: DateTime<Local>,
+    /// Excerpts around the matched words, with them between `MATCH_START` and `MATCH_END`
+    pub prompt: String,
+    pub response: String,
+}
+
+fn index_path() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| anyhow!("Could not determine the local data directory"))?
+        .join("ai-cli");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(INDEX_FILE))
+}
+
+fn open() -> Result<Connection> {
+    let connection = Connection::open(index_path()?)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    connection.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS exchanges USING fts5(
+             prompt, response,
+             conversation_id UNINDEXED, turn UNINDEXED, timestamp UNINDEXED,
+             tokenize = 'porter unicode61'
+         );
+         CREATE TABLE IF NOT EXISTS indexed (
+             conversation_id TEXT PRIMARY KEY,
+             updated_at TEXT NOT NULL
+         );",
+    )?;
+    Ok(connection)
+}
+
+/// Brings the index up to date with local history: conversations that changed since they were
+/// indexed are indexed again and deleted ones are dropped. History stays the source of truth,
+/// so the index can be deleted at any time and is rebuilt on the next search.
+fn sync(connection: &mut Connection, records: &[ConversationRecord]) -> Result<()> {
+    // Taking the write lock up front keeps two searches from indexing the same conversation twice.
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let indexed: HashMap<String, String> = transaction
+        .prepare("SELECT conversation_id, updated_at FROM indexed")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    for record in records {
+        let updated_at = record.updated_at.to_rfc3339();
+        if indexed.get(&record.id) == Some(&updated_at) {
+            continue;
+        }
+        transaction.execute("DELETE FROM exchanges WHERE conversation_id = ?1", params![record.id])?;
+        for (index, turn) in record.turns.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO exchanges (prompt, response, conversation_id, turn, timestamp) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![turn.prompt, turn.response, record.id, index as i64, turn.timestamp.to_rfc3339()],
+            )?;
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO indexed (conversation_id, updated_at) VALUES (?1, ?2)",
+            params![record.id, updated_at],
+        )?;
+    }
+    for id in indexed.keys().filter(|id| !records.iter().any(|record| &record.id == *id)) {
+        transaction.execute("DELETE FROM exchanges WHERE conversation_id = ?1", params![id])?;
+        transaction.execute("DELETE FROM indexed WHERE conversation_id = ?1", params![id])?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Turns what was typed into an FTS5 query that matches exchanges containing every word.
+/// Each word is quoted, so punctuation and FTS5 operators in it are searched for literally.
+fn fts_query(query: &str) -> String {
+    query.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Full-text searches the prompts and responses in local history, best matches first.
+pub fn search(query: &str, limit: usize) -> Result<Vec<Hit>> {
+    let query = fts_query(query);
+    if query.is_empty() {
+        return Err(anyhow!("The search query is empty"));
+    }
+
+    let records = history::list()?;
+    let mut connection = open()?;
+    sync(&mut connection, &records)?;
+
+    let sql = format!(
+        "SELECT conversation_id, turn, timestamp,
+                snippet(exchanges, 0, '{start}', '{end}', '…', {words}),
+                snippet(exchanges, 1, '{start}', '{end}', '…', {words})
+         FROM exchanges WHERE exchanges MATCH ?1 ORDER BY rank LIMIT ?2",
+        start = MATCH_START,
+        end = MATCH_END,
+        words = SNIPPET_WORDS
+    );
+    let titles: HashMap<&str, &str> = records.iter().map(|record| (record.id.as_str(), record.title.as_str())).collect();
+    let mut statement = connection.prepare(&sql)?;
+    let rows = statement.query_map(params![query, limit as i64], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?,
+        ))
+    })?;
+
+    let mut hits = Vec::new();
+    for row in rows {
+        let (conversation_id, turn, timestamp, prompt, response) = row?;
+        hits.push(Hit {
+            title: titles.get(conversation_id.as_str()).copied().unwrap_or_default().to_string(),
+            conversation_id,
+            turn: turn as usize,
+            timestamp: DateTime::parse_from_rfc3339(&timestamp)?.with_timezone(&Local),
+            prompt,
+            response,
+        });
+    }
+    Ok(hits)
+}
+
+/// Lists search hits numbered for `search --resume`, with the matched words in bold on a terminal.
+pub fn print_hits(hits: &[Hit]) {
+    let bold = io::stdout().is_terminal();
+    let highlight = |excerpt: &str| {
+        let excerpt = excerpt.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut out = String::new();
+        for (i, part) in excerpt.split(MATCH_START).enumerate() {
+            match part.split_once(MATCH_END) {
+                Some((matched, rest)) if i > 0 => {
+                    if bold {
+                        out.push_str(&console::style(matched).bold().to_string());
+                    } else {
+                        out.push_str(matched);
+                    }
+                    out.push_str(rest);
+                }
+                _ => out.push_str(&part.replace(MATCH_END, "")),
+            }
+        }
+        out
+    };
+
+    for (number, hit) in hits.iter().enumerate() {
+        println!(
+            "[{}] {}  {}  {}",
+            number + 1,
+            hit.timestamp.format("%Y-%m-%d %H:%M"),
+            hit.conversation_id,
+            hit.title
+        );
+        println!("    You: {}", highlight(&hit.prompt));
+        println!("    AI:  {}\n", highlight(&hit.response));
+    }
+}
+
+/// The conversation of the hit to resume: hit `number` when given, otherwise the only hit or
+/// the one the user picks.
+pub fn pick_hit(hits: &[Hit], number: Option<usize>) -> Result<ConversationRecord> {
+    let number = match (number, hits.len()) {
+        (Some(number), _) => number,
+        (None, 1) => 1,
+        (None, count) => {
+            ensure_interactive("Choosing a conversation to resume", "pass the hit's number, e.g. --resume=2")?;
+            Input::new()
+                .with_prompt(format!("Resume which hit (1-{})", count))
+                .validate_with(|n: &usize| if (1..=count).contains(n) { Ok(()) } else { Err("no such hit") })
+                .interact_text()?
+        }
+    };
+    let hit = number.checked_sub(1).and_then(|index| hits.get(index))
+        .ok_or_else(|| anyhow!("There is no hit {}; the search found {}", number, hits.len()))?;
+    history::load(&hit.conversation_id)
+}
@@ -0,0 +1,502 @@
+//! `ai-cli serve`: an OpenAI-compatible `/v1/chat/completions` endpoint backed by the configured
+//! provider, so tools that speak the OpenAI API can go through a 1min.ai subscription.
+//! Clients authenticate with a bearer key, so neither other machines nor web pages the user
+//! visits (which can post to localhost) can spend the subscription's credits.
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use hyper::body::{Bytes, HttpBody};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::error::AiCliError;
+use crate::providers::redacting::Redacting;
+use crate::providers::{ChatRequest, Message, Provider, RequestDefaults};
+use crate::redact::Redaction;
+use crate::sampling::{self, Sampling};
+use crate::{cost, models, usage};
+
+type Response = hyper::Response<Body>;
+
+/// Request bodies beyond this are refused rather than read into memory.
+pub const MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+
+/// A message of a chat completion request. Content may be a string or a list of parts, of
+/// which only the text ones are used.
+#[derive(Deserialize)]
+struct RequestMessage {
+    role: String,
+    #[serde(default)]
+    content: Value,
+}
+
+impl RequestMessage {
+    fn text(&self) -> String {
+        match &self.content {
+            Value::String(text) => text.clone(),
+            Value::Array(parts) => parts.iter()
+                .filter_map(|part| part.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CompletionRequest {
+    model: Option<String>,
+    messages: Vec<RequestMessage>,
+    #[serde(default)]
+    stream: bool,
+    max_tokens: Option<u32>,
+    #[serde(flatten)]
+    sampling: Sampling,
+}
+
+/// Answers chat completion requests with a provider.
+pub struct Server {
+    provider: Arc<dyn Provider>,
+    /// Model used when a request names none
+    model: String,
+    defaults: RequestDefaults,
+    model_rates: BTreeMap<String, f64>,
+    redaction: Redaction,
+    /// Key clients send as `Authorization: Bearer <key>`
+    api_key: String,
+    next_id: AtomicU64,
+}
+
+/// A random key for clients to authenticate with, for when the config file sets none.
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; 24];
+    OsRng.fill_bytes(&mut bytes);
+    format!("sk-ai-cli-{}", bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+}
+
+/// An error response in the OpenAI format.
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let kind = if status.is_client_error() { "invalid_request_error" } else { "api_error" };
+    let body = json!({ "error": { "message": message, "type": kind, "code": Value::Null } });
+    hyper::Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("valid response")
+}
+
+fn json_response(body: Value) -> Response {
+    hyper::Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("valid response")
+}
+
+/// The status to answer with when the provider fails: its own problems are the gateway's, a
+/// prompt blocked for containing secrets is the client's.
+fn provider_error(e: &AiCliError) -> Response {
+    let status = match e {
+        AiCliError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+        AiCliError::TimedOut(_) => StatusCode::GATEWAY_TIMEOUT,
+        AiCliError::SecretsFound(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    error_response(status, &e.to_string())
+}
+
+impl Server {
+    pub fn new(
+        provider: Arc<dyn Provider>,
+        model: String,
+        defaults: RequestDefaults,
+        model_rates: BTreeMap<String, f64>,
+        redaction: Redaction,
+        api_key: String,
+    ) -> Self {
+        Server { provider, model, defaults, model_rates, redaction, api_key, next_id: AtomicU64::new(1) }
+    }
+
+    /// Whether the request carries the key, compared in constant time.
+    fn authorized(&self, request: &hyper::Request<Body>) -> bool {
+        let given = request.headers().get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        given.len() == self.api_key.len()
+            && given.bytes().zip(self.api_key.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+    }
+
+    /// Turns the messages into a prompt and earlier history. Providers that keep conversations
+    /// themselves take a single prompt, so for them the earlier messages are written into it.
+    fn prompt(&self, messages: &[RequestMessage]) -> Result<(String, Vec<Message>)> {
+        let (last, earlier) = messages.split_last().ok_or_else(|| anyhow!("'messages' must not be empty"))?;
+        if last.role != "user" {
+            return Err(anyhow!("The last message must be from the user"));
+        }
+
+        if !self.provider.hosts_conversations() {
+            let history = earlier.iter().map(|message| Message::new(&message.role, message.text())).collect();
+            return Ok((last.text(), history));
+        }
+
+        let system: Vec<String> = earlier.iter().filter(|m| m.role == "system").map(RequestMessage::text).collect();
+        let transcript: Vec<String> = earlier.iter()
+            .filter(|m| m.role != "system")
+            .map(|m| format!("{}: {}", if m.role == "assistant" { "Assistant" } else { "User" }, m.text()))
+            .collect();
+        let mut prompt = system.join("\n\n");
+        if !transcript.is_empty() {
+            prompt = format!("{}\n\nThe conversation so far:\n\n{}\n\n---", prompt, transcript.join("\n\n"));
+        }
+        if !prompt.is_empty() {
+            prompt.push_str("\n\n");
+        }
+        prompt.push_str(&last.text());
+        Ok((prompt.trim_start().to_string(), Vec::new()))
+    }
+
+    async fn handle(self: Arc<Self>, request: hyper::Request<Body>) -> Response {
+        if !self.authorized(&request) {
+            let mut response = error_response(StatusCode::UNAUTHORIZED, "Missing or wrong API key: send the key ai-cli serve printed, or serve_api_key from its config file, as a bearer token");
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+            return response;
+        }
+        match (request.method(), request.uri().path()) {
+            (&Method::POST, "/v1/chat/completions") => {
+                // Anything but JSON is refused, which also keeps browsers from sending requests
+                // without a CORS preflight, which this server never grants.
+                let json = request.headers().get(header::CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.split(';').next())
+                    .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+                if !json {
+                    return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Requests must be sent as application/json");
+                }
+                let body = match read_body(request.into_body(), MAX_BODY_BYTES).await {
+                    Ok(body) => body,
+                    Err(response) => return response,
+                };
+                match serde_json::from_slice::<CompletionRequest>(&body) {
+                    Ok(completion) => self.complete(completion).await,
+                    Err(e) => error_response(StatusCode::BAD_REQUEST, &format!("Invalid request: {}", e)),
+                }
+            }
+            (&Method::GET, "/v1/models") => self.list_models().await,
+            (_, "/v1/chat/completions" | "/v1/models") => error_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+            (_, path) => error_response(StatusCode::NOT_FOUND, &format!("Unknown endpoint {}", path)),
+        }
+    }
+
+    async fn list_models(&self) -> Response {
+        match models::fetch_models(self.provider.as_ref()).await {
+            Ok(models) => json_response(json!({
+                "object": "list",
+                "data": models.iter().map(|model| json!({
+                    "id": model.id,
+                    "object": "model",
+                    "owned_by": self.provider.name(),
+                })).collect::<Vec<_>>(),
+            })),
+            Err(e) => error_response(StatusCode::BAD_GATEWAY, &format!("{:#}", e)),
+        }
+    }
+
+    async fn complete(self: Arc<Self>, completion: CompletionRequest) -> Response {
+        let model = completion.model.filter(|model| !model.is_empty()).unwrap_or_else(|| self.model.clone());
+        if let Err(e) = models::validate(&model) {
+            return error_response(StatusCode::BAD_REQUEST, &format!("{:#}", e));
+        }
+        let (prompt, history) = match self.prompt(&completion.messages) {
+            Ok(prompt) => prompt,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        };
+        let max_words = completion.max_tokens.map_or(self.defaults.max_words, cost::words_for_tokens);
+        let max_tokens = completion.max_tokens.or(self.defaults.max_tokens);
+
+        let sampling = match sampling::resolve(&model, completion.sampling, self.defaults.sampling) {
+            Ok(sampling) => sampling,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, &format!("{:#}", e)),
+        };
+
+        let request = ChatRequest { model: &model, prompt: &prompt, max_words, max_tokens, sampling, conversation_id: None, history: &history };
+        let mut stream = match Redacting::new(self.provider.as_ref(), self.redaction).chat_stream(request).await {
+            Ok(stream) => stream,
+            Err(e) => return provider_error(&e),
+        };
+        let id = format!("chatcmpl-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let created = Utc::now().timestamp();
+
+        if !completion.stream {
+            let mut response = String::new();
+            while let Some(chunk) = stream.next().await {
+                match chunk {
+                    Ok(text) => response.push_str(&text),
+                    Err(e) => return provider_error(&e),
+                }
+            }
+            self.record_usage(&model, &prompt, &response);
+            let (prompt_tokens, completion_tokens) = (cost::estimate_tokens(&prompt), cost::estimate_tokens(&response));
+            return json_response(json!({
+                "id": id,
+                "object": "chat.completion",
+                "created": created,
+                "model": model,
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": response },
+                    "finish_reason": "stop",
+                }],
+                "usage": {
+                    "prompt_tokens": prompt_tokens,
+                    "completion_tokens": completion_tokens,
+                    "total_tokens": prompt_tokens + completion_tokens,
+                },
+            }));
+        }
+
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            let event = |delta: Value, finish_reason: Value| {
+                let chunk = json!({
+                    "id": id,
+                    "object": "chat.completion.chunk",
+                    "created": created,
+                    "model": model,
+                    "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+                });
+                Bytes::from(format!("data: {}\n\n", chunk))
+            };
+
+            let mut response = String::new();
+            let mut first = true;
+            while let Some(chunk) = stream.next().await {
+                let delta = match chunk {
+                    Ok(text) if first => json!({ "role": "assistant", "content": text }),
+                    Ok(text) => json!({ "content": text }),
+                    Err(e) => {
+                        // The status is already sent, so the error goes into the stream.
+                        let error = json!({ "error": { "message": e.to_string(), "type": "api_error" } });
+                        let _ = sender.send_data(Bytes::from(format!("data: {}\n\n", error))).await;
+                        return;
+                    }
+                };
+                if let Some(text) = delta["content"].as_str() {
+                    response.push_str(text);
+                }
+                first = false;
+                if sender.send_data(event(delta, Value::Null)).await.is_err() {
+                    // The client went away.
+                    return;
+                }
+            }
+            let _ = sender.send_data(event(json!({}), json!("stop"))).await;
+            let _ = sender.send_data(Bytes::from("data: [DONE]\n\n")).await;
+            self.record_usage(&model, &prompt, &response);
+        });
+
+        hyper::Response::builder()
+            .header(header::CONTENT_TYPE, "text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(body)
+            .expect("valid response")
+    }
+
+    fn record_usage(&self, model: &str, prompt: &str, response: &str) {
+        if let Err(e) = usage::record(&usage::UsageEntry::new(model, prompt, response, &self.model_rates)) {
+            tracing::debug!("could not record usage: {:#}", e);
+        }
+    }
+}
+
+/// Reads a request body of at most `limit` bytes, answering with 413 as soon as it is larger,
+/// whether or not it declared its length.
+async fn read_body(mut body: Body, limit: usize) -> Result<Vec<u8>, Response> {
+    let too_large = || error_response(StatusCode::PAYLOAD_TOO_LARGE, &format!("Requests are limited to {} bytes", limit));
+    if body.size_hint().lower() > limit as u64 {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| error_response(StatusCode::BAD_REQUEST, &e.to_string()))?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Serves the OpenAI-compatible API on `addr` until Ctrl-C.
+pub async fn serve(server: Server, addr: SocketAddr) -> Result<()> {
+    let server = Arc::new(server);
+    let make_service = make_service_fn(move |_| {
+        let server = Arc::clone(&server);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let server = Arc::clone(&server);
+                async move { Ok::<_, Infallible>(server.handle(request).await) }
+            }))
+        }
+    });
+
+    let listener = hyper::Server::try_bind(&addr)
+        .map_err(|e| anyhow!("Could not listen on {}: {}", addr, e))?
+        .serve(make_service);
+    println!("Serving the OpenAI-compatible API at http://{}/v1 (Ctrl-C to stop).", listener.local_addr());
+    listener
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::OneMinClient;
+    use crate::retry;
+    use mockito::{Matcher, ServerGuard};
+
+    const KEY: &str = "sk-test";
+
+    fn server(upstream: &ServerGuard) -> Arc<Server> {
+        usage::use_scratch_dirs();
+        retry::configure(0, 0);
+        let client = OneMinClient::new(reqwest::Client::new(), "upstream-key").with_base_url(upstream.url());
+        let defaults = RequestDefaults { max_words: 100, max_tokens: None, sampling: Sampling::default() };
+        Arc::new(Server::new(Arc::new(client), "gpt-4o".to_string(), defaults, BTreeMap::new(), Redaction::Off, KEY.to_string()))
+    }
+
+    fn post(key: Option<&str>, content_type: &str, body: impl Into<Body>) -> hyper::Request<Body> {
+        let mut request = hyper::Request::post("/v1/chat/completions").header(header::CONTENT_TYPE, content_type);
+        if let Some(key) = key {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", key));
+        }
+        request.body(body.into()).unwrap()
+    }
+
+    async fn json_body(response: Response) -> Value {
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_without_the_key() {
+        let upstream = mockito::Server::new_async().await;
+        let server = server(&upstream);
+        for key in [None, Some("sk-wrong"), Some("sk-test-but-longer")] {
+            let response = Arc::clone(&server).handle(post(key, "application/json", "{}")).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        }
+        let models = hyper::Request::get("/v1/models").body(Body::empty()).unwrap();
+        assert_eq!(server.handle(models).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn refuses_bodies_that_are_not_json() {
+        let upstream = mockito::Server::new_async().await;
+        let response = server(&upstream).handle(post(Some(KEY), "text/plain", r#"{"messages":[]}"#)).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn refuses_oversized_bodies_with_or_without_a_length() {
+        let upstream = mockito::Server::new_async().await;
+        let server = server(&upstream);
+        let response = Arc::clone(&server).handle(post(Some(KEY), "application/json", vec![b' '; MAX_BODY_BYTES + 1])).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..5 {
+                if sender.send_data(Bytes::from(vec![b' '; MAX_BODY_BYTES / 4])).await.is_err() {
+                    return;
+                }
+            }
+        });
+        let response = server.handle(post(Some(KEY), "application/json", body)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn answers_chat_completions_with_earlier_messages_in_the_prompt() {
+        let mut upstream = mockito::Server::new_async().await;
+        let mock = upstream.mock("POST", "/api/features")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::PartialJson(json!({
+                "model": "gpt-4o",
+                "promptObject": {"prompt": "Be brief.\n\nThe conversation so far:\n\nUser: Hi\n\nAssistant: Hello\n\n---\n\nHow are you?"},
+            })))
+            .with_body("Fine, thanks.")
+            .create_async()
+            .await;
+
+        let body = json!({
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "Hi"},
+                {"role": "assistant", "content": [{"type": "text", "text": "Hello"}]},
+                {"role": "user", "content": "How are you?"},
+            ],
+        });
+        let response = server(&upstream).handle(post(Some(KEY), "application/json; charset=utf-8", body.to_string())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let completion = json_body(response).await;
+        assert_eq!(completion["object"], "chat.completion");
+        assert_eq!(completion["model"], "gpt-4o");
+        assert_eq!(completion["choices"][0]["message"]["content"], "Fine, thanks.");
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn streams_completions_as_server_sent_events() {
+        let mut upstream = mockito::Server::new_async().await;
+        upstream.mock("POST", "/api/features").match_query(Matcher::Any).with_body("Hi there").create_async().await;
+
+        let body = json!({ "model": "gpt-4o-mini", "stream": true, "messages": [{"role": "user", "content": "Hi"}] });
+        let response = server(&upstream).handle(post(Some(KEY), "application/json", body.to_string())).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+        let events = String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+        let events: Vec<&str> = events.split("\n\n").filter(|event| !event.is_empty()).collect();
+        let first: Value = serde_json::from_str(events[0].strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(first["model"], "gpt-4o-mini");
+        assert_eq!(first["choices"][0]["delta"], json!({ "role": "assistant", "content": "Hi there" }));
+        assert_eq!(events.last(), Some(&"data: [DONE]"));
+    }
+
+    #[tokio::test]
+    async fn answers_invalid_requests_and_upstream_failures_in_the_openai_format() {
+        let mut upstream = mockito::Server::new_async().await;
+        upstream.mock("POST", "/api/features").match_query(Matcher::Any).with_status(500).create_async().await;
+        let server = server(&upstream);
+
+        let response = Arc::clone(&server).handle(post(Some(KEY), "application/json", r#"{"messages": []}"#)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"]["message"], "'messages' must not be empty");
+
+        let body = json!({ "messages": [{"role": "user", "content": "Hi"}] }).to_string();
+        let response = Arc::clone(&server).handle(post(Some(KEY), "application/json", body)).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(json_body(response).await["error"]["type"], "api_error");
+
+        let get = hyper::Request::get("/v1/chat/completions").header(header::AUTHORIZATION, "Bearer sk-test").body(Body::empty()).unwrap();
+        assert_eq!(Arc::clone(&server).handle(get).await.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let unknown = hyper::Request::get("/v1/embeddings").header(header::AUTHORIZATION, "Bearer sk-test").body(Body::empty()).unwrap();
+        assert_eq!(server.handle(unknown).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn generated_keys_differ() {
+        assert_ne!(generate_api_key(), generate_api_key());
+    }
+}
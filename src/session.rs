@@ -0,0 +1,1151 @@
+//! A chat session: the conversation prompts go to, how responses are shown and saved, and the
+//! interactive, `--follow` and `--watch` loops that keep sending prompts within it.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use clap::ValueEnum;
+use dialoguer::Editor;
+use reqwest::Client;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::client::{ChatStream, OneMinClient, DEFAULT_TEXT_TO_SPEECH_MODEL};
+use crate::providers::{ChatRequest, Provider};
+use crate::repl::{self, SlashCommand};
+use crate::sampling::{self, Sampling};
+use crate::ui::{check_budget, confirm_large_prompt, confirm_tool, ensure_interactive, print_stats, show_progress, waiting_spinner};
+use crate::{auth, cache, chunk, clipboard, code, compact, compare, config, context, cost, error, export, history, interrupt, knowledge, mcp, media, models, pager, redact, references, render, schema, summarize, tools, usage, voice, watch};
+
+const AUTO_TITLE_WORDS: usize = 5;
+/// How much of the first prompt and response the title is based on
+const AUTO_TITLE_EXCERPT_CHARS: usize = 2000;
+
+/// How text responses are presented on the terminal.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TextStyle {
+    /// Raw text, as streamed
+    Plain,
+    /// Markdown rendered with ANSI styling
+    Markdown,
+    /// Plain linear text with explicit begin/end markers and no terminal effects
+    ScreenReader,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable streamed text
+    Text,
+    /// A single JSON object once the response is complete
+    Json,
+    /// Newline-delimited JSON chunks while streaming, then the final object
+    Jsonl,
+}
+
+/// State of a chat session that slash commands can change between messages.
+pub struct Session<'a> {
+    pub client: &'a OneMinClient,
+    /// Answers the prompts; the client itself unless another provider was selected
+    pub provider: &'a dyn Provider,
+    pub config: config::Config,
+    /// Id of the conversation in local history, which is also its API id when `remote` is set
+    pub conversation_uuid: String,
+    /// Whether prompts are sent within a conversation on the API, or each on its own
+    pub remote: bool,
+    pub model: String,
+    pub system: Option<String>,
+    pub max_words: u32,
+    /// Token limit of responses, for providers that take one
+    pub max_tokens: Option<u32>,
+    /// Sampling parameters given on the command line; those from the config file are added per model
+    pub sampling: Sampling,
+    pub quiet: bool,
+    pub voice_output: bool,
+    /// API voice to speak responses with, instead of the system's `say`
+    pub tts_voice: Option<String>,
+    pub text_style: TextStyle,
+    pub output: OutputFormat,
+    /// When printed responses are shown again in the pager
+    pub paging: pager::Paging,
+    pub stall_timeout: Duration,
+    pub yes: bool,
+    /// Attached files not yet sent, added to the next interactive prompt
+    pub pending_context: Option<String>,
+    /// Uploaded `--image` asset paths, sent with the next message
+    pub pending_images: Vec<String>,
+    /// Turns of the checkpoint a branch started from, sent ahead of its first prompt when the
+    /// provider keeps conversations and so doesn't know them
+    pub replay: Option<String>,
+    /// Approximate tokens of the conversation so far, and how many it may reach before it is compacted
+    pub context_tokens: usize,
+    pub context_limit: usize,
+    pub copy: Option<CopyTarget>,
+    /// Keep only the code blocks of responses, of this language unless empty
+    pub code_only: Option<String>,
+    /// File each response is written to
+    pub out: Option<PathBuf>,
+    /// Schema responses must match, and how often to ask again when one doesn't
+    pub schema: Option<Arc<schema::Schema>>,
+    pub schema_retries: u32,
+    /// Markdown transcript that every exchange is appended to
+    pub transcript: Option<PathBuf>,
+    /// Directory each response is saved to as a note of its own, with `--save-response`
+    pub save_responses: Option<PathBuf>,
+    /// Options recorded in the front matter of saved responses
+    pub response_options: Vec<(&'static str, String)>,
+    /// Name each new conversation after its first exchange
+    pub auto_title: bool,
+    /// Whether the current conversation still waits for its automatic title
+    pub needs_title: bool,
+    /// Print latency and throughput after each response
+    pub stats: bool,
+    /// Answer prompts that don't depend on a conversation from the response cache
+    pub cache: bool,
+    /// The uploaded document prompts are about, when chatting with a document
+    pub document: Option<history::Document>,
+    pub last_prompt: Option<String>,
+    pub last_response: Option<String>,
+    /// Index of the stored turn holding the last prompt asked, retries aside
+    pub last_original_turn: Option<usize>,
+    /// Set while regenerating: the stored turn the next response is an alternative to
+    pub regenerate: Option<usize>,
+    /// Tools the model may call, from `--tools`
+    pub tools: Vec<tools::Tool>,
+    /// MCP servers whose tools the model may call, from `--mcp`
+    pub mcp: Vec<mcp::Connection>,
+    /// The index prompts are augmented from, with `--knowledge`
+    pub knowledge: Option<Knowledge>,
+    /// The repository whose relevant files are added to prompts, with `--repo`
+    pub repo: Option<context::Repo>,
+    /// For tools that fetch web pages
+    pub http: Client,
+}
+
+/// A document index and the provider that embeds prompts the way its chunks were embedded.
+pub struct Knowledge {
+    pub index: knowledge::Index,
+    pub embedder: Box<dyn Provider>,
+    pub top_k: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum CompareLayout {
+    /// One response after another
+    Sequential,
+    /// Responses side by side, one column per model
+    Columns,
+}
+
+/// What `--copy` places on the clipboard.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum CopyTarget {
+    /// The whole response
+    Response,
+    /// The first code block of the response
+    Code,
+}
+
+/// Starts a conversation in local history, creating it on the API as well when `remote` is set.
+///
+/// Without a remote conversation prompts go straight to the features endpoint, skipping a round-trip.
+pub async fn initialize_conversation(client: &OneMinClient, remote: bool, title: Option<&str>) -> Result<String> {
+    let now = Local::now();
+    let title = match title {
+        Some(title) => title.to_string(),
+        None => format!("API - {}", now.format("%Y/%m/%d at %I:%M:%S %p")),
+    };
+
+    let uuid = if remote {
+        auth::with_recovery(client, || client.create_conversation(&title)).await?
+    } else {
+        format!("{}{}", history::LOCAL_ID_PREFIX, now.format("%Y%m%d%H%M%S%3f"))
+    };
+
+    history::start_conversation(&uuid, &title)?;
+    Ok(uuid)
+}
+
+/// Uploads a document to the asset store for chatting about it.
+pub async fn upload_document(client: &OneMinClient, path: &Path) -> Result<history::Document> {
+    if !path.is_file() {
+        return Err(anyhow!("No such document: {}", path.display()));
+    }
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+    eprintln!("Uploading {}...", name);
+    let asset = media::upload_asset(client, path).await?;
+    Ok(history::Document { name, asset })
+}
+
+/// Starts a conversation about an uploaded document, on the API and in local history. Document
+/// chats always live on the API, which keeps the document's contents on its side.
+pub async fn initialize_document_conversation(client: &OneMinClient, document: &history::Document, title: Option<&str>) -> Result<String> {
+    let title = match title {
+        Some(title) => title.to_string(),
+        None => format!("{} - {}", document.name, Local::now().format("%Y/%m/%d at %I:%M:%S %p")),
+    };
+
+    let uuid = auth::with_recovery(client, || client.create_document_conversation(&title, std::slice::from_ref(&document.asset))).await?;
+
+    history::start_conversation(&uuid, &title)?;
+    history::set_document(&uuid, document)?;
+    Ok(uuid)
+}
+
+/// How many prompts the turns answer, not counting regenerated responses.
+pub fn exchange_count(turns: &[history::Turn]) -> usize {
+    turns.iter().filter(|turn| turn.retry_of.is_none()).count()
+}
+
+/// What a branched or compacted conversation starts from, written out as context for its first
+/// prompt.
+fn replay_context(record: &history::ConversationRecord) -> String {
+    format!(
+        "We are continuing an earlier conversation, repeated here for context:\n\n{}\n\n---",
+        compact::transcript(record.summary.as_deref(), &record.turns)
+    )
+}
+
+/// Starts a new conversation for the session, about its document if it has one.
+async fn new_conversation(session: &Session<'_>, title: Option<&str>) -> Result<String> {
+    match &session.document {
+        Some(document) => initialize_document_conversation(session.client, document, title).await,
+        None => initialize_conversation(session.client, session.remote && session.provider.hosts_conversations(), title).await,
+    }
+}
+
+/// Switches the session to `record`, a conversation that continues an earlier one. A provider
+/// that keeps conversations itself gets what it starts from with the next prompt; others get
+/// it as history with every request.
+fn continue_in(session: &mut Session<'_>, record: &history::ConversationRecord) {
+    let hosted = session.provider.hosts_conversations() || session.document.is_some();
+    let has_context = record.summary.is_some() || !record.turns.is_empty();
+    session.conversation_uuid = record.id.clone();
+    session.replay = (session.remote && hosted && has_context).then(|| replay_context(record));
+    session.context_tokens = compact::context_tokens(record.summary.as_deref(), &record.turns);
+    session.needs_title = false;
+    session.last_prompt = None;
+    session.last_response = None;
+    session.last_original_turn = None;
+}
+
+/// Switches the session to `record`, a stored conversation picked up where it left off. A
+/// provider that keeps conversations continues it itself and others get it as history, except
+/// that one kept only locally stays local with a provider that needs its own.
+pub fn resume_in(session: &mut Session<'_>, record: &history::ConversationRecord) {
+    let local_only = record.id.starts_with(history::LOCAL_ID_PREFIX) && session.provider.hosts_conversations();
+    session.conversation_uuid = record.id.clone();
+    session.remote = !local_only || session.document.is_some();
+    session.context_tokens = compact::context_tokens(record.summary.as_deref(), &record.turns);
+    session.needs_title = false;
+    eprintln!("Continuing '{}' after {} exchange(s).", record.title, exchange_count(&record.turns));
+}
+
+/// Summarizes all but the latest exchanges of the conversation and continues in a new one that
+/// starts from the summary. Returns false when there was nothing to compact.
+async fn compact_conversation(session: &mut Session<'_>) -> Result<bool> {
+    let record = history::load(&session.conversation_uuid)?;
+    let (earlier, recent) = compact::split(&record.turns);
+    if earlier.is_empty() {
+        return Ok(false);
+    }
+
+    let (provider, model, words) = (session.provider, session.model.as_str(), session.max_words);
+    let rates = &session.config.model_rates;
+    let chunk_tokens = chunk::DEFAULT_CHUNK_TOKENS.min(session.context_limit.max(1000) / 2);
+    let summary = chunk::map_reduce(
+        &compact::transcript(record.summary.as_deref(), &earlier),
+        chunk_tokens,
+        summarize::PART_INSTRUCTION,
+        compact::INSTRUCTION,
+        |prompt| async move {
+            let response = auth::with_recovery(provider, || provider.chat(model, &prompt, words)).await?;
+            usage::record(&usage::UsageEntry::new(model, &prompt, &response, rates))?;
+            Ok(response)
+        },
+        |_, _| {},
+    ).await?;
+
+    let id = new_conversation(session, Some(&record.title)).await?;
+    let continued = history::compacted(&id, &record, summary.trim(), recent)?;
+    let before = session.context_tokens.max(compact::context_tokens(record.summary.as_deref(), &record.turns));
+    continue_in(session, &continued);
+    println!(
+        "Summarized {} earlier exchange(s): ~{} tokens of context instead of ~{}.",
+        exchange_count(&earlier),
+        session.context_tokens,
+        before
+    );
+    Ok(true)
+}
+
+/// Compacts the conversation first when the prompt would take it past `--context-limit`.
+async fn fit_context(session: &mut Session<'_>, prompt: &str) -> Result<()> {
+    if !session.remote || session.context_limit == 0 {
+        return Ok(());
+    }
+    let tokens = session.context_tokens + cost::estimate_tokens(prompt);
+    if tokens > session.context_limit {
+        eprintln!("The conversation is at ~{} tokens, over the limit of {}; summarizing earlier exchanges...", tokens, session.context_limit);
+        compact_conversation(session).await?;
+    }
+    Ok(())
+}
+
+/// Asks the model for a short title for the conversation's first exchange and renames it, both
+/// locally and on the API.
+async fn title_conversation(session: &Session<'_>, prompt: &str, response: &str) -> Result<()> {
+    let excerpt = |text: &str| text.chars().take(AUTO_TITLE_EXCERPT_CHARS).collect::<String>();
+    let request = format!(
+        "Write a title of at most {} words for a conversation that starts with the exchange below. \
+         Respond with only the title, without quotes or punctuation at the end.\n\nUser: {}\n\nAssistant: {}",
+        AUTO_TITLE_WORDS,
+        excerpt(prompt),
+        excerpt(response)
+    );
+
+    let answer = session.provider.chat(&session.model, &request, session.max_words).await?;
+    let title: String = answer.lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default()
+        .trim()
+        .trim_matches(|c: char| c == '"' || c == '\'' || c == '*' || c == '#' || c == '.')
+        .trim()
+        .to_string();
+    if title.is_empty() {
+        return Err(anyhow!("the model returned an empty title"));
+    }
+
+    if session.remote && session.provider.hosts_conversations() {
+        session.client.rename_conversation(&session.conversation_uuid, &title).await?;
+    }
+    history::rename(&session.conversation_uuid, &title)
+}
+
+async fn chat_with_ai(session: &mut Session<'_>, prompt: &str) -> Result<String> {
+    let started = Instant::now();
+    let client = session.client;
+    let model = session.model.as_str();
+    let conversation_uuid = session.conversation_uuid.as_str();
+    let remote_uuid = session.remote.then_some(conversation_uuid);
+    let history = match remote_uuid {
+        Some(id) if !session.provider.hosts_conversations() => history::load(id)?.messages(),
+        _ => Vec::new(),
+    };
+    let (quiet, output, text_style) = (session.quiet, session.output, session.text_style);
+    let mut full_prompt = match &session.system {
+        Some(system) => format!("{}\n\n{}", system, prompt),
+        None => prompt.to_string(),
+    };
+    if let Some(replay) = &session.replay {
+        full_prompt = format!("{}\n\n{}", replay, full_prompt);
+    }
+
+    let code_only = session.code_only.as_deref().map(|lang| (!lang.is_empty()).then_some(lang));
+    // With --code-only the response is printed once it is complete and its code can be extracted;
+    // with --schema only once it has been validated, by `ask`.
+    let print_text = !quiet && output == OutputFormat::Text && code_only.is_none() && session.schema.is_none();
+    // The spinner fills the silence until the first token.
+    let mut spinner = (!quiet && output == OutputFormat::Text && show_progress())
+        .then(waiting_spinner);
+    // Until the response is complete, Ctrl-C cancels it rather than ending the process.
+    let _request = interrupt::begin();
+
+    let sampling = sampling::resolve(model, session.sampling, session.config.sampling)?;
+    // Answers within a conversation, or about images or a document, depend on more than the prompt.
+    let cache_key = (session.cache && remote_uuid.is_none() && session.document.is_none() && session.pending_images.is_empty())
+        .then(|| cache::CacheKey {
+            provider: session.provider.name().to_string(),
+            model: model.to_string(),
+            prompt: full_prompt.clone(),
+            max_words: session.max_words,
+            sampling,
+        });
+    let cached = cache_key.as_ref().and_then(cache::lookup);
+    let from_cache = cached.is_some();
+
+    let mut stream = match cached {
+        Some(response) => ChatStream::from_text(response),
+        None => {
+            let (session, full_prompt, history) = (&*session, &full_prompt, &history);
+            let request = auth::with_recovery(session.provider, move || async move {
+                match (&session.document, session.pending_images.as_slice()) {
+                    (Some(_), _) => match redact::filter(full_prompt, session.config.redaction) {
+                        Ok(prompt) => client.document_chat_stream(conversation_uuid, model, &prompt, session.max_words, sampling).await,
+                        Err(e) => Err(e),
+                    },
+                    (None, []) => session.provider.chat_stream(ChatRequest {
+                        model,
+                        prompt: full_prompt,
+                        max_words: session.max_words,
+                        max_tokens: session.max_tokens,
+                        sampling,
+                        conversation_id: remote_uuid,
+                        history,
+                    }).await,
+                    (None, images) => match redact::filter(full_prompt, session.config.redaction) {
+                        Ok(prompt) => client.image_chat_stream(remote_uuid, model, &prompt, images, session.max_words, sampling).await,
+                        Err(e) => Err(e),
+                    },
+                }
+            });
+            tokio::select! {
+                stream = request => stream?,
+                _ = interrupt::cancelled() => return Err(error::AiCliError::Interrupted.into()),
+            }
+        }
+    };
+
+    let mut full_response = String::with_capacity(1024);
+    let mut renderer = (text_style == TextStyle::Markdown).then(render::MarkdownRenderer::new);
+    // The response as printed, for the pager
+    let mut shown = String::new();
+    let mut first_chunk_at = None;
+    let mut interrupted = false;
+
+    loop {
+        let next = tokio::select! {
+            next = tokio::time::timeout(session.stall_timeout, stream.next()) => next,
+            _ = interrupt::cancelled() => {
+                interrupted = true;
+                break;
+            }
+        };
+        let text_chunk = match next {
+            Ok(Some(item)) => Some(item?),
+            Ok(None) => None,
+            Err(_) => {
+                return Err(anyhow!(
+                    "No data received from the model for {}s, giving up",
+                    session.stall_timeout.as_secs()
+                ));
+            }
+        };
+
+        if first_chunk_at.is_none() {
+            first_chunk_at = Some(started.elapsed());
+            if print_text {
+                if let Some(spinner) = spinner.take() {
+                    spinner.finish_and_clear();
+                }
+                shown = if text_style == TextStyle::ScreenReader {
+                    format!("AI response begins ({}):\n", model)
+                } else {
+                    format!("AI({}): ", model)
+                };
+                print!("{}", shown);
+                io::stdout().flush()?;
+            }
+        }
+        let Some(text_chunk) = text_chunk else { break };
+
+        if print_text {
+            let printed = match renderer.as_mut() {
+                Some(renderer) => renderer.push(&text_chunk),
+                None => text_chunk.clone(),
+            };
+            print!("{}", printed);
+            io::stdout().flush()?;
+            shown.push_str(&printed);
+        } else if output == OutputFormat::Jsonl {
+            println!("{}", serde_json::json!({ "type": "chunk", "text": text_chunk }));
+            io::stdout().flush()?;
+        }
+
+        full_response.push_str(&text_chunk);
+    }
+
+    if let Some(spinner) = spinner.take() {
+        spinner.finish_and_clear();
+    }
+    if interrupted && full_response.is_empty() {
+        return Err(error::AiCliError::Interrupted.into());
+    }
+    let code = code_only.and_then(|lang| code::extract_code(&full_response, lang));
+
+    if print_text {
+        if let Some(renderer) = renderer.as_mut() {
+            let rest = renderer.finish();
+            print!("{}", rest);
+            shown.push_str(&rest);
+        }
+        println!();
+        if text_style == TextStyle::ScreenReader {
+            println!("AI response ends.");
+        }
+        if !interrupted && session.paging.wants(&shown) {
+            if let Err(e) = pager::page(&shown) {
+                eprintln!("Warning: {:#}", e);
+            }
+        }
+    } else if output == OutputFormat::Text {
+        if let (Some(code), false) = (&code, quiet) {
+            print!("{}", code);
+            io::stdout().flush()?;
+        }
+    } else if session.schema.is_none() {
+        let mut result = serde_json::json!({
+            "model": model,
+            "conversation_id": remote_uuid,
+            "prompt": prompt,
+            "response": full_response,
+            "elapsed_ms": started.elapsed().as_millis() as u64,
+        });
+        if output == OutputFormat::Jsonl {
+            result["type"] = serde_json::json!("response");
+        }
+        if from_cache {
+            result["cached"] = serde_json::json!(true);
+        }
+        if code_only.is_some() {
+            result["code"] = serde_json::json!(code);
+        }
+        if interrupted {
+            result["interrupted"] = serde_json::json!(true);
+        }
+        println!("{}", result);
+    }
+
+    if interrupted && output == OutputFormat::Text {
+        eprintln!("[interrupted; the partial response is kept in history]");
+    }
+
+    if session.stats {
+        print_stats(first_chunk_at, started.elapsed(), &full_response);
+    }
+
+    if session.voice_output && !interrupted {
+        match &session.tts_voice {
+            Some(voice) => media::synthesize_speech(client, &full_response, DEFAULT_TEXT_TO_SPEECH_MODEL, voice, None, true).await?,
+            None => voice::speak_response(&full_response, &session.config.voice_map)?,
+        }
+    }
+
+    // A cached answer cost nothing this time.
+    let credits = if from_cache {
+        None
+    } else {
+        let usage = usage::UsageEntry::new(model, &full_prompt, &full_response, &session.config.model_rates);
+        usage::record(&usage)?;
+        if let (Some(key), false) = (&cache_key, interrupted) {
+            cache::store(key, &full_response)?;
+        }
+        Some(usage.credits)
+    };
+    let turn = history::Turn {
+        timestamp: Local::now(),
+        model: model.to_string(),
+        prompt: prompt.to_string(),
+        response: full_response.clone(),
+        credits,
+        retry_of: session.regenerate.take(),
+        interrupted,
+    };
+    let index = history::record_turn(conversation_uuid, &turn, session.system.as_deref())?;
+    session.last_original_turn = Some(turn.retry_of.unwrap_or(index));
+    if let Some(path) = &session.transcript {
+        export::append_turn(path, &turn)?;
+    }
+    if let (Some(dir), false) = (&session.save_responses, interrupted) {
+        let path = save_response(dir, &turn, conversation_uuid, &session.response_options)?;
+        if session.output == OutputFormat::Text && !session.quiet {
+            eprintln!("Saved to {}", path.display());
+        }
+    }
+    if session.needs_title && !interrupted {
+        session.needs_title = false;
+        // A missing title is no reason to fail the request that was just answered.
+        if let Err(e) = title_conversation(session, prompt, &full_response).await {
+            eprintln!("Warning: could not title the conversation: {:#}", e);
+        }
+    }
+    // Everything below gets the code alone under --code-only.
+    let postprocessed = match code_only {
+        Some(_) => code.as_deref().unwrap_or_default(),
+        None => full_response.as_str(),
+    };
+    if session.schema.is_none() && !interrupted {
+        deliver(session, &full_response, postprocessed)?;
+    }
+
+    session.pending_images.clear();
+    session.replay = None;
+    session.context_tokens += cost::estimate_tokens(&full_prompt) + cost::estimate_tokens(&full_response);
+    session.last_response = Some(full_response.clone());
+    if interrupted {
+        return Err(error::AiCliError::Interrupted.into());
+    }
+    if let (Some(lang), None) = (code_only, &code) {
+        return Err(anyhow!("The response has no {}code block", lang.map(|lang| format!("{} ", lang)).unwrap_or_default()));
+    }
+    Ok(full_response)
+}
+
+/// Writes an exchange to `<date>-<prompt slug>.md` in `dir`, numbering the name if it is taken.
+fn save_response(dir: &Path, turn: &history::Turn, conversation: &str, options: &[(&str, String)]) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Could not create {}", dir.display()))?;
+    let first_line = turn.prompt.lines().find(|line| !line.trim().is_empty()).unwrap_or("response");
+    let name = format!("{}-{}.md", turn.timestamp.format("%Y-%m-%d"), media::slugify(first_line));
+    let path = media::unique_path(&dir.join(name));
+    std::fs::write(&path, export::render_response(turn, conversation, options))
+        .with_context(|| format!("Could not write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Hands the final text of a response to `--copy` and `--out`.
+fn deliver(session: &Session<'_>, response: &str, text: &str) -> Result<()> {
+    match session.copy {
+        Some(CopyTarget::Response) => clipboard::copy(text)?,
+        Some(CopyTarget::Code) => clipboard::copy(&code::strip_fences(response))?,
+        None => {}
+    }
+    if let Some(path) = &session.out {
+        std::fs::write(path, text).with_context(|| format!("Could not write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Sends a prompt, with the relevant chunks of the `--knowledge` index ahead of it and the
+/// relevant files of the repository after it with `--repo`, and lists the chunks it was sent with.
+pub async fn ask(session: &mut Session<'_>, prompt: &str) -> Result<String> {
+    let mut augmented = prompt.to_string();
+    let mut sources = Vec::new();
+    if let Some(knowledge) = &session.knowledge {
+        let chunks = knowledge.index.retrieve(knowledge.embedder.as_ref(), prompt, knowledge.top_k).await?;
+        augmented = knowledge::augment(prompt, &chunks);
+        sources = chunks.iter()
+            .enumerate()
+            .map(|(index, chunk)| format!("  [{}] {}", index + 1, chunk.citation()))
+            .collect();
+    }
+    if let Some(repo) = &mut session.repo {
+        let limit = match session.context_limit {
+            0 => compact::DEFAULT_CONTEXT_LIMIT_TOKENS,
+            limit => limit,
+        };
+        let budget = limit.saturating_sub(session.context_tokens + cost::estimate_tokens(&augmented));
+        let mut selection = repo.select(prompt, budget, &[]);
+        if !session.quiet && session.output == OutputFormat::Text {
+            report_repo_context(&selection);
+        }
+        let credits = cost::estimate_credits(selection.tokens(), &session.model, &session.config.model_rates);
+        if !selection.files.is_empty() && !check_budget("Adding the repository files", credits, &session.config)? {
+            eprintln!("Sending the prompt without them.");
+            selection.files.clear();
+        }
+        augmented.push_str(&selection.render());
+        // Files sent in a conversation stay in it; later prompts only add new ones.
+        if session.remote {
+            repo.mark_sent(&selection);
+        }
+    }
+    let response = answer(session, &augmented).await?;
+    if !session.quiet && session.output == OutputFormat::Text && !sources.is_empty() {
+        eprintln!("Sources:\n{}", sources.join("\n"));
+    }
+    Ok(response)
+}
+
+/// Tells which files of the repository `--repo` adds to a prompt.
+pub fn report_repo_context(selection: &context::Selection) {
+    if selection.files.is_empty() {
+        if selection.left_out > 0 {
+            eprintln!("None of the {} relevant file(s) of the repository fit in the context.", selection.left_out);
+        }
+        return;
+    }
+    let paths: Vec<&str> = selection.files.iter().map(|file| file.path.as_str()).collect();
+    eprintln!("Adding {} file(s) of the repository (~{} tokens): {}", paths.len(), selection.tokens(), paths.join(", "));
+    if selection.left_out > 0 {
+        eprintln!("{} more relevant file(s) didn't fit in the context.", selection.left_out);
+    }
+}
+
+/// Sends a prompt, and with `--schema` asks again until the response matches the schema, then
+/// prints just the JSON.
+async fn answer(session: &mut Session<'_>, prompt: &str) -> Result<String> {
+    if !session.tools.is_empty() || !session.mcp.is_empty() {
+        return ask_with_tools(session, prompt).await;
+    }
+    let Some(schema) = session.schema.clone() else {
+        return chat_with_ai(session, prompt).await;
+    };
+    let instructed = format!("{}\n\n{}", prompt, schema.instructions());
+    let mut request = instructed.clone();
+    let mut attempt = 0;
+    loop {
+        let response = chat_with_ai(session, &request).await?;
+        let errors = match schema.check(&response) {
+            Ok(value) => {
+                let json = match session.output {
+                    OutputFormat::Text => serde_json::to_string_pretty(&value)?,
+                    OutputFormat::Json | OutputFormat::Jsonl => value.to_string(),
+                };
+                if !session.quiet || session.output != OutputFormat::Text {
+                    println!("{}", json);
+                }
+                deliver(session, &response, &json)?;
+                return Ok(response);
+            }
+            Err(errors) => errors,
+        };
+        if attempt == session.schema_retries {
+            return Err(anyhow!(
+                "The response didn't match the schema after {} attempt(s):\n  {}",
+                attempt + 1,
+                errors.join("\n  ")
+            ));
+        }
+        attempt += 1;
+        eprintln!("The response didn't match the schema ({}), asking again ({}/{})...", errors[0], attempt, session.schema_retries);
+        request = schema.retry_prompt(&instructed, &response, &errors);
+    }
+}
+
+/// Sends a prompt with the `--tools` described, runs the tools the model calls and sends back
+/// their results, until it answers without calling one.
+async fn ask_with_tools(session: &mut Session<'_>, prompt: &str) -> Result<String> {
+    let remote: Vec<String> = session.mcp.iter().flat_map(mcp::Connection::descriptions).collect();
+    let mut request = format!("{}\n\n{}", tools::instructions(&session.tools, &remote), prompt);
+    for _ in 0..tools::MAX_STEPS {
+        let response = chat_with_ai(session, &request).await?;
+        let (tool, result) = match tools::parse_call(&response) {
+            None => return Ok(response),
+            Some(Ok(call)) => (call.tool.clone(), run_tool(session, &call).await),
+            Some(Err(e)) => ("tool_call".to_string(), Err(e)),
+        };
+        request = tools::result_prompt(&tool, &result);
+    }
+    Err(anyhow!("Stopped after {} tool calls without an answer", tools::MAX_STEPS))
+}
+
+/// Runs a tool call from the model if its policy allows it, asking first when the policy says so.
+/// Without a terminal to ask on, those calls are refused.
+async fn run_tool(session: &Session<'_>, call: &tools::ToolCall) -> Result<String> {
+    if let Some((server, tool)) = mcp::find(&session.mcp, &call.tool) {
+        eprintln!("[tool] {}", call.summary());
+        // Reading a resource only looks at what the server offers, so only `deny` refuses it.
+        let policy = match (tool, server.policy) {
+            (mcp::READ_RESOURCE, tools::Policy::Ask) => tools::Policy::Allow,
+            (_, policy) => policy,
+        };
+        confirm_tool(policy, &call.tool)?;
+        return match tool {
+            mcp::READ_RESOURCE => server.read_resource(call.arg("uri")?).await,
+            tool => server.call_tool(tool, &call.args).await,
+        };
+    }
+    let tool = tools::find(&session.tools, call)?;
+    eprintln!("[tool] {}", call.summary());
+    confirm_tool(session.config.tool_policy(tool), tool.name())?;
+    tools::execute(tool, call, &session.http).await
+}
+
+/// Sends one prompt to several models concurrently, each in its own local conversation, and
+/// prints the responses together with how long each model took.
+pub async fn compare_models(session: &Session<'_>, prompt: &str, models: &[String], layout: CompareLayout) -> Result<()> {
+    let mut samplings = Vec::with_capacity(models.len());
+    for model in models {
+        models::validate(model)?;
+        samplings.push(sampling::resolve(model, session.sampling, session.config.sampling)?);
+    }
+    let full_prompt = match &session.system {
+        Some(system) => format!("{}\n\n{}", system, prompt),
+        None => prompt.to_string(),
+    };
+
+    let spinner = (session.output == OutputFormat::Text && show_progress())
+        .then(waiting_spinner);
+    let remaining = std::sync::atomic::AtomicUsize::new(models.len());
+    if let Some(spinner) = &spinner {
+        spinner.set_message(format!("waiting for {} models…", models.len()));
+    }
+
+    let requests = models.iter().zip(samplings).map(|(model, sampling)| {
+        let (full_prompt, spinner, remaining) = (&full_prompt, &spinner, &remaining);
+        async move {
+            let started = Instant::now();
+            let result = loop {
+                let request = ChatRequest {
+                    model,
+                    prompt: full_prompt,
+                    max_words: session.max_words,
+                    max_tokens: session.max_tokens,
+                    sampling,
+                    conversation_id: None,
+                    history: &[],
+                };
+                let result = match session.provider.chat_stream(request).await {
+                    Ok(stream) => stream.collect().await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Err(e) if e.is_auth() => auth::reauthenticate(session.provider)?,
+                    result => break result,
+                }
+            };
+            let elapsed = started.elapsed();
+            let left = remaining.fetch_sub(1, Ordering::Relaxed) - 1;
+            if let Some(spinner) = spinner {
+                spinner.set_message(format!("{} answered in {:.2}s, waiting for {} more…", model, elapsed.as_secs_f64(), left));
+            }
+            anyhow::Ok((model, result, elapsed))
+        }
+    });
+    let results = futures_util::future::join_all(requests).await;
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+
+    let started_at = Local::now();
+    let mut responses = Vec::with_capacity(results.len());
+    for (i, result) in results.into_iter().enumerate() {
+        let (model, result, elapsed) = result?;
+        let conversation_id = format!("{}{}-{}", history::LOCAL_ID_PREFIX, started_at.format("%Y%m%d%H%M%S%3f"), i + 1);
+        let (response, error) = match result {
+            Ok(response) => {
+                let usage = usage::UsageEntry::new(model, &full_prompt, &response, &session.config.model_rates);
+                usage::record(&usage)?;
+                history::start_conversation(&conversation_id, &format!("Compare ({}) - {}", model, started_at.format("%Y/%m/%d at %I:%M:%S %p")))?;
+                history::record_turn(&conversation_id, &history::Turn {
+                    timestamp: Local::now(),
+                    model: model.clone(),
+                    prompt: prompt.to_string(),
+                    response: response.clone(),
+                    credits: Some(usage.credits),
+                    retry_of: None,
+                    interrupted: false,
+                }, session.system.as_deref())?;
+                (response, None)
+            }
+            Err(e) => (String::new(), Some(e.to_string())),
+        };
+        responses.push(compare::ModelResponse {
+            model: model.clone(),
+            conversation_id,
+            response,
+            error,
+            elapsed_ms: elapsed.as_millis() as u64,
+        });
+    }
+
+    match session.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&responses)?),
+        OutputFormat::Jsonl => {
+            for response in &responses {
+                println!("{}", serde_json::to_string(response)?);
+            }
+        }
+        OutputFormat::Text => {
+            let width = console::Term::stdout().size_checked().map(|(_, columns)| columns as usize);
+            let columns = match (layout, width) {
+                (CompareLayout::Columns, Some(width)) => compare::render_columns(&responses, width),
+                _ => None,
+            };
+            if layout == CompareLayout::Columns && columns.is_none() {
+                eprintln!("The terminal is too narrow for {} columns; showing the responses one after another.", responses.len());
+            }
+            print!("{}", columns.unwrap_or_else(|| compare::render_sequential(&responses)));
+        }
+    }
+
+    let failed = responses.iter().filter(|r| r.error.is_some()).count();
+    if failed == responses.len() {
+        return Err(anyhow!("None of the models answered"));
+    }
+    Ok(())
+}
+
+/// Opens `$EDITOR` on `initial` and returns the saved text, or `None` if it was left empty.
+pub fn prompt_from_editor(initial: &str) -> Result<Option<String>> {
+    ensure_interactive("Writing a prompt in $EDITOR", "pass the prompt as an argument instead")?;
+    let edited = Editor::new().extension(".md").edit(initial)?;
+    Ok(edited.map(|text| text.trim().to_string()).filter(|text| !text.is_empty()))
+}
+
+pub async fn follow_stdin(session: &mut Session<'_>, instruction: &str, batch_lines: usize, batch_secs: u64) -> Result<()> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+    std::thread::spawn(move || {
+        for line in io::stdin().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let window = Duration::from_secs(batch_secs);
+    let mut batch: Vec<String> = Vec::new();
+    let mut deadline = None;
+    let mut open = true;
+
+    while open || !batch.is_empty() {
+        let received = match deadline {
+            Some(deadline) if open => tokio::time::timeout_at(deadline, rx.recv()).await.ok(),
+            None if open => Some(rx.recv().await),
+            _ => None,
+        };
+
+        match received {
+            Some(Some(line)) => {
+                if batch.is_empty() {
+                    deadline = Some(tokio::time::Instant::now() + window);
+                }
+                batch.push(line);
+                if batch.len() < batch_lines {
+                    continue;
+                }
+            }
+            Some(None) => open = false,
+            None => {}
+        }
+
+        if batch.is_empty() {
+            continue;
+        }
+
+        let prompt = format!("{}\n\nNew input:\n```\n{}\n```", instruction, batch.join("\n"));
+        fit_context(session, &prompt).await?;
+        ask(session, &prompt).await?;
+
+        batch.clear();
+        deadline = None;
+    }
+
+    Ok(())
+}
+
+/// Sends the instruction with each saved change of the files, in one conversation so the model
+/// knows the earlier versions, until interrupted.
+pub async fn watch_files(session: &mut Session<'_>, instruction: &str, files: &[PathBuf], full: bool) -> Result<()> {
+    let mut watcher = watch::FileWatcher::new(files)?;
+    // What the model last saw of each file
+    let mut sent: Vec<Option<String>> = vec![None; files.len()];
+    let mut current: Vec<String> = (0..files.len()).map(|i| watcher.read(i)).collect::<Result<_>>()?;
+    let names: Vec<String> = files.iter().map(|file| file.display().to_string()).collect();
+    eprintln!("Watching {}. Press Ctrl-C to stop.", names.join(", "));
+
+    loop {
+        for i in watcher.changed().await? {
+            let contents = match watcher.read(i) {
+                Ok(contents) => contents,
+                // Saved half-way, deleted or grown too large: wait for the next save.
+                Err(e) => {
+                    eprintln!("Warning: {:#}", e);
+                    continue;
+                }
+            };
+            if contents == current[i] {
+                continue;
+            }
+            current[i] = contents.clone();
+            let prompt = watch::change_prompt(instruction, &names[i], sent[i].as_deref(), &contents, full);
+            eprintln!("{} changed.", names[i]);
+            if !confirm_large_prompt(&prompt, &session.model, &session.config, session.yes)? {
+                println!("Skipped.");
+                continue;
+            }
+            // One failed request, e.g. a network hiccup, shouldn't end the watch. The file counts
+            // as unsent, so the next change is compared with what the model did see.
+            let result = match fit_context(session, &prompt).await {
+                Ok(()) => ask(session, &prompt).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => sent[i] = Some(contents),
+                Err(e) if matches!(e.downcast_ref(), Some(error::AiCliError::Interrupted)) => {}
+                Err(e) => eprintln!("Error: {:#}. Still watching.", e),
+            }
+        }
+    }
+}
+
+/// Expands file references, confirms large prompts and sends a prompt typed in interactive mode.
+pub async fn send_interactive(session: &mut Session<'_>, prompt: &str) -> Result<()> {
+    session.last_prompt = Some(prompt.to_string());
+
+    let mut expanded = match references::expand(prompt) {
+        Ok(expanded) => expanded,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            return Ok(());
+        }
+    };
+    if let Some(context) = &session.pending_context {
+        expanded.push_str("\n\n");
+        expanded.push_str(context);
+    }
+
+    if confirm_large_prompt(&expanded, &session.model, &session.config, session.yes)? {
+        fit_context(session, &expanded).await?;
+        match ask(session, &expanded).await {
+            // Ctrl-C only cancels the response, back to the prompt.
+            Err(e) if matches!(e.downcast_ref(), Some(error::AiCliError::Interrupted)) => {}
+            // API errors end the session as before; a response without the requested code or
+            // JSON only fails this prompt.
+            Err(e) if e.downcast_ref::<error::AiCliError>().is_none() => eprintln!("Error: {:#}", e),
+            result => {
+                result?;
+            }
+        }
+        session.pending_context = None;
+    }
+    Ok(())
+}
+
+pub async fn run_slash_command(session: &mut Session<'_>, command: SlashCommand) -> Result<()> {
+    match command {
+        SlashCommand::Model(None) => println!("Current model: {}", session.model),
+        SlashCommand::Model(Some(model)) => {
+            models::validate(&model)?;
+            sampling::resolve(&model, session.sampling, session.config.sampling)?;
+            println!("Switched to {}.", model);
+            session.model = model;
+        }
+        SlashCommand::New => {
+            session.conversation_uuid = new_conversation(session, None).await?;
+            session.needs_title = session.auto_title;
+            session.replay = None;
+            session.context_tokens = 0;
+            session.last_prompt = None;
+            session.last_response = None;
+            session.last_original_turn = None;
+            println!("Started a new conversation.");
+        }
+        SlashCommand::Checkpoint(None) => {
+            let record = history::load(&session.conversation_uuid)?;
+            if record.checkpoints.is_empty() {
+                println!("No checkpoints yet; set one with /checkpoint <name>.");
+            }
+            for (name, turns) in &record.checkpoints {
+                println!("{:<20} after {} exchange(s)", name, exchange_count(&record.turns[..*turns]));
+            }
+        }
+        SlashCommand::Checkpoint(Some(name)) => {
+            let record = history::add_checkpoint(&session.conversation_uuid, &name)?;
+            println!("Checkpoint '{}' set after {} exchange(s).", name, exchange_count(&record.turns));
+        }
+        SlashCommand::Branch(name) => {
+            let from = history::load(&session.conversation_uuid)?;
+            if !from.checkpoints.contains_key(&name) {
+                return Err(anyhow!("No checkpoint named '{}'; /checkpoint lists them", name));
+            }
+            let title = format!("{} ({})", from.title, name);
+            let id = new_conversation(session, Some(&title)).await?;
+            let record = history::branch(&id, &from, &name)?;
+            continue_in(session, &record);
+            println!(
+                "Branched from '{}' after {} exchange(s); the original conversation is kept as {}.",
+                name,
+                exchange_count(&record.turns),
+                from.id
+            );
+        }
+        SlashCommand::Save(path) => {
+            let record = history::load(&session.conversation_uuid)?;
+            std::fs::write(&path, export::render_markdown(&record))?;
+            println!("Conversation saved to {}", path.display());
+        }
+        SlashCommand::System(Some(system)) => {
+            session.system = Some(system);
+            println!("System prompt set.");
+        }
+        SlashCommand::System(None) => {
+            session.system = None;
+            println!("System prompt cleared.");
+        }
+        SlashCommand::Clear if session.text_style == TextStyle::ScreenReader => {
+            println!("The screen is not cleared in screen reader mode.");
+        }
+        SlashCommand::Clear => {
+            print!("\x1B[2J\x1B[H");
+            io::stdout().flush()?;
+        }
+        SlashCommand::Edit => {
+            let initial = session.last_prompt.clone().unwrap_or_default();
+            match prompt_from_editor(&initial)? {
+                Some(prompt) => send_interactive(session, &prompt).await?,
+                None => println!("Nothing to send."),
+            }
+        }
+        SlashCommand::Retry(model) => match session.last_prompt.clone() {
+            Some(prompt) => {
+                if let Some(model) = &model {
+                    models::validate(model)?;
+                }
+                // Both responses stay in the conversation store, so they can be compared later.
+                let original_model = model.map(|model| std::mem::replace(&mut session.model, model));
+                session.regenerate = session.last_original_turn;
+                let result = send_interactive(session, &prompt).await;
+                session.regenerate = None;
+                if let Some(original_model) = original_model {
+                    session.model = original_model;
+                }
+                result?;
+            }
+            None => println!("Nothing to retry yet."),
+        },
+        SlashCommand::Copy { code: code_only } => match &session.last_response {
+            Some(response) if code_only => {
+                clipboard::copy(&code::strip_fences(response))?;
+                println!("Copied the code from the last response to the clipboard.");
+            }
+            Some(response) => {
+                clipboard::copy(response)?;
+                println!("Copied the last response to the clipboard.");
+            }
+            None => println!("No response to copy yet."),
+        },
+        SlashCommand::Code(lang) => match &session.last_response {
+            Some(response) => match code::extract_code(response, lang.as_deref()) {
+                Some(code) => print!("{}", code),
+                None => println!("The last response has no {}code block.", lang.map(|lang| format!("{} ", lang)).unwrap_or_default()),
+            },
+            None => println!("No response yet."),
+        },
+        SlashCommand::Compact => {
+            if !compact_conversation(session).await? {
+                println!("Nothing to compact yet.");
+            }
+        }
+        SlashCommand::Help => println!("{}", repl::HELP),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(prompt: &str, retry_of: Option<usize>) -> history::Turn {
+        history::Turn {
+            timestamp: Local::now(),
+            model: "gpt-4o".to_string(),
+            prompt: prompt.to_string(),
+            response: "An answer".to_string(),
+            credits: None,
+            retry_of,
+            interrupted: false,
+        }
+    }
+
+    #[test]
+    fn counts_exchanges_without_retries() {
+        let turns = [turn("one", None), turn("one", Some(0)), turn("two", None)];
+        assert_eq!(exchange_count(&turns), 2);
+    }
+
+    #[test]
+    fn saves_each_response_under_a_new_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let turn = turn("\nWhat is a monad?\nIn short.", None);
+        let first = save_response(dir.path(), &turn, "local-1", &[]).unwrap();
+        let second = save_response(dir.path(), &turn, "local-1", &[]).unwrap();
+
+        let date = turn.timestamp.format("%Y-%m-%d");
+        assert_eq!(first, dir.path().join(format!("{}-what-is-a-monad.md", date)));
+        assert_eq!(second, dir.path().join(format!("{}-what-is-a-monad-1.md", date)));
+        assert!(std::fs::read_to_string(&first).unwrap().contains("An answer"));
+    }
+}
@@ -0,0 +1,146 @@
+//! `ai-cli share`: publishing a conversation's Markdown transcript as a secret GitHub gist or on
+//! a paste service, for a link to hand to someone.
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use dialoguer::Confirm;
+use reqwest::{header, Client};
+use serde_json::{json, Value};
+use std::process::Command;
+
+use crate::session::exchange_count;
+use crate::ui::ensure_interactive;
+use crate::{config, export, history, media, redact, voice};
+
+const GIST_API_URL: &str = "https://api.github.com/gists";
+/// Environment variables a GitHub token is read from, in order
+const TOKEN_ENVS: &[&str] = &["GITHUB_TOKEN", "GH_TOKEN"];
+
+/// Where a conversation is shared.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+#[value(rename_all = "lowercase")]
+pub enum Target {
+    /// A secret GitHub gist, using GITHUB_TOKEN, GH_TOKEN or the GitHub CLI's login
+    Gist,
+    /// The paste service at paste_url in the config file
+    Paste,
+}
+
+/// A GitHub token from the environment, or from the GitHub CLI when it is logged in.
+pub fn github_token() -> Option<String> {
+    let from_env = TOKEN_ENVS.iter().find_map(|name| std::env::var(name).ok());
+    let token = from_env.or_else(|| {
+        if !voice::is_installed("gh") {
+            return None;
+        }
+        let output = Command::new("gh").args(["auth", "token"]).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    })?;
+    Some(token.trim().to_string()).filter(|token| !token.is_empty())
+}
+
+/// Uploads `content` as a secret gist with a single file and returns the gist's URL.
+pub async fn gist(http: &Client, token: &str, filename: &str, description: &str, content: &str) -> Result<String> {
+    let body = json!({
+        "description": description,
+        "public": false,
+        "files": { filename: { "content": content } },
+    });
+    let response = http.post(GIST_API_URL)
+        .bearer_auth(token)
+        .header(header::ACCEPT, "application/vnd.github+json")
+        .header(header::USER_AGENT, concat!("ai-cli/", env!("CARGO_PKG_VERSION")))
+        .json(&body)
+        .send()
+        .await
+        .context("Could not reach GitHub")?;
+    let status = response.status();
+    let reply: Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        let message = reply.get("message").and_then(Value::as_str).unwrap_or("no details");
+        return Err(anyhow!("GitHub refused to create the gist: {} - {}", status, message));
+    }
+    reply.get("html_url")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("GitHub's answer has no gist URL"))
+}
+
+/// Posts `content` as the body of a request to a paste service and returns the paste's URL: the
+/// answer itself (as paste.rs, dpaste and similar services send it), or its `url` or `link` when
+/// the answer is JSON.
+pub async fn paste(http: &Client, url: &str, content: &str) -> Result<String> {
+    let response = http.post(url)
+        .header(header::CONTENT_TYPE, "text/markdown; charset=utf-8")
+        .body(content.to_string())
+        .send()
+        .await
+        .with_context(|| format!("Could not reach {}", url))?;
+    let status = response.status();
+    let reply = response.text().await?;
+    if !status.is_success() {
+        return Err(anyhow!("{} refused the paste: {} - {}", url, status, reply.trim()));
+    }
+    let link = match serde_json::from_str::<Value>(&reply) {
+        Ok(json) => ["url", "link", "html_url"].iter()
+            .find_map(|key| json.get(key).and_then(Value::as_str).map(str::to_string))
+            .ok_or_else(|| anyhow!("The answer of {} has no URL: {}", url, reply.trim()))?,
+        Err(_) => reply.trim().to_string(),
+    };
+    if !link.starts_with("http") {
+        return Err(anyhow!("The answer of {} is not a URL: {}", url, link));
+    }
+    Ok(link)
+}
+
+/// Uploads a conversation's Markdown transcript once confirmed and prints the link to it.
+pub async fn publish(http: &Client, id: &str, to: Option<Target>, redact: bool, yes: bool) -> Result<()> {
+    let config = config::Config::load()?;
+    let record = history::load(id)?;
+    let token = github_token();
+    let target = match (to, &token, &config.paste_url) {
+        (Some(target), _, _) => target,
+        (None, Some(_), _) => Target::Gist,
+        (None, None, Some(_)) => Target::Paste,
+        (None, None, None) => return Err(anyhow!(
+            "Nowhere to share to: set GITHUB_TOKEN (or log in with gh) for a gist, or set paste_url in the config file"
+        )),
+    };
+
+    let mut markdown = export::render_markdown(&record);
+    if redact {
+        markdown = redact::strip_paths(&redact::mask_secrets(&markdown));
+    } else if !redact::find_secrets(&markdown).is_empty() {
+        eprintln!("Warning: the conversation seems to contain secrets; pass --redact to strip them.");
+    }
+
+    let destination = match target {
+        Target::Gist => "a secret GitHub gist".to_string(),
+        Target::Paste => config.paste_url.clone().unwrap_or_default(),
+    };
+    let confirmed = yes || {
+        ensure_interactive("Sharing a conversation", "pass --yes to upload it")?;
+        Confirm::new()
+            .with_prompt(format!("Upload '{}' ({} exchange(s)) to {}? Anyone with the link can read it", record.title, exchange_count(&record.turns), destination))
+            .default(false)
+            .interact()?
+    };
+    if !confirmed {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    let url = match target {
+        Target::Gist => {
+            let token = token.ok_or_else(|| anyhow!("Sharing as a gist needs GITHUB_TOKEN, GH_TOKEN or a login with gh"))?;
+            let filename = format!("{}.md", media::slugify(&record.title));
+            gist(http, &token, &filename, &record.title, &markdown).await?
+        }
+        Target::Paste => {
+            let url = config.paste_url.as_deref().ok_or_else(|| anyhow!("Set paste_url in the config file to share to a paste service"))?;
+            paste(http, url, &markdown).await?
+        }
+    };
+    println!("{}", url);
+    Ok(())
+}
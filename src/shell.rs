@@ -0,0 +1,46 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+/// The user's shell, from `$SHELL`, falling back to `sh`.
+pub fn detect_shell() -> String {
+    std::env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| "sh".to_string())
+}
+
+fn shell_name(shell: &str) -> &str {
+    Path::new(shell).file_name().and_then(|name| name.to_str()).unwrap_or(shell)
+}
+
+/// Builds the prompt asking for a single command for this OS and shell.
+pub fn command_prompt(task: &str, shell: &str) -> String {
+    format!(
+        "Give me a single {} command for {} that does the following: {}\n\
+         Respond with only the command, on one line if possible, with no explanation and no code fences. \
+         Prefer widely available tools and avoid anything destructive unless the task asks for it.",
+        shell_name(shell),
+        std::env::consts::OS,
+        task
+    )
+}
+
+/// Extracts the command from the model's answer, dropping code fences and a leading `$ ` prompt.
+pub fn clean_command(response: &str) -> String {
+    let lines: Vec<&str> = response.lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect();
+    let command = lines.join("\n");
+    let command = command.trim();
+    command.strip_prefix("$ ").unwrap_or(command).to_string()
+}
+
+/// Runs the command in the given shell with the terminal attached.
+pub fn run(command: &str, shell: &str) -> Result<ExitStatus> {
+    Command::new(shell)
+        .arg("-c")
+        .arg(command)
+        .status()
+        .with_context(|| format!("Could not run {}", shell))
+}
@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Result};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+use crate::client::{OneMinClient, MODELS_PATH};
+use crate::config::Config;
+
+/// The outcome of probing one endpoint.
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+    pub latency: Option<Duration>,
+}
+
+/// An unresolved incident published on the provider's status page.
+pub struct Incident {
+    pub name: String,
+    pub status: String,
+    pub impact: String,
+}
+
+/// The subset of a Statuspage `/api/v2/summary.json` response we report on.
+#[derive(Deserialize)]
+struct StatusPageSummary {
+    #[serde(default)]
+    incidents: Vec<StatusPageIncident>,
+}
+
+#[derive(Deserialize)]
+struct StatusPageIncident {
+    name: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    impact: String,
+}
+
+fn describe_failure(error: &reqwest::Error) -> String {
+    if error.is_timeout() {
+        "unreachable (timed out)".to_string()
+    } else if error.is_connect() {
+        "unreachable (connection failed)".to_string()
+    } else {
+        format!("unreachable ({})", error)
+    }
+}
+
+/// Probes a URL; any HTTP response counts as reachable, except server errors.
+async fn probe(http: &Client, name: &'static str, url: &str) -> Check {
+    let started = Instant::now();
+    match http.get(url).send().await {
+        Ok(response) => Check {
+            name,
+            ok: !response.status().is_server_error(),
+            detail: format!("reachable ({})", response.status()),
+            latency: Some(started.elapsed()),
+        },
+        Err(e) => Check { name, ok: false, detail: describe_failure(&e), latency: None },
+    }
+}
+
+/// Makes a lightweight authenticated request to confirm the API key is accepted.
+async fn probe_authenticated(client: &OneMinClient, api_key: &str) -> Check {
+    let name = "API (authenticated)";
+    let started = Instant::now();
+    let request = client.http()
+        .get(format!("{}{}", client.base_url(), MODELS_PATH))
+        .header("API-KEY", api_key);
+
+    match request.send().await {
+        Ok(response) => {
+            let status = response.status();
+            let detail = match status {
+                s if s.is_success() => format!("OK ({})", s),
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => format!("API key rejected ({})", status),
+                s => format!("error ({})", s),
+            };
+            Check { name, ok: status.is_success(), detail, latency: Some(started.elapsed()) }
+        }
+        Err(e) => Check { name, ok: false, detail: describe_failure(&e), latency: None },
+    }
+}
+
+/// Runs every check. The authenticated check is skipped when no API key is configured.
+pub async fn run_checks(client: &OneMinClient, api_key: Option<&str>) -> Vec<Check> {
+    let mut checks = vec![probe(client.http(), "API", client.base_url()).await];
+    checks.push(match api_key {
+        Some(api_key) => probe_authenticated(client, api_key).await,
+        None => Check {
+            name: "API (authenticated)",
+            ok: true,
+            detail: "skipped (no API key configured)".to_string(),
+            latency: None,
+        },
+    });
+    checks.push(probe(client.http(), "Asset storage", client.asset_base_url()).await);
+    checks
+}
+
+/// Fetches unresolved incidents from a Statuspage-compatible status page.
+pub async fn incidents(http: &Client, status_page_url: &str) -> Result<Vec<Incident>> {
+    let url = format!("{}/api/v2/summary.json", status_page_url.trim_end_matches('/'));
+    let summary: StatusPageSummary = http.get(url).send().await?.error_for_status()?.json().await?;
+
+    Ok(summary.incidents.into_iter()
+        .map(|incident| Incident { name: incident.name, status: incident.status, impact: incident.impact })
+        .collect())
+}
+
+pub fn print_table(checks: &[Check]) {
+    let name_width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0).max("ENDPOINT".len());
+    let detail_width = checks.iter().map(|c| c.detail.len()).max().unwrap_or(0).max("STATUS".len());
+
+    println!("{:<name_width$}  {:<detail_width$}  LATENCY", "ENDPOINT", "STATUS");
+    for check in checks {
+        let latency = check.latency
+            .map(|l| format!("{} ms", l.as_millis()))
+            .unwrap_or_else(|| "-".to_string());
+        println!("{:<name_width$}  {:<detail_width$}  {}", check.name, check.detail, latency);
+    }
+}
+
+/// Reports endpoint health and latency, plus any open incidents on the configured status page.
+pub async fn report(client: &OneMinClient, api_key: Option<&str>) -> Result<()> {
+    let checks = run_checks(client, api_key).await;
+    print_table(&checks);
+
+    if let Some(status_page_url) = Config::load()?.status_page_url {
+        println!();
+        match incidents(client.http(), &status_page_url).await {
+            Ok(incidents) if incidents.is_empty() => println!("No open incidents on {}", status_page_url),
+            Ok(incidents) => {
+                println!("Open incidents on {}:", status_page_url);
+                for incident in incidents {
+                    println!("  - {} ({}, impact: {})", incident.name, incident.status, incident.impact);
+                }
+            }
+            Err(e) => println!("Could not read the status page: {:#}", e),
+        }
+    }
+
+    let failed = checks.iter().filter(|c| !c.ok).count();
+    if failed > 0 {
+        return Err(anyhow!("{} of {} checks failed", failed, checks.len()));
+    }
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use serde_json::Value;
+
+/// How the response body is framed, detected from its first bytes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Framing {
+    /// Plain text, passed through as is
+    Raw,
+    /// Server-sent events: `data:` lines, each payload either plain text or a JSON delta
+    Sse,
+    /// One JSON delta object per line
+    JsonLines,
+}
+
+/// Turns the raw bytes of a streamed response into clean text tokens.
+///
+/// Bytes are buffered so multi-byte UTF-8 characters split across chunks are decoded intact,
+/// and SSE or JSON-delta framing is stripped so only the generated text reaches the printer.
+#[derive(Default)]
+pub struct StreamDecoder {
+    /// Undecoded bytes, at most the start of one incomplete UTF-8 character
+    pending_bytes: Vec<u8>,
+    /// Decoded text not yet consumed by the framing parser
+    text: String,
+    framing: Option<Framing>,
+    /// Data lines of the SSE event currently being received
+    event_data: Vec<String>,
+}
+
+/// Extracts the text from a JSON delta in any of the common streaming shapes.
+fn delta_text(json: &Value) -> Option<String> {
+    let candidates = [
+        json.pointer("/choices/0/delta/content"),
+        json.pointer("/choices/0/text"),
+        json.pointer("/delta/text"),
+        json.pointer("/delta/content"),
+        json.get("content"),
+        json.get("text"),
+        json.get("token"),
+    ];
+    candidates.into_iter()
+        .flatten()
+        .find_map(|value| value.as_str())
+        .map(|text| text.to_string())
+}
+
+/// Whether a line looks like a JSON delta rather than the start of a JSON answer from the model.
+fn is_json_delta(line: &str) -> bool {
+    serde_json::from_str::<Value>(line)
+        .map(|json| json.get("choices").is_some() || json.get("delta").is_some())
+        .unwrap_or(false)
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a chunk of the response body, returning any text that is now complete.
+    pub fn push(&mut self, chunk: &[u8]) -> String {
+        self.decode_utf8(chunk);
+        self.drain(false)
+    }
+
+    /// Flushes whatever is still buffered once the response has ended.
+    pub fn finish(&mut self) -> String {
+        if !self.pending_bytes.is_empty() {
+            self.text.push_str(&String::from_utf8_lossy(&self.pending_bytes));
+            self.pending_bytes.clear();
+        }
+        self.drain(true)
+    }
+
+    fn decode_utf8(&mut self, chunk: &[u8]) {
+        self.pending_bytes.extend_from_slice(chunk);
+        let mut rest = self.pending_bytes.as_slice();
+
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    self.text.push_str(text);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    self.text.push_str(std::str::from_utf8(valid).expect("validated prefix"));
+                    match e.error_len() {
+                        // Invalid bytes: substitute them and keep going
+                        Some(len) => {
+                            self.text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        // The chunk ends partway through a character: wait for the rest
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.pending_bytes = rest.to_vec();
+    }
+
+    fn detect_framing(&mut self, finished: bool) -> Option<Framing> {
+        if let Some(framing) = self.framing {
+            return Some(framing);
+        }
+
+        let start = self.text.trim_start();
+        if start.is_empty() {
+            return finished.then_some(Framing::Raw);
+        }
+
+        let framing = if start.starts_with("data:") || start.starts_with("event:") || start.starts_with(": ") {
+            Framing::Sse
+        } else if start.starts_with('{') {
+            // Wait for a whole line before telling a JSON delta from a JSON answer.
+            match start.split_once('\n') {
+                Some((line, _)) if is_json_delta(line.trim()) => Framing::JsonLines,
+                Some(_) => Framing::Raw,
+                None if finished && is_json_delta(start.trim()) => Framing::JsonLines,
+                None if finished => Framing::Raw,
+                None => return None,
+            }
+        } else {
+            Framing::Raw
+        };
+
+        self.framing = Some(framing);
+        Some(framing)
+    }
+
+    /// Takes complete lines off the decoded text (and the trailing partial line when finished).
+    fn take_lines(&mut self, finished: bool) -> Vec<String> {
+        let end = match self.text.rfind('\n') {
+            Some(index) => index + 1,
+            None if finished => self.text.len(),
+            None => return Vec::new(),
+        };
+
+        let complete: String = self.text.drain(..end).collect();
+        complete.lines().map(|line| line.trim_end_matches('\r').to_string()).collect()
+    }
+
+    fn drain(&mut self, finished: bool) -> String {
+        match self.detect_framing(finished) {
+            None => String::new(),
+            Some(Framing::Raw) => std::mem::take(&mut self.text),
+            Some(Framing::JsonLines) => self.take_lines(finished)
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+                .filter_map(|json| delta_text(&json))
+                .collect(),
+            Some(Framing::Sse) => {
+                let mut out = String::new();
+                for line in self.take_lines(finished) {
+                    if line.is_empty() {
+                        out.push_str(&self.dispatch_event());
+                    } else if let Some(data) = line.strip_prefix("data:") {
+                        self.event_data.push(data.strip_prefix(' ').unwrap_or(data).to_string());
+                    }
+                    // Other fields (event:, id:, retry:) and comments carry no text.
+                }
+                if finished {
+                    out.push_str(&self.dispatch_event());
+                }
+                out
+            }
+        }
+    }
+
+    /// Turns the data of one complete SSE event into text.
+    fn dispatch_event(&mut self) -> String {
+        if self.event_data.is_empty() {
+            return String::new();
+        }
+        let data = self.event_data.join("\n");
+        self.event_data.clear();
+
+        if data.trim() == "[DONE]" {
+            return String::new();
+        }
+        match serde_json::from_str::<Value>(&data) {
+            Ok(json) if json.is_object() => delta_text(&json).unwrap_or_default(),
+            _ => data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes a response delivered in `chunks`, as the client does.
+    fn decode(chunks: &[&[u8]]) -> String {
+        let mut decoder = StreamDecoder::new();
+        let mut text: String = chunks.iter().map(|chunk| decoder.push(chunk)).collect();
+        text.push_str(&decoder.finish());
+        text
+    }
+
+    #[test]
+    fn passes_plain_text_through() {
+        assert_eq!(decode(&[b"Hello, ", b"world"]), "Hello, world");
+    }
+
+    #[test]
+    fn joins_characters_split_across_chunks() {
+        let mut decoder = StreamDecoder::new();
+        assert_eq!(decoder.push(b"caf\xc3"), "caf");
+        assert_eq!(decoder.push(b"\xa9 \xe2\x82"), "é ");
+        assert_eq!(decoder.push(b"\xac"), "€");
+        assert_eq!(decoder.finish(), "");
+    }
+
+    #[test]
+    fn replaces_invalid_bytes() {
+        assert_eq!(decode(&[b"a\xffb"]), "a\u{fffd}b");
+        // A character still incomplete when the response ends
+        assert_eq!(decode(&[b"a\xc3"]), "a\u{fffd}");
+    }
+
+    #[test]
+    fn extracts_sse_json_deltas() {
+        let body: &[&[u8]] = &[
+            b"data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\nda",
+            b"ta: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n",
+            b"data: [DONE]\n\n",
+        ];
+        assert_eq!(decode(body), "Hello");
+    }
+
+    #[test]
+    fn joins_multiline_sse_data_and_skips_other_fields() {
+        let body: &[&[u8]] = &[b": keep-alive\r\nevent: message\r\nid: 1\r\ndata: first\r\ndata: second\r\n\r\ndata: last"];
+        assert_eq!(decode(body), "first\nsecondlast");
+    }
+
+    #[test]
+    fn splits_multibyte_characters_inside_sse_events() {
+        let event = "data: {\"choices\":[{\"delta\":{\"content\":\"größer\"}}]}\n\n".as_bytes();
+        let (first, second) = event.split_at(event.windows(2).position(|pair| pair == "ö".as_bytes()).unwrap() + 1);
+        assert_eq!(decode(&[first, second]), "größer");
+    }
+
+    #[test]
+    fn extracts_json_lines_deltas() {
+        let body: &[&[u8]] = &[b"{\"delta\":{\"text\":\"a\"}}\n{\"delta\":", b"{\"text\":\"b\"}}\n\n{\"delta\":{\"text\":\"c\"}}"];
+        assert_eq!(decode(body), "abc");
+    }
+
+    #[test]
+    fn keeps_json_answers_as_text() {
+        let answer = "{\"name\": \"ai-cli\"}\n";
+        assert_eq!(decode(&[answer.as_bytes()]), answer);
+        assert_eq!(decode(&[b"{\"name\":", b" \"ai-cli\"}"]), "{\"name\": \"ai-cli\"}");
+    }
+}
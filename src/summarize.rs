@@ -0,0 +1,51 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use std::io::Read;
+
+use crate::web;
+
+/// Instruction for condensing one part of a text too long to summarize in one request.
+pub const PART_INSTRUCTION: &str = "Summarize the following part of a longer text. Keep every key point, \
+    decision, figure and name, since this summary will be combined with those of the other parts. \
+    Respond with only the summary.";
+
+/// Text to summarize, with a label for where it came from.
+pub struct Source {
+    pub label: String,
+    pub text: String,
+}
+
+/// Reads a file, `-` for stdin, or an `http(s)://` URL (reduced to its readable text).
+pub async fn read_source(http: &Client, source: &str) -> Result<Source> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let page = web::fetch(http, source).await?;
+        let label = match page.title {
+            Some(title) => format!("{} ({})", title, source),
+            None => source.to_string(),
+        };
+        return Ok(Source { label, text: page.text });
+    }
+
+    let text = if source == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        let bytes = std::fs::read(source).with_context(|| format!("Could not read {}", source))?;
+        String::from_utf8(bytes).map_err(|_| anyhow!("{} is not a UTF-8 text file", source))?
+    };
+    Ok(Source { label: source.to_string(), text })
+}
+
+/// Instruction for the final summary. `length` describes how long it should be.
+pub fn final_instruction(label: &str, length: &str, bullets: bool) -> String {
+    let form = if bullets {
+        "as a bulleted list of the key points"
+    } else {
+        "in prose"
+    };
+    format!(
+        "Summarize the following text from {} {}, {}. Respond with only the summary.",
+        label, form, length
+    )
+}
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use crate::export;
+use crate::history::ConversationRecord;
+
+/// An action item extracted from a conversation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Task {
+    pub task: String,
+    /// Who the conversation suggests should do it, if anyone
+    #[serde(default)]
+    pub assignee: Option<String>,
+    /// Due date as YYYY-MM-DD, if one can be inferred
+    #[serde(default)]
+    pub due: Option<String>,
+}
+
+/// Builds the prompt asking the model to extract action items from a transcript as JSON.
+pub fn extraction_prompt(record: &ConversationRecord) -> String {
+    format!(
+        "Extract every action item from the conversation transcript below.\n\
+         Respond with only a JSON array, no prose and no code fences. Each element must be an object with:\n\
+         - \"task\": a short imperative description\n\
+         - \"assignee\": the person who should do it if the transcript suggests one, otherwise null\n\
+         - \"due\": the due date as YYYY-MM-DD if one is stated or implied (the conversation took place on {}), otherwise null\n\
+         If there are no action items, respond with [].\n\n\
+         Transcript:\n\n{}",
+        record.updated_at.format("%Y-%m-%d"),
+        export::render_markdown(record)
+    )
+}
+
+/// Parses the model's answer, tolerating code fences or text around the JSON array.
+pub fn parse_tasks(response: &str) -> Result<Vec<Task>> {
+    let start = response.find('[');
+    let end = response.rfind(']');
+    let json = match (start, end) {
+        (Some(start), Some(end)) if start < end => &response[start..=end],
+        _ => return Err(anyhow!("The model did not return a list of tasks:\n{}", response.trim())),
+    };
+
+    serde_json::from_str(json).map_err(|e| anyhow!("Could not parse the extracted tasks: {}", e))
+}
+
+/// Renders tasks as a Markdown checklist, e.g. `- [ ] Send the draft (@sam, due 2024-05-01)`.
+pub fn render_markdown(tasks: &[Task]) -> String {
+    tasks.iter()
+        .map(|task| {
+            let details: Vec<String> = [
+                task.assignee.as_ref().map(|a| format!("@{}", a.trim_start_matches('@'))),
+                task.due.as_ref().map(|d| format!("due {}", d)),
+            ].into_iter().flatten().collect();
+
+            if details.is_empty() {
+                format!("- [ ] {}\n", task.task)
+            } else {
+                format!("- [ ] {} ({})\n", task.task, details.join(", "))
+            }
+        })
+        .collect()
+}
+
+/// Appends a Markdown checklist to a TODO file, creating it if needed.
+pub fn append_to_file(path: &Path, markdown: &str) -> Result<()> {
+    let needs_newline = std::fs::read_to_string(path)
+        .map(|existing| !existing.is_empty() && !existing.ends_with('\n'))
+        .unwrap_or(false);
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if needs_newline {
+        writeln!(file)?;
+    }
+    file.write_all(markdown.as_bytes())?;
+    Ok(())
+}
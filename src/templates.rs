@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config;
+
+const TEMPLATES_DIR: &str = "templates";
+const PERSONAS_DIR: &str = "personas";
+const TEMPLATE_EXTENSION: &str = "toml";
+
+/// Skeleton written by `templates new`.
+const NEW_TEMPLATE: &str = r#"description = "What this template does"
+
+# Use {{name}} placeholders; {{input}} is filled from the prompt argument or piped stdin.
+prompt = """
+{{input}}
+"""
+
+# Values used when a placeholder isn't given on the command line.
+[defaults]
+"#;
+
+/// A reusable prompt stored as `templates/<name>.toml`, with `{{name}}` placeholders.
+#[derive(Deserialize)]
+pub struct PromptTemplate {
+    #[serde(default)]
+    pub description: String,
+    pub prompt: String,
+    #[serde(default)]
+    pub defaults: BTreeMap<String, String>,
+}
+
+pub fn templates_dir() -> Result<PathBuf> {
+    Ok(config::config_dir()?.join(TEMPLATES_DIR))
+}
+
+/// Looks up a persona stored as `templates/personas/<name>.md` (or `.txt`) in the prompt library.
+pub fn load_persona(name: &str) -> Option<String> {
+    let dir = templates_dir().ok()?.join(PERSONAS_DIR);
+    ["md", "txt"].iter()
+        .find_map(|ext| fs::read_to_string(dir.join(format!("{}.{}", name, ext))).ok())
+        .map(|text| text.trim().to_string())
+}
+
+fn run_git(args: &[&str], dir: Option<&Path>) -> Result<()> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let status = command.args(args)
+        .status()
+        .map_err(|e| anyhow!("Could not run git: {}", e))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("git {} failed with {}", args.join(" "), status))
+    }
+}
+
+/// Clones the shared prompt library into the templates directory, or fast-forwards an existing checkout.
+pub fn sync(repo: Option<&str>) -> Result<()> {
+    let dir = templates_dir()?;
+
+    if dir.join(".git").exists() {
+        println!("Updating prompt library in {}...", dir.display());
+        return run_git(&["pull", "--ff-only"], Some(&dir));
+    }
+
+    let repo = repo.ok_or_else(|| anyhow!(
+        "No prompt library repository configured. Set templates_repo in the config file or pass --repo <url>."
+    ))?;
+
+    if dir.exists() && fs::read_dir(&dir)?.next().is_some() {
+        return Err(anyhow!(
+            "{} already exists and is not a git checkout; move it aside before syncing",
+            dir.display()
+        ));
+    }
+
+    println!("Cloning prompt library from {} into {}...", repo, dir.display());
+    let target = dir.to_string_lossy();
+    run_git(&["clone", repo, &target], None)
+}
+
+fn template_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(anyhow!("Invalid template name '{}'", name));
+    }
+    Ok(templates_dir()?.join(format!("{}.{}", name, TEMPLATE_EXTENSION)))
+}
+
+pub fn load_template(name: &str) -> Result<PromptTemplate> {
+    let path = template_path(name)?;
+    let contents = fs::read_to_string(&path)
+        .map_err(|_| anyhow!("No template named '{}' (looked for {})", name, path.display()))?;
+    toml::from_str(&contents)
+        .map_err(|e| anyhow!("Invalid template {}: {}", path.display(), e))
+}
+
+/// All templates in the library, sorted by name. Files that don't parse are skipped.
+pub fn list_templates() -> Result<Vec<(String, PromptTemplate)>> {
+    let dir = templates_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut templates = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(TEMPLATE_EXTENSION) {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+        if let Ok(template) = load_template(name) {
+            templates.push((name.to_string(), template));
+        }
+    }
+    templates.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(templates)
+}
+
+impl PromptTemplate {
+    /// Placeholder names in order of first appearance.
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        let mut rest = self.prompt.as_str();
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else { break };
+            let name = rest[start + 2..start + end].trim().to_string();
+            if !name.is_empty() && !names.contains(&name) {
+                names.push(name);
+            }
+            rest = &rest[start + end + 2..];
+        }
+        names
+    }
+
+    /// Fills in the placeholders, falling back to the template's defaults. Fails listing every
+    /// placeholder that has no value.
+    pub fn render(&self, vars: &BTreeMap<String, String>) -> Result<String> {
+        let missing: Vec<String> = self.placeholders().into_iter()
+            .filter(|name| !vars.contains_key(name) && !self.defaults.contains_key(name))
+            .map(|name| format!("--{}", name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!("Missing template values: {}", missing.join(", ")));
+        }
+
+        let mut prompt = String::with_capacity(self.prompt.len());
+        let mut rest = self.prompt.as_str();
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else { break };
+            let name = rest[start + 2..start + end].trim();
+            prompt.push_str(&rest[..start]);
+            match vars.get(name).or_else(|| self.defaults.get(name)) {
+                Some(value) => prompt.push_str(value),
+                None => prompt.push_str(&rest[start..start + end + 2]),
+            }
+            rest = &rest[start + end + 2..];
+        }
+        prompt.push_str(rest);
+        Ok(prompt.trim().to_string())
+    }
+}
+
+/// Parses template values given as `--name=value` or `--name value`.
+pub fn parse_vars(args: &[String]) -> Result<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flag = arg.strip_prefix("--")
+            .ok_or_else(|| anyhow!("Unexpected argument '{}': pass template values as --name=value", arg))?;
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, value.to_string()),
+            None => (flag, args.next().cloned().ok_or_else(|| anyhow!("No value given for --{}", flag))?),
+        };
+        vars.insert(name.to_string(), value);
+    }
+    Ok(vars)
+}
+
+/// Writes a skeleton template and returns its path. Fails if the template already exists.
+pub fn create_template(name: &str) -> Result<PathBuf> {
+    let path = template_path(name)?;
+    if path.exists() {
+        return Err(anyhow!("Template '{}' already exists; use `templates edit {}`", name, name));
+    }
+    fs::create_dir_all(templates_dir()?)?;
+    fs::write(&path, NEW_TEMPLATE)?;
+    Ok(path)
+}
+
+/// Opens a template in `$VISUAL` or `$EDITOR` (falling back to `vi`).
+pub fn edit_template(name: &str) -> Result<()> {
+    let path = template_path(name)?;
+    if !path.exists() {
+        return Err(anyhow!("No template named '{}'; create it with `templates new {}`", name, name));
+    }
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program)
+        .args(parts)
+        .arg(&path)
+        .status()
+        .map_err(|e| anyhow!("Could not start editor '{}': {}", editor, e))?;
+
+    if !status.success() {
+        return Err(anyhow!("Editor exited with {}", status));
+    }
+    load_template(name).map(|_| ())
+}
+
+/// Fills in a prompt template from `--name=value` arguments. `{{input}}` defaults to the prompt
+/// argument, or to piped stdin.
+pub fn render_template(name: &str, args: &[String], input: Option<&str>) -> Result<String> {
+    let template = load_template(name)?;
+    let mut vars = parse_vars(args)?;
+
+    if let Some(input) = input {
+        vars.entry("input".to_string()).or_insert_with(|| input.to_string());
+    }
+    let needs_input = template.placeholders().iter().any(|p| p == "input");
+    if needs_input && !vars.contains_key("input") && !io::stdin().is_terminal() {
+        let mut stdin = String::new();
+        io::Read::read_to_string(&mut io::stdin(), &mut stdin)?;
+        vars.insert("input".to_string(), stdin);
+    }
+    template.render(&vars)
+}
@@ -0,0 +1,335 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+
+use crate::{explain, web};
+
+/// Most tool calls answered for one prompt before giving up
+pub const MAX_STEPS: usize = 10;
+/// Tool output beyond this is cut off before it is sent to the model
+const MAX_RESULT_BYTES: usize = 20 * 1024;
+const CALL_FENCE: &str = "```tool_call";
+
+/// A tool the model can call with `--tools`.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Tool {
+    /// Run a shell command and return its output
+    Shell,
+    /// Return the contents of a text file
+    ReadFile,
+    /// Download a web page and return its readable text
+    WebFetch,
+    /// Evaluate an arithmetic expression
+    Calculator,
+}
+
+/// Whether a tool call runs without asking: `[tools]` in the config file.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+#[value(rename_all = "lowercase")]
+pub enum Policy {
+    Ask,
+    Allow,
+    Deny,
+}
+
+impl Tool {
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Shell => "shell",
+            Tool::ReadFile => "read_file",
+            Tool::WebFetch => "web_fetch",
+            Tool::Calculator => "calculator",
+        }
+    }
+
+    /// How the model is told to use the tool: its name, arguments and what it does.
+    fn description(self) -> String {
+        match self {
+            Tool::Shell => format!(
+                "shell {{\"command\": string}}: runs a command with the user's shell on {} and returns its output and exit status",
+                std::env::consts::OS
+            ),
+            Tool::ReadFile => "read_file {\"path\": string}: returns the contents of a text file".to_string(),
+            Tool::WebFetch => "web_fetch {\"url\": string}: downloads a web page and returns its readable text".to_string(),
+            Tool::Calculator => {
+                "calculator {\"expression\": string}: evaluates arithmetic with + - * / % ^ and parentheses".to_string()
+            }
+        }
+    }
+
+    /// Only the calculator is allowed by default. Reading files is asked about like running
+    /// commands and fetching pages, as any file could be asked for, keys and `.env` files included.
+    pub fn default_policy(self) -> Policy {
+        match self {
+            Tool::Calculator => Policy::Allow,
+            Tool::Shell | Tool::ReadFile | Tool::WebFetch => Policy::Ask,
+        }
+    }
+}
+
+/// A tool call the model asked for.
+#[derive(Deserialize, Debug)]
+pub struct ToolCall {
+    pub tool: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+impl ToolCall {
+    pub fn arg(&self, name: &str) -> Result<&str> {
+        self.args.get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("{} needs a string argument '{}'", self.tool, name))
+    }
+
+    /// A one-line summary of the call, for showing it to the user.
+    pub fn summary(&self) -> String {
+        match self.args.as_object().map(|args| args.values().collect::<Vec<_>>()) {
+            Some(values) if values.len() == 1 => match values[0] {
+                Value::String(value) => format!("{}: {}", self.tool, value),
+                value => format!("{}: {}", self.tool, value),
+            },
+            _ => format!("{}: {}", self.tool, self.args),
+        }
+    }
+}
+
+/// Instructions describing the tools, sent ahead of the prompt. `more` describes tools from
+/// elsewhere, such as MCP servers.
+pub fn instructions(tools: &[Tool], more: &[String]) -> String {
+    let descriptions: Vec<String> = tools.iter()
+        .map(|tool| tool.description())
+        .chain(more.iter().cloned())
+        .map(|description| format!("- {}", description))
+        .collect();
+    format!(
+        "You can use tools to help with the request below. To call one, reply with only a block like\n\n\
+         {}\n{{\"tool\": \"<name>\", \"args\": {{...}}}}\n```\n\n\
+         and nothing else; its result comes back in the next message. Call one tool at a time. \
+         Once you have what you need, answer normally, without a tool_call block.\n\n\
+         Available tools:\n{}\n\n---",
+        CALL_FENCE,
+        descriptions.join("\n")
+    )
+}
+
+/// The tool call in a response, if it has one. A block that isn't valid JSON is an error, so
+/// the model can be told and try again.
+pub fn parse_call(response: &str) -> Option<Result<ToolCall>> {
+    let start = response.find(CALL_FENCE)? + CALL_FENCE.len();
+    let body = &response[start..];
+    let body = body.find("```").map_or(body, |end| &body[..end]);
+    Some(serde_json::from_str(body.trim()).map_err(|e| anyhow!("The tool_call block is not valid JSON: {}", e)))
+}
+
+/// The message that sends a tool's result (or why it couldn't run) back to the model.
+pub fn result_prompt(tool: &str, result: &Result<String>) -> String {
+    match result {
+        Ok(output) => format!("Result of {}:\n```\n{}\n```", tool, truncate(output)),
+        Err(e) => format!("{} failed: {:#}", tool, e),
+    }
+}
+
+fn truncate(text: &str) -> &str {
+    if text.len() <= MAX_RESULT_BYTES {
+        return text;
+    }
+    let mut end = MAX_RESULT_BYTES;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Looks up the tool a call is for among the enabled ones.
+pub fn find(tools: &[Tool], call: &ToolCall) -> Result<Tool> {
+    tools.iter()
+        .copied()
+        .find(|tool| tool.name() == call.tool)
+        .ok_or_else(|| anyhow!("There is no tool named '{}'", call.tool))
+}
+
+/// Runs a tool call and returns its output.
+pub async fn execute(tool: Tool, call: &ToolCall, http: &Client) -> Result<String> {
+    match tool {
+        Tool::Shell => {
+            let run = explain::run_shell(call.arg("command")?)?;
+            let status = match run.code {
+                Some(code) => format!("exit status {}", code),
+                None => "killed by a signal".to_string(),
+            };
+            Ok(format!("{}\nstdout:\n{}\nstderr:\n{}", status, run.stdout.trim_end(), run.stderr.trim_end()))
+        }
+        Tool::ReadFile => {
+            let path = call.arg("path")?;
+            let bytes = fs::read(path).map_err(|e| anyhow!("Could not read {}: {}", path, e))?;
+            if bytes.contains(&0) {
+                return Err(anyhow!("{} looks like a binary file", path));
+            }
+            Ok(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        Tool::WebFetch => {
+            let page = web::fetch(http, call.arg("url")?).await?;
+            Ok(match page.title {
+                Some(title) => format!("{}\n\n{}", title, page.text),
+                None => page.text,
+            })
+        }
+        Tool::Calculator => {
+            let value = calculate(call.arg("expression")?)?;
+            Ok(if value.fract() == 0.0 && value.abs() < 1e15 { format!("{}", value as i64) } else { value.to_string() })
+        }
+    }
+}
+
+/// Evaluates an arithmetic expression: numbers, `+ - * / % ^`, unary minus and parentheses.
+pub fn calculate(expression: &str) -> Result<f64> {
+    let mut parser = Calculator { chars: expression.chars().filter(|c| !c.is_whitespace()).collect(), position: 0 };
+    let value = parser.sum()?;
+    match parser.peek() {
+        None if value.is_finite() => Ok(value),
+        None => Err(anyhow!("'{}' has no finite value", expression)),
+        Some(c) => Err(anyhow!("Unexpected '{}' in '{}'", c, expression)),
+    }
+}
+
+struct Calculator {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Calculator {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next_if(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn sum(&mut self) -> Result<f64> {
+        let mut value = self.product()?;
+        loop {
+            if self.next_if('+') {
+                value += self.product()?;
+            } else if self.next_if('-') {
+                value -= self.product()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<f64> {
+        let mut value = self.power()?;
+        loop {
+            if self.next_if('*') {
+                value *= self.power()?;
+            } else if self.next_if('/') {
+                value /= self.power()?;
+            } else if self.next_if('%') {
+                value %= self.power()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// `^` binds tighter than unary minus on its left and is right-associative: -2^2 is -4.
+    fn power(&mut self) -> Result<f64> {
+        if self.next_if('-') {
+            return Ok(-self.power()?);
+        }
+        let base = self.atom()?;
+        if self.next_if('^') {
+            return Ok(base.powf(self.power()?));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<f64> {
+        if self.next_if('(') {
+            let value = self.sum()?;
+            if !self.next_if(')') {
+                return Err(anyhow!("Missing ')'"));
+            }
+            return Ok(value);
+        }
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.position += 1;
+        }
+        let number: String = self.chars[start..self.position].iter().collect();
+        match self.peek() {
+            _ if !number.is_empty() => number.parse().map_err(|_| anyhow!("Invalid number '{}'", number)),
+            Some(c) => Err(anyhow!("Unexpected '{}'", c)),
+            None => Err(anyhow!("The expression ends too early")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> f64 {
+        calculate(expression).unwrap()
+    }
+
+    #[test]
+    fn follows_operator_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("8 / 2 / 2"), 2.0);
+        assert_eq!(eval("2 * 3 ^ 2"), 18.0);
+    }
+
+    #[test]
+    fn binds_powers_tighter_than_unary_minus() {
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("(-2)^2"), 4.0);
+        assert_eq!(eval("2^-1"), 0.5);
+        assert_eq!(eval("2^3^2"), 512.0);
+        assert_eq!(eval("--3"), 3.0);
+    }
+
+    #[test]
+    fn takes_remainders_like_multiplication() {
+        assert_eq!(eval("7 % 3"), 1.0);
+        assert_eq!(eval("-7 % 3"), -1.0);
+        assert_eq!(eval("1 + 7 % 3 * 2"), 3.0);
+        assert_eq!(eval("5.5 % 2"), 1.5);
+    }
+
+    #[test]
+    fn evaluates_parentheses_first() {
+        assert_eq!(eval("2 * (3 + 4)"), 14.0);
+        assert_eq!(eval("((1 + 1) * (2 + 2)) ^ 2"), 64.0);
+        assert_eq!(eval("-(2 + 3) * 2"), -10.0);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for expression in ["(1 + 2", "1 +", "2 * x", "1.2.3", "1 / 0", "3)", ""] {
+            assert!(calculate(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn asks_before_anything_but_arithmetic() {
+        assert_eq!(Tool::Calculator.default_policy(), Policy::Allow);
+        for tool in [Tool::Shell, Tool::ReadFile, Tool::WebFetch] {
+            assert_eq!(tool.default_policy(), Policy::Ask);
+        }
+    }
+}
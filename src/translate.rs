@@ -0,0 +1,135 @@
+use anyhow::Result;
+use std::future::Future;
+
+use crate::{chunk, cost};
+
+/// Line the model is asked to start its answer with, naming the language it detected.
+const SOURCE_LANGUAGE_PREFIX: &str = "Source language:";
+
+/// Fenced code blocks, swapped for placeholders before translating so they come back untouched.
+pub struct ProtectedCode {
+    blocks: Vec<String>,
+}
+
+fn placeholder(index: usize) -> String {
+    format!("[[CODE_BLOCK_{}]]", index + 1)
+}
+
+/// Replaces each fenced code block with a placeholder line.
+pub fn protect_code(text: &str) -> (String, ProtectedCode) {
+    let mut out = String::with_capacity(text.len());
+    let mut blocks = Vec::new();
+    let mut block: Option<(String, String)> = None;
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        match &mut block {
+            Some((fence, contents)) => {
+                contents.push_str(line);
+                if trimmed.trim_end() == fence.as_str() {
+                    let ends_with_newline = contents.ends_with('\n');
+                    blocks.push(std::mem::take(contents));
+                    out.push_str(&placeholder(blocks.len() - 1));
+                    if ends_with_newline {
+                        out.push('\n');
+                    }
+                    block = None;
+                }
+            }
+            None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => {
+                let marker = trimmed.chars().next().unwrap_or('`');
+                let fence: String = trimmed.chars().take_while(|&c| c == marker).collect();
+                block = Some((fence, line.to_string()));
+            }
+            None => out.push_str(line),
+        }
+    }
+    // An unclosed fence runs to the end of the text.
+    if let Some((_, contents)) = block {
+        blocks.push(contents);
+        out.push_str(&placeholder(blocks.len() - 1));
+    }
+    (out, ProtectedCode { blocks })
+}
+
+impl ProtectedCode {
+    /// Puts the code blocks back in place of their placeholders.
+    pub fn restore(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let placeholder = placeholder(i);
+            let block = block.strip_suffix('\n').unwrap_or(block);
+            text = text.replace(&placeholder, block);
+        }
+        text
+    }
+}
+
+/// Builds the prompt asking for a translation that keeps the text's formatting.
+pub fn translate_prompt(text: &str, to: &str, from: Option<&str>) -> String {
+    let source = match from {
+        Some(from) => format!("from {} ", from),
+        None => String::new(),
+    };
+    format!(
+        "Translate the text below {}into {}. Preserve the formatting exactly: Markdown, line breaks, \
+         indentation, lists and tables. Leave inline code, URLs, file paths and placeholders like \
+         [[CODE_BLOCK_1]] unchanged. Start your answer with a line `{} <language name>` naming the \
+         language the text is written in, then a blank line, then only the translation.\n\n{}",
+        source, to, SOURCE_LANGUAGE_PREFIX, text
+    )
+}
+
+/// Splits the model's answer into the detected source language and the translation.
+pub fn parse_response(response: &str) -> (Option<String>, String) {
+    let trimmed = response.trim_start();
+    let Some(rest) = trimmed.strip_prefix(SOURCE_LANGUAGE_PREFIX) else {
+        return (None, response.trim().to_string());
+    };
+    let (language, translation) = rest.split_once('\n').unwrap_or((rest, ""));
+    let language = language.trim().trim_matches('`').trim().to_string();
+    let translation = translation.trim_start_matches(['\r', '\n']).trim_end().to_string();
+    ((!language.is_empty()).then_some(language), translation)
+}
+
+/// Translates `text` part by part, with its code blocks kept out of the model's hands. Returns
+/// the source language the model detected, if it named one, and the translation.
+///
+/// `ask` sends a prompt and returns the response, so callers choose the model and handle auth.
+/// `on_part` is called with (part, total) before each request of a text sent in several parts.
+pub async fn translate<F, Fut>(
+    text: &str,
+    to: &str,
+    from: Option<&str>,
+    chunk_tokens: usize,
+    mut ask: F,
+    mut on_part: impl FnMut(usize, usize),
+) -> Result<(Option<String>, String)>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let (protected, code_blocks) = protect_code(text);
+    let parts = if cost::estimate_tokens(&protected) <= chunk_tokens {
+        vec![protected]
+    } else {
+        chunk::split(&protected, chunk_tokens)
+    };
+
+    let mut detected = None;
+    let mut translated = Vec::with_capacity(parts.len());
+    for (i, part) in parts.iter().enumerate() {
+        if parts.len() > 1 {
+            on_part(i + 1, parts.len());
+        }
+        let (language, translation) = parse_response(&ask(translate_prompt(part, to, from)).await?);
+        detected = detected.or(language);
+        translated.push(translation);
+    }
+
+    let mut translation = code_blocks.restore(&translated.join("\n\n"));
+    if text.ends_with('\n') {
+        translation.push('\n');
+    }
+    Ok((detected, translation))
+}
@@ -0,0 +1,555 @@
+//! The state of the TUI, and what keys and streamed responses do to it.
+
+use ansi_to_tui::IntoText;
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use ratatui::backend::Backend;
+use ratatui::style::Stylize;
+use ratatui::text::Line;
+use ratatui::widgets::Clear;
+use ratatui::DefaultTerminal;
+
+use super::input::{Edit, Input};
+use super::{ui, Options};
+use crate::client::ChatStream;
+use crate::error::AiCliError;
+use crate::history::{self, ConversationRecord, Turn};
+use crate::providers::{ChatRequest, Message};
+use crate::render::MarkdownRenderer;
+use crate::{compact, models, redact, sampling, usage};
+
+/// Longest title a new conversation gets from its first prompt, in characters
+const TITLE_CHARS: usize = 60;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    Conversations,
+    Input,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// A message for the status bar, shown until the next one.
+pub struct Status {
+    pub text: String,
+    pub severity: Severity,
+}
+
+impl Status {
+    fn new(severity: Severity, text: impl Into<String>) -> Option<Status> {
+        Some(Status { text: text.into(), severity })
+    }
+}
+
+/// The response streaming in.
+pub struct Reply {
+    pub prompt: String,
+    /// What was sent: the prompt with the system prompt and any replayed context, after redaction
+    sent: String,
+    pub response: String,
+    renderer: MarkdownRenderer,
+    /// The response's complete lines, rendered
+    pub rendered: String,
+}
+
+/// The model picker, shown over the chat.
+pub struct Picker {
+    models: Vec<String>,
+    /// Typed text narrowing the list down, or a model name the list doesn't have
+    pub filter: String,
+    pub selected: usize,
+}
+
+impl Picker {
+    pub fn matches(&self) -> Vec<&str> {
+        let filter = self.filter.to_lowercase();
+        self.models.iter()
+            .filter(|model| model.to_lowercase().contains(&filter))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+enum Action {
+    None,
+    Send,
+    Cancel,
+    PickModel,
+    Redraw,
+    Quit,
+}
+
+pub struct App<'a> {
+    options: Options<'a>,
+    pub model: String,
+    /// Stored conversations, most recently active first
+    pub conversations: Vec<ConversationRecord>,
+    /// Entry selected in the conversation list: 0 is a new conversation, then `conversations`
+    pub selected: usize,
+    /// The conversation in the chat pane; none for a new one until its first prompt is sent
+    pub open: Option<ConversationRecord>,
+    /// The open conversation's turns, rendered
+    pub transcript: Vec<Line<'static>>,
+    pub reply: Option<Reply>,
+    pub input: Input,
+    pub focus: Focus,
+    /// How many lines the chat pane is scrolled up from the end
+    pub scroll: usize,
+    /// Height of the chat pane when it was last drawn, for paging through it
+    pub chat_height: usize,
+    pub picker: Option<Picker>,
+    pub status: Option<Status>,
+    last_prompt: Option<String>,
+}
+
+impl<'a> App<'a> {
+    pub fn new(options: Options<'a>) -> Result<App<'a>> {
+        let model = options.model.clone();
+        let mut app = App {
+            options,
+            model,
+            conversations: Vec::new(),
+            selected: 0,
+            open: None,
+            transcript: Vec::new(),
+            reply: None,
+            input: Input::default(),
+            focus: Focus::Input,
+            scroll: 0,
+            chat_height: 0,
+            picker: None,
+            status: None,
+            last_prompt: None,
+        };
+        app.reload(None)?;
+        Ok(app)
+    }
+
+    /// Runs until the user quits, drawing after every key press and streamed chunk.
+    pub async fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let mut events = EventStream::new();
+        let mut request: Option<BoxFuture<'a, Result<ChatStream, AiCliError>>> = None;
+        let mut stream: Option<ChatStream> = None;
+        loop {
+            terminal.draw(|frame| ui::draw(frame, self))?;
+            tokio::select! {
+                event = events.next() => {
+                    let Some(event) = event else { return Ok(()) };
+                    match self.handle(event?) {
+                        Action::None => {}
+                        Action::Send => match self.send().await {
+                            Ok(sending) => request = Some(sending),
+                            Err(e) => self.fail(e),
+                        },
+                        Action::Cancel => {
+                            request = None;
+                            stream = None;
+                            self.finish(true);
+                        }
+                        Action::PickModel => {
+                            self.status = Status::new(Severity::Info, "Loading models…");
+                            terminal.draw(|frame| ui::draw(frame, self))?;
+                            self.open_picker().await;
+                        }
+                        Action::Redraw => repaint(terminal)?,
+                        Action::Quit => return Ok(()),
+                    }
+                }
+                result = async { request.as_mut().expect("a pending request").await }, if request.is_some() => {
+                    request = None;
+                    match result {
+                        Ok(response) => stream = Some(response),
+                        Err(e) => self.fail(e.into()),
+                    }
+                    // Retries and logging may have written over the screen meanwhile.
+                    repaint(terminal)?;
+                }
+                chunk = async { stream.as_mut().expect("a response stream").next().await }, if stream.is_some() => {
+                    match chunk {
+                        Some(Ok(text)) => self.receive(&text),
+                        Some(Err(e)) => {
+                            stream = None;
+                            self.fail(e.into());
+                        }
+                        None => {
+                            stream = None;
+                            self.finish(false);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event) -> Action {
+        match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => self.key(key),
+            Event::Paste(text) if self.picker.is_none() => {
+                self.focus = Focus::Input;
+                self.input.insert_str(&text);
+                Action::None
+            }
+            _ => Action::None,
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Action {
+        if self.picker.is_some() {
+            self.picker_key(key);
+            return Action::None;
+        }
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let page = self.chat_height.saturating_sub(2).max(1);
+        match key.code {
+            KeyCode::Char('c') if control && self.reply.is_some() => return Action::Cancel,
+            KeyCode::Char('c') | KeyCode::Char('q') if control => return Action::Quit,
+            KeyCode::Esc if self.reply.is_some() => return Action::Cancel,
+            KeyCode::Char('l') if control => return Action::Redraw,
+            KeyCode::Char('o') if control => return Action::PickModel,
+            KeyCode::Char('n') if control => self.new_conversation(),
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Conversations => Focus::Input,
+                    Focus::Input => Focus::Conversations,
+                };
+            }
+            KeyCode::PageUp => self.scroll += page,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(page),
+            _ => match self.focus {
+                Focus::Conversations => self.list_key(key),
+                Focus::Input => return self.input_key(key),
+            },
+        }
+        Action::None
+    }
+
+    fn list_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(self.conversations.len()),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = self.conversations.len(),
+            KeyCode::Enter => self.open_selected(),
+            KeyCode::Esc => self.focus = Focus::Input,
+            _ => {}
+        }
+    }
+
+    fn input_key(&mut self, key: KeyEvent) -> Action {
+        match self.input.handle(key) {
+            Edit::Submit if self.reply.is_some() => {
+                self.status = Status::new(Severity::Warning, "Wait for the response, or press Esc to stop it");
+            }
+            Edit::Submit if !self.input.is_blank() => return Action::Send,
+            // Up in an empty input brings the last prompt back, to change and send again.
+            Edit::Unhandled if key.code == KeyCode::Up && self.input.is_blank() => {
+                if let Some(prompt) = &self.last_prompt {
+                    self.input.set_text(prompt);
+                }
+            }
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn picker_key(&mut self, key: KeyEvent) {
+        let Some(picker) = self.picker.as_mut() else { return };
+        let matches = picker.matches().len();
+        match key.code {
+            KeyCode::Esc => self.picker = None,
+            KeyCode::Up => picker.selected = picker.selected.saturating_sub(1),
+            KeyCode::Down => picker.selected = (picker.selected + 1).min(matches.saturating_sub(1)),
+            KeyCode::Backspace => {
+                picker.filter.pop();
+                picker.selected = 0;
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                picker.filter.push(c);
+                picker.selected = 0;
+            }
+            KeyCode::Enter => {
+                // A name the list doesn't have is taken as it is, as with --model.
+                let model = match picker.matches().get(picker.selected) {
+                    Some(model) => model.to_string(),
+                    None => picker.filter.trim().to_string(),
+                };
+                if model.is_empty() {
+                    return;
+                }
+                self.status = match models::validate(&model) {
+                    Ok(()) => {
+                        self.picker = None;
+                        let status = Status::new(Severity::Info, format!("Using {}", model));
+                        self.model = model;
+                        status
+                    }
+                    Err(e) => Status::new(Severity::Error, format!("{:#}", e)),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Opens the model picker on the chat models of the cached list, fetching the list first
+    /// when there is none.
+    async fn open_picker(&mut self) {
+        let cached = models::load_cache().filter(|models| !models.is_empty());
+        let listed = match cached {
+            Some(models) => Ok(models),
+            None => models::fetch_models(self.options.provider).await,
+        };
+        let mut models: Vec<String> = match listed {
+            Ok(models) => {
+                self.status = None;
+                models.into_iter().filter(|model| model.kind == "chat").map(|model| model.id).collect()
+            }
+            Err(e) => {
+                self.status = Status::new(Severity::Warning, format!("Could not list models ({:#}); type a model name", e));
+                Vec::new()
+            }
+        };
+        if !models.contains(&self.model) {
+            models.insert(0, self.model.clone());
+        }
+        let selected = models.iter().position(|model| *model == self.model).unwrap_or(0);
+        self.picker = Some(Picker { models, filter: String::new(), selected });
+    }
+
+    /// Reloads the conversation list, selecting `id` in it.
+    fn reload(&mut self, id: Option<&str>) -> Result<()> {
+        // Document chats need their document, which only `ai-cli doc chat` sends along.
+        self.conversations = history::list()?.into_iter().filter(|record| record.document.is_none()).collect();
+        self.selected = id
+            .and_then(|id| self.conversations.iter().position(|record| record.id == id))
+            .map_or(0, |index| index + 1);
+        Ok(())
+    }
+
+    fn busy(&mut self) -> bool {
+        if self.reply.is_some() {
+            self.status = Status::new(Severity::Warning, "Wait for the response, or press Esc to stop it");
+        }
+        self.reply.is_some()
+    }
+
+    fn new_conversation(&mut self) {
+        if self.busy() {
+            return;
+        }
+        self.open = None;
+        self.transcript.clear();
+        self.selected = 0;
+        self.scroll = 0;
+        self.focus = Focus::Input;
+    }
+
+    fn open_selected(&mut self) {
+        if self.selected == 0 {
+            return self.new_conversation();
+        }
+        if self.busy() {
+            return;
+        }
+        let record = self.conversations[self.selected - 1].clone();
+        if !record.model.is_empty() {
+            self.model = record.model.clone();
+        }
+        self.transcript = record.summary.iter()
+            .map(|summary| Line::from(format!("Summary of the earlier conversation: {}", summary).dim()))
+            .chain(record.turns.iter().flat_map(turn_lines))
+            .collect();
+        self.open = Some(record);
+        self.scroll = 0;
+        self.focus = Focus::Input;
+        self.status = None;
+    }
+
+    /// Sends the input as a prompt in the open conversation, starting one if needed, and returns
+    /// the request for the response.
+    async fn send(&mut self) -> Result<BoxFuture<'a, Result<ChatStream, AiCliError>>> {
+        models::validate(&self.model)?;
+        let sampling = sampling::resolve(&self.model, self.options.sampling, self.options.default_sampling)?;
+        let prompt = self.input.text().trim().to_string();
+        let provider = self.options.provider;
+        let hosted = provider.hosts_conversations();
+
+        if self.open.is_none() {
+            let title = title(&prompt);
+            let id = match self.options.client.filter(|_| hosted) {
+                Some(client) => client.create_conversation(&title).await?,
+                None => format!("{}{}", history::LOCAL_ID_PREFIX, Local::now().format("%Y%m%d%H%M%S%3f")),
+            };
+            history::start_conversation(&id, &title)?;
+            self.open = Some(history::load(&id)?);
+            self.reload(Some(&id))?;
+        }
+        let record = self.open.as_ref().ok_or_else(|| anyhow!("No open conversation"))?;
+
+        // A conversation started with a provider that doesn't keep conversations is replayed to
+        // one that does; the others get its messages as history with every request.
+        let remote = hosted && !record.id.starts_with(history::LOCAL_ID_PREFIX);
+        let mut full_prompt = match &record.system {
+            Some(system) => format!("{}\n\n{}", system, prompt),
+            None => prompt.clone(),
+        };
+        if hosted && !remote && (!record.turns.is_empty() || record.summary.is_some()) {
+            full_prompt = format!(
+                "We are continuing an earlier conversation, repeated here for context:\n\n{}\n\n---\n\n{}",
+                compact::transcript(record.summary.as_deref(), &record.turns),
+                full_prompt
+            );
+        }
+        let history = if hosted { Vec::new() } else { messages(record) };
+        let (sent, notice) = redact::apply(&full_prompt, self.options.redaction)?;
+        let sent = sent.into_owned();
+        self.status = notice.and_then(|notice| Status::new(Severity::Warning, notice));
+
+        let model = self.model.clone();
+        let (max_words, max_tokens) = (self.options.max_words, self.options.max_tokens);
+        let conversation_id = remote.then(|| record.id.clone());
+        self.reply = Some(Reply {
+            prompt: prompt.clone(),
+            sent: sent.clone(),
+            response: String::new(),
+            renderer: MarkdownRenderer::new(),
+            rendered: String::new(),
+        });
+        self.last_prompt = Some(prompt);
+        self.input.clear();
+        self.scroll = 0;
+        Ok(Box::pin(async move {
+            provider.chat_stream(ChatRequest {
+                model: &model,
+                prompt: &sent,
+                max_words,
+                max_tokens,
+                sampling,
+                conversation_id: conversation_id.as_deref(),
+                history: &history,
+            }).await
+        }))
+    }
+
+    fn receive(&mut self, text: &str) {
+        if let Some(reply) = self.reply.as_mut() {
+            reply.response.push_str(text);
+            let rendered = reply.renderer.push(text);
+            reply.rendered.push_str(&rendered);
+        }
+    }
+
+    /// Records the finished (or, when `interrupted`, stopped) response in history.
+    fn finish(&mut self, interrupted: bool) {
+        let Some(reply) = self.reply.take() else { return };
+        if interrupted && reply.response.is_empty() {
+            self.restore_prompt(reply.prompt);
+            self.status = Status::new(Severity::Info, "Stopped");
+            return;
+        }
+        if let Err(e) = self.record(reply, interrupted) {
+            self.status = Status::new(Severity::Error, format!("Could not save the response: {:#}", e));
+        } else if interrupted {
+            self.status = Status::new(Severity::Info, "Stopped; the partial response is kept in history");
+        }
+    }
+
+    fn record(&mut self, reply: Reply, interrupted: bool) -> Result<()> {
+        let record = self.open.as_ref().ok_or_else(|| anyhow!("No open conversation"))?;
+        let usage = usage::UsageEntry::new(&self.model, &reply.sent, &reply.response, &self.options.model_rates);
+        usage::record(&usage)?;
+        let turn = Turn {
+            timestamp: Local::now(),
+            model: self.model.clone(),
+            prompt: reply.prompt,
+            response: reply.response,
+            credits: Some(usage.credits),
+            retry_of: None,
+            interrupted,
+        };
+        self.transcript.extend(turn_lines(&turn));
+        let id = record.id.clone();
+        history::record_turn(&id, &turn, record.system.as_deref())?;
+        self.open = Some(history::load(&id)?);
+        self.reload(Some(&id))
+    }
+
+    /// Drops the response after an error, putting its prompt back to try again.
+    fn fail(&mut self, error: anyhow::Error) {
+        if let Some(reply) = self.reply.take() {
+            self.restore_prompt(reply.prompt);
+        }
+        self.status = Status::new(Severity::Error, format!("{:#}", error));
+    }
+
+    fn restore_prompt(&mut self, prompt: String) {
+        if self.input.is_blank() {
+            self.input.set_text(&prompt);
+        }
+    }
+}
+
+/// Repaints the whole screen on the next draw. `Terminal::clear` would ask the terminal for the
+/// cursor position, and the event stream would take the answer.
+fn repaint(terminal: &mut DefaultTerminal) -> Result<()> {
+    terminal.draw(|frame| frame.render_widget(Clear, frame.area()))?;
+    terminal.backend_mut().clear()?;
+    Ok(())
+}
+
+/// A conversation's title, from the first line of its first prompt.
+fn title(prompt: &str) -> String {
+    let line = prompt.lines().next().unwrap_or_default().trim();
+    match line.char_indices().nth(TITLE_CHARS) {
+        Some((end, _)) => format!("{}…", line[..end].trim_end()),
+        None => line.to_string(),
+    }
+}
+
+/// The conversation so far, for providers that don't keep conversations.
+fn messages(record: &ConversationRecord) -> Vec<Message> {
+    let summary = record.summary.iter()
+        .map(|summary| Message::new("system", format!("Summary of the earlier conversation: {}", summary)));
+    summary
+        .chain(record.turns.iter()
+            .filter(|turn| turn.retry_of.is_none())
+            .flat_map(|turn| [Message::new("user", &turn.prompt), Message::new("assistant", &turn.response)]))
+        .collect()
+}
+
+/// The heading and text of a prompt, followed by the heading of its response.
+pub fn prompt_lines(prompt: &str, model: &str) -> Vec<Line<'static>> {
+    let mut lines = vec![Line::from("You".bold().cyan())];
+    lines.extend(prompt.lines().map(|line| Line::from(line.to_string())));
+    lines.push(Line::default());
+    lines.push(Line::from(format!("AI({})", model).bold().green()));
+    lines
+}
+
+/// Lines of Markdown rendered by [`MarkdownRenderer`].
+pub fn rendered_lines(rendered: &str) -> Vec<Line<'static>> {
+    match rendered.into_text() {
+        Ok(text) => text.lines,
+        Err(_) => rendered.lines().map(|line| Line::from(line.to_string())).collect(),
+    }
+}
+
+fn turn_lines(turn: &Turn) -> Vec<Line<'static>> {
+    let mut renderer = MarkdownRenderer::new();
+    let mut rendered = renderer.push(&turn.response);
+    rendered.push_str(&renderer.finish());
+    let mut lines = prompt_lines(&turn.prompt, &turn.model);
+    lines.extend(rendered_lines(&rendered));
+    if turn.interrupted {
+        lines.push(Line::from("[interrupted]".dim()));
+    }
+    lines.push(Line::default());
+    lines
+}
@@ -0,0 +1,149 @@
+//! The multi-line input box the next prompt is written in.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Text being edited, as lines of characters, with a cursor.
+#[derive(Default)]
+pub struct Input {
+    lines: Vec<Vec<char>>,
+    row: usize,
+    column: usize,
+}
+
+/// What a key did to the input.
+pub enum Edit {
+    /// The key was used (or ignored) by the input
+    Handled,
+    /// Enter was pressed to send the text
+    Submit,
+    /// The key means nothing to the input
+    Unhandled,
+}
+
+impl Input {
+    pub fn lines(&self) -> impl Iterator<Item = String> + '_ {
+        self.lines.iter().map(|line| line.iter().collect())
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len().max(1)
+    }
+
+    /// The cursor position, as line and character index.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    pub fn text(&self) -> String {
+        self.lines().collect::<Vec<_>>().join("\n")
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.lines.iter().all(|line| line.iter().all(|c| c.is_whitespace()))
+    }
+
+    pub fn clear(&mut self) {
+        *self = Input::default();
+    }
+
+    /// Replaces the text, leaving the cursor at its end.
+    pub fn set_text(&mut self, text: &str) {
+        self.clear();
+        self.insert_str(text);
+    }
+
+    /// Inserts text at the cursor, e.g. a paste; line breaks start new lines.
+    pub fn insert_str(&mut self, text: &str) {
+        for c in text.chars().filter(|c| *c != '\r') {
+            if c == '\n' {
+                self.newline();
+            } else {
+                self.insert(c);
+            }
+        }
+    }
+
+    fn current(&mut self) -> &mut Vec<char> {
+        if self.lines.is_empty() {
+            self.lines.push(Vec::new());
+        }
+        &mut self.lines[self.row]
+    }
+
+    fn insert(&mut self, c: char) {
+        let column = self.column;
+        self.current().insert(column, c);
+        self.column += 1;
+    }
+
+    fn newline(&mut self) {
+        let column = self.column;
+        let rest = self.current().split_off(column);
+        self.lines.insert(self.row + 1, rest);
+        self.row += 1;
+        self.column = 0;
+    }
+
+    fn backspace(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+            let column = self.column;
+            self.current().remove(column);
+        } else if self.row > 0 {
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.column = self.lines[self.row].len();
+            self.lines[self.row].extend(line);
+        }
+    }
+
+    fn delete(&mut self) {
+        let column = self.column;
+        if column < self.current().len() {
+            self.current().remove(column);
+        } else if self.row + 1 < self.lines.len() {
+            let line = self.lines.remove(self.row + 1);
+            self.lines[self.row].extend(line);
+        }
+    }
+
+    /// Moves the cursor up or down a line, keeping its column where the line is long enough.
+    fn move_row(&mut self, down: bool) -> bool {
+        let row = if down { self.row + 1 } else { self.row.wrapping_sub(1) };
+        match self.lines.get(row) {
+            Some(line) => {
+                self.column = self.column.min(line.len());
+                self.row = row;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies a key press. Enter submits; Alt+Enter, Shift+Enter or Ctrl+J starts a new line.
+    pub fn handle(&mut self, key: KeyEvent) -> Edit {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter if key.modifiers.intersects(KeyModifiers::ALT | KeyModifiers::SHIFT) => self.newline(),
+            KeyCode::Char('j') if control => self.newline(),
+            KeyCode::Enter => return Edit::Submit,
+            KeyCode::Char('u') if control => self.clear(),
+            KeyCode::Char('a') if control => self.column = 0,
+            KeyCode::Char('e') if control => self.column = self.current().len(),
+            KeyCode::Char(c) if !control => self.insert(c),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left if self.column > 0 => self.column -= 1,
+            KeyCode::Left if self.move_row(false) => self.column = self.lines[self.row].len(),
+            KeyCode::Right if self.column < self.current().len() => self.column += 1,
+            KeyCode::Right if self.move_row(true) => self.column = 0,
+            KeyCode::Left | KeyCode::Right => {}
+            KeyCode::Up if self.move_row(false) => {}
+            KeyCode::Down if self.move_row(true) => {}
+            KeyCode::Home => self.column = 0,
+            KeyCode::End => self.column = self.current().len(),
+            _ => return Edit::Unhandled,
+        }
+        Edit::Handled
+    }
+}
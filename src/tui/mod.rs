@@ -0,0 +1,48 @@
+//! `ai-cli tui`: a full-screen chat with the stored conversations in a pane beside it.
+//!
+//! The conversation list comes from local history and every exchange is recorded there, so
+//! conversations started here can be continued with `--resume` and the other way round.
+//! Responses stream into the chat pane, rendered by the same Markdown renderer as the CLI.
+
+mod app;
+mod input;
+mod ui;
+
+use anyhow::Result;
+use crossterm::event::{DisableBracketedPaste, EnableBracketedPaste};
+use crossterm::execute;
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::client::OneMinClient;
+use crate::providers::Provider;
+use crate::redact::Redaction;
+use crate::sampling::Sampling;
+
+/// What the TUI chats with and how.
+pub struct Options<'a> {
+    pub provider: &'a dyn Provider,
+    /// Creates conversations on the API, for a provider that keeps them (1min.ai)
+    pub client: Option<&'a OneMinClient>,
+    /// Model of new conversations, until another is picked
+    pub model: String,
+    pub max_words: u32,
+    pub max_tokens: Option<u32>,
+    /// Sampling parameters given on the command line, which the model must accept
+    pub sampling: Sampling,
+    /// Those from the config file, sent where the model accepts them
+    pub default_sampling: Sampling,
+    pub model_rates: BTreeMap<String, f64>,
+    pub redaction: Redaction,
+}
+
+/// Takes over the terminal until the user quits.
+pub async fn run(options: Options<'_>) -> Result<()> {
+    let mut app = app::App::new(options)?;
+    let mut terminal = ratatui::init();
+    execute!(io::stdout(), EnableBracketedPaste)?;
+    let result = app.run(&mut terminal).await;
+    let _ = execute!(io::stdout(), DisableBracketedPaste);
+    ratatui::restore();
+    result
+}
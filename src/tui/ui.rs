@@ -0,0 +1,156 @@
+//! Drawing the TUI: the conversation list, the chat, the input box, the status bar and the model
+//! picker.
+
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Clear, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+
+use super::app::{prompt_lines, rendered_lines, App, Focus, Severity};
+
+/// Widest the conversation list gets; on narrow terminals it takes a third of the width
+const LIST_WIDTH: u16 = 32;
+/// Lines the input box grows to before it scrolls
+const INPUT_LINES: u16 = 8;
+const PICKER_WIDTH: u16 = 50;
+const HELP: &str = "Enter send · Alt+Enter new line · Tab conversations · Ctrl+N new · Ctrl+O model · PgUp/PgDn scroll · Ctrl+C quit";
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [main, status] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let list_width = LIST_WIDTH.min(main.width / 3);
+    let [list, right] = Layout::horizontal([Constraint::Length(list_width), Constraint::Min(0)]).areas(main);
+    let input_height = (app.input.line_count() as u16).min(INPUT_LINES) + 2;
+    let [chat, input] = Layout::vertical([Constraint::Min(0), Constraint::Length(input_height)]).areas(right);
+
+    draw_conversations(frame, app, list);
+    draw_chat(frame, app, chat);
+    draw_input(frame, app, input);
+    draw_status(frame, app, status);
+    draw_picker(frame, app, chat);
+}
+
+fn border(focused: bool) -> Style {
+    if focused {
+        Style::new().cyan()
+    } else {
+        Style::new().dark_gray()
+    }
+}
+
+fn draw_conversations(frame: &mut Frame, app: &App, area: Rect) {
+    let focused = app.focus == Focus::Conversations && app.picker.is_none();
+    let items: Vec<ListItem> = std::iter::once(ListItem::new("+ New conversation".italic()))
+        .chain(app.conversations.iter().map(|record| {
+            ListItem::new(if record.title.is_empty() { record.id.clone() } else { record.title.clone() })
+        }))
+        .collect();
+    let list = List::new(items)
+        .block(Block::bordered().title(" Conversations ").border_style(border(focused)))
+        .highlight_style(if focused { Style::new().reversed() } else { Style::new().bold() });
+    let mut state = ListState::default().with_selected(Some(app.selected));
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn draw_chat(frame: &mut Frame, app: &mut App, area: Rect) {
+    let mut lines = app.transcript.clone();
+    if let Some(reply) = &app.reply {
+        lines.extend(prompt_lines(&reply.prompt, &app.model));
+        if !reply.rendered.is_empty() {
+            lines.extend(rendered_lines(&reply.rendered));
+        }
+        // The line still streaming in is shown as it is until it is complete and rendered.
+        let partial = reply.response.rsplit('\n').next().unwrap_or_default();
+        lines.push(if reply.response.is_empty() {
+            Line::from("waiting for model…".dim())
+        } else {
+            Line::from(format!("{}▍", partial))
+        });
+    }
+    if lines.is_empty() {
+        lines.push(Line::from("Type a message below and press Enter to send it. Tab moves to the conversation list.".dim()));
+    }
+
+    let title = match &app.open {
+        Some(record) => format!(" {} ", record.title),
+        None => " New conversation ".to_string(),
+    };
+    let mut block = Block::bordered().title(title).border_style(border(false));
+    let paragraph = Paragraph::new(lines).wrap(Wrap { trim: false });
+    let height = area.height.saturating_sub(2) as usize;
+    let end = paragraph.line_count(area.width.saturating_sub(2)).saturating_sub(height);
+    app.chat_height = height;
+    app.scroll = app.scroll.min(end);
+    if app.scroll > 0 {
+        block = block.title_bottom(format!(" {} more lines below (PgDn) ", app.scroll));
+    }
+    let top = (end - app.scroll).min(u16::MAX as usize) as u16;
+    frame.render_widget(paragraph.block(block).scroll((top, 0)), area);
+}
+
+fn draw_input(frame: &mut Frame, app: &App, area: Rect) {
+    let focused = app.focus == Focus::Input && app.picker.is_none();
+    let title = if app.reply.is_some() { " Waiting for the response (Esc stops it) " } else { " Message " };
+    let block = Block::bordered().title(title).border_style(border(focused));
+    let inner = block.inner(area);
+
+    // Long lines scroll sideways rather than wrap, so the cursor is easy to place.
+    let lines: Vec<String> = app.input.lines().collect();
+    let (row, column) = app.input.cursor();
+    let x = lines.get(row).map_or(0, |line| console::measure_text_width(&line.chars().take(column).collect::<String>()));
+    let scroll_y = (row + 1).saturating_sub(inner.height as usize);
+    let scroll_x = (x + 1).saturating_sub(inner.width as usize);
+    let text: Vec<Line> = lines.into_iter().map(Line::from).collect();
+    frame.render_widget(Paragraph::new(text).block(block).scroll((scroll_y as u16, scroll_x as u16)), area);
+    if focused {
+        frame.set_cursor_position(Position::new(inner.x + (x - scroll_x) as u16, inner.y + (row - scroll_y) as u16));
+    }
+}
+
+fn draw_status(frame: &mut Frame, app: &App, area: Rect) {
+    let (text, style) = match &app.status {
+        Some(status) => (status.text.replace('\n', " "), match status.severity {
+            Severity::Info => Style::new(),
+            Severity::Warning => Style::new().yellow(),
+            Severity::Error => Style::new().red(),
+        }),
+        None => (HELP.to_string(), Style::new().dim()),
+    };
+    let line = Line::from(vec![
+        Span::styled(format!(" {} ", app.model), Style::new().reversed()),
+        Span::raw(" "),
+        Span::styled(text, style),
+    ]);
+    frame.render_widget(Paragraph::new(line), area);
+}
+
+fn draw_picker(frame: &mut Frame, app: &App, area: Rect) {
+    let Some(picker) = &app.picker else { return };
+    let matches = picker.matches();
+    let width = PICKER_WIDTH.min(area.width);
+    // Borders and the filter line around the list
+    let height = (matches.len().max(1) as u16).saturating_add(3).min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    frame.render_widget(Clear, popup);
+    let block = Block::bordered().title(" Model (Enter picks, Esc closes) ").border_style(border(true));
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let [filter, list] = Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+    let prompt = "Filter: ";
+    frame.render_widget(Paragraph::new(Line::from(vec![prompt.dim(), Span::raw(picker.filter.as_str())])), filter);
+    let items: Vec<ListItem> = if matches.is_empty() {
+        vec![ListItem::new(format!("Enter uses \"{}\"", picker.filter).dim())]
+    } else {
+        matches.iter().map(|model| ListItem::new(model.to_string())).collect()
+    };
+    let mut state = ListState::default().with_selected((!matches.is_empty()).then_some(picker.selected));
+    frame.render_stateful_widget(List::new(items).highlight_style(Style::new().reversed()), list, &mut state);
+    let x = (prompt.len() + console::measure_text_width(&picker.filter)) as u16;
+    frame.set_cursor_position(Position::new((filter.x + x).min(filter.right().saturating_sub(1)), filter.y));
+}
@@ -0,0 +1,149 @@
+//! What ai-cli shows on and asks at the terminal: spinners and progress bars, and the
+//! confirmations that `--non-interactive` turns into errors.
+
+use anyhow::{anyhow, Result};
+use dialoguer::Confirm;
+use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::error::AiCliError;
+use crate::{cost, tools, usage};
+
+const SPINNER_TICK_MS: u64 = 80;
+
+/// Set when prompts must not be shown, e.g. under cron or in a pipeline where nobody can answer them.
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Set with `--screen-reader`, which turns off spinners and progress bars along with the other
+/// terminal effects.
+static SCREEN_READER: AtomicBool = AtomicBool::new(false);
+
+/// Sets how the terminal is used for the rest of the process.
+pub fn configure(non_interactive: bool, screen_reader: bool) {
+    NON_INTERACTIVE.store(non_interactive, Ordering::Relaxed);
+    SCREEN_READER.store(screen_reader, Ordering::Relaxed);
+}
+
+pub fn non_interactive() -> bool {
+    NON_INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// Whether spinners and progress bars are drawn on stderr: only on a terminal, and never for
+/// screen readers, which would read out every redraw.
+pub fn show_progress() -> bool {
+    !SCREEN_READER.load(Ordering::Relaxed) && io::stderr().is_terminal()
+}
+
+/// Fails instead of prompting when running non-interactively. `needed` says what the prompt was
+/// for, `fix` how to provide the answer up front.
+pub fn ensure_interactive(needed: &str, fix: &str) -> Result<()> {
+    if non_interactive() {
+        return Err(AiCliError::InputRequired(format!(
+            "{} needs input, but ai-cli is running non-interactively: {}",
+            needed, fix
+        )).into());
+    }
+    Ok(())
+}
+
+pub fn waiting_spinner() -> ProgressBar {
+    let spinner = ProgressBar::new_spinner()
+        .with_style(ProgressStyle::default_spinner().template("{spinner} {msg}").expect("valid spinner template"))
+        .with_message("waiting for model…")
+        .with_finish(ProgressFinish::AndClear);
+    spinner.enable_steady_tick(Duration::from_millis(SPINNER_TICK_MS));
+    spinner
+}
+
+/// Prints time to first token, total time and approximate throughput to stderr.
+pub fn print_stats(first_chunk_at: Option<Duration>, total: Duration, response: &str) {
+    let tokens = cost::estimate_tokens(response);
+    let first_token = first_chunk_at.unwrap_or(total);
+    // Throughput is measured over the streaming itself, not the wait for the first token.
+    let streaming = total.saturating_sub(first_token);
+    let seconds = if streaming.is_zero() { total } else { streaming }.as_secs_f64();
+    let rate = if seconds > 0.0 { tokens as f64 / seconds } else { 0.0 };
+
+    eprintln!(
+        "[first token {:.2}s, total {:.2}s, ~{} tokens, ~{:.0} tokens/s]",
+        first_token.as_secs_f64(),
+        total.as_secs_f64(),
+        tokens,
+        rate
+    );
+}
+
+/// Fails unless `policy` lets a call of `name` run, asking when it says to.
+pub fn confirm_tool(policy: tools::Policy, name: &str) -> Result<()> {
+    let allowed = match policy {
+        tools::Policy::Allow => true,
+        tools::Policy::Deny => false,
+        tools::Policy::Ask if non_interactive() => false,
+        tools::Policy::Ask => Confirm::new()
+            .with_prompt(format!("Allow this {} call?", name))
+            .default(false)
+            .interact()?,
+    };
+    if !allowed {
+        eprintln!("[tool] {} call refused", name);
+        return Err(anyhow!("The user did not allow this {} call", name));
+    }
+    Ok(())
+}
+
+/// Shows a token and cost estimate and asks for confirmation when a prompt exceeds the configured
+/// size or the cost limits.
+pub fn confirm_large_prompt(prompt: &str, model: &str, config: &Config, yes: bool) -> Result<bool> {
+    let tokens = cost::estimate_tokens(prompt);
+    if !check_budget("Sending the prompt", cost::estimate_credits(tokens, model, &config.model_rates), config)? {
+        return Ok(false);
+    }
+    let threshold = config.confirm_above_tokens.unwrap_or(cost::DEFAULT_CONFIRM_ABOVE_TOKENS);
+    if yes || tokens <= threshold {
+        return Ok(true);
+    }
+
+    ensure_interactive(
+        &format!("Sending a large request (~{} tokens)", tokens),
+        "pass --yes to send it anyway, or raise confirm_above_tokens in the config file",
+    )?;
+    println!("This is a large request:");
+    println!("  Model:          {}", model);
+    println!("  Prompt tokens:  ~{}", tokens);
+    println!("  Estimated cost: ~{:.0} credits (input only)", cost::estimate_credits(tokens, model, &config.model_rates));
+
+    Ok(Confirm::new()
+        .with_prompt("Send it?")
+        .default(false)
+        .interact()?)
+}
+
+/// Enforces `max_cost_per_request` and `monthly_budget` for an operation estimated at `credits`:
+/// over either, it needs confirmation even with `--yes`, and is refused when running
+/// non-interactively.
+pub fn check_budget(what: &str, credits: f64, config: &Config) -> Result<bool> {
+    if config.max_cost_per_request.is_none() && config.monthly_budget.is_none() {
+        return Ok(true);
+    }
+    let spent = match config.monthly_budget {
+        Some(_) => usage::spent_this_month()?,
+        None => 0.0,
+    };
+    let Some(problem) = cost::over_limit(credits, config.max_cost_per_request, config.monthly_budget, spent) else {
+        return Ok(true);
+    };
+    if non_interactive() {
+        return Err(AiCliError::BudgetExceeded(format!(
+            "{}: {}. Raise the limit in the config file to allow it",
+            what, problem
+        )).into());
+    }
+    eprintln!("{} would cost more than allowed: {}.", what, problem);
+    Ok(Confirm::new()
+        .with_prompt("Go ahead anyway?")
+        .default(false)
+        .interact()?)
+}
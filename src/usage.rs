@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::{cost, lock};
+
+const LEDGER_FILE: &str = "usage.jsonl";
+
+/// One request in the usage ledger.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UsageEntry {
+    pub timestamp: DateTime<Local>,
+    pub model: String,
+    pub prompt_words: usize,
+    pub response_words: usize,
+    pub prompt_tokens: usize,
+    pub response_tokens: usize,
+    /// Estimated from the model's rate, as the streaming API doesn't report credits used
+    pub credits: f64,
+}
+
+/// Totals for one group of ledger entries.
+#[derive(Serialize, Default)]
+pub struct UsageTotals {
+    pub requests: usize,
+    pub words: usize,
+    pub tokens: usize,
+    pub credits: f64,
+}
+
+#[derive(Serialize)]
+pub struct UsageSummary {
+    pub since: DateTime<Local>,
+    pub total: UsageTotals,
+    pub by_model: BTreeMap<String, UsageTotals>,
+    pub by_day: BTreeMap<NaiveDate, UsageTotals>,
+}
+
+impl UsageEntry {
+    pub fn new(model: &str, prompt: &str, response: &str, rates: &BTreeMap<String, f64>) -> UsageEntry {
+        let prompt_tokens = cost::estimate_tokens(prompt);
+        let response_tokens = cost::estimate_tokens(response);
+
+        UsageEntry {
+            timestamp: Local::now(),
+            model: model.to_string(),
+            prompt_words: prompt.split_whitespace().count(),
+            response_words: response.split_whitespace().count(),
+            prompt_tokens,
+            response_tokens,
+            credits: cost::estimate_credits(prompt_tokens + response_tokens, model, rates),
+        }
+    }
+}
+
+impl UsageEntry {
+    /// An entry for generated images, which are priced per image rather than per token.
+    pub fn image(model: &str, prompt: &str, credits: f64) -> UsageEntry {
+        UsageEntry {
+            timestamp: Local::now(),
+            model: model.to_string(),
+            prompt_words: prompt.split_whitespace().count(),
+            response_words: 0,
+            prompt_tokens: cost::estimate_tokens(prompt),
+            response_tokens: 0,
+            credits,
+        }
+    }
+}
+
+impl UsageTotals {
+    fn add(&mut self, entry: &UsageEntry) {
+        self.requests += 1;
+        self.words += entry.prompt_words + entry.response_words;
+        self.tokens += entry.prompt_tokens + entry.response_tokens;
+        self.credits += entry.credits;
+    }
+}
+
+fn ledger_path() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| anyhow!("Could not determine the local data directory"))?
+        .join("ai-cli");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(LEDGER_FILE))
+}
+
+/// Appends an entry to the ledger, as a single line written in one call while holding the
+/// lock on the ledger's directory, so concurrent processes and tasks never interleave (appends
+/// alone aren't atomic on every filesystem).
+pub fn record(entry: &UsageEntry) -> Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    let path = ledger_path()?;
+    let _lock = lock::lock_dir(path.parent().expect("the ledger is in a directory"))?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Reads ledger entries recorded at or after `since`, skipping lines that don't parse.
+pub fn load_since(since: DateTime<Local>) -> Result<Vec<UsageEntry>> {
+    let path = ledger_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str::<UsageEntry>(line).ok())
+        .filter(|entry| entry.timestamp >= since)
+        .collect())
+}
+
+/// Estimated credits spent since the start of the current month, for `monthly_budget`.
+pub fn spent_this_month() -> Result<f64> {
+    let today = Local::now().date_naive();
+    let first = today.with_day(1).expect("the first of a month is valid");
+    let since = Local.from_local_datetime(&first.and_hms_opt(0, 0, 0).expect("midnight is valid"))
+        .earliest()
+        .ok_or_else(|| anyhow!("Invalid date: {}", first))?;
+    Ok(load_since(since)?.iter().map(|entry| entry.credits).sum())
+}
+
+/// Parses a `--since` value: a relative period like `12h`, `7d` or `4w`, or a date (`2024-05-01`).
+pub fn parse_since(value: &str) -> Result<DateTime<Local>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Local.from_local_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight is valid"))
+            .earliest()
+            .ok_or_else(|| anyhow!("Invalid date: {}", value));
+    }
+
+    let invalid = || anyhow!("Invalid period '{}': use e.g. 12h, 7d, 4w or a date like 2024-05-01", value);
+    let split = value.len().checked_sub(1).filter(|&i| value.is_char_boundary(i)).ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let period = match unit {
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        "w" => Duration::weeks(amount),
+        _ => return Err(invalid()),
+    };
+    Ok(Local::now() - period)
+}
+
+pub fn summarize(entries: &[UsageEntry], since: DateTime<Local>) -> UsageSummary {
+    let mut summary = UsageSummary {
+        since,
+        total: UsageTotals::default(),
+        by_model: BTreeMap::new(),
+        by_day: BTreeMap::new(),
+    };
+
+    for entry in entries {
+        summary.total.add(entry);
+        summary.by_model.entry(entry.model.clone()).or_default().add(entry);
+        summary.by_day.entry(entry.timestamp.date_naive()).or_default().add(entry);
+    }
+    summary
+}
+
+fn print_totals_table<'a, K: ToString>(heading: &str, groups: impl Iterator<Item = (K, &'a UsageTotals)>) {
+    let rows: Vec<[String; 5]> = groups.map(|(key, totals)| [
+        key.to_string(),
+        totals.requests.to_string(),
+        totals.words.to_string(),
+        totals.tokens.to_string(),
+        format!("{:.0}", totals.credits),
+    ]).collect();
+
+    let headers = [heading, "REQUESTS", "WORDS", "TOKENS", "EST. CREDITS"];
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells.iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+
+    print_row(headers.to_vec());
+    for row in &rows {
+        print_row(row.iter().map(|cell| cell.as_str()).collect());
+    }
+}
+
+/// Prints how much of `monthly_budget` this month's requests have used.
+pub fn print_budget(budget: f64) -> Result<()> {
+    let spent = spent_this_month()?;
+    println!(
+        "This month: ~{:.0} of {:.0} credits ({:.0}%)",
+        spent,
+        budget,
+        if budget > 0.0 { spent / budget * 100.0 } else { 100.0 }
+    );
+    Ok(())
+}
+
+pub fn print_summary(summary: &UsageSummary) {
+    println!(
+        "Since {}: {} requests, {} tokens, ~{:.0} credits",
+        summary.since.format("%Y-%m-%d %H:%M"),
+        summary.total.requests,
+        summary.total.tokens,
+        summary.total.credits,
+    );
+    if summary.total.requests == 0 {
+        return;
+    }
+
+    println!();
+    print_totals_table("MODEL", summary.by_model.iter());
+    println!();
+    print_totals_table("DAY", summary.by_day.iter());
+}
+
+/// Points the data and cache directories at a scratch directory, so tests that make requests
+/// don't add to the real usage ledger and history.
+#[cfg(test)]
+pub(crate) fn use_scratch_dirs() {
+    static SCRATCH: std::sync::Once = std::sync::Once::new();
+    SCRATCH.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("ai-cli-tests-{}", std::process::id()));
+        std::env::set_var("XDG_DATA_HOME", dir.join("data"));
+        std::env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+    });
+}
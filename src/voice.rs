@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use whatlang::Lang;
+
+pub const VOICE_INPUT_FILENAME: &str = "ai-cli-voice-input.wav";
+/// Speech commands tried in order by [`speak_response`], for `-v` when no API voice is chosen:
+/// `say` on macOS, eSpeak elsewhere.
+pub const SPEECH_COMMANDS: &[&str] = &["say", "espeak-ng", "espeak"];
+/// Recorders tried in order by [`record_voice_input`].
+pub const RECORDERS: &[&str] = &["sox", "arecord"];
+
+/// Voices for the languages responses are detected in: the `say` voice and the eSpeak voice.
+/// English, and languages not listed, are read with the default voice.
+const LANGUAGE_VOICES: &[(Lang, &str, &str)] = &[
+    (Lang::Ara, "Maged", "ar"),
+    (Lang::Ces, "Zuzana", "cs"),
+    (Lang::Cmn, "Tingting", "cmn"),
+    (Lang::Dan, "Sara", "da"),
+    (Lang::Deu, "Anna", "de"),
+    (Lang::Ell, "Melina", "el"),
+    (Lang::Fin, "Satu", "fi"),
+    (Lang::Fra, "Thomas", "fr"),
+    (Lang::Heb, "Carmit", "he"),
+    (Lang::Hin, "Lekha", "hi"),
+    (Lang::Hun, "Mariska", "hu"),
+    (Lang::Ind, "Damayanti", "id"),
+    (Lang::Ita, "Alice", "it"),
+    (Lang::Jpn, "Kyoko", "ja"),
+    (Lang::Kor, "Yuna", "ko"),
+    (Lang::Nld, "Xander", "nl"),
+    (Lang::Nob, "Nora", "nb"),
+    (Lang::Pol, "Zosia", "pl"),
+    (Lang::Por, "Luciana", "pt"),
+    (Lang::Ron, "Ioana", "ro"),
+    (Lang::Rus, "Milena", "ru"),
+    (Lang::Slk, "Laura", "sk"),
+    (Lang::Spa, "Monica", "es"),
+    (Lang::Swe, "Alva", "sv"),
+    (Lang::Tha, "Kanya", "th"),
+    (Lang::Tur, "Yelda", "tr"),
+    (Lang::Ukr, "Lesya", "uk"),
+];
+
+/// Command-line audio players tried in order by [`play_audio`], with their quiet-playback flags.
+const AUDIO_PLAYERS: &[(&str, &[&str])] = &[
+    ("afplay", &[]),
+    ("mpv", &["--really-quiet", "--no-video"]),
+    ("ffplay", &["-nodisp", "-autoexit", "-loglevel", "quiet"]),
+    ("mpg123", &["-q"]),
+];
+
+/// Whether `program` is an executable on the PATH.
+pub fn is_installed(program: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| {
+            let candidate = dir.join(program);
+            candidate.is_file() || candidate.with_extension("exe").is_file()
+        })
+    })
+}
+
+pub fn audio_player_names() -> Vec<&'static str> {
+    AUDIO_PLAYERS.iter().map(|(player, _)| *player).collect()
+}
+
+/// The audio players [`play_audio`] can use, in the order it tries them.
+pub fn installed_audio_players() -> Vec<&'static str> {
+    audio_player_names().into_iter().filter(|player| is_installed(player)).collect()
+}
+
+/// Records from the default microphone into a WAV file until the user presses Enter.
+pub fn record_voice_input(path: &Path) -> Result<()> {
+    let mut recorder = if is_installed("sox") {
+        Command::new("sox")
+            .args(["-q", "-d", "-c", "1", "-r", "16000"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .spawn()?
+    } else if is_installed("arecord") {
+        Command::new("arecord")
+            .args(["-q", "-f", "S16_LE", "-c", "1", "-r", "16000", "-t", "wav"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .spawn()?
+    } else {
+        return Err(anyhow!("Voice input requires either 'sox' or 'arecord' to be installed."));
+    };
+
+    print!("Recording... press Enter to stop. ");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+
+    // Interrupt rather than kill so the recorder finalizes the WAV header.
+    Command::new("kill")
+        .args(["-INT", &recorder.id().to_string()])
+        .status()?;
+    recorder.wait()?;
+    Ok(())
+}
+
+/// Plays an audio file with the first available player and waits until it finishes.
+pub fn play_audio(path: &Path) -> Result<()> {
+    let (player, args) = AUDIO_PLAYERS.iter()
+        .find(|(player, _)| is_installed(player))
+        .ok_or_else(|| anyhow!(
+            "Playing audio requires one of {} to be installed.",
+            audio_player_names().join(", ")
+        ))?;
+
+    let status = Command::new(player)
+        .args(*args)
+        .arg(path)
+        .stdin(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", player, status));
+    }
+    Ok(())
+}
+
+/// The first of [`SPEECH_COMMANDS`] that is installed.
+pub fn speech_command() -> Option<&'static str> {
+    SPEECH_COMMANDS.iter().copied().find(|command| is_installed(command))
+}
+
+/// The language `text` is written in, when it can be told reliably.
+pub fn detect_language(text: &str) -> Option<Lang> {
+    whatlang::detect(text).filter(|info| info.is_reliable()).map(|info| info.lang())
+}
+
+/// The voice `command` reads `text` with: the `voice_map` entry for its language, keyed by
+/// ISO 639-3 code ("deu") or English name ("German"), or else the built-in one. None leaves the
+/// default voice, for English and for text whose language isn't clear.
+pub fn voice_for(text: &str, command: &str, voice_map: &BTreeMap<String, String>) -> Option<String> {
+    let lang = detect_language(text)?;
+    let mapped = voice_map.iter()
+        .find(|(language, _)| language.eq_ignore_ascii_case(lang.code()) || language.eq_ignore_ascii_case(lang.eng_name()));
+    if let Some((_, voice)) = mapped {
+        return Some(voice.clone());
+    }
+    let (_, say, espeak) = LANGUAGE_VOICES.iter().find(|(known, _, _)| *known == lang)?;
+    Some(if command == "say" { say } else { espeak }.to_string())
+}
+
+/// Reads `text` aloud with `say` or eSpeak, in a voice for the language it is written in.
+pub fn speak_response(text: &str, voice_map: &BTreeMap<String, String>) -> Result<()> {
+    let command = speech_command().ok_or_else(|| anyhow!(
+        "Voice output requires one of {} to be installed, or --tts-voice.",
+        SPEECH_COMMANDS.join(", ")
+    ))?;
+    let mut speech = Command::new(command);
+    if let Some(voice) = voice_for(text, command, voice_map) {
+        speech.args(["-v", &voice]);
+    }
+    speech.arg(text).spawn()?.wait()?;
+    Ok(())
+}
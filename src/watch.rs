@@ -0,0 +1,108 @@
+//! `ai-cli watch`: noticing when watched files are saved and building the prompts that tell the
+//! model what changed.
+
+use anyhow::{anyhow, Context, Result};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use similar::TextDiff;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+use crate::references;
+
+/// Editors save in several steps (write, rename, touch); events this close together are one save.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+/// Lines of unchanged context around each change in a diff
+const DIFF_CONTEXT: usize = 3;
+
+/// Watches files for changes. Their directories are watched rather than the files themselves, so
+/// a file an editor replaces on save (writing a new one and renaming it over the old) is still
+/// noticed afterwards.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    events: UnboundedReceiver<notify::Result<notify::Event>>,
+    /// The watched files, canonicalized to compare with the paths of events
+    files: Vec<PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new(files: &[PathBuf]) -> Result<FileWatcher> {
+        let (tx, events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })?;
+        let mut canonical = Vec::with_capacity(files.len());
+        let mut directories = HashSet::new();
+        for file in files {
+            let path = file.canonicalize().with_context(|| format!("Could not watch {}", file.display()))?;
+            if !path.is_file() {
+                return Err(anyhow!("{} is not a file", file.display()));
+            }
+            let directory = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("/"));
+            if directories.insert(directory.clone()) {
+                watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+            }
+            canonical.push(path);
+        }
+        Ok(FileWatcher { _watcher: watcher, events, files: canonical })
+    }
+
+    /// Waits until one or more watched files are saved and returns their indices in the list
+    /// given to [`FileWatcher::new`], in that order.
+    pub async fn changed(&mut self) -> Result<Vec<usize>> {
+        let mut changed = HashSet::new();
+        loop {
+            let event = if changed.is_empty() {
+                self.events.recv().await
+            } else {
+                match tokio::time::timeout(DEBOUNCE, self.events.recv()).await {
+                    Ok(event) => event,
+                    Err(_) => break,
+                }
+            };
+            let event = event.ok_or_else(|| anyhow!("Stopped receiving file events"))??;
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+            for path in &event.paths {
+                if let Some(index) = self.files.iter().position(|file| file == path) {
+                    changed.insert(index);
+                }
+            }
+        }
+        let mut changed: Vec<usize> = changed.into_iter().collect();
+        changed.sort_unstable();
+        Ok(changed)
+    }
+
+    /// Reads a watched file as text.
+    pub fn read(&self, index: usize) -> Result<String> {
+        references::read_text(&self.files[index].display().to_string())
+    }
+}
+
+/// A unified diff from `old` to `new`, or nothing when they are the same.
+pub fn diff(path: &str, old: &str, new: &str) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    if diff.ratio() == 1.0 {
+        return String::new();
+    }
+    diff.unified_diff()
+        .context_radius(DIFF_CONTEXT)
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+/// The prompt for a saved file: the instruction with the whole file the first time and when
+/// `full` is set, otherwise with the diff from the version the model saw last, unless that is
+/// no shorter than the file.
+pub fn change_prompt(instruction: &str, path: &str, previous: Option<&str>, contents: &str, full: bool) -> String {
+    let diff = previous.filter(|_| !full).map(|previous| diff(path, previous, contents));
+    match diff {
+        Some(diff) if diff.len() < contents.len() => {
+            format!("{}\n\n{} was saved with these changes:\n\n```diff\n{}```", instruction, path, diff)
+        }
+        _ => format!("{}\n\n{} was saved:\n\n{}", instruction, path, references::fenced_block(path, contents)),
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Result};
+use reqwest::{header, Client};
+
+use crate::error::AiCliError;
+use crate::retry;
+
+/// Elements whose contents are never part of the readable text.
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "script", "style", "noscript", "svg", "template", "head", "nav", "header", "footer", "aside", "form",
+];
+/// Elements that start a new line in the extracted text.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p", "div", "section", "article", "main", "br", "li", "ul", "ol", "tr", "table", "blockquote",
+    "pre", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "dd", "dt", "figcaption",
+];
+
+/// A fetched page reduced to its readable text.
+pub struct Page {
+    pub title: Option<String>,
+    pub text: String,
+}
+
+/// Downloads a URL and extracts its readable text. HTML is stripped down to its main content;
+/// other text formats are returned as they are.
+pub async fn fetch(http: &Client, url: &str) -> Result<Page> {
+    let response = retry::send(http.get(url)).await.map_err(AiCliError::from)?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Fetching {} failed with {}", url, response.status()));
+    }
+    let content_type = response.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    if !content_type.is_empty() && !content_type.starts_with("text/") && !content_type.contains("html")
+        && !content_type.contains("json") && !content_type.contains("xml")
+    {
+        return Err(anyhow!("{} is not a text page ({})", url, content_type));
+    }
+
+    let body = response.text().await.map_err(AiCliError::from)?;
+    if content_type.contains("html") || (content_type.is_empty() && body.trim_start().starts_with('<')) {
+        Ok(extract_readable(&body))
+    } else {
+        Ok(Page { title: None, text: body })
+    }
+}
+
+/// Finds `<tag ...>` or `</tag>` at byte offset `at` of lowercased HTML, returning the tag name,
+/// whether it closes an element, and the offset just past the tag.
+fn parse_tag(lower: &str, at: usize) -> Option<(&str, bool, usize)> {
+    let rest = &lower[at + 1..];
+    let (closing, rest) = match rest.strip_prefix('/') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let name_len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+    let end = lower[at..].find('>')? + at + 1;
+    Some((&rest[..name_len], closing, end))
+}
+
+/// The contents of the first `<tag>` element, for narrowing a page down to `<article>` or `<main>`.
+fn element_contents<'a>(html: &'a str, lower: &str, tag: &str) -> Option<&'a str> {
+    let start = lower.find(&format!("<{}", tag))
+        .filter(|&at| parse_tag(lower, at).is_some_and(|(name, _, _)| name == tag))?;
+    let open_end = lower[start..].find('>')? + start + 1;
+    let close = lower[open_end..].rfind(&format!("</{}", tag))? + open_end;
+    Some(&html[open_end..close])
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&mdash;", "—")
+        .replace("&ndash;", "–")
+        .replace("&hellip;", "…")
+        .replace("&amp;", "&")
+}
+
+/// Reduces an HTML page to the text a reader would see: the `<article>` or `<main>` element when
+/// there is one, without scripts, navigation, headers, footers and sidebars.
+pub fn extract_readable(html: &str) -> Page {
+    let lower = html.to_ascii_lowercase();
+    let title = element_contents(html, &lower, "title")
+        .map(|title| decode_entities(title.trim()))
+        .filter(|title| !title.is_empty());
+
+    let body = ["article", "main", "body"].iter()
+        .find_map(|tag| element_contents(html, &lower, tag))
+        .unwrap_or(html);
+    let lower = body.to_ascii_lowercase();
+
+    let mut text = String::with_capacity(body.len() / 2);
+    let mut skipping: Option<&str> = None;
+    let mut at = 0;
+    while at < body.len() {
+        let Some(next) = body[at..].find('<').map(|i| i + at) else {
+            if skipping.is_none() {
+                text.push_str(&body[at..]);
+            }
+            break;
+        };
+        if skipping.is_none() {
+            text.push_str(&body[at..next]);
+        }
+
+        if lower[next..].starts_with("<!--") {
+            at = lower[next..].find("-->").map(|i| next + i + 3).unwrap_or(body.len());
+            continue;
+        }
+        let Some((name, closing, end)) = parse_tag(&lower, next) else {
+            if skipping.is_none() {
+                text.push('<');
+            }
+            at = next + 1;
+            continue;
+        };
+        match skipping {
+            Some(skipped) if closing && name == skipped => skipping = None,
+            Some(_) => {}
+            None if !closing && SKIPPED_ELEMENTS.contains(&name) && !lower[..end].ends_with("/>") => {
+                skipping = SKIPPED_ELEMENTS.iter().copied().find(|element| *element == name);
+            }
+            None if BLOCK_ELEMENTS.contains(&name) => text.push('\n'),
+            None => {}
+        }
+        at = end;
+    }
+
+    // Collapse the whitespace HTML ignores, keeping single blank lines between blocks.
+    let mut readable = String::with_capacity(text.len());
+    let mut blank = false;
+    for line in decode_entities(&text).lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            blank = !readable.is_empty();
+            continue;
+        }
+        if blank {
+            readable.push('\n');
+            blank = false;
+        }
+        readable.push_str(&line);
+        readable.push('\n');
+    }
+    Page { title, text: readable }
+}